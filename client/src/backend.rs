@@ -0,0 +1,383 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+use bevy_rapier3d::prelude::*;
+
+use shared::{hooks::ContactRules, scene::SceneCollider, BodyCommand, Request, SimulationState};
+
+use crate::plugin::{
+    BodyCommands, MirrorSync, PendingBodyCommands, RemoteRayCasts, RemoteRayHit, RemoteScene,
+    RemoteShapeHit, RemoteShapeIntersections, RemoteShapeQueries, RequestQueue, RequestResult,
+    RequestWindow, SimulationControl, TemplateRegistry,
+};
+
+/// Which backend steps the world.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Remote,
+    /// The plain bevy_rapier plugin, to compare the remote backend against.
+    Local,
+}
+
+/// Switches to the other backend at the start of the next frame. The world is
+/// created again on the other side from the entities' components, so ragdoll
+/// joints, ropes, controllers and fluid volumes only work remotely.
+#[derive(Debug, Clone, Copy)]
+pub struct SwitchBackend;
+
+/// The colliders of the scene loaded on the server, created locally while the
+/// local backend runs.
+#[derive(Resource, Default)]
+pub struct LoadedScene(pub Vec<SceneCollider>);
+
+#[derive(Component)]
+pub struct LocalSceneCollider;
+
+pub fn remote_backend(backend: Res<Backend>) -> ShouldRun {
+    if *backend == Backend::Remote {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+pub fn local_backend(backend: Res<Backend>) -> ShouldRun {
+    if *backend == Backend::Local {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+pub fn keep_scene(mut scenes: EventReader<RemoteScene>, mut loaded: ResMut<LoadedScene>) {
+    for scene in scenes.iter() {
+        loaded
+            .0
+            .extend(scene.colliders.iter().map(|(_, collider)| collider.clone()));
+    }
+}
+
+/// Runs right after the remote backend's responses were written back.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn switch_backend(
+    mut commands: Commands,
+    mut switches: EventReader<SwitchBackend>,
+    mut backend: ResMut<Backend>,
+    mut context: ResMut<RapierContext>,
+    handles: Query<Entity, Or<(With<RapierRigidBodyHandle>, With<RapierColliderHandle>)>>,
+    local_scene: Query<Entity, With<LocalSceneCollider>>,
+    loaded_scene: Res<LoadedScene>,
+    config: Res<RapierConfiguration>,
+    contact_rules: Res<ContactRules>,
+    mut request_queue: ResMut<RequestQueue>,
+    mut registry: ResMut<TemplateRegistry>,
+    mut body_commands: ResMut<BodyCommands>,
+    mut pending: ResMut<PendingBodyCommands>,
+    mut mirror: Option<ResMut<MirrorSync>>,
+    window: Res<RequestWindow>,
+    result: Res<RequestResult>,
+) {
+    // Switching twice in a frame goes nowhere
+    if switches.iter().count().is_multiple_of(2) {
+        return;
+    }
+
+    forget_world(&mut commands, &handles, &mut context, &mut mirror);
+
+    *backend = match *backend {
+        Backend::Remote => {
+            drop_remote_requests(&window, &result, &mut request_queue);
+
+            for collider in &loaded_scene.0 {
+                let mut scene_collider = commands.spawn((
+                    collider.shape.collider(),
+                    TransformBundle::from_transform(collider.transform()),
+                    LocalSceneCollider,
+                ));
+                if let Some(friction) = collider.friction {
+                    scene_collider.insert(Friction::coefficient(friction));
+                }
+                if let Some(restitution) = collider.restitution {
+                    scene_collider.insert(Restitution::coefficient(restitution));
+                }
+            }
+
+            // Commands waiting for a remote body apply to the local one
+            for (entity, buffered) in pending.0.drain() {
+                body_commands
+                    .0
+                    .extend(buffered.into_iter().map(|command| (entity, command)));
+            }
+            Backend::Local
+        }
+        Backend::Local => {
+            for entity in local_scene.iter() {
+                commands.entity(entity).despawn();
+            }
+
+            restart_remote(&mut registry, &mut request_queue, &config, &contact_rules);
+            Backend::Remote
+        }
+    };
+    info!("Switched to the {:?} backend", *backend);
+}
+
+/// Applies `SimulationControl` to the backend switched to. The local backend
+/// pauses with its configuration, and the server is sent the state whenever
+/// it differs from what it was last sent.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn control_simulation(
+    mut commands: Commands,
+    mut control: ResMut<SimulationControl>,
+    mut sent_state: Local<SimulationState>,
+    backend: Res<Backend>,
+    mut context: ResMut<RapierContext>,
+    mut config: ResMut<RapierConfiguration>,
+    handles: Query<Entity, Or<(With<RapierRigidBodyHandle>, With<RapierColliderHandle>)>>,
+    mut request_queue: ResMut<RequestQueue>,
+    mut registry: ResMut<TemplateRegistry>,
+    mut mirror: Option<ResMut<MirrorSync>>,
+) {
+    let active = control.state == SimulationState::Running;
+    if config.physics_pipeline_active != active {
+        config.physics_pipeline_active = active;
+    }
+    if *backend == Backend::Remote && control.state != *sent_state {
+        *sent_state = control.state;
+        request_queue
+            .0
+            .push(Request::SetSimulationState(control.state));
+    }
+
+    if control.reset {
+        control.reset = false;
+        forget_world(&mut commands, &handles, &mut context, &mut mirror);
+        if *backend == Backend::Remote {
+            // The server forgets the templates along with the world
+            registry.templates.clear();
+            request_queue.0.insert(0, Request::ResetWorld);
+        }
+        info!("Restarted the world");
+    }
+}
+
+/// Counts the client's reconnects, see `PhysicsClient::reconnects`.
+#[derive(Resource)]
+pub struct Reconnects(pub Arc<AtomicUsize>);
+
+/// Creates the world again on the session the client reconnected to. Runs
+/// right after the responses were written back.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn recover_from_reconnect(
+    mut commands: Commands,
+    reconnects: Res<Reconnects>,
+    mut recovered: Local<usize>,
+    mut context: ResMut<RapierContext>,
+    handles: Query<Entity, Or<(With<RapierRigidBodyHandle>, With<RapierColliderHandle>)>>,
+    config: Res<RapierConfiguration>,
+    contact_rules: Res<ContactRules>,
+    mut request_queue: ResMut<RequestQueue>,
+    mut registry: ResMut<TemplateRegistry>,
+    mut mirror: Option<ResMut<MirrorSync>>,
+    window: Res<RequestWindow>,
+    result: Res<RequestResult>,
+) {
+    let reconnects = reconnects.0.load(Ordering::SeqCst);
+    if reconnects == *recovered {
+        return;
+    }
+    *recovered = reconnects;
+
+    info!("Reconnected, creating the world again");
+    drop_remote_requests(&window, &result, &mut request_queue);
+    forget_world(&mut commands, &handles, &mut context, &mut mirror);
+    restart_remote(&mut registry, &mut request_queue, &config, &contact_rules);
+}
+
+/// Without their handles, the next backend creates bodies and colliders again
+/// from their current components.
+#[allow(clippy::type_complexity)]
+fn forget_world(
+    commands: &mut Commands,
+    handles: &Query<Entity, Or<(With<RapierRigidBodyHandle>, With<RapierColliderHandle>)>>,
+    context: &mut RapierContext,
+    mirror: &mut Option<ResMut<MirrorSync>>,
+) {
+    for entity in handles.iter() {
+        commands.entity(entity).remove::<(
+            RapierRigidBodyHandle,
+            RapierColliderHandle,
+            RapierImpulseJointHandle,
+            RapierMultibodyJointHandle,
+        )>();
+    }
+    *context = RapierContext::default();
+    if let Some(mirror) = mirror {
+        mirror.bodies.clear();
+        mirror.colliders.clear();
+    }
+}
+
+/// Requests and responses still around refer to the remote world being left.
+fn drop_remote_requests(
+    window: &RequestWindow,
+    result: &RequestResult,
+    request_queue: &mut RequestQueue,
+) {
+    window.wait_until(|window| window.in_flight() == 0);
+    result.0.lock().unwrap().clear();
+    request_queue.0.clear();
+}
+
+/// Has the server start over from an empty world, before anything else.
+fn restart_remote(
+    registry: &mut TemplateRegistry,
+    request_queue: &mut RequestQueue,
+    config: &RapierConfiguration,
+    contact_rules: &ContactRules,
+) {
+    // The server forgets the templates along with the world
+    registry.templates.clear();
+    request_queue.0.splice(
+        0..0,
+        [
+            Request::ResetWorld,
+            Request::UpdateConfig((*config).into(), contact_rules.clone()),
+        ],
+    );
+}
+
+/// Applies body commands to the local bodies, as the server would.
+pub fn apply_body_commands_locally(
+    mut commands: Commands,
+    mut body_commands: ResMut<BodyCommands>,
+    mut impulses: Query<&mut ExternalImpulse>,
+) {
+    for (entity, command) in body_commands.0.drain(..) {
+        match command {
+            BodyCommand::Force { force, torque } => {
+                if let Some(mut entity) = commands.get_entity(entity) {
+                    entity.insert(ExternalForce { force, torque });
+                }
+            }
+            BodyCommand::Impulse {
+                impulse,
+                torque_impulse,
+            } => match impulses.get_mut(entity) {
+                Ok(mut external) => {
+                    external.impulse += impulse;
+                    external.torque_impulse += torque_impulse;
+                }
+                Err(_) => {
+                    if let Some(mut entity) = commands.get_entity(entity) {
+                        entity.insert(ExternalImpulse {
+                            impulse,
+                            torque_impulse,
+                        });
+                    }
+                }
+            },
+            BodyCommand::SetVelocity(velocity) => {
+                if let Some(mut entity) = commands.get_entity(entity) {
+                    entity.insert(velocity);
+                }
+            }
+        }
+    }
+}
+
+/// Answers ray casts with the local world, as the server would.
+pub fn cast_rays_locally(
+    context: Res<RapierContext>,
+    mut ray_casts: ResMut<RemoteRayCasts>,
+    mut ray_hits: EventWriter<RemoteRayHit>,
+) {
+    for ray in ray_casts.rays.drain(..) {
+        let hit = context.cast_ray(
+            ray.origin,
+            ray.dir,
+            ray.max_toi,
+            ray.solid,
+            QueryFilter::default(),
+        );
+        ray_hits.send(RemoteRayHit {
+            id: ray.id,
+            hit,
+            normal: None,
+        });
+    }
+
+    for (ray, filter) in ray_casts.filtered_rays.drain(..) {
+        let hit = context.cast_ray_and_get_normal(
+            ray.origin,
+            ray.dir,
+            ray.max_toi,
+            ray.solid,
+            filter.into(),
+        );
+        ray_hits.send(RemoteRayHit {
+            id: ray.id,
+            hit: hit.map(|(entity, hit)| (entity, hit.toi)),
+            normal: hit.map(|(_, hit)| hit.normal),
+        });
+    }
+}
+
+/// Answers shape casts and intersection tests with the local world, as the
+/// server would.
+pub fn query_shapes_locally(
+    context: Res<RapierContext>,
+    mut shape_queries: ResMut<RemoteShapeQueries>,
+    mut shape_hits: EventWriter<RemoteShapeHit>,
+    mut shape_intersections: EventWriter<RemoteShapeIntersections>,
+) {
+    for (id, request) in shape_queries.queries.drain(..) {
+        match request {
+            Request::CastShape {
+                shape_pos,
+                shape_rot,
+                shape_vel,
+                shape,
+                max_toi,
+                filter,
+            } => {
+                let hit = context.cast_shape(
+                    shape_pos,
+                    shape_rot,
+                    shape_vel,
+                    &Collider::from(shape),
+                    max_toi,
+                    filter.into(),
+                );
+                shape_hits.send(RemoteShapeHit {
+                    id,
+                    hit: hit.map(|(entity, toi)| (entity, toi.into())),
+                });
+            }
+            Request::IntersectionsWithShape {
+                shape_pos,
+                shape_rot,
+                shape,
+                filter,
+            } => {
+                let mut entities = vec![];
+                context.intersections_with_shape(
+                    shape_pos,
+                    shape_rot,
+                    &Collider::from(shape),
+                    filter.into(),
+                    |entity| {
+                        entities.push(entity);
+                        true
+                    },
+                );
+                shape_intersections.send(RemoteShapeIntersections { id, entities });
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::marker::PhantomData;
+use std::mem;
+
+use bevy_rapier3d::rapier::prelude::RigidBodyHandle;
+
+use shared::{
+    serializable::{SerializableExternalForce, SerializableExternalImpulse},
+    BodyCommand, CreatedBody, CreatedCollider, Request,
+};
+
+use crate::plugin::RequestQueue;
+
+/// Well below tungstenite's frame size limit, so that a single message
+/// doesn't hold up the others on the connection for long.
+pub const DEFAULT_MAX_BYTES: u64 = 1 << 20;
+
+/// Where a `BulkRequestBuilder` is in the order of a step.
+pub struct Bodies;
+pub struct Colliders;
+pub struct Forces;
+pub struct Stepped;
+
+/// Stages colliders can still be created in.
+pub trait BeforeColliders {}
+impl BeforeColliders for Bodies {}
+impl BeforeColliders for Colliders {}
+
+/// Stages forces can still be applied in.
+pub trait BeforeForces {}
+impl BeforeForces for Bodies {}
+impl BeforeForces for Colliders {}
+impl BeforeForces for Forces {}
+
+/// Composes bulk requests in the order the server needs them: bodies, then
+/// the colliders attached to them, then forces and commands, then the step.
+/// Going back to an earlier stage doesn't compile, and any stage can be
+/// skipped. Forces and commands need the handles of bodies, so they can only
+/// be applied to bodies created by earlier requests.
+///
+/// The requests are split into as many bulk requests as it takes to keep each
+/// under the size limit, lists being split in halves if they don't fit alone.
+pub struct BulkRequestBuilder<Stage = Bodies> {
+    requests: Vec<Request>,
+    /// The most bytes a bulk request is serialized into, unless a single
+    /// request that can't be split is larger.
+    max_bytes: u64,
+    stage: PhantomData<Stage>,
+}
+
+impl BulkRequestBuilder<Bodies> {
+    pub fn new() -> Self {
+        Self {
+            requests: vec![],
+            max_bytes: DEFAULT_MAX_BYTES,
+            stage: PhantomData,
+        }
+    }
+
+    pub fn create_bodies(self, bodies: Vec<CreatedBody>) -> Self {
+        self.push(bodies, Request::CreateBodies)
+    }
+}
+
+impl Default for BulkRequestBuilder<Bodies> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Stage: BeforeColliders> BulkRequestBuilder<Stage> {
+    pub fn create_colliders(
+        self,
+        colliders: Vec<CreatedCollider>,
+    ) -> BulkRequestBuilder<Colliders> {
+        self.push(colliders, Request::CreateColliders)
+    }
+}
+
+impl<Stage: BeforeForces> BulkRequestBuilder<Stage> {
+    pub fn apply_forces(
+        self,
+        forces: Vec<(
+            RigidBodyHandle,
+            Option<SerializableExternalForce>,
+            Option<SerializableExternalImpulse>,
+        )>,
+    ) -> BulkRequestBuilder<Forces> {
+        self.push(forces, Request::ApplyForces)
+    }
+
+    pub fn apply_commands(
+        self,
+        commands: Vec<(RigidBodyHandle, BodyCommand)>,
+    ) -> BulkRequestBuilder<Forces> {
+        self.push(commands, Request::ApplyCommands)
+    }
+
+    pub fn step(mut self, delta_time: f32) -> BulkRequestBuilder<Stepped> {
+        self.requests.push(Request::SimulateStep(delta_time));
+        self.into_stage()
+    }
+}
+
+impl<Stage> BulkRequestBuilder<Stage> {
+    /// Leaves empty lists out.
+    fn push<T, Next>(
+        mut self,
+        items: Vec<T>,
+        request: fn(Vec<T>) -> Request,
+    ) -> BulkRequestBuilder<Next> {
+        if !items.is_empty() {
+            self.requests.push(request(items));
+        }
+        self.into_stage()
+    }
+
+    fn into_stage<Next>(self) -> BulkRequestBuilder<Next> {
+        BulkRequestBuilder {
+            requests: self.requests,
+            max_bytes: self.max_bytes,
+            stage: PhantomData,
+        }
+    }
+
+    pub fn build(self) -> Vec<Request> {
+        let max_bytes = self.max_bytes;
+        let mut bulks = vec![];
+        let mut bulk = vec![];
+        let mut bulk_len = 0;
+        for request in self
+            .requests
+            .into_iter()
+            .flat_map(|request| split(request, max_bytes))
+        {
+            let len = serialized_len(&request);
+            if !bulk.is_empty() && bulk_len + len > max_bytes {
+                bulks.push(Request::BulkRequest(mem::take(&mut bulk)));
+                bulk_len = 0;
+            }
+            bulk_len += len;
+            bulk.push(request);
+        }
+        if !bulk.is_empty() {
+            bulks.push(Request::BulkRequest(bulk));
+        }
+        bulks
+    }
+
+    /// Queues the bulk requests to be sent with the rest of the frame's.
+    pub fn queue(self, request_queue: &mut RequestQueue) {
+        request_queue.0.extend(self.build());
+    }
+}
+
+fn serialized_len(request: &Request) -> u64 {
+    bincode::serialized_size(request).unwrap_or(0)
+}
+
+/// Splits the lists of requests larger than `max_bytes` in halves until they
+/// fit.
+fn split(request: Request, max_bytes: u64) -> Vec<Request> {
+    if serialized_len(&request) <= max_bytes {
+        return vec![request];
+    }
+    match request {
+        Request::CreateBodies(bodies) if bodies.len() > 1 => {
+            halves(bodies, Request::CreateBodies, max_bytes)
+        }
+        Request::CreateColliders(colliders) if colliders.len() > 1 => {
+            halves(colliders, Request::CreateColliders, max_bytes)
+        }
+        Request::ApplyForces(forces) if forces.len() > 1 => {
+            halves(forces, Request::ApplyForces, max_bytes)
+        }
+        Request::ApplyCommands(commands) if commands.len() > 1 => {
+            halves(commands, Request::ApplyCommands, max_bytes)
+        }
+        // Sent alone
+        request => vec![request],
+    }
+}
+
+fn halves<T>(mut items: Vec<T>, request: fn(Vec<T>) -> Request, max_bytes: u64) -> Vec<Request> {
+    let second = items.split_off(items.len() / 2);
+    [items, second]
+        .into_iter()
+        .flat_map(|half| split(request(half), max_bytes))
+        .collect()
+}
+
+/// Puts the requests of a frame into bulk requests, leaving those built with
+/// `BulkRequestBuilder` as they are so that they keep under their size limit.
+pub fn bundle(requests: Vec<Request>) -> Vec<Request> {
+    let mut bundled = vec![];
+    let mut bulk = vec![];
+    for request in requests {
+        match request {
+            Request::BulkRequest(_) => {
+                if !bulk.is_empty() {
+                    bundled.push(Request::BulkRequest(mem::take(&mut bulk)));
+                }
+                bundled.push(request);
+            }
+            request => bulk.push(request),
+        }
+    }
+    if !bulk.is_empty() {
+        bundled.push(Request::BulkRequest(bulk));
+    }
+    bundled
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_rapier3d::prelude::RigidBody;
+
+    use super::*;
+
+    fn body(id: u64) -> CreatedBody {
+        CreatedBody {
+            id,
+            body: RigidBody::Dynamic,
+            transform: None,
+            additional_mass_properties: None,
+            damping: None,
+            gravity_scale: None,
+            ccd: false,
+            dominance_group: None,
+            locked_axes: None,
+            tag: None,
+        }
+    }
+
+    fn bodies(ids: std::ops::Range<u64>) -> Vec<CreatedBody> {
+        ids.map(body).collect()
+    }
+
+    /// The ids of the bodies every bulk request creates.
+    fn created(bulks: &[Request]) -> Vec<Vec<Vec<u64>>> {
+        bulks
+            .iter()
+            .map(|bulk| match bulk {
+                Request::BulkRequest(requests) => requests
+                    .iter()
+                    .map(|request| match request {
+                        Request::CreateBodies(bodies) => {
+                            bodies.iter().map(|body| body.id).collect()
+                        }
+                        request => panic!("Unexpected request {}", request.name()),
+                    })
+                    .collect(),
+                request => panic!("Unexpected request {}", request.name()),
+            })
+            .collect()
+    }
+
+    fn builder(max_bytes: u64) -> BulkRequestBuilder {
+        BulkRequestBuilder {
+            max_bytes,
+            ..BulkRequestBuilder::new()
+        }
+    }
+
+    #[test]
+    fn requests_at_the_limit_are_not_split() {
+        let max_bytes = serialized_len(&Request::CreateBodies(bodies(0..4)));
+
+        let bulks = builder(max_bytes).create_bodies(bodies(0..4)).build();
+
+        assert_eq!(created(&bulks), vec![vec![vec![0, 1, 2, 3]]]);
+    }
+
+    #[test]
+    fn requests_over_the_limit_are_split_in_halves() {
+        let max_bytes = serialized_len(&Request::CreateBodies(bodies(0..2)));
+
+        let bulks = builder(max_bytes).create_bodies(bodies(0..4)).build();
+
+        assert_eq!(created(&bulks), vec![vec![vec![0, 1]], vec![vec![2, 3]]]);
+    }
+
+    #[test]
+    fn oversized_requests_are_sent_alone() {
+        let bulks = builder(1).create_bodies(bodies(0..1)).step(0.1).build();
+
+        assert_eq!(bulks.len(), 2);
+        assert!(matches!(
+            &bulks[0],
+            Request::BulkRequest(requests)
+                if matches!(requests.as_slice(), [Request::CreateBodies(bodies)] if bodies.len() == 1)
+        ));
+        assert!(matches!(
+            &bulks[1],
+            Request::BulkRequest(requests)
+                if matches!(requests.as_slice(), [Request::SimulateStep(_)])
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, utils::Instant};
+use rand::{thread_rng, Rng};
+use shared::{Request, Response};
+
+use crate::{client::PhysicsClient, error::Result};
+
+/// The round trip time is the fastest of this many pings.
+const PINGS: u32 = 5;
+
+/// About the size of a snapshot of a few hundred bodies.
+const LARGE_PAYLOAD: usize = 64 * 1024;
+
+/// About as many bodies as a busy level of the demo.
+pub const MEASURED_BODIES: usize = 200;
+
+/// The network and server costs measured before the first frame, and the
+/// parameters picked from them.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Calibration {
+    /// The round trip time of a request with an empty payload.
+    pub rtt: Duration,
+    /// In bytes per second, `None` if too fast to tell from the round trip
+    /// time.
+    pub bandwidth: Option<f64>,
+    /// The server's step time of a world of `MEASURED_BODIES` bodies.
+    pub step_time: Duration,
+    /// How far behind the server remote state should be shown so that the
+    /// next snapshot has usually arrived: a round trip, a step and the
+    /// transfer of a large snapshot.
+    pub interpolation_delay: Duration,
+    /// Whether the requests of a frame should be sent as a single bulk
+    /// request, when a round trip costs more than the step it waits for.
+    pub batch_requests: bool,
+}
+
+/// Runs a short burst of pings and a step measurement on the server.
+pub fn calibrate(client: &mut PhysicsClient) -> Result<Calibration> {
+    let rtt = fastest_round_trip(client, 0)?;
+    let large_rtt = fastest_round_trip(client, LARGE_PAYLOAD)?;
+    // The payload is sent both ways
+    let transfer_time = large_rtt.saturating_sub(rtt);
+    let bandwidth = (!transfer_time.is_zero())
+        .then(|| (2 * LARGE_PAYLOAD) as f64 / transfer_time.as_secs_f64());
+
+    let step_time = match client.send_request(Request::MeasureStep(MEASURED_BODIES))? {
+        Response::StepMeasured(step_time) => step_time,
+        resp => {
+            warn!("Unexpected calibration response <{}>", resp.name());
+            Duration::ZERO
+        }
+    };
+
+    Ok(Calibration {
+        rtt,
+        bandwidth,
+        step_time,
+        interpolation_delay: rtt + step_time + transfer_time / 2,
+        batch_requests: rtt > step_time,
+    })
+}
+
+fn fastest_round_trip(client: &mut PhysicsClient, payload_len: usize) -> Result<Duration> {
+    let mut fastest = Duration::MAX;
+    for _ in 0..PINGS {
+        // Random bytes, so that compression doesn't shrink them
+        let mut payload = vec![0; payload_len];
+        thread_rng().fill(&mut payload[..]);
+
+        let start = Instant::now();
+        client.send_request(Request::Ping {
+            payload,
+            reply_len: payload_len,
+        })?;
+        fastest = fastest.min(start.elapsed());
+    }
+    Ok(fastest)
+}
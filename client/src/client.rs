@@ -1,27 +1,229 @@
 use std::{
-    io::{Read, Write},
-    net::TcpStream,
+    mem,
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::sleep,
+    time::Duration,
 };
 
 use bevy::{prelude::*, utils::Instant};
-use bincode::{deserialize, serialize};
-use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression, Decompress};
-use shared::*;
-use tungstenite::{connect, stream::MaybeTlsStream, Message, WebSocket};
+use rand::{thread_rng, Rng};
+use shared::{
+    channel::Channel,
+    codec::Encoding,
+    compression::{Compression, Compressor},
+    framing::Framing,
+    protocol::{
+        ProtocolVersion, ROOM_HEADER, ROOM_MEMBER_HEADER, SESSION_ID_HEADER, SESSION_RESUMED_HEADER,
+    },
+    *,
+};
+use tungstenite::{
+    client::IntoClientRequest,
+    connect,
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue, StatusCode},
+    stream::MaybeTlsStream,
+    Message, WebSocket,
+};
 use url::Url;
 
 use human_bytes::human_bytes;
 
 use crate::error::Result;
+use crate::handover::{Handover, HandoverKind, DELAY_RANGE};
+
+/// Traffic statistics accumulated since they were last taken.
+#[derive(Debug, Default)]
+pub struct RequestStats {
+    pub latencies: Vec<Duration>,
+    pub bytes_sent: usize,
+    pub bytes_received: usize,
+    pub exchanges: Vec<Exchange>,
+}
+
+/// A request and its response, as sent over the wire.
+#[derive(Debug, Clone)]
+// Shown by the console, the only reader
+#[cfg_attr(not(feature = "console"), allow(dead_code))]
+pub struct Exchange {
+    pub channel: Channel,
+    pub request: &'static str,
+    pub response: &'static str,
+    pub bytes_sent: usize,
+    pub bytes_received: usize,
+    pub latency: Duration,
+}
+
+/// Lets other threads cut the connection while a request waits on it, and
+/// have the client connect again before its next request.
+#[derive(Clone)]
+pub struct ConnectionControl {
+    /// A handle to the socket's stream, `None` if it isn't plain TCP.
+    stream: Arc<Mutex<Option<TcpStream>>>,
+    reset: Arc<AtomicBool>,
+}
+
+impl ConnectionControl {
+    /// Shuts the socket down, failing the request waiting on it.
+    pub fn reset(&self) {
+        self.reset.store(true, Ordering::SeqCst);
+        if let Some(stream) = &*self.stream.lock().unwrap() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn status(&self) -> String {
+        match &*self.stream.lock().unwrap() {
+            Some(stream) => format!(
+                "peer {:?}, local {:?}, pending error {:?}",
+                stream.peer_addr(),
+                stream.local_addr(),
+                stream.take_error()
+            ),
+            None => "unknown, not a plain TCP connection".to_string(),
+        }
+    }
+
+    fn watch(&self, socket: &WebSocket<MaybeTlsStream<TcpStream>>) {
+        *self.stream.lock().unwrap() = match socket.get_ref() {
+            MaybeTlsStream::Plain(stream) => stream.try_clone().ok(),
+            _ => None,
+        };
+    }
+}
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Connects asking for the current protocol version, which servers that speak
+/// it answer with, to resume the session if one is given, and to join the
+/// room if one is given.
+#[allow(clippy::result_large_err)]
+fn open(
+    url: &Url,
+    session_id: Option<u64>,
+    room: Option<&str>,
+) -> tungstenite::Result<(Socket, tungstenite::handshake::client::Response)> {
+    let mut request = url.into_client_request()?;
+    request.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(ProtocolVersion::CURRENT.name()),
+    );
+    if let Some(id) = session_id {
+        request
+            .headers_mut()
+            .insert(SESSION_ID_HEADER, HeaderValue::from(id));
+    }
+    if let Some(room) = room {
+        let room = HeaderValue::from_str(room)
+            .map_err(|err| tungstenite::Error::HttpFormat(err.into()))?;
+        request.headers_mut().insert(ROOM_HEADER, room);
+    }
+    let (socket, response) = connect(request)?;
+    let answered = response
+        .headers()
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|answered| answered.to_str().ok());
+    if answered != Some(ProtocolVersion::CURRENT.name()) {
+        return Err(tungstenite::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "the server doesn't speak {}, it answered {:?}",
+                ProtocolVersion::CURRENT,
+                answered
+            ),
+        )));
+    }
+    Ok((socket, response))
+}
+
+/// The id of the session the server serves the connection with, `None` if it
+/// doesn't keep sessions, and whether it's the one asked to resume.
+fn session_of(response: &tungstenite::handshake::client::Response) -> (Option<u64>, bool) {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let id = header(SESSION_ID_HEADER).and_then(|id| id.parse().ok());
+    (id, header(SESSION_RESUMED_HEADER) == Some("true"))
+}
+
+/// The member number of the session in the room it joined, `None` if it
+/// didn't join one.
+fn member_of(response: &tungstenite::handshake::client::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(ROOM_MEMBER_HEADER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// A request written to the socket whose response wasn't read yet.
+struct PendingRequest {
+    channel: Channel,
+    request_type: &'static str,
+    sent_len: usize,
+    start: Instant,
+}
 
 pub struct PhysicsClient {
+    url: Url,
     socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    control: ConnectionControl,
+    stats: Vec<Arc<Mutex<RequestStats>>>,
+    /// The latest requests configuring the session, sent again to the new
+    /// session after a reconnect.
+    setup: Vec<Request>,
+    handover: Option<Handover>,
+    /// Counts the reconnects to new sessions, after which the world has to be
+    /// created again.
+    reconnects: Arc<AtomicUsize>,
+    /// Of the server, presented on reconnects to resume it.
+    session_id: Option<u64>,
+    /// Asked to join on every connection.
+    room: Option<String>,
+    /// Of the session in the room it joined.
+    room_member: Option<u64>,
+    /// Of the requests, with the compression agreed on in the handshake.
+    compressor: Compressor,
+    framing: Framing,
+    encoding: Encoding,
+    /// The results the server pushed since they were last taken.
+    pushed: Arc<Mutex<Vec<Response>>>,
+    /// Whether the server was asked to push results, or pushes them on its
+    /// own.
+    streaming: bool,
+    /// Whether the server pushes results without being asked, stepping at its
+    /// tick rate or as the host of the room does.
+    pushing: bool,
 }
 
 impl PhysicsClient {
-    pub fn new(url: Url) -> Self {
+    /// Connects, joining the room of the name if one is given, whose world
+    /// the session then shares with the room's other members.
+    pub fn connect(url: Url, room: Option<String>) -> Self {
         println!("Connecting to {}", url);
-        let (socket, response) = connect(url).expect("Can't connect to physics server");
+        let (socket, response) = match open(&url, None, room.as_deref()) {
+            Ok(connected) => connected,
+            // Turned away with a hint of when to retry and where else to go
+            Err(tungstenite::Error::Http(response))
+                if response.status() == StatusCode::SERVICE_UNAVAILABLE =>
+            {
+                let message = response
+                    .body()
+                    .as_deref()
+                    .map(String::from_utf8_lossy)
+                    .unwrap_or_default();
+                panic!("Physics server unavailable: {}", message);
+            }
+            Err(err) => panic!("Can't connect to physics server: {:?}", err),
+        };
 
         println!("Connected to the server");
         println!("Response HTTP code: {}", response.status());
@@ -30,26 +232,253 @@ impl PhysicsClient {
             println!("* {}", header);
         }
 
-        Self { socket }
+        let (session_id, _) = session_of(&response);
+        if let Some(id) = session_id {
+            info!("Served by session {}", id);
+        }
+        let room_member = member_of(&response);
+        match (&room, room_member) {
+            (Some(room), Some(member)) => info!("Joined room {} as member {}", room, member),
+            (Some(room), None) => warn!("The server has no rooms, not joining {}", room),
+            _ => {}
+        }
+
+        let control = ConnectionControl {
+            stream: Arc::new(Mutex::new(None)),
+            reset: Arc::new(AtomicBool::new(false)),
+        };
+        control.watch(&socket);
+
+        Self {
+            url,
+            socket,
+            control,
+            stats: vec![],
+            setup: vec![],
+            handover: None,
+            reconnects: Arc::new(AtomicUsize::new(0)),
+            session_id,
+            room,
+            room_member,
+            compressor: Compressor::default(),
+            framing: Framing::Binary,
+            encoding: Encoding::Bincode,
+            pushed: Arc::new(Mutex::new(vec![])),
+            streaming: room_member.is_some(),
+            pushing: room_member.is_some(),
+        }
+    }
+
+    /// Reads the results the server pushes on its own, as servers that step
+    /// every world at their tick rate do.
+    pub fn expect_pushes(&mut self) {
+        self.pushing = true;
+        self.streaming = true;
+    }
+
+    /// The member number of the session in the room it joined, which the
+    /// physics ids of its bodies start from.
+    pub fn room_member(&self) -> Option<u64> {
+        self.room_member
+    }
+
+    pub fn set_handover(&mut self, handover: Handover) {
+        self.handover = Some(handover);
+    }
+
+    pub fn reconnects(&self) -> Arc<AtomicUsize> {
+        self.reconnects.clone()
+    }
+
+    pub fn control(&self) -> ConnectionControl {
+        self.control.clone()
+    }
+
+    pub fn pushed(&self) -> Arc<Mutex<Vec<Response>>> {
+        self.pushed.clone()
+    }
+
+    pub fn set_compression_threshold(&mut self, threshold: usize) {
+        self.compressor.threshold = threshold;
+    }
+
+    pub fn set_compression_level(&mut self, level: i32) {
+        self.compressor.level = Some(level);
+    }
+
+    /// Statistics of the requests sent from now on. Each subscriber takes its
+    /// own, and they are kept outside of the client so they can be read while
+    /// a request is in flight.
+    pub fn subscribe_stats(&mut self) -> Arc<Mutex<RequestStats>> {
+        let stats = Arc::new(Mutex::new(RequestStats::default()));
+        self.stats.push(stats.clone());
+        stats
     }
 
     pub fn send_request(&mut self, request: Request) -> Result<Response> {
-        let serialized = serialize(&request)?;
+        self.prepare()?;
+        self.remember_setup(&request);
+        self.exchange(request)
+    }
 
-        let msg = {
-            #[cfg(feature = "compression")]
-            {
-                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-                encoder.write_all(&serialized)?;
-                let compressed = encoder.finish()?;
+    /// Sends every request before reading the first response, so that a
+    /// server coalescing requests answers them at once. Stops at the first
+    /// error, which is the last result.
+    pub fn send_requests(&mut self, requests: Vec<Request>) -> Vec<Result<Response>> {
+        if let Err(err) = self.prepare() {
+            return vec![Err(err)];
+        }
+        let mut sent = vec![];
+        for request in requests {
+            self.remember_setup(&request);
+            match self.write_request(request) {
+                Ok(pending) => sent.push(pending),
+                Err(err) => {
+                    let mut results: Vec<_> = sent
+                        .into_iter()
+                        .map(|pending| self.read_response(pending))
+                        .collect();
+                    results.push(Err(err));
+                    return results;
+                }
+            }
+        }
+        let mut results = vec![];
+        for pending in sent {
+            let result = self.read_response(pending);
+            let failed = result.is_err();
+            results.push(result);
+            if failed {
+                break;
+            }
+        }
+        results
+    }
 
-                Message::Binary(compressed)
+    /// Reconnects if the connection was reset or a handover calls for it.
+    fn prepare(&mut self) -> Result<()> {
+        if self.control.reset.swap(false, Ordering::SeqCst) {
+            warn!("Connection reset, reconnecting to {}", self.url);
+            self.reconnect()?;
+        }
+        if let Some(handover) = self.handover.filter(Handover::is_active) {
+            match handover.kind {
+                HandoverKind::Delay => {
+                    sleep(Duration::from_millis(thread_rng().gen_range(DELAY_RANGE)));
+                }
+                HandoverKind::Reconnect => {
+                    // Only once
+                    self.handover = None;
+                    warn!("Handover: reconnecting to {}", self.url);
+                    self.reconnect()?;
+                }
             }
-            #[cfg(not(feature = "compression"))]
-            {
-                Message::Binary(serialized)
+        }
+        Ok(())
+    }
+
+    fn remember_setup(&mut self, request: &Request) {
+        match request {
+            // Patches are folded into one, which the whole configuration
+            // replaces
+            Request::UpdateConfig(..) | Request::UseProfile(_) => {
+                self.setup.retain(|setup| {
+                    !matches!(setup, Request::PatchConfig(_))
+                        && mem::discriminant(setup) != mem::discriminant(request)
+                });
+                self.setup.push(request.clone());
             }
-        };
+            Request::PatchConfig(patch) => {
+                let remembered = self.setup.iter_mut().find_map(|setup| match setup {
+                    Request::PatchConfig(remembered) => Some(remembered),
+                    _ => None,
+                });
+                match remembered {
+                    Some(remembered) => remembered.merge(patch.clone()),
+                    None => self.setup.push(request.clone()),
+                }
+            }
+            Request::RegisterLayers(_)
+            | Request::SetStepPacing(_)
+            | Request::SetImpairment { .. }
+            | Request::SetStreaming(_)
+            | Request::Handshake(..)
+            | Request::LoadScene { .. } => {
+                // Only the latest of every kind matters
+                self.setup
+                    .retain(|setup| mem::discriminant(setup) != mem::discriminant(request));
+                self.setup.push(request.clone());
+            }
+            // Worlds are set up again empty, so that requests to them keep
+            // reaching one
+            Request::CreateWorld(_) => self.setup.push(request.clone()),
+            Request::DestroyWorld(id) => self
+                .setup
+                .retain(|setup| !matches!(setup, Request::CreateWorld(created) if created == id)),
+            Request::BulkRequest(requests) => {
+                for request in requests {
+                    self.remember_setup(request);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Connects again, resuming the session if the server still keeps it and
+    /// setting a new one up like it otherwise.
+    fn reconnect(&mut self) -> Result<()> {
+        // The old connection is abandoned without closing it, as a lost mobile
+        // link would be, so that the server keeps the session
+        let (socket, response) = open(&self.url, self.session_id, self.room.as_deref())?;
+        self.control.watch(&socket);
+        self.socket = socket;
+        // Until the handshake is sent again
+        self.framing = Framing::Binary;
+        self.encoding = Encoding::Bincode;
+        self.compressor.compression = Compression::None;
+
+        let (session_id, resumed) = session_of(&response);
+        if resumed {
+            info!("Resumed session {}", session_id.unwrap_or_default());
+            // The world is still there, only the connection is new
+            let handshakes: Vec<Request> = self
+                .setup
+                .iter()
+                .filter(|setup| matches!(setup, Request::Handshake(..)))
+                .cloned()
+                .collect();
+            for request in handshakes {
+                self.exchange(request)?;
+            }
+            return Ok(());
+        }
+        if let Some(id) = session_id {
+            info!("Served by new session {}", id);
+        }
+        self.session_id = session_id;
+        // The ids of its bodies don't collide with those of the members since
+        self.room_member = member_of(&response);
+
+        for request in self.setup.clone() {
+            self.exchange(request)?;
+        }
+        self.reconnects.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn exchange(&mut self, request: Request) -> Result<Response> {
+        let pending = self.write_request(request)?;
+        self.read_response(pending)
+    }
+
+    fn write_request(&mut self, mut request: Request) -> Result<PendingRequest> {
+        if let Request::SetStreaming(period) = &request {
+            self.streaming = period.is_some() || self.pushing;
+        }
+        // Stamped as late as possible, rather than when the request was queued
+        stamp_time_syncs(&mut request, clock::now_micros());
+        let channel = Channel::of(&request);
+        let msg = self.encode(channel, &request)?;
 
         let msg_len = msg.len();
         let request_type = request.name();
@@ -66,25 +495,47 @@ impl PhysicsClient {
         let start = Instant::now();
         self.socket.write_message(msg)?;
 
-        let msg = self.socket.read_message()?;
-        let msg_len = msg.len();
-        let msg_data = msg.into_data();
-
-        let serialized = {
-            #[cfg(feature = "compression")]
-            {
-                let mut decoder = ZlibDecoder::new(msg_data.as_slice());
-                let mut decompressed = Vec::new();
-                decoder.read_to_end(&mut decompressed)?;
+        Ok(PendingRequest {
+            channel,
+            request_type,
+            sent_len: msg_len,
+            start,
+        })
+    }
 
-                decompressed
-            }
-            #[cfg(not(feature = "compression"))]
-            {
-                msg_data
+    fn read_response(&mut self, pending: PendingRequest) -> Result<Response> {
+        let PendingRequest {
+            channel,
+            request_type,
+            sent_len,
+            start,
+        } = pending;
+        let (msg_len, response_channel, mut response) = loop {
+            let msg = self.socket.read_message()?;
+            let msg_len = msg.len();
+            if let Some((channel, response)) = self.decode_answer(msg)? {
+                break (msg_len, channel, response);
             }
         };
-        let response = deserialize::<Response>(serialized.as_slice())?;
+        if response_channel != channel {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "response to <{}> came on the {} channel instead of {}",
+                    request_type,
+                    response_channel.name(),
+                    channel.name()
+                ),
+            )
+            .into());
+        }
+        stamp_time_samples(&mut response, clock::now_micros());
+        // Messages after the handshake are framed the way it settled on
+        if let Response::Handshake(_, framing, encoding, compression, _) = &response {
+            self.framing = *framing;
+            self.encoding = *encoding;
+            self.compressor.compression = *compression;
+        }
         let response_type = response.name();
         let elapsed = start.elapsed();
 
@@ -99,6 +550,139 @@ impl PhysicsClient {
         );
         trace!("Received response: {:?}", response);
 
+        for stats in &self.stats {
+            let mut stats = stats.lock().unwrap();
+            stats.latencies.push(elapsed);
+            stats.bytes_sent += sent_len;
+            stats.bytes_received += msg_len;
+            stats.exchanges.push(Exchange {
+                channel,
+                request: request_type,
+                response: response_type,
+                bytes_sent: sent_len,
+                bytes_received: msg_len,
+                latency: elapsed,
+            });
+        }
+
         Ok(response)
     }
+
+    /// Reads the results the server pushed while no request waited on them.
+    /// Only plain TCP connections are read without waiting, others block
+    /// until the next push.
+    pub fn poll_pushed(&mut self) -> Result<()> {
+        if !self.streaming {
+            return Ok(());
+        }
+        self.set_nonblocking(true)?;
+        let result = loop {
+            match self.socket.read_message() {
+                Ok(msg) => match self.decode_answer(msg) {
+                    Ok(None) => {}
+                    Ok(Some((_, response))) => {
+                        break Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("<{}> arrived without a request", response.name()),
+                        )
+                        .into())
+                    }
+                    Err(err) => break Err(err),
+                },
+                Err(tungstenite::Error::Io(err))
+                    if err.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    break Ok(())
+                }
+                Err(err) => break Err(err.into()),
+            }
+        };
+        self.set_nonblocking(false)?;
+        result
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        match self.socket.get_ref() {
+            MaybeTlsStream::Plain(stream) => stream.set_nonblocking(nonblocking),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decodes the answer to a request, setting pushed results aside and
+    /// skipping the server's pings.
+    fn decode_answer(&mut self, msg: Message) -> Result<Option<(Channel, Response)>> {
+        if msg.is_ping() || msg.is_pong() {
+            return Ok(None);
+        }
+        match self.decode(msg)? {
+            (_, Response::Pushed(response)) => {
+                self.pushed.lock().unwrap().push(*response);
+                Ok(None)
+            }
+            answer => Ok(Some(answer)),
+        }
+    }
+
+    fn encode(&self, channel: Channel, request: &Request) -> Result<Message> {
+        match self.framing {
+            Framing::Binary => {
+                let serialized = self.encoding.encode(request)?;
+                let serialized = self.compressor.encode(&serialized)?;
+                Ok(Message::Binary(envelope::seal(&channel::wrap(
+                    channel,
+                    &serialized,
+                ))))
+            }
+            Framing::Json => Ok(Message::Text(framing::to_json(
+                channel,
+                request.name(),
+                request,
+            )?)),
+        }
+    }
+
+    fn decode(&self, msg: Message) -> Result<(Channel, Response)> {
+        match self.framing {
+            Framing::Binary => {
+                let msg_data = msg.into_data();
+                let (channel, msg_data) = channel::unwrap(envelope::open(&msg_data)?)?;
+                let response = self
+                    .encoding
+                    .decode(&compression::decode(self.compressor.compression, msg_data)?)?;
+                Ok((channel, response))
+            }
+            Framing::Json => Ok(framing::from_json(msg.to_text()?)?),
+        }
+    }
+}
+
+/// Sets when `TimeSync` requests were sent.
+fn stamp_time_syncs(request: &mut Request, sent: u64) {
+    match request {
+        Request::TimeSync(client_sent) => *client_sent = sent,
+        Request::BulkRequest(requests) => {
+            for request in requests {
+                stamp_time_syncs(request, sent);
+            }
+        }
+        Request::InWorld { request, .. } => stamp_time_syncs(request, sent),
+        _ => {}
+    }
+}
+
+/// Sets when the answers to `TimeSync` requests arrived.
+fn stamp_time_samples(response: &mut Response, received: u64) {
+    match response {
+        Response::TimeSync(sample) => sample.client_received = received,
+        Response::BulkResponse(responses) => {
+            for response in responses {
+                stamp_time_samples(response, received);
+            }
+        }
+        Response::Degraded(_, response)
+        | Response::Paced(_, response)
+        | Response::Status(_, response)
+        | Response::InWorld(_, Ok(response)) => stamp_time_samples(response, received),
+        _ => {}
+    }
 }
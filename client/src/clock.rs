@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bevy::{prelude::*, utils::Instant};
+
+use shared::{
+    channel::Channel,
+    clock::{self, TimeSample},
+    Request,
+};
+
+use crate::plugin::{RequestQueue, RequestWindow};
+
+/// The estimate is taken from the fastest of this many latest samples, whose
+/// round trip was the least disturbed by queueing.
+const SAMPLES: usize = 8;
+
+/// Between samples once there are `SAMPLES`, one a frame before.
+const SYNC_PERIOD: Duration = Duration::from_secs(1);
+
+/// The server's clock as estimated from `TimeSync` exchanges.
+#[derive(Resource, Debug, Default)]
+pub struct ClockSync {
+    samples: VecDeque<TimeSample>,
+    /// How far the server's clock is ahead of the client's, in microseconds.
+    pub offset: Option<i64>,
+    pub rtt: Option<Duration>,
+    last_sent: Option<Instant>,
+}
+
+impl ClockSync {
+    pub fn add(&mut self, sample: TimeSample) {
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        if let Some(fastest) = self.samples.iter().min_by_key(|sample| sample.rtt()) {
+            self.offset = Some(fastest.offset());
+            self.rtt = Some(Duration::from_micros(fastest.rtt()));
+        }
+    }
+
+    /// The one-way latencies of the latest sample to the server and back, as
+    /// far as the estimated offset is right.
+    pub fn one_way_latencies(&self) -> Option<(i64, i64)> {
+        let (sample, offset) = (self.samples.back()?, self.offset?);
+        Some((sample.uplink(offset), sample.downlink(offset)))
+    }
+}
+
+/// Samples the server's clock, a burst of a sample a frame first and then one
+/// every `SYNC_PERIOD`.
+pub fn sync_clock(
+    mut sync: ResMut<ClockSync>,
+    window: Res<RequestWindow>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let due = sync.samples.len() < SAMPLES
+        || sync
+            .last_sent
+            .is_none_or(|last| last.elapsed() >= SYNC_PERIOD);
+    if !due || !window.has_room(Channel::Queries) {
+        return;
+    }
+    sync.last_sent = Some(Instant::now());
+    request_queue.0.push(Request::TimeSync(clock::now_micros()));
+}
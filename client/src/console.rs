@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use bevy::{prelude::*, utils::Instant};
+use bevy_egui::{egui, EguiContext};
+use human_bytes::human_bytes;
+use shared::{layers::LayerRegistry, Request};
+
+use crate::{
+    backend::{Backend, SwitchBackend},
+    client::{Exchange, RequestStats},
+    plugin::{RemoteDegradation, RequestQueue, RequestWindow, SavedWorld, StateRequests},
+};
+
+/// How many of the latest requests the console lists.
+const EXCHANGES_SHOWN: usize = 50;
+
+/// What the console shows of the traffic with the server.
+#[derive(Resource)]
+pub struct ConsoleLog {
+    pub server: String,
+    pub stats: Arc<Mutex<RequestStats>>,
+    pub exchanges: VecDeque<Exchange>,
+    /// The requests of the last frame sent, or held back by a full window.
+    pub pending: Vec<&'static str>,
+    pub last_response: Option<Instant>,
+}
+
+/// Runs right before the frame's requests are sent, when the queue holds all
+/// of them.
+pub fn collect(mut log: ResMut<ConsoleLog>, request_queue: Res<RequestQueue>) {
+    log.pending = request_queue.0.iter().map(Request::name).collect();
+
+    let stats = mem::take(&mut *log.stats.lock().unwrap());
+    if !stats.exchanges.is_empty() {
+        log.last_response = Some(Instant::now());
+    }
+    log.exchanges.extend(stats.exchanges);
+    let excess = log.exchanges.len().saturating_sub(EXCHANGES_SHOWN);
+    log.exchanges.drain(..excess);
+}
+
+pub fn show(
+    mut egui_context: ResMut<EguiContext>,
+    log: Res<ConsoleLog>,
+    backend: Res<Backend>,
+    window: Res<RequestWindow>,
+    degradation: Res<RemoteDegradation>,
+    layers: Res<LayerRegistry>,
+    mut request_queue: ResMut<RequestQueue>,
+    mut state_requests: ResMut<StateRequests>,
+    saved_world: Res<SavedWorld>,
+    mut switches: EventWriter<SwitchBackend>,
+) {
+    egui::Window::new("Remote physics").show(egui_context.ctx_mut(), |ui| {
+        ui.label(format!("Server: {}", log.server));
+        ui.label(format!(
+            "Connection: {}",
+            connection_state(*backend, &window, &log)
+        ));
+        ui.label(format!(
+            "In flight: {}/{} frames",
+            window.in_flight(),
+            window.max_in_flight
+        ));
+        ui.label(format!("Server degradation: {}", degradation.latest));
+
+        ui.horizontal(|ui| {
+            let remote = *backend == Backend::Remote;
+            if ui
+                .add_enabled(remote, egui::Button::new("Resync"))
+                .clicked()
+            {
+                state_requests.resync = true;
+                request_queue.0.push(Request::GetState);
+            }
+            if ui
+                .add_enabled(remote, egui::Button::new("Export snapshot"))
+                .clicked()
+            {
+                state_requests.export = true;
+                request_queue.0.push(Request::GetState);
+            }
+            if ui
+                .add_enabled(remote, egui::Button::new("Save world"))
+                .clicked()
+            {
+                request_queue.0.push(Request::TakeSnapshot);
+            }
+            if ui
+                .add_enabled(
+                    remote && saved_world.0.is_some(),
+                    egui::Button::new("Restore world"),
+                )
+                .clicked()
+            {
+                if let Some(snapshot) = &saved_world.0 {
+                    request_queue
+                        .0
+                        .push(Request::RestoreSnapshot(snapshot.clone()));
+                }
+            }
+            let switch = match *backend {
+                Backend::Remote => "Switch to local",
+                Backend::Local => "Switch to remote",
+            };
+            if ui.button(switch).clicked() {
+                switches.send(SwitchBackend);
+            }
+        });
+
+        ui.collapsing("Collision layers", |ui| {
+            for (name, bits) in layers.layers() {
+                ui.label(format!("{}: {:#034b}", name, bits));
+            }
+        });
+
+        ui.collapsing(format!("Pending requests ({})", log.pending.len()), |ui| {
+            for request in &log.pending {
+                ui.label(*request);
+            }
+        });
+
+        ui.collapsing("Latest responses", |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(240.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("exchanges").striped(true).show(ui, |ui| {
+                        for heading in [
+                            "Channel", "Request", "Response", "Sent", "Received", "Latency",
+                        ] {
+                            ui.strong(heading);
+                        }
+                        ui.end_row();
+
+                        for exchange in log.exchanges.iter().rev() {
+                            ui.label(exchange.channel.name());
+                            ui.label(exchange.request);
+                            ui.label(exchange.response);
+                            ui.label(human_bytes(exchange.bytes_sent as f64));
+                            ui.label(human_bytes(exchange.bytes_received as f64));
+                            ui.label(format!("{:.1?}", exchange.latency));
+                            ui.end_row();
+                        }
+                    });
+                });
+        });
+    });
+}
+
+fn connection_state(backend: Backend, window: &RequestWindow, log: &ConsoleLog) -> &'static str {
+    if backend == Backend::Local {
+        return "idle, local backend";
+    }
+    match log.last_response {
+        None => "waiting for the first response",
+        Some(last) if window.in_flight() > 0 && last.elapsed() >= RequestWindow::STALL_TIMEOUT => {
+            "stalled"
+        }
+        Some(_) => "connected",
+    }
+}
@@ -0,0 +1,103 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+    utils::Instant,
+};
+use shared::{channel::Channel, Request, Response};
+
+use crate::{
+    client::RequestStats,
+    error::Result,
+    plugin::{RequestQueue, RequestWindow},
+};
+
+/// How often the server is asked for its step time.
+const STEP_TIME_PERIOD: Duration = Duration::from_secs(1);
+
+pub const RTT: DiagnosticId = DiagnosticId::from_u128(0x4f1c_2a6e_83d9_4b57_a1e0_6c9d_3b28_f715);
+pub const BYTES_SENT: DiagnosticId =
+    DiagnosticId::from_u128(0x9a3e_7d10_5c42_4e8b_b6f2_1d07_8e4a_c953);
+pub const BYTES_RECEIVED: DiagnosticId =
+    DiagnosticId::from_u128(0x2d86_e5b4_1f7a_4c93_8e15_a40c_62d9_7b1e);
+pub const QUEUE_DEPTH: DiagnosticId =
+    DiagnosticId::from_u128(0x7c51_0b9f_e2d4_4a16_9f83_5e6a_1c07_d2b4);
+pub const IN_FLIGHT: DiagnosticId =
+    DiagnosticId::from_u128(0xb8e2_46c1_9a0d_4f75_a3b9_02e7_5d1f_8c60);
+pub const REMOTE_STEP_TIME: DiagnosticId =
+    DiagnosticId::from_u128(0x61d9_c83a_7e25_4b0f_8d46_f1b2_9a5c_03e7);
+
+/// The statistics of the requests sent since the last measurement.
+#[derive(Resource)]
+pub struct DiagnosticsStats {
+    pub stats: Arc<Mutex<RequestStats>>,
+    pub last_measurement: Instant,
+}
+
+pub fn setup(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(RTT, "remote_rtt", 20).with_suffix("ms"));
+    diagnostics.add(Diagnostic::new(BYTES_SENT, "remote_bytes_sent", 20).with_suffix("B/s"));
+    diagnostics
+        .add(Diagnostic::new(BYTES_RECEIVED, "remote_bytes_received", 20).with_suffix("B/s"));
+    diagnostics
+        .add(Diagnostic::new(QUEUE_DEPTH, "remote_queue_depth", 20).with_suffix(" requests"));
+    diagnostics.add(Diagnostic::new(IN_FLIGHT, "remote_in_flight", 20).with_suffix(" frames"));
+    diagnostics.add(Diagnostic::new(REMOTE_STEP_TIME, "remote_step_time", 5).with_suffix("ms"));
+}
+
+/// Runs right before the frame's requests are sent, when the queue holds all
+/// of them.
+pub fn measure(
+    mut diagnostics: ResMut<Diagnostics>,
+    mut source: ResMut<DiagnosticsStats>,
+    request_queue: Res<RequestQueue>,
+    window: Res<RequestWindow>,
+) {
+    diagnostics.add_measurement(QUEUE_DEPTH, || request_queue.0.len() as f64);
+    diagnostics.add_measurement(IN_FLIGHT, || window.in_flight() as f64);
+
+    let elapsed = source.last_measurement.elapsed().as_secs_f64();
+    if elapsed == 0.0 {
+        return;
+    }
+    source.last_measurement = Instant::now();
+    let stats = mem::take(&mut *source.stats.lock().unwrap());
+
+    diagnostics.add_measurement(BYTES_SENT, || stats.bytes_sent as f64 / elapsed);
+    diagnostics.add_measurement(BYTES_RECEIVED, || stats.bytes_received as f64 / elapsed);
+    // Frames without a response keep the last round trip time
+    if !stats.latencies.is_empty() {
+        let total: Duration = stats.latencies.iter().sum();
+        let mean = total / stats.latencies.len() as u32;
+        diagnostics.add_measurement(RTT, || mean.as_secs_f64() * 1000.0);
+    }
+}
+
+pub fn request_step_time(
+    mut last_request: Local<Option<Instant>>,
+    window: Res<RequestWindow>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if last_request.is_some_and(|last| last.elapsed() < STEP_TIME_PERIOD)
+        || !window.has_room(Channel::Queries)
+    {
+        return;
+    }
+    *last_request = Some(Instant::now());
+    request_queue.0.push(Request::TakeStepTime);
+}
+
+pub fn handle_step_time_response(
+    resp: Result<Response>,
+    diagnostics: &mut Option<ResMut<Diagnostics>>,
+) {
+    if let (Ok(Response::StepTime(step_time)), Some(diagnostics)) = (resp, diagnostics) {
+        // Zero when the server didn't step, which says nothing of its cost
+        if !step_time.is_zero() {
+            diagnostics.add_measurement(REMOTE_STEP_TIME, || step_time.as_secs_f64() * 1000.0);
+        }
+    }
+}
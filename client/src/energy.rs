@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::log::warn;
+
+/// Clock ticks per second used by `/proc/<pid>/stat`, which is 100 on every
+/// mainstream Linux configuration.
+const CLOCK_TICKS_PER_SECOND: u64 = 100;
+
+const POWERCAP_PATH: &str = "/sys/class/powercap";
+
+struct RaplZone {
+    path: PathBuf,
+    max_energy_range: u64,
+    last_energy: u64,
+}
+
+/// Samples the CPU time of this process and, where RAPL is exposed through
+/// powercap, the energy consumed by the CPU packages.
+pub struct EnergySampler {
+    last_cpu_time: Option<Duration>,
+    zones: Vec<RaplZone>,
+}
+
+impl EnergySampler {
+    pub fn new() -> Self {
+        let zones = rapl_zones();
+        if zones.is_empty() {
+            warn!("RAPL energy counters are unavailable, only sampling CPU time");
+        }
+
+        Self {
+            last_cpu_time: process_cpu_time(),
+            zones,
+        }
+    }
+
+    /// Returns the CPU time and the energy in joules used since the last sample.
+    pub fn sample(&mut self) -> (Option<Duration>, Option<f64>) {
+        let cpu_time = process_cpu_time();
+        let cpu_delta = match (cpu_time, self.last_cpu_time) {
+            (Some(now), Some(last)) => Some(now.saturating_sub(last)),
+            _ => None,
+        };
+        self.last_cpu_time = cpu_time;
+
+        if self.zones.is_empty() {
+            return (cpu_delta, None);
+        }
+
+        let mut energy = 0;
+        for zone in &mut self.zones {
+            let current = match read_u64(zone.path.join("energy_uj")) {
+                Some(current) => current,
+                None => return (cpu_delta, None),
+            };
+            // The counter wraps around after reaching max_energy_range_uj
+            energy += if current >= zone.last_energy {
+                current - zone.last_energy
+            } else {
+                zone.max_energy_range - zone.last_energy + current
+            };
+            zone.last_energy = current;
+        }
+
+        (cpu_delta, Some(energy as f64 / 1_000_000.0))
+    }
+}
+
+fn read_u64(path: PathBuf) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn process_cpu_time() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name may contain spaces, so fields are counted after it
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(Duration::from_millis(
+        (utime + stime) * 1000 / CLOCK_TICKS_PER_SECOND,
+    ))
+}
+
+/// Finds the top level RAPL zones (one per CPU package), skipping sub-zones
+/// like `intel-rapl:0:0` whose energy is already included in their package.
+fn rapl_zones() -> Vec<RaplZone> {
+    let entries = match fs::read_dir(POWERCAP_PATH) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            if !name.starts_with("intel-rapl:") || name.matches(':').count() != 1 {
+                return None;
+            }
+            Some(RaplZone {
+                max_energy_range: read_u64(path.join("max_energy_range_uj"))?,
+                last_energy: read_u64(path.join("energy_uj"))?,
+                path,
+            })
+        })
+        .collect()
+}
@@ -10,6 +10,7 @@ pub enum ErrorKind {
     Network(tungstenite::Error),
     Compression(flate2::CompressError),
     Decmpression(flate2::DecompressError),
+    Protocol(shared::envelope::EnvelopeError),
 }
 
 impl StdError for ErrorKind {
@@ -20,6 +21,7 @@ impl StdError for ErrorKind {
             ErrorKind::Network(ref err) => Some(err),
             ErrorKind::Compression(ref err) => Some(err),
             ErrorKind::Decmpression(ref err) => Some(err),
+            ErrorKind::Protocol(ref err) => Some(err),
         }
     }
 }
@@ -54,6 +56,12 @@ impl From<flate2::DecompressError> for Error {
     }
 }
 
+impl From<shared::envelope::EnvelopeError> for Error {
+    fn from(err: shared::envelope::EnvelopeError) -> Error {
+        ErrorKind::Protocol(err).into()
+    }
+}
+
 impl fmt::Display for ErrorKind {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -62,6 +70,7 @@ impl fmt::Display for ErrorKind {
             ErrorKind::Network(ref err) => write!(fmt, "network error: {}", err),
             ErrorKind::Compression(ref err) => write!(fmt, "compression error: {}", err),
             ErrorKind::Decmpression(ref err) => write!(fmt, "decompression error: {}", err),
+            ErrorKind::Protocol(ref err) => write!(fmt, "protocol error: {}", err),
         }
     }
 }
@@ -0,0 +1,87 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::{app::AppExit, prelude::*};
+
+use crate::client::RequestStats;
+
+/// Counts rendered frames by how many frames ago the snapshot they show
+/// arrived, reported at exit along with the frame rate and round trip time.
+#[derive(Resource)]
+pub struct FrameReport {
+    pub stats: Arc<Mutex<RequestStats>>,
+    /// Set when a step's response is written back, and cleared by the frame
+    /// showing it.
+    pub snapshot_arrived: bool,
+    /// Frames since the last snapshot arrived, `None` before the first one.
+    pub snapshot_age: Option<u64>,
+    /// Frames showing a snapshot 0, 1 and 2 or more frames old.
+    pub buckets: [u64; 3],
+    pub frame_time: Duration,
+    pub rtt_total: Duration,
+    pub responses: u32,
+}
+
+impl FrameReport {
+    pub fn new(stats: Arc<Mutex<RequestStats>>) -> Self {
+        Self {
+            stats,
+            snapshot_arrived: false,
+            snapshot_age: None,
+            buckets: [0; 3],
+            frame_time: Duration::ZERO,
+            rtt_total: Duration::ZERO,
+            responses: 0,
+        }
+    }
+
+    fn log(&self) {
+        let frames: u64 = self.buckets.iter().sum();
+        if frames == 0 {
+            info!("Frame report: no frame showed a remote snapshot");
+            return;
+        }
+
+        let share = |count: u64| count as f64 / frames as f64 * 100.0;
+        let rtt = self
+            .rtt_total
+            .checked_div(self.responses)
+            .unwrap_or_default();
+        info!(
+            "Frame report: {} frames at {:.1} fps with a mean round trip of {:?}, {:.1}% fresh, {:.1}% 1 frame stale, {:.1}% 2 or more frames stale",
+            frames,
+            frames as f64 / self.frame_time.as_secs_f64(),
+            rtt,
+            share(self.buckets[0]),
+            share(self.buckets[1]),
+            share(self.buckets[2])
+        );
+    }
+}
+
+/// Runs last in the frame, once everything it shows is decided.
+pub fn count_frame(mut report: ResMut<FrameReport>, time: Res<Time>) {
+    let stats = mem::take(&mut *report.stats.lock().unwrap());
+    report.rtt_total += stats.latencies.iter().sum::<Duration>();
+    report.responses += stats.latencies.len() as u32;
+
+    let age = if mem::take(&mut report.snapshot_arrived) {
+        Some(0)
+    } else {
+        report.snapshot_age.map(|age| age + 1)
+    };
+    report.snapshot_age = age;
+
+    // Frames before the first snapshot don't show one
+    if let Some(age) = age {
+        report.buckets[age.min(2) as usize] += 1;
+        report.frame_time += time.delta();
+    }
+}
+
+pub fn log_on_exit(report: Res<FrameReport>, mut exits: EventReader<AppExit>) {
+    if exits.iter().next().is_some() {
+        report.log();
+    }
+}
@@ -0,0 +1,55 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use bevy::utils::Instant;
+
+/// How long every request is held back while a delay handover lasts, in
+/// milliseconds.
+pub const DELAY_RANGE: Range<u64> = 200..800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandoverKind {
+    /// Every request is held back a random `DELAY_RANGE` while it lasts.
+    Delay,
+    /// The connection is dropped and made again once, to a new session on
+    /// the server that the world is created again in.
+    Reconnect,
+}
+
+impl HandoverKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Delay => "delay",
+            Self::Reconnect => "reconnect",
+        }
+    }
+}
+
+/// A simulated mobile network handover, scheduled relative to when the
+/// connection was made.
+#[derive(Debug, Clone, Copy)]
+pub struct Handover {
+    pub kind: HandoverKind,
+    pub start: Instant,
+    pub duration: Duration,
+}
+
+impl Handover {
+    pub fn new(kind: HandoverKind, after: Duration, duration: Duration) -> Self {
+        Self {
+            kind,
+            start: Instant::now() + after,
+            duration,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        let now = Instant::now();
+        now >= self.start && now < self.start + self.duration
+    }
+
+    /// Whether the handover lasted through part of the time since `since`.
+    pub fn overlaps(&self, since: Instant) -> bool {
+        since < self.start + self.duration && Instant::now() >= self.start
+    }
+}
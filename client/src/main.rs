@@ -1,21 +1,56 @@
+use std::collections::HashMap;
+
 use bevy::{
-    app::AppExit,
+    app::{AppExit, ScheduleRunnerSettings},
     core_pipeline::bloom::BloomSettings,
+    diagnostic::LogDiagnosticsPlugin,
+    ecs::system::SystemParam,
     log::LogPlugin,
     pbr::{NotShadowCaster, NotShadowReceiver},
     prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    scene::ScenePlugin,
 };
 use bevy_rapier3d::prelude::*;
-use clap::{arg, command, value_parser};
+use clap::{arg, builder::RangedU64ValueParser, command, value_parser};
 use rand::Rng;
+use shared::{
+    channel::Channel,
+    codec::Encoding,
+    compression::Compression,
+    framing::Framing,
+    hooks::ContactRules,
+    impairment::Impairment,
+    layers::LayerRegistry,
+    metadata::RunMetadata,
+    pacing::StepPacing,
+    profile::Profile, ragdoll::Skeleton, rope::RopeAnchor, scene::SceneShape, BodyCommand,
+    Controller, FluidVolume, Request, SimulationState, Tag, UpdateRate,
+};
 
 use color_space::{Lch, ToRgb};
 
+mod backend;
+mod bulk;
+mod calibration;
 mod client;
+mod clock;
+#[cfg(feature = "console")]
+mod console;
+mod diagnostics;
+mod energy;
 mod error;
+mod frame_report;
+mod handover;
 mod log;
+mod mirror;
 mod plugin;
+mod prewarm;
+mod replay;
 mod systems;
+mod trajectory;
+mod validation;
+mod watchdog;
 
 #[derive(Component)]
 struct Shape;
@@ -28,6 +63,8 @@ struct SpawnIndicator;
 struct BallData {
     mesh: Handle<Mesh>,
     materials: Vec<Handle<StandardMaterial>>,
+    /// The events the balls' contacts are reported with, if any.
+    active_events: ActiveEvents,
 }
 
 #[derive(Resource, Default)]
@@ -39,9 +76,62 @@ struct SpawnHeight(f32);
 #[derive(Resource)]
 struct SpawnTimerDuration(i32);
 
+#[derive(Resource)]
+struct SpawnUpdateRate(UpdateRate);
+
 #[derive(Resource)]
 struct BallLimit(i32);
 
+#[derive(Resource)]
+struct NpcCount(i32);
+
+#[derive(Resource)]
+struct BackendSwitchTimer(Timer);
+
+/// Inserted when impacts are shown, for the balls to report their contacts.
+#[derive(Resource)]
+struct ShowImpacts;
+
+#[derive(Resource)]
+struct ImpactEffects {
+    sound: Handle<AudioSource>,
+    particle_material: Handle<StandardMaterial>,
+}
+
+#[derive(Component)]
+struct Particle {
+    velocity: Vec3,
+    lifetime: Timer,
+}
+
+/// Contacts pushing softer than this are too frequent to be worth showing, a
+/// resting ball's weight among them.
+const MIN_IMPACT_FORCE: f32 = 30.0;
+
+/// Impacts at least this hard get full volume and the most particles.
+const FULL_IMPACT_FORCE: f32 = 600.0;
+
+const MAX_PARTICLES_PER_IMPACT: f32 = 12.0;
+
+const PLAYER_BALL_PRIORITY: f32 = 4.0;
+
+/// How fast bodies written back to `RemotePhysicsPose` catch up with it, per
+/// second, without a calibration to take the interpolation delay from.
+const POSE_EASING: f32 = 20.0;
+
+/// The id of the world W creates beside the demo's own.
+const SIDE_WORLD: u64 = 1;
+
+/// Kicking a ball hanging from a rope is enough to break the rope.
+const ROPE_BREAK_FORCE: f32 = 100.0;
+
+const BALL_RADIUS: f32 = 0.5;
+
+const BALL_RESTITUTION: f32 = 0.7;
+
+/// Frames per second of the headless client, which has no display to sync to.
+const HEADLESS_FRAME_RATE: f64 = 60.0;
+
 fn main() {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "client=debug");
@@ -50,7 +140,7 @@ fn main() {
     let matches = command!()
         .arg(
             arg!(
-                -a --addr <ADDR> "The address to connect to"
+                -a --addr <ADDR> "The address to connect to, a host name or an IPv4 or IPv6 address"
             )
             .required(false)
             .value_parser(value_parser!(String)),
@@ -69,6 +159,15 @@ fn main() {
             .required(false)
             .value_parser(value_parser!(i32).range(1..)),
         )
+        .arg(
+            arg!(
+                -u --"update-rate" <RATE> "How often automatically spawned balls are updated by the server"
+            )
+            .required(false)
+            .requires("spawn")
+            .default_value("every-step")
+            .value_parser(["every-step", "every2", "every4", "on-sleep-change"]),
+        )
         .arg(
             arg!(
                 -c --close <BALLS> "Close the window after spawning given number of balls"
@@ -76,16 +175,367 @@ fn main() {
             .required(false)
             .value_parser(value_parser!(i32).range(1..)),
         )
+        .arg(
+            arg!(
+                -t --templates "Send repeated body archetypes as template instances"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --metrics <PATH> "Append per-second traffic metrics to the given CSV file"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --energy "Add CPU time and RAPL energy samples to the metrics"
+            )
+            .required(false)
+            .requires("metrics"),
+        )
+        .arg(
+            arg!(
+                --placement <PATH> "Append the placement error of every spawned body to the given CSV file"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --mirror <SECONDS> "Mirror the server's world into the local RapierContext every given number of seconds"
+            )
+            .required(false)
+            .value_parser(value_parser!(f32)),
+        )
+        .arg(
+            arg!(
+                --compact <SECONDS> "Have the server compact the world every given number of seconds"
+            )
+            .required(false)
+            .value_parser(value_parser!(f32)),
+        )
+        .arg(
+            arg!(
+                --stream <MS> "Have the server step every given number of milliseconds on its own and push the results, instead of stepping every frame"
+            )
+            .required(false)
+            .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(
+                --room <NAME> "Join the room of the name on servers started with --rooms, sharing its world with the other members"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                -i --impacts "Play sounds and spawn particles on the collision events of balls, as hard as their contact forces"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                -n --npcs <BALLS> "Spawn balls that wander around, steered by the server"
+            )
+            .required(false)
+            .value_parser(value_parser!(i32).range(1..)),
+        )
+        .arg(
+            arg!(
+                --water "Fill half of the ground with water that balls float in"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --scene <NAME> "Load the level from the given scene on the server instead of uploading it"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --prewarm "Upload the level before the first frame instead of with the first frame's requests"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --"max-in-flight" <FRAMES> "How many frames of requests can wait for their responses at once"
+            )
+            .required(false)
+            .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            arg!(
+                --"channel-limit" <LIMIT> "How many batches with polling requests on a channel can be in flight at once, as <control|snapshots|queries>=<BATCHES>"
+            )
+            .required(false)
+            .action(clap::ArgAction::Append)
+            .value_parser(parse_channel_limit),
+        )
+        .arg(
+            arg!(
+                --"switch-backend" <SECONDS> "Switch between the remote and the local backend every given number of seconds"
+            )
+            .required(false)
+            .value_parser(value_parser!(f32)),
+        )
+        .arg(
+            arg!(
+                --handover <SECONDS> "Simulate a mobile network handover the given number of seconds after connecting"
+            )
+            .required(false)
+            .value_parser(value_parser!(f32)),
+        )
+        .arg(
+            arg!(
+                --"handover-kind" <KIND> "Whether the handover delays every request by 200-800 ms or drops and makes the connection again"
+            )
+            .required(false)
+            .requires("handover")
+            .default_value("delay")
+            .value_parser(["delay", "reconnect"]),
+        )
+        .arg(
+            arg!(
+                --"handover-duration" <SECONDS> "How long the handover lasts"
+            )
+            .required(false)
+            .requires("handover")
+            .default_value("2")
+            .value_parser(value_parser!(f32)),
+        )
+        .arg(
+            arg!(
+                --profile <NAME> "Start with the physics parameters of a profile instead of the demo's"
+            )
+            .required(false)
+            .value_parser(Profile::ALL.map(Profile::name)),
+        )
+        .arg(
+            arg!(
+                --"step-pacing" <PACING> "How the server runs steps reaching it in a burst: immediate, cap:<STEPS>/<MILLISECONDS> or collapse:<MILLISECONDS>"
+            )
+            .required(false)
+            .value_parser(StepPacing::parse),
+        )
+        .arg(
+            arg!(
+                --"contact-rules" <RULES> "Filter contact pairs on the server by a comma separated list of allow:<GROUPS>/<GROUPS>, deny:<GROUPS>/<GROUPS> and one-way:<GROUPS>, groups being layers separated by |"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --layer <LAYER> "Name the groups of the given bits in hex in logs and contact rules, besides the player, debris, sensor-zones and environment presets, as <NAME>=0x<BITS>"
+            )
+            .required(false)
+            .action(clap::ArgAction::Append)
+            .value_parser(parse_layer),
+        )
+        .arg(
+            arg!(
+                --impairment <IMPAIRMENT> "Have the server simulate other network conditions for this client, as latency=<MILLISECONDS>[,min=<MILLISECONDS>][,bandwidth=<KBPS>][,loss=<SHARE>]"
+            )
+            .required(false)
+            .requires("impairment-key")
+            .value_parser(Impairment::parse),
+        )
+        .arg(
+            arg!(
+                --"impairment-key" <KEY> "The key the server was started with, needed to set an impairment"
+            )
+            .required(false)
+            .requires("impairment")
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --framing <FRAMING> "Frame messages after the handshake as bincode, or as JSON text to read the traffic with standard tools"
+            )
+            .required(false)
+            .default_value("binary")
+            .value_parser(["binary", "json"]),
+        )
+        .arg(
+            arg!(
+                --encoding <ENCODING> "Encode binary messages after the handshake as bincode, postcard, MessagePack or CBOR"
+            )
+            .required(false)
+            .default_value("bincode")
+            .value_parser(["bincode", "postcard", "msgpack", "cbor"]),
+        )
+        .arg(
+            arg!(
+                --compression <COMPRESSION> "Compress binary messages after the handshake with zlib, lz4 or zstd"
+            )
+            .required(false)
+            .default_value("none")
+            .value_parser(["none", "zlib", "lz4", "zstd"]),
+        )
+        .arg(
+            arg!(
+                --"compression-level" <LEVEL> "Compress requests at this level instead of the compression's default"
+            )
+            .required(false)
+            .allow_hyphen_values(true)
+            .value_parser(value_parser!(i32)),
+        )
+        .arg(
+            arg!(
+                --"compression-threshold" <BYTES> "Requests shorter than this aren't compressed, with a compression"
+            )
+            .required(false)
+            .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(
+                --"frame-report" "Log at exit how stale the remote snapshots shown by rendered frames were"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --"max-distance" <METERS> "Quarantine bodies the server puts farther from the origin, 10000 by default"
+            )
+            .required(false)
+            .value_parser(value_parser!(f32)),
+        )
+        .arg(
+            arg!(
+                --"max-speed" <SPEED> "Quarantine bodies the server moves faster, in meters or radians per second, 1000 by default"
+            )
+            .required(false)
+            .value_parser(value_parser!(f32)),
+        )
+        .arg(
+            arg!(
+                --writeback <TARGET> "Write the states of bodies from the server to their Transform, to a pose the demo eases Transform towards, or to events the demo applies"
+            )
+            .required(false)
+            .default_value("transform")
+            .value_parser(["transform", "pose", "events"]),
+        )
+        .arg(
+            arg!(
+                --"record-snapshots" <PATH> "Record what every frame shows of the bodies to the given file, to play back with --playback"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --playback <PATH> "Render a recording made with --record-snapshots instead of connecting to a server"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --console "Show a console of the traffic with the server, with buttons to resync, export a snapshot, save and restore the world and switch backends"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --diagnostics "Log the round trip time, traffic, queue depth and server step time with Bevy's diagnostics"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --"no-calibration" "Skip measuring the network and the server before the first frame"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --watchdog <FRAMES> "Reset the connection after this many frames without a response, 60 by default"
+            )
+            .required(false)
+            .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            arg!(
+                --"no-watchdog" "Never reset a connection the server stopped answering on"
+            )
+            .required(false)
+            .conflicts_with("watchdog"),
+        )
+        .arg(
+            arg!(
+                --heartbeat <SECONDS> "Ping the server after this many seconds without a request, 5 by default"
+            )
+            .required(false)
+            .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(
+                --"no-heartbeat" "Never ping the server, leaving a quiet client to its idle timeout"
+            )
+            .required(false)
+            .conflicts_with("heartbeat"),
+        )
+        .arg(
+            arg!(
+                --headless "Run without a window, rendering or input, spawning balls with -s or -n only"
+            )
+            .required(false)
+            .conflicts_with_all(["playback", "console", "impacts"]),
+        )
         .get_matches();
 
+    if let Some(path) = matches.get_one::<String>("playback") {
+        App::new()
+            .add_plugins(DefaultPlugins)
+            .add_plugin(replay::ReplayPlugin::new(path))
+            .add_startup_system(setup_graphics)
+            .add_startup_system(setup_light)
+            .add_system(bevy::window::close_on_esc)
+            .insert_resource(ClearColor(Color::rgb(0.9, 0.6, 0.3)))
+            .run();
+        return;
+    }
+
+    let mut layers = LayerRegistry::default();
+    let named_layers = matches.get_many::<(String, u32)>("layer");
+    let custom_layers = named_layers.is_some();
+    for (name, bits) in named_layers.into_iter().flatten() {
+        layers.register(name, *bits);
+    }
+    // Parsed once the layers they can name are known
+    let contact_rules = matches.get_one::<String>("contact-rules").map(|rules| {
+        ContactRules::parse(rules, &layers).unwrap_or_else(|err| {
+            clap::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                format!("invalid value '{}' for '--contact-rules': {}\n", rules, err),
+            )
+            .exit()
+        })
+    });
+
     let mut app = App::new();
     let mut prefixes = vec!["client"];
 
     #[cfg(feature = "bulk-requests")]
     prefixes.push("bulk");
 
-    #[cfg(feature = "compression")]
-    prefixes.push("comp");
+    let compression = matches
+        .get_one::<String>("compression")
+        .and_then(|name| Compression::from_name(name))
+        .unwrap_or_default();
+    if compression != Compression::None {
+        prefixes.push(compression.name());
+    }
+
+    let templates = matches.get_flag("templates");
+    if templates {
+        prefixes.push("tmpl");
+    }
 
     let file_name = format!(
         "{}_{}.log",
@@ -93,8 +543,25 @@ fn main() {
         chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
     );
 
-    app.add_plugins(DefaultPlugins.build().disable::<LogPlugin>())
-        .add_plugin(log::LogPlugin {
+    let headless = matches.get_flag("headless");
+    if headless {
+        // Only the assets the bodies and bevy_rapier's async colliders refer
+        // to, at a fixed frame rate rather than as fast as possible
+        app.insert_resource(ScheduleRunnerSettings::run_loop(
+            std::time::Duration::from_secs_f64(1.0 / HEADLESS_FRAME_RATE),
+        ))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(ImagePlugin::default())
+        .add_plugin(ScenePlugin)
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>();
+    } else {
+        app.add_plugins(DefaultPlugins.build().disable::<LogPlugin>());
+    }
+    app.add_plugin(log::LogPlugin {
             file_appender_settings: Some(log::FileAppenderSettings {
                 rolling: log::Rolling::Never,
                 path: "".into(),
@@ -113,37 +580,296 @@ fn main() {
         rapier_physics = rapier_physics.with_port(port);
     }
 
+    rapier_physics = rapier_physics.with_templates(templates);
+
+    if let Some(path) = matches.get_one::<String>("metrics") {
+        rapier_physics = rapier_physics.with_metrics(path.as_str());
+    }
+
+    rapier_physics = rapier_physics.with_energy_sampling(matches.get_flag("energy"));
+
+    if let Some(path) = matches.get_one::<String>("placement") {
+        rapier_physics = rapier_physics.with_placement_report(path.as_str());
+    }
+
+    if let Some(&seconds) = matches.get_one::<f32>("mirror") {
+        rapier_physics = rapier_physics.with_mirror(std::time::Duration::from_secs_f32(seconds));
+    }
+
+    if let Some(&seconds) = matches.get_one::<f32>("compact") {
+        rapier_physics =
+            rapier_physics.with_compaction(std::time::Duration::from_secs_f32(seconds));
+    }
+
+    if let Some(&millis) = matches.get_one::<u64>("stream") {
+        rapier_physics = rapier_physics.with_streaming(std::time::Duration::from_millis(millis));
+    }
+
+    if let Some(room) = matches.get_one::<String>("room") {
+        rapier_physics = rapier_physics.with_room(room);
+    }
+
+    let impacts = matches.get_flag("impacts");
+
+    let scene = matches.get_one::<String>("scene");
+    if let Some(scene) = scene {
+        rapier_physics = rapier_physics.with_scene(scene.as_str());
+    }
+
+    rapier_physics = rapier_physics.with_prewarm(matches.get_flag("prewarm"));
+
+    if let Some(&max_in_flight) = matches.get_one::<usize>("max-in-flight") {
+        rapier_physics = rapier_physics.with_max_in_flight(max_in_flight);
+    }
+    for &(channel, limit) in matches
+        .get_many::<(Channel, usize)>("channel-limit")
+        .into_iter()
+        .flatten()
+    {
+        rapier_physics = rapier_physics.with_channel_limit(channel, limit);
+    }
+
+    rapier_physics = rapier_physics.with_calibration(!matches.get_flag("no-calibration"));
+    if matches.get_flag("no-watchdog") {
+        rapier_physics = rapier_physics.with_watchdog(None);
+    } else if let Some(&frames) = matches.get_one::<u32>("watchdog") {
+        rapier_physics = rapier_physics.with_watchdog(Some(frames));
+    }
+    if matches.get_flag("no-heartbeat") {
+        rapier_physics = rapier_physics.with_heartbeat(None);
+    } else if let Some(&seconds) = matches.get_one::<u64>("heartbeat") {
+        rapier_physics =
+            rapier_physics.with_heartbeat(Some(std::time::Duration::from_secs(seconds)));
+    }
+
+    let diagnostics = matches.get_flag("diagnostics");
+    rapier_physics = rapier_physics.with_diagnostics(diagnostics);
+    rapier_physics = rapier_physics.with_console(matches.get_flag("console"));
+    rapier_physics = rapier_physics.with_frame_report(matches.get_flag("frame-report"));
+    if let Some(path) = matches.get_one::<String>("record-snapshots") {
+        rapier_physics = rapier_physics.with_snapshot_recording(path.as_str());
+    }
+    let max_distance = matches.get_one::<f32>("max-distance");
+    let max_speed = matches.get_one::<f32>("max-speed");
+    if max_distance.is_some() || max_speed.is_some() {
+        let defaults = validation::ResultValidation::default();
+        rapier_physics = rapier_physics.with_validation(
+            max_distance.copied().unwrap_or(defaults.max_distance),
+            max_speed.copied().unwrap_or(defaults.max_speed),
+        );
+    }
+    let writeback_target = match matches.get_one::<String>("writeback").unwrap().as_str() {
+        "pose" => plugin::WritebackTarget::Pose,
+        "events" => plugin::WritebackTarget::Events,
+        _ => plugin::WritebackTarget::Transform,
+    };
+    rapier_physics = rapier_physics.with_writeback_target(writeback_target);
+
+    if let Some(&after) = matches.get_one::<f32>("handover") {
+        let kind = match matches.get_one::<String>("handover-kind").unwrap().as_str() {
+            "reconnect" => handover::HandoverKind::Reconnect,
+            _ => handover::HandoverKind::Delay,
+        };
+        let duration = *matches.get_one::<f32>("handover-duration").unwrap();
+        rapier_physics = rapier_physics.with_handover(
+            kind,
+            std::time::Duration::from_secs_f32(after),
+            std::time::Duration::from_secs_f32(duration),
+        );
+    }
+    rapier_physics = rapier_physics.with_compression(
+        compression,
+        matches.get_one::<i32>("compression-level").copied(),
+    );
+    if let Some(&threshold) = matches.get_one::<usize>("compression-threshold") {
+        rapier_physics = rapier_physics.with_compression_threshold(threshold);
+    }
+    if let Some(framing) = matches
+        .get_one::<String>("framing")
+        .and_then(|name| Framing::from_name(name))
+    {
+        rapier_physics = rapier_physics.with_framing(framing);
+    }
+    if let Some(encoding) = matches
+        .get_one::<String>("encoding")
+        .and_then(|name| Encoding::from_name(name))
+    {
+        rapier_physics = rapier_physics.with_encoding(encoding);
+    }
+    rapier_physics = rapier_physics.with_metadata(RunMetadata::collect(
+        "client",
+        env!("CARGO_PKG_VERSION"),
+        &features(),
+    ));
+    if diagnostics {
+        app.add_plugin(LogDiagnosticsPlugin::default());
+    }
+
+    // The demo's own gravity, unless a profile decides it
+    app.insert_resource(RapierConfiguration {
+        gravity: Vec3::new(0.0, -30.0, 0.0),
+        ..Default::default()
+    });
+    let profile = matches
+        .get_one::<String>("profile")
+        .and_then(|name| Profile::from_name(name));
+    if let Some(profile) = profile {
+        rapier_physics = rapier_physics.with_profile(profile);
+    }
+    if let Some(&step_pacing) = matches.get_one::<StepPacing>("step-pacing") {
+        rapier_physics = rapier_physics.with_step_pacing(step_pacing);
+    }
+    if let Some(contact_rules) = contact_rules {
+        rapier_physics = rapier_physics.with_contact_rules(contact_rules);
+    }
+    if custom_layers {
+        rapier_physics = rapier_physics.with_layers(layers);
+    }
+    if let (Some(&impairment), Some(key)) = (
+        matches.get_one::<Impairment>("impairment"),
+        matches.get_one::<String>("impairment-key"),
+    ) {
+        rapier_physics = rapier_physics.with_impairment(key, impairment);
+    }
+
     app.add_plugin(rapier_physics);
 
+    if impacts {
+        app.insert_resource(ShowImpacts)
+        .add_startup_system(setup_impact_effects)
+        .add_system(show_impacts)
+        .add_system(update_particles);
+    }
+
+    match writeback_target {
+        plugin::WritebackTarget::Pose => {
+            app.add_system(ease_to_remote_poses);
+        }
+        plugin::WritebackTarget::Events => {
+            app.add_system(apply_remote_poses);
+        }
+        plugin::WritebackTarget::Transform => {}
+    }
+
+    if scene.is_some() {
+        app.add_system(spawn_scene)
+        .add_system(upload_level_on_mismatch);
+    } else {
+        app.add_startup_system(setup_level);
+    }
+
     if let Some(frames) = matches.get_one::<i32>("spawn") {
+        let update_rate = match matches.get_one::<String>("update-rate").unwrap().as_str() {
+            "every2" => UpdateRate::Every2,
+            "every4" => UpdateRate::Every4,
+            "on-sleep-change" => UpdateRate::OnSleepChange,
+            _ => UpdateRate::EveryStep,
+        };
         app.insert_resource(SpawnTimerDuration(*frames))
+        .insert_resource(SpawnUpdateRate(update_rate))
         .add_system(add_balls_automatically);
     }
 
+    if matches.get_flag("water") {
+        app.add_startup_system(setup_water);
+    }
+
+    if let Some(&npcs) = matches.get_one::<i32>("npcs") {
+        app.insert_resource(NpcCount(npcs))
+        .add_startup_system(spawn_npcs);
+    }
+
+    if let Some(&seconds) = matches.get_one::<f32>("switch-backend") {
+        app.insert_resource(BackendSwitchTimer(Timer::from_seconds(
+            seconds,
+            TimerMode::Repeating,
+        )))
+        .add_system(switch_backend_periodically);
+    }
+
     if let Some(balls) = matches.get_one::<i32>("close") {
         app.insert_resource(BallLimit(*balls))
         .add_system(close_after_n_balls);
     }
 
     app.add_startup_system(setup_resources.at_start())
-        .add_startup_system(setup_graphics)
-        .add_startup_system(setup_physics)
         .add_system(rotate)
-        .add_system(add_ball_on_click)
+        .add_system(log_live_balls)
+        .add_system(log_joint_breaks)
+        .add_system(log_quarantined_bodies)
+        .add_system(log_intersections)
+        .add_system(log_world_responses)
+        .add_system(log_remote_ray_hits)
+        .add_system(log_remote_shape_queries);
+
+    if !headless {
+        app.add_startup_system(setup_graphics)
+        .add_startup_system(setup_light)
+        .add_startup_system(setup_physics)
+        .add_system(place_ghost)
+        .add_system(follow_ghost.after(place_ghost))
+        .add_system(add_ball_on_click.after(place_ghost))
+        .add_system(trajectory::update_previews.after(follow_ghost))
         .add_system(adjust_spawn_height)
+        .add_system(toggle_remote_trajectory)
+        .add_system(kick_balls)
+        .add_system(spawn_ragdoll)
+        .add_system(spawn_rope)
+        .add_system(show_ropes)
+        .add_system(switch_backend_on_key)
+        .add_system(control_simulation_on_key)
+        .add_system(toggle_side_world)
+        .add_system(show_ragdoll_bones)
+        .add_system(compare_ray_casts)
         .add_system(bevy::window::close_on_esc);
+    }
 
     app.insert_resource(ClearColor(Color::rgb(0.9, 0.6, 0.3)))
-        .insert_resource(RapierConfiguration {
-            gravity: Vec3::new(0.0, -30.0, 0.0),
-            ..Default::default()
-        })
         .insert_resource(SpawnHeight(5.0))
         .insert_resource(BallsSpawned::default());
 
     app.run();
 }
 
+/// The cargo features the client was built with.
+fn features() -> Vec<&'static str> {
+    [
+        ("bulk-requests", cfg!(feature = "bulk-requests")),
+        ("console", cfg!(feature = "console")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature)
+    .collect()
+}
+
+/// Parses `<NAME>=0x<BITS>`.
+fn parse_layer(layer: &str) -> Result<(String, u32), String> {
+    let (name, bits) = layer
+        .split_once('=')
+        .ok_or_else(|| format!("expected <NAME>=0x<BITS>, got {}", layer))?;
+    let bits = bits
+        .strip_prefix("0x")
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .filter(|&bits| bits != 0)
+        .ok_or_else(|| format!("expected nonzero bits in hex, got {}", bits))?;
+    Ok((name.to_string(), bits))
+}
+
+/// Parses `<CHANNEL>=<BATCHES>`.
+fn parse_channel_limit(limit: &str) -> Result<(Channel, usize), String> {
+    let (name, batches) = limit
+        .split_once('=')
+        .ok_or_else(|| format!("expected <CHANNEL>=<BATCHES>, got {}", limit))?;
+    let channel = Channel::from_name(name).ok_or_else(|| format!("unknown channel {}", name))?;
+    let batches = batches
+        .parse::<usize>()
+        .ok()
+        .filter(|&batches| batches > 0)
+        .ok_or_else(|| format!("expected a positive number of batches, got {}", batches))?;
+    Ok((channel, batches))
+}
+
 fn setup_graphics(mut commands: Commands) {
     commands.spawn((
         Camera3dBundle {
@@ -160,6 +886,7 @@ fn setup_graphics(mut commands: Commands) {
             ..default()
         },
         BloomSettings::default(),
+        plugin::SnapshotFocus,
     ));
 }
 
@@ -170,6 +897,7 @@ fn setup_resources(
     server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    show_impacts: Option<Res<ShowImpacts>>,
 ) {
     let mut ball_materials = vec![];
     let texture: Handle<Image> = server.load("checkerboard.jpg");
@@ -198,15 +926,18 @@ fn setup_resources(
             .into(),
         ),
         materials: ball_materials,
+        active_events: if show_impacts.is_some() {
+            ActiveEvents::COLLISION_EVENTS | ActiveEvents::CONTACT_FORCE_EVENTS
+        } else {
+            ActiveEvents::empty()
+        },
     });
 }
 
-fn setup_physics(
+fn setup_level(
     mut commands: Commands,
-    ball_data: Res<BallData>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    balls_spawned: ResMut<BallsSpawned>,
 ) {
     spawn_box(
         &mut commands,
@@ -229,7 +960,9 @@ fn setup_physics(
         Vec3::new(10.0, 2.0, 10.0),
         Vec3::new(4.0, 1.0, 4.0),
     );
+}
 
+fn setup_light(mut commands: Commands) {
     commands.spawn(DirectionalLightBundle {
         directional_light: DirectionalLight {
             illuminance: 20000.0,
@@ -241,7 +974,14 @@ fn setup_physics(
             .with_scale(Vec3::splat(0.2)),
         ..default()
     });
+}
 
+fn setup_physics(
+    mut commands: Commands,
+    ball_data: Res<BallData>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
     commands.spawn((
         PbrBundle {
             mesh: ball_data.mesh.clone(),
@@ -256,6 +996,7 @@ fn setup_physics(
         NotShadowCaster,
         NotShadowReceiver,
         Ghost,
+        plugin::LocalPhysicsOnly,
     ));
 
     commands.spawn((
@@ -272,7 +1013,34 @@ fn setup_physics(
         },
         NotShadowCaster,
         NotShadowReceiver,
-        SpawnIndicator,
+        SpawnIndicator,
+        plugin::LocalPhysicsOnly,
+    ));
+
+    // Where the ghost would fall, known before the server steps the ball
+    let mut mesh = Mesh::new(PrimitiveTopology::LineStrip);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<[f32; 3]>::new());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, Vec::<[f32; 3]>::new());
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(mesh),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                unlit: true,
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            }),
+            ..default()
+        },
+        NotShadowCaster,
+        NotShadowReceiver,
+        trajectory::TrajectoryPreview {
+            start: Vec3::ZERO,
+            velocity: Vec3::ZERO,
+            radius: BALL_RADIUS,
+            restitution: BALL_RESTITUTION,
+            remote: false,
+        },
     ));
 }
 
@@ -299,16 +1067,113 @@ fn spawn_box(
     ));
 }
 
+/// Renders the colliders of the scene the server loaded. They have no
+/// `Collider`, the server already created them.
+fn spawn_scene(
+    mut commands: Commands,
+    mut scenes: EventReader<plugin::RemoteScene>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for scene in scenes.iter() {
+        for (_, collider) in &scene.colliders {
+            commands.spawn(PbrBundle {
+                mesh: meshes.add(scene_mesh(&collider.shape)),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.2, 0.5, 1.0),
+                    perceptual_roughness: 0.3,
+                    ..default()
+                }),
+                transform: collider.transform(),
+                ..default()
+            });
+        }
+    }
+}
+
+/// Uploads the demo's own level when the server has another version of the
+/// scene, rather than playing without a level.
+fn upload_level_on_mismatch(
+    mut mismatches: EventReader<plugin::RemoteSceneMismatch>,
+    commands: Commands,
+    meshes: ResMut<Assets<Mesh>>,
+    materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if let Some(mismatch) = mismatches.iter().last() {
+        warn!(
+            "The server's scene hashes to {:x} instead of {:x}, uploading the demo's level instead",
+            mismatch.actual, mismatch.expected
+        );
+        setup_level(commands, meshes, materials);
+    }
+}
+
+fn scene_mesh(shape: &SceneShape) -> Mesh {
+    match shape {
+        SceneShape::Cuboid { half_extents } => {
+            let size = *half_extents * 2.0;
+            shape::Box::new(size.x, size.y, size.z).into()
+        }
+        SceneShape::Ball { radius } => shape::UVSphere {
+            radius: *radius,
+            sectors: 18,
+            stacks: 9,
+        }
+        .into(),
+        SceneShape::Trimesh { vertices, indices } => {
+            let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+            let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.to_array()).collect();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+            mesh.set_indices(Some(Indices::U32(indices.concat())));
+            mesh.duplicate_vertices();
+            mesh.compute_flat_normals();
+            mesh
+        }
+    }
+}
+
+fn setup_water(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let volume = FluidVolume {
+        min: Vec3::new(-10.0, 0.0, -10.0),
+        max: Vec3::new(0.0, 3.0, 10.0),
+        density: 2.0,
+        linear_drag: 1.0,
+        angular_drag: 1.0,
+    };
+    let size = volume.max - volume.min;
+    commands.spawn((
+        volume,
+        PbrBundle {
+            mesh: meshes.add(shape::Box::new(size.x, size.y, size.z).into()),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(0.1, 0.3, 0.8, 0.4),
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            }),
+            transform: Transform::from_translation((volume.min + volume.max) / 2.0),
+            ..default()
+        },
+        NotShadowCaster,
+    ));
+}
+
 fn spawn_ball(
     commands: &mut Commands,
     ball_data: BallData,
     pos: Vec3,
-    mut balls_spawned: ResMut<BallsSpawned>,
-) {
-    commands.spawn((
+    balls_spawned: &mut BallsSpawned,
+) -> Entity {
+    let entity = commands.spawn((
         RigidBody::Dynamic,
-        Collider::ball(0.5),
-        Restitution::coefficient(0.7),
+        Collider::ball(BALL_RADIUS),
+        Restitution::coefficient(BALL_RESTITUTION),
+        ball_data.active_events,
+        ContactForceEventThreshold(MIN_IMPACT_FORCE),
+        Tag::new("ball"),
         Shape,
         PbrBundle {
             mesh: ball_data.mesh,
@@ -317,8 +1182,9 @@ fn spawn_ball(
                 .with_rotation(Quat::from_rotation_x(90_f32.to_radians())),
             ..default()
         },
-    ));
+    )).id();
     balls_spawned.0 += 1;
+    entity
 }
 fn rotate(mut query: Query<&mut Transform, With<Shape>>, time: Res<Time>) {
     for mut transform in &mut query {
@@ -326,16 +1192,15 @@ fn rotate(mut query: Query<&mut Transform, With<Shape>>, time: Res<Time>) {
     }
 }
 
-fn add_ball_on_click(
-    mut commands: Commands,
-    mouse_button_input: Res<Input<MouseButton>>,
+/// Puts the ghost where a click would spawn a ball, above the point under the
+/// cursor.
+fn place_ghost(
     windows: Res<Windows>,
-    ball_data: Res<BallData>,
     camera_query: Query<(&GlobalTransform, &Camera)>,
     spawn_height: Res<SpawnHeight>,
     mut ghost_query: Query<&mut Transform, With<Ghost>>,
     mut indicator_query: Query<&mut Transform, (With<SpawnIndicator>, Without<Ghost>)>,
-    balls_spawned: ResMut<BallsSpawned>,
+    context: Res<RapierContext>,
 ) {
     let window = windows.get_primary().unwrap();
     let mouse_position = if let Some(pos) = window.cursor_position() {
@@ -350,18 +1215,79 @@ fn add_ball_on_click(
         .viewport_to_world(camera_transform, mouse_position)
         .unwrap();
 
-    let t = -mouse_ray.origin.y / mouse_ray.direction.y;
+    // Only finds the boxes when the server's world is mirrored, the ground
+    // plane is used otherwise
+    let t = context
+        .cast_ray(
+            mouse_ray.origin,
+            mouse_ray.direction,
+            Real::MAX,
+            true,
+            QueryFilter::only_fixed(),
+        )
+        .map(|(_, toi)| toi)
+        .unwrap_or(-mouse_ray.origin.y / mouse_ray.direction.y);
     let hit_pos = mouse_ray.origin + mouse_ray.direction * t;
 
     let spawn_pos = hit_pos + Vec3::Y * spawn_height.0;
 
     ghost_query.single_mut().translation = spawn_pos;
     indicator_query.single_mut().translation = hit_pos;
+}
+
+/// Starts the trajectory preview at the ghost.
+fn follow_ghost(
+    ghost_query: Query<&Transform, With<Ghost>>,
+    mut preview_query: Query<&mut trajectory::TrajectoryPreview>,
+) {
+    let spawn_pos = ghost_query.single().translation;
+    let mut preview = preview_query.single_mut();
+    // Moving the start reruns the prediction
+    if preview.start != spawn_pos {
+        preview.start = spawn_pos;
+    }
+}
+
+fn add_ball_on_click(
+    mut commands: Commands,
+    mouse_button_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    ball_data: Res<BallData>,
+    ghost_query: Query<&Transform, With<Ghost>>,
+    mut balls_spawned: ResMut<BallsSpawned>,
+) {
+    // Clicks outside the window would spawn at a stale ghost
+    if windows.get_primary().unwrap().cursor_position().is_none() {
+        return;
+    }
 
     if mouse_button_input.just_pressed(MouseButton::Left)
         || mouse_button_input.pressed(MouseButton::Right)
     {
-        spawn_ball(&mut commands, ball_data.clone(), spawn_pos, balls_spawned);
+        let spawn_pos = ghost_query.single().translation;
+        let ball = spawn_ball(&mut commands, ball_data.clone(), spawn_pos, &mut balls_spawned);
+        // The player's own balls are updated first under a snapshot budget
+        commands
+            .entity(ball)
+            .insert(plugin::SnapshotPriority(PLAYER_BALL_PRIORITY));
+    }
+}
+
+/// T switches the ghost's trajectory between the local prediction and the
+/// server's.
+fn toggle_remote_trajectory(
+    input: Res<Input<KeyCode>>,
+    mut previews: Query<&mut trajectory::TrajectoryPreview>,
+) {
+    if !input.just_pressed(KeyCode::T) {
+        return;
+    }
+    for mut preview in &mut previews {
+        preview.remote = !preview.remote;
+        info!(
+            "Trajectory predicted {}",
+            if preview.remote { "by the server" } else { "locally" }
+        );
     }
 }
 
@@ -383,21 +1309,540 @@ fn random_position() -> Vec3 {
     Vec3::new(x, 5.0, z)
 }
 
+fn spawn_npcs(
+    mut commands: Commands,
+    ball_data: Res<BallData>,
+    mut balls_spawned: ResMut<BallsSpawned>,
+    npcs: Res<NpcCount>,
+) {
+    for _ in 0..npcs.0 {
+        let ball = spawn_ball(&mut commands, ball_data.clone(), random_position(), &mut balls_spawned);
+        commands.entity(ball).insert(Controller::Wander {
+            max_speed: 4.0,
+            max_force: 10.0,
+        });
+    }
+}
+
+/// The number of bodies the server accepts, once it told it.
+#[derive(SystemParam)]
+struct BodyLimit<'w, 's> {
+    capabilities: Option<Res<'w, plugin::ServerCapabilities>>,
+    bodies: Query<'w, 's, (), With<RigidBody>>,
+}
+
+impl<'w, 's> BodyLimit<'w, 's> {
+    fn reached(&self) -> bool {
+        let max_bodies = self
+            .capabilities
+            .as_ref()
+            .and_then(|capabilities| capabilities.0.max_bodies);
+        max_bodies.is_some_and(|max_bodies| self.bodies.iter().count() >= max_bodies)
+    }
+}
+
 fn add_balls_automatically(
     mut commands: Commands,
-    time: Res<Time>,
     ball_data: Res<BallData>,
-    balls_spawned: ResMut<BallsSpawned>,
+    mut balls_spawned: ResMut<BallsSpawned>,
     mut timer: Local<i32>,
     duration: Res<SpawnTimerDuration>,
+    update_rate: Res<SpawnUpdateRate>,
+    body_limit: BodyLimit,
 ) {
+    // The server would refuse bodies past its limit
+    if body_limit.reached() {
+        return;
+    }
+
     *timer -= 1;
     if *timer <= 0 {
-        spawn_ball(&mut commands, ball_data.clone(), random_position(), balls_spawned);
+        let ball = spawn_ball(&mut commands, ball_data.clone(), random_position(), &mut balls_spawned);
+        commands.entity(ball).insert(update_rate.0);
         *timer = duration.0;
     }
 }
 
+fn kick_balls(
+    input: Res<Input<KeyCode>>,
+    balls: Query<Entity, With<Shape>>,
+    mut body_commands: ResMut<plugin::BodyCommands>,
+) {
+    if !input.just_pressed(KeyCode::Space) {
+        return;
+    }
+
+    // Balls whose body isn't created on the server yet are kicked once it is
+    for entity in balls.iter() {
+        body_commands.0.push((
+            entity,
+            BodyCommand::Impulse {
+                impulse: Vec3::Y * 5.0,
+                torque_impulse: Vec3::ZERO,
+            },
+        ));
+    }
+}
+
+fn spawn_ragdoll(input: Res<Input<KeyCode>>, mut commands: Commands) {
+    if !input.just_pressed(KeyCode::R) {
+        return;
+    }
+
+    commands.spawn((
+        plugin::Ragdoll(Skeleton::humanoid()),
+        TransformBundle::from_transform(Transform::from_translation(random_position())),
+    ));
+}
+
+fn show_ragdoll_bones(
+    mut commands: Commands,
+    bones: Query<(Entity, &plugin::RagdollBone), Added<plugin::RagdollBone>>,
+    ragdolls: Query<&plugin::Ragdoll>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, bone) in bones.iter() {
+        let bone = match ragdolls.get(bone.ragdoll) {
+            Ok(ragdoll) => &ragdoll.0.bones[bone.index],
+            Err(_) => continue,
+        };
+        commands.entity(entity).insert((
+            meshes.add(
+                shape::Capsule {
+                    radius: bone.radius,
+                    depth: (bone.length - 2.0 * bone.radius).max(0.0),
+                    ..default()
+                }
+                .into(),
+            ),
+            materials.add(StandardMaterial {
+                base_color: Color::rgb(0.9, 0.8, 0.7),
+                ..default()
+            }),
+            VisibilityBundle::default(),
+        ));
+    }
+}
+
+/// Hangs a ball from a rope tied to a fixed point above a random position.
+fn spawn_rope(
+    input: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    ball_data: Res<BallData>,
+    mut balls_spawned: ResMut<BallsSpawned>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !input.just_pressed(KeyCode::C) {
+        return;
+    }
+
+    let end = random_position();
+    let start = end + Vec3::new(2.0, 3.0, 0.0);
+    let ball = spawn_ball(&mut commands, ball_data.clone(), end, &mut balls_spawned);
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineStrip);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<[f32; 3]>::new());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, Vec::<[f32; 3]>::new());
+    commands.spawn((
+        plugin::Rope {
+            start,
+            end,
+            start_anchor: RopeAnchor::Fixed(start),
+            end_anchor: RopeAnchor::Body {
+                id: ball.to_bits(),
+                offset: Vec3::ZERO,
+            },
+            segments: 12,
+            radius: 0.05,
+            stiffness: 0.0,
+            break_force: Some(ROPE_BREAK_FORCE),
+        },
+        PbrBundle {
+            mesh: meshes.add(mesh),
+            material: materials.add(StandardMaterial {
+                base_color: Color::BLACK,
+                unlit: true,
+                ..default()
+            }),
+            ..default()
+        },
+        NotShadowCaster,
+    ));
+}
+
+fn switch_backend_on_key(
+    input: Res<Input<KeyCode>>,
+    mut switches: EventWriter<backend::SwitchBackend>,
+) {
+    if input.just_pressed(KeyCode::B) {
+        switches.send(backend::SwitchBackend);
+    }
+}
+
+/// P pauses and resumes the world, L restarts it without the balls.
+fn control_simulation_on_key(
+    input: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    balls: Query<Entity, With<Shape>>,
+    mut balls_spawned: ResMut<BallsSpawned>,
+    mut control: ResMut<plugin::SimulationControl>,
+) {
+    if input.just_pressed(KeyCode::P) {
+        control.state = match control.state {
+            SimulationState::Running => SimulationState::Paused,
+            SimulationState::Paused => SimulationState::Running,
+        };
+        info!("Simulation {:?}", control.state);
+    }
+    if input.just_pressed(KeyCode::L) {
+        for ball in balls.iter() {
+            commands.entity(ball).despawn_recursive();
+        }
+        balls_spawned.0 = 0;
+        control.reset = true;
+    }
+}
+
+/// W creates a world beside the demo's own, as a match world beside a lobby
+/// would be, and asks it for its stats, or destroys it again.
+fn toggle_side_world(
+    input: Res<Input<KeyCode>>,
+    mut request_queue: ResMut<plugin::RequestQueue>,
+    mut created: Local<bool>,
+) {
+    if !input.just_pressed(KeyCode::W) {
+        return;
+    }
+    if *created {
+        request_queue.0.push(Request::DestroyWorld(SIDE_WORLD));
+    } else {
+        request_queue.0.extend([
+            Request::CreateWorld(SIDE_WORLD),
+            Request::InWorld {
+                world_id: SIDE_WORLD,
+                request: Box::new(Request::GetStats),
+            },
+        ]);
+    }
+    *created = !*created;
+}
+
+fn log_world_responses(mut responses: EventReader<plugin::RemoteWorldResponse>) {
+    for response in responses.iter() {
+        info!("World {} answered {:?}", response.world_id, response.response);
+    }
+}
+
+fn switch_backend_periodically(
+    time: Res<Time>,
+    mut timer: ResMut<BackendSwitchTimer>,
+    mut switches: EventWriter<backend::SwitchBackend>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        switches.send(backend::SwitchBackend);
+    }
+}
+
+fn log_joint_breaks(mut joint_breaks: EventReader<plugin::RemoteJointBreak>) {
+    for joint_break in joint_breaks.iter() {
+        info!(
+            "Joint between {:?} and {:?} broke under {} N",
+            joint_break.entities.0, joint_break.entities.1, joint_break.force
+        );
+    }
+}
+
+fn log_quarantined_bodies(mut quarantined: EventReader<validation::QuarantinedBodies>) {
+    for bodies in quarantined.iter() {
+        for (entity, corruption) in &bodies.0 {
+            warn!("Kept {:?} where it was, its state being {:?}", entity, corruption);
+        }
+    }
+}
+
+fn log_intersections(mut intersections: EventReader<plugin::RemoteIntersection>) {
+    for intersection in intersections.iter() {
+        info!(
+            "{:?} and {:?} {} intersecting",
+            intersection.entities.0,
+            intersection.entities.1,
+            if intersection.intersecting {
+                "started"
+            } else {
+                "stopped"
+            }
+        );
+    }
+}
+
+fn show_ropes(
+    ropes: Query<(&plugin::RopePoints, &Handle<Mesh>), Changed<plugin::RopePoints>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (points, mesh) in ropes.iter() {
+        if let Some(mesh) = meshes.get_mut(mesh) {
+            let positions: Vec<[f32; 3]> = points.0.iter().map(|p| p.to_array()).collect();
+            let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        }
+    }
+}
+
+/// Casts a ray under the cursor against both the mirrored and the server's
+/// world, to compare the approximate answer with the authoritative one, and
+/// one against the server's fixed colliders only, which picks the surface a
+/// ball would be dropped on.
+fn compare_ray_casts(
+    mouse_button_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_query: Query<(&GlobalTransform, &Camera)>,
+    ghost_query: Query<&Transform, With<Ghost>>,
+    mut ray_caster: mirror::RayCaster,
+    mut shape_queries: ResMut<plugin::RemoteShapeQueries>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Middle) {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let mouse_position = if let Some(pos) = window.cursor_position() {
+        pos
+    } else {
+        return;
+    };
+
+    let (camera_transform, camera) = camera_query.single();
+    let ray = if let Some(ray) = camera.viewport_to_world(camera_transform, mouse_position) {
+        ray
+    } else {
+        return;
+    };
+
+    for mode in [mirror::QueryMode::Local, mirror::QueryMode::Remote] {
+        match ray_caster.cast_ray(ray.origin, ray.direction, Real::MAX, true, mode) {
+            mirror::RayCastResult::Hit(hit) => info!("Local ray hit: {:?}", hit),
+            mirror::RayCastResult::Pending(id) => info!("Remote ray {} sent", id),
+        }
+    }
+
+    let id = ray_caster.cast_filtered_ray(
+        ray.origin,
+        ray.direction,
+        Real::MAX,
+        true,
+        QueryFilter::only_fixed(),
+    );
+    info!("Remote ray {} against the fixed colliders sent", id);
+
+    let ball = Collider::ball(BALL_RADIUS);
+    let id = shape_queries.cast_shape(
+        ray.origin,
+        Quat::IDENTITY,
+        ray.direction,
+        &ball,
+        Real::MAX,
+        QueryFilter::default(),
+    );
+    info!("Remote ball cast {} sent", id);
+
+    // Whether a ball spawned at the ghost would start out overlapping others
+    let id = shape_queries.intersections_with_shape(
+        ghost_query.single().translation,
+        Quat::IDENTITY,
+        &ball,
+        QueryFilter::default(),
+    );
+    info!("Remote intersections with the ghost {} sent", id);
+}
+
+fn log_remote_shape_queries(
+    mut shape_hits: EventReader<plugin::RemoteShapeHit>,
+    mut shape_intersections: EventReader<plugin::RemoteShapeIntersections>,
+) {
+    for shape_hit in shape_hits.iter() {
+        match shape_hit.hit {
+            Some((entity, hit)) => info!(
+                "Remote ball cast {} hit {:?} after {}",
+                shape_hit.id, entity, hit.toi
+            ),
+            None => info!("Remote ball cast {} hit nothing", shape_hit.id),
+        }
+    }
+    for intersections in shape_intersections.iter() {
+        info!(
+            "Remote intersections with the ghost {}: {:?}",
+            intersections.id, intersections.entities
+        );
+    }
+}
+
+/// Eases the bodies written back to `RemotePhysicsPose` towards it, as a game
+/// running its own smoothing would.
+fn ease_to_remote_poses(
+    time: Res<Time>,
+    calibration: Option<Res<calibration::Calibration>>,
+    mut bodies: Query<(&mut Transform, &plugin::RemotePhysicsPose)>,
+) {
+    // Catching up over the interpolation delay hides snapshots arriving late
+    let catch_up = calibration.map_or(1.0 / POSE_EASING, |calibration| {
+        calibration.interpolation_delay.as_secs_f32()
+    });
+    let t = (time.delta_seconds() / catch_up.max(f32::EPSILON)).min(1.0);
+    for (mut transform, pose) in bodies.iter_mut() {
+        transform.translation = transform.translation.lerp(pose.transform.translation, t);
+        transform.rotation = transform.rotation.slerp(pose.transform.rotation, t);
+    }
+}
+
+/// Moves the bodies written back with events to the states they carry.
+fn apply_remote_poses(
+    mut poses: EventReader<plugin::RemotePoseUpdated>,
+    mut bodies: Query<(&mut Transform, Option<&mut Velocity>)>,
+) {
+    for pose in poses.iter() {
+        if let Ok((mut transform, velocity)) = bodies.get_mut(pose.entity) {
+            transform.translation = pose.transform.translation;
+            transform.rotation = pose.transform.rotation;
+            if let Some(mut velocity) = velocity {
+                *velocity = pose.velocity;
+            }
+        }
+    }
+}
+
+fn log_remote_ray_hits(mut ray_hits: EventReader<plugin::RemoteRayHit>) {
+    for ray_hit in ray_hits.iter() {
+        match ray_hit.normal {
+            Some(normal) => info!(
+                "Remote ray {} hit: {:?}, normal {}",
+                ray_hit.id, ray_hit.hit, normal
+            ),
+            None => info!("Remote ray {} hit: {:?}", ray_hit.id, ray_hit.hit),
+        }
+    }
+}
+
+fn setup_impact_effects(
+    mut commands: Commands,
+    server: Res<AssetServer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ImpactEffects {
+        sound: server.load("impact.wav"),
+        particle_material: materials.add(StandardMaterial {
+            base_color: Color::rgb(1.0, 0.9, 0.6),
+            emissive: Color::rgb(4.0, 3.0, 1.5),
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+/// Shows the contacts of balls that started this frame, as hard as the force
+/// reported for them in the same step, from the server or the local world.
+fn show_impacts(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    mut contact_forces: EventReader<ContactForceEvent>,
+    balls: Query<&GlobalTransform, With<Shape>>,
+    effects: Res<ImpactEffects>,
+    ball_data: Res<BallData>,
+    audio: Res<Audio>,
+) {
+    let forces: HashMap<(Entity, Entity), f32> = contact_forces
+        .iter()
+        .map(|event| {
+            (
+                (event.collider1, event.collider2),
+                event.total_force_magnitude,
+            )
+        })
+        .collect();
+
+    let mut rng = rand::thread_rng();
+    for collision in collisions.iter() {
+        let (entity1, entity2) = match collision {
+            CollisionEvent::Started(entity1, entity2, _) => (*entity1, *entity2),
+            CollisionEvent::Stopped(..) => continue,
+        };
+        // Contacts too soft for a force event aren't shown
+        let force = match forces
+            .get(&(entity1, entity2))
+            .or_else(|| forces.get(&(entity2, entity1)))
+        {
+            Some(&force) => force,
+            None => continue,
+        };
+        let point = match balls.get(entity1).or_else(|_| balls.get(entity2)) {
+            Ok(transform) => transform.translation(),
+            Err(_) => continue,
+        };
+
+        // Harder impacts are louder and throw more, faster particles
+        let strength = (force / FULL_IMPACT_FORCE).min(1.0);
+        audio.play_with_settings(
+            effects.sound.clone(),
+            PlaybackSettings::ONCE.with_volume(strength),
+        );
+
+        let particles = (strength * MAX_PARTICLES_PER_IMPACT).ceil() as usize;
+        for _ in 0..particles {
+            let direction = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.2..1.0),
+                rng.gen_range(-1.0..1.0),
+            )
+            .normalize();
+
+            commands.spawn((
+                PbrBundle {
+                    mesh: ball_data.mesh.clone(),
+                    material: effects.particle_material.clone(),
+                    transform: Transform::from_translation(point)
+                        .with_scale(Vec3::splat(0.1)),
+                    ..default()
+                },
+                NotShadowCaster,
+                Particle {
+                    velocity: direction * (2.0 + 6.0 * strength),
+                    lifetime: Timer::from_seconds(0.5, TimerMode::Once),
+                },
+            ));
+        }
+    }
+}
+
+fn update_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    config: Res<RapierConfiguration>,
+    mut particles: Query<(Entity, &mut Transform, &mut Particle)>,
+) {
+    for (entity, mut transform, mut particle) in &mut particles {
+        particle.lifetime.tick(time.delta());
+        if particle.lifetime.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        particle.velocity += config.gravity * time.delta_seconds();
+        transform.translation += particle.velocity * time.delta_seconds();
+    }
+}
+
+fn log_live_balls(mut ready: EventReader<plugin::RemoteReady>, balls: Query<(), With<Shape>>) {
+    for event in ready.iter() {
+        if let plugin::RemoteReady::Body(entity) = event {
+            if balls.contains(*entity) {
+                debug!("Ball {:?} is live on the server", entity);
+            }
+        }
+    }
+}
+
 fn close_after_n_balls(
     balls_spawned: Res<BallsSpawned>,
     ball_limit: Res<BallLimit>,
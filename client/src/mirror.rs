@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{ColliderBuilder, RigidBodyBuilder, RigidBodyHandle};
+
+use shared::{mirror::WorldState, serializable::SerializableQueryFilter, RayCast};
+
+use crate::plugin::{MirrorSync, PhysicsIds, RemoteRayCasts};
+
+/// Replaces the content of the client's `RapierContext` with the server's
+/// state, so that scene queries like `cast_ray` work against an approximate,
+/// read-only copy of the world. The context is never stepped. Bodies and
+/// colliders whose ids were released are left out.
+pub fn rebuild(
+    context: &mut RapierContext,
+    mirror: &mut MirrorSync,
+    state: WorldState,
+    ids: &PhysicsIds,
+) {
+    *context = RapierContext::default();
+    mirror.bodies.clear();
+    mirror.colliders.clear();
+
+    // bevy_rapier's queries make the entities they answer with of user data
+    let user_data = |id| ids.entity(id).map(|entity| entity.to_bits().into());
+
+    for body in state.bodies {
+        let user_data = match user_data(body.id) {
+            Some(user_data) => user_data,
+            None => continue,
+        };
+        let handle = context.bodies.insert(
+            RigidBodyBuilder::new(body.body.into())
+                .position(body.position)
+                .linvel(body.linvel.into())
+                .angvel(body.angvel.into())
+                .user_data(user_data),
+        );
+        mirror.bodies.insert(body.handle, handle);
+    }
+
+    for collider in state.colliders {
+        let user_data = match user_data(collider.id) {
+            Some(user_data) => user_data,
+            None => continue,
+        };
+        let builder = ColliderBuilder::new(collider.shape.raw)
+            .position(collider.position)
+            .sensor(collider.sensor)
+            .user_data(user_data);
+
+        let handle = match collider.parent {
+            Some(parent) => match mirror.bodies.get(&parent) {
+                Some(&parent) => {
+                    context
+                        .colliders
+                        .insert_with_parent(builder, parent, &mut context.bodies)
+                }
+                None => continue,
+            },
+            None => context.colliders.insert(builder),
+        };
+        mirror.colliders.insert(collider.handle, handle);
+    }
+
+    context
+        .query_pipeline
+        .update(&context.bodies, &context.colliders);
+}
+
+/// Moves the mirrored bodies to the positions of the latest step.
+pub fn update_positions(
+    context: &mut RapierContext,
+    mirror: &MirrorSync,
+    result: &HashMap<RigidBodyHandle, (Transform, Velocity)>,
+) {
+    let physics_scale = context.physics_scale();
+    for (handle, (transform, velocity)) in result {
+        let rb = match mirror
+            .bodies
+            .get(handle)
+            .and_then(|&handle| context.bodies.get_mut(handle))
+        {
+            Some(rb) => rb,
+            None => continue,
+        };
+
+        rb.set_position(shared::transform_to_iso(transform, physics_scale), false);
+        rb.set_linvel((velocity.linvel / physics_scale).into(), false);
+        rb.set_angvel(velocity.angvel.into(), false);
+    }
+
+    context
+        .bodies
+        .propagate_modified_body_positions_to_colliders(&mut context.colliders);
+    context
+        .query_pipeline
+        .update(&context.bodies, &context.colliders);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryMode {
+    /// Answered immediately from the mirrored world, which may be slightly
+    /// stale and is empty unless mirroring is enabled.
+    Local,
+    /// Answered by the server a roundtrip later with a `RemoteRayHit` event.
+    Remote,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RayCastResult {
+    Hit(Option<(Entity, Real)>),
+    /// The id of the `RemoteRayHit` event the result will arrive with.
+    Pending(u64),
+}
+
+/// Casts rays against either the mirrored world or the server's.
+#[derive(SystemParam)]
+pub struct RayCaster<'w, 's> {
+    context: Res<'w, RapierContext>,
+    remote: ResMut<'w, RemoteRayCasts>,
+    #[system_param(ignore)]
+    marker: std::marker::PhantomData<&'s ()>,
+}
+
+impl<'w, 's> RayCaster<'w, 's> {
+    pub fn cast_ray(
+        &mut self,
+        origin: Vect,
+        dir: Vect,
+        max_toi: Real,
+        solid: bool,
+        mode: QueryMode,
+    ) -> RayCastResult {
+        match mode {
+            QueryMode::Local => RayCastResult::Hit(self.context.cast_ray(
+                origin,
+                dir,
+                max_toi,
+                solid,
+                QueryFilter::default(),
+            )),
+            QueryMode::Remote => {
+                let id = self.remote.next_id;
+                self.remote.next_id += 1;
+                self.remote.rays.push(RayCast {
+                    id,
+                    origin,
+                    dir,
+                    max_toi,
+                    solid,
+                });
+                RayCastResult::Pending(id)
+            }
+        }
+    }
+
+    /// Casts a ray against the server's world through the colliders the
+    /// filter lets through, answered a roundtrip later with a `RemoteRayHit`
+    /// event carrying the normal at the hit. Returns the event's id.
+    pub fn cast_filtered_ray(
+        &mut self,
+        origin: Vect,
+        dir: Vect,
+        max_toi: Real,
+        solid: bool,
+        filter: QueryFilter,
+    ) -> u64 {
+        let id = self.remote.next_id;
+        self.remote.next_id += 1;
+        self.remote.filtered_rays.push((
+            RayCast {
+                id,
+                origin,
+                dir,
+                max_toi,
+                solid,
+            },
+            SerializableQueryFilter::from(filter),
+        ));
+        id
+    }
+}
@@ -1,12 +1,63 @@
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::Ipv6Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use bevy::prelude::*;
+use bevy::{asset::FileAssetIo, diagnostic::Diagnostics, prelude::*, utils::Instant};
 use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{ColliderHandle, RigidBodyHandle};
 
-use shared::{Request, Response};
+use shared::{
+    capabilities::Capabilities,
+    channel::Channel,
+    codec::Encoding,
+    compression::Compression,
+    degradation::Degradation,
+    framing::Framing,
+    hooks::ContactRules,
+    ids::{IdMap, PhysicsId},
+    impairment::Impairment,
+    layers::LayerRegistry,
+    metadata::RunMetadata,
+    metrics::CsvWriter,
+    operator::SessionStatus,
+    pacing::StepPacing,
+    profile::Profile,
+    protocol::ROOM_MEMBER_ID_SHIFT,
+    ragdoll::Skeleton,
+    recording::Recorder,
+    rope::RopeAnchor,
+    scene::{content_hash, SceneCollider, SCENE_ENTITY},
+    serializable::{ConfigPatch, SerializableQueryFilter},
+    BodyCommand, RayCast, Request, Response, ShapeCastHit, SimulationState, WorldSnapshot,
+};
 use url::Url;
 
-use crate::{client::PhysicsClient, error::Result, systems};
+use crate::{
+    backend::{self, Backend, LoadedScene, Reconnects, SwitchBackend},
+    calibration,
+    client::{PhysicsClient, RequestStats},
+    clock::{self, ClockSync},
+    diagnostics::{self, DiagnosticsStats},
+    energy::EnergySampler,
+    error::Result,
+    frame_report::{self, FrameReport},
+    handover::{Handover, HandoverKind},
+    prewarm::{self, PrewarmProgress, ScenePrewarm},
+    replay::{self, SnapshotRecording},
+    systems::{self, RequestBatch},
+    trajectory::{self, PendingTrajectories},
+    validation::{self, QuarantinedBodies, ResultValidation},
+    watchdog::{self, Watchdog},
+};
+
+#[cfg(feature = "console")]
+use crate::console::{self, ConsoleLog};
+
+type LocalPhysicsPlugin = bevy_rapier3d::plugin::RapierPhysicsPlugin<NoUserData>;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
 enum PhysicsStage {
@@ -17,6 +68,40 @@ enum PhysicsStage {
 pub struct RapierPhysicsPlugin {
     addr: String,
     port: u16,
+    templates: bool,
+    metrics_path: Option<String>,
+    energy_sampling: bool,
+    placement_path: Option<String>,
+    mirror_period: Option<Duration>,
+    scene: Option<String>,
+    prewarm: bool,
+    calibration: bool,
+    max_in_flight: usize,
+    channel_limits: [Option<usize>; 3],
+    profile: Option<Profile>,
+    diagnostics: bool,
+    console: bool,
+    compression: Compression,
+    compression_level: Option<i32>,
+    compression_threshold: Option<usize>,
+    frame_report: bool,
+    writeback_target: WritebackTarget,
+    snapshot_recording_path: Option<String>,
+    handover: Option<(HandoverKind, Duration, Duration)>,
+    streaming: Option<Duration>,
+    room: Option<String>,
+    layers: Option<LayerRegistry>,
+    step_pacing: Option<StepPacing>,
+    contact_rules: Option<ContactRules>,
+    impairment: Option<(String, Impairment)>,
+    compaction_period: Option<Duration>,
+    framing: Framing,
+    encoding: Encoding,
+    metadata: Option<RunMetadata>,
+    watchdog_frames: Option<u32>,
+    heartbeat_period: Option<Duration>,
+    max_distance: f32,
+    max_speed: f32,
 }
 
 impl RapierPhysicsPlugin {
@@ -24,11 +109,49 @@ impl RapierPhysicsPlugin {
         Self {
             addr: "localhost".to_string(),
             port: 8080,
+            templates: false,
+            metrics_path: None,
+            energy_sampling: false,
+            placement_path: None,
+            mirror_period: None,
+            scene: None,
+            prewarm: false,
+            calibration: true,
+            max_in_flight: 1,
+            channel_limits: [None; 3],
+            profile: None,
+            diagnostics: false,
+            console: false,
+            compression: Compression::None,
+            compression_level: None,
+            compression_threshold: None,
+            frame_report: false,
+            writeback_target: WritebackTarget::Transform,
+            snapshot_recording_path: None,
+            handover: None,
+            streaming: None,
+            room: None,
+            layers: None,
+            step_pacing: None,
+            contact_rules: None,
+            impairment: None,
+            compaction_period: None,
+            framing: Framing::Binary,
+            encoding: Encoding::Bincode,
+            metadata: None,
+            watchdog_frames: Some(watchdog::DEFAULT_FRAMES),
+            heartbeat_period: Some(Heartbeat::DEFAULT_PERIOD),
+            max_distance: ResultValidation::default().max_distance,
+            max_speed: ResultValidation::default().max_speed,
         }
     }
 
+    /// A host name or an IPv4 or IPv6 address, with or without brackets.
     pub fn with_addr(mut self, addr: &str) -> Self {
-        self.addr = addr.to_string();
+        self.addr = addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
         self
     }
 
@@ -36,32 +159,348 @@ impl RapierPhysicsPlugin {
         self.port = port;
         self
     }
+
+    pub fn with_templates(mut self, templates: bool) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    pub fn with_metrics(mut self, path: &str) -> Self {
+        self.metrics_path = Some(path.to_string());
+        self
+    }
+
+    pub fn with_energy_sampling(mut self, energy_sampling: bool) -> Self {
+        self.energy_sampling = energy_sampling;
+        self
+    }
+
+    pub fn with_placement_report(mut self, path: &str) -> Self {
+        self.placement_path = Some(path.to_string());
+        self
+    }
+
+    pub fn with_mirror(mut self, period: Duration) -> Self {
+        self.mirror_period = Some(period);
+        self
+    }
+
+    /// Loads the static level geometry from a scene on the server instead of
+    /// uploading it. If `assets/scenes/<name>.ron` exists, the server refuses
+    /// to load a different version of the scene, which is told with a
+    /// `RemoteSceneMismatch` event.
+    pub fn with_scene(mut self, name: &str) -> Self {
+        self.scene = Some(name.to_string());
+        self
+    }
+
+    /// Uploads the static colliders spawned by startup systems before the
+    /// first frame, sending `PrewarmProgress` events, instead of with the
+    /// first frame's requests.
+    pub fn with_prewarm(mut self, prewarm: bool) -> Self {
+        self.prewarm = prewarm;
+        self
+    }
+
+    /// Starts with the configuration of a profile, which the server also gives
+    /// colliders that don't set their restitution. Colliders of the local
+    /// backend keep their own.
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Registers the round trip time, traffic, queue depth and server step
+    /// time with Bevy's `Diagnostics`, for `LogDiagnosticsPlugin` and
+    /// diagnostics overlays to show.
+    pub fn with_diagnostics(mut self, diagnostics: bool) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Shows an egui window listing the requests and responses, with buttons
+    /// to resync the bodies, export a snapshot of the server's world and
+    /// switch backends. Needs the `console` feature.
+    pub fn with_console(mut self, console: bool) -> Self {
+        self.console = console;
+        self
+    }
+
+    /// Compresses the binary messages after the handshake as `compression`,
+    /// at `level` if given instead of its default.
+    pub fn with_compression(mut self, compression: Compression, level: Option<i32>) -> Self {
+        self.compression = compression;
+        self.compression_level = level;
+        self
+    }
+
+    /// Requests shorter than `threshold` bytes are sent uncompressed, with a
+    /// compression. 256 by default.
+    pub fn with_compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = Some(threshold);
+        self
+    }
+
+    /// Logs at exit how many frames showed a snapshot that arrived that frame,
+    /// the frame before or earlier, with the frame rate and round trip time.
+    pub fn with_frame_report(mut self, frame_report: bool) -> Self {
+        self.frame_report = frame_report;
+        self
+    }
+
+    /// What the states of bodies without a `WritebackTarget` of their own are
+    /// written to. `WritebackTarget::Transform` by default.
+    pub fn with_writeback_target(mut self, target: WritebackTarget) -> Self {
+        self.writeback_target = target;
+        self
+    }
+
+    /// Records the transforms of the bodies every rendered frame shows, and
+    /// the shapes of the colliders, for `ReplayPlugin` to play back.
+    pub fn with_snapshot_recording(mut self, path: &str) -> Self {
+        self.snapshot_recording_path = Some(path.to_string());
+        self
+    }
+
+    /// Simulates a mobile network handover `after` the connection is made,
+    /// lasting `duration`. Metrics rows overlapping it are tagged with its
+    /// kind.
+    pub fn with_handover(
+        mut self,
+        kind: HandoverKind,
+        after: Duration,
+        duration: Duration,
+    ) -> Self {
+        self.handover = Some((kind, after, duration));
+        self
+    }
+
+    /// Names collision group bits for the server's logs and the console,
+    /// instead of the presets. The registry is inserted as a resource either
+    /// way, for looking up the groups of a layer.
+    pub fn with_layers(mut self, layers: LayerRegistry) -> Self {
+        self.layers = Some(layers);
+        self
+    }
+
+    /// How the server runs steps that reach it in a burst, instead of its
+    /// default pacing.
+    pub fn with_step_pacing(mut self, step_pacing: StepPacing) -> Self {
+        self.step_pacing = Some(step_pacing);
+        self
+    }
+
+    /// Has the server filter contact pairs by the rules, sent along with the
+    /// `RapierConfiguration` whenever either changes. Only the remote backend
+    /// follows them.
+    pub fn with_contact_rules(mut self, contact_rules: ContactRules) -> Self {
+        self.contact_rules = Some(contact_rules);
+        self
+    }
+
+    /// Has the server simulate `impairment` for this client instead of its
+    /// default, if `key` is the one it was started with.
+    pub fn with_impairment(mut self, key: &str, impairment: Impairment) -> Self {
+        self.impairment = Some((key.to_string(), impairment));
+        self
+    }
+
+    /// Has the server compact the world every `period`, moving bodies to new
+    /// handles. Other requests wait while it does.
+    pub fn with_compaction(mut self, period: Duration) -> Self {
+        self.compaction_period = Some(period);
+        self
+    }
+
+    /// Frames the messages after the handshake as `framing`, e.g. JSON to
+    /// read the traffic with standard tools while debugging.
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Encodes the binary messages after the handshake as `encoding`, to
+    /// compare the overhead of the formats.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Has the server step the world every `period` on its own and push the
+    /// results, which are written back as they arrive instead of stepping
+    /// every frame.
+    pub fn with_streaming(mut self, period: Duration) -> Self {
+        self.streaming = Some(period);
+        self
+    }
+
+    /// Joins the room of the name on servers started with `--rooms`, sharing
+    /// its world with the other members. Only the bodies the client created
+    /// can be changed, and the results of the steps of the room's first member
+    /// are pushed to the others.
+    pub fn with_room(mut self, name: &str) -> Self {
+        self.room = Some(name.to_string());
+        self
+    }
+
+    /// Exchanges `metadata` with the server when connecting, and writes both
+    /// in front of the log and the metrics and placement reports.
+    pub fn with_metadata(mut self, metadata: RunMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Measures the network and the server before the first frame to insert a
+    /// `Calibration` resource, which decides whether requests are batched.
+    /// Enabled by default.
+    pub fn with_calibration(mut self, calibration: bool) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    /// Resets the connection and creates the world again when no response
+    /// arrives for `frames` frames while requests are in flight, after dumping
+    /// the state of the request pipeline to a file. `None` disables it, it
+    /// waits 60 frames by default.
+    pub fn with_watchdog(mut self, frames: Option<u32>) -> Self {
+        self.watchdog_frames = frames;
+        self
+    }
+
+    /// Pings the server after `period` without a request, so that its idle
+    /// timeout doesn't tear down the session of a client that is quiet but
+    /// still there. `None` disables it, it pings after 5 seconds by default.
+    pub fn with_heartbeat(mut self, period: Option<Duration>) -> Self {
+        self.heartbeat_period = period;
+        self
+    }
+
+    /// Bodies the server puts farther than `max_distance` meters from the
+    /// origin or moving faster than `max_speed` are quarantined, kept where
+    /// they were like bodies with non-finite states, and reported with a
+    /// `QuarantinedBodies` event. 10 km and 1 km/s by default.
+    pub fn with_validation(mut self, max_distance: f32, max_speed: f32) -> Self {
+        self.max_distance = max_distance;
+        self.max_speed = max_speed;
+        self
+    }
+
+    /// Lets up to `max_in_flight` frames of requests wait for their responses
+    /// instead of every frame waiting for the previous one's, 1 by default.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// Lets polling requests on `channel` wait for their responses in at most
+    /// `max_in_flight` batches, so that e.g. slow snapshots don't hold back
+    /// queries. Channels are only bounded by the request window by default.
+    pub fn with_channel_limit(mut self, channel: Channel, max_in_flight: usize) -> Self {
+        self.channel_limits[channel.id() as usize] = Some(max_in_flight.max(1));
+        self
+    }
+
+    /// The address and port, with IPv6 addresses in brackets as URLs need.
+    fn host(&self) -> String {
+        match self.addr.parse::<Ipv6Addr>() {
+            Ok(ip) => format!("[{}]:{}", ip, self.port),
+            Err(_) => format!("{}:{}", self.addr, self.port),
+        }
+    }
 }
 
+/// What the server said it supports in the handshake.
+#[derive(Resource, Debug, Clone)]
+pub struct ServerCapabilities(pub Capabilities);
+
 #[derive(Resource)]
 pub struct PhysicsClientWrapper(pub Arc<Mutex<PhysicsClient>>);
 
 // Couldn't get futures working with Bevy
 // TODO: Implement this with futures instead of polling
-#[cfg(feature = "bulk-requests")]
+/// The results the server pushed since the last writeback, oldest first.
 #[derive(Resource)]
-pub struct RequestResult(pub Arc<Mutex<Option<Result<Response>>>>);
-
-#[cfg(feature = "bulk-requests")]
-impl Default for RequestResult {
-    fn default() -> Self {
-        Self(Arc::new(Mutex::new(None)))
-    }
+pub struct PushedResults {
+    pub results: Arc<Mutex<Vec<Response>>>,
+    /// Whether the server steps on its own.
+    pub streaming: bool,
 }
 
-#[cfg(not(feature = "bulk-requests"))]
-#[derive(Resource)]
+/// The responses received since the last writeback, in the order the requests
+/// were sent.
+#[derive(Resource, Default)]
 pub struct RequestResult(pub Arc<Mutex<Vec<Result<Response>>>>);
 
-#[cfg(not(feature = "bulk-requests"))]
-impl Default for RequestResult {
-    fn default() -> Self {
-        Self(Arc::new(Mutex::new(Vec::new())))
+/// Hands every frame's requests to the thread sending them.
+#[derive(Resource)]
+pub struct RequestSender(pub Mutex<Sender<RequestBatch>>);
+
+/// Bounds how many frames of requests can wait for their responses. While the
+/// window is full, new requests stay queued, steps are coalesced and polling
+/// requests are skipped.
+#[derive(Resource)]
+pub struct RequestWindow {
+    pub max_in_flight: usize,
+    pub in_flight: Arc<AtomicUsize>,
+    /// The frames whose batches are in flight, oldest first.
+    pub in_flight_frames: Arc<Mutex<Vec<u64>>>,
+    /// Notified whenever a batch leaves the window, which happens under the
+    /// lock of `in_flight_frames`.
+    pub batch_done: Arc<Condvar>,
+    /// By channel, the batches in flight with requests on it and how many
+    /// can be before its polling requests are skipped.
+    pub channel_in_flight: Arc<[AtomicUsize; 3]>,
+    pub channel_limits: [Option<usize>; 3],
+    /// Set while a request that moves bodies to new handles is in flight,
+    /// holding back every other request until its response was handled.
+    pub barrier: bool,
+    /// Occupancy samples since the metrics were last exported: the number of
+    /// frames, batches in flight summed over them and frames finding the
+    /// window full.
+    pub frames: usize,
+    pub in_flight_total: usize,
+    pub full_frames: usize,
+    /// When the latest batch was sent.
+    pub last_sent: Instant,
+}
+
+impl RequestWindow {
+    /// How long a frame waits for a full window to free up before going on
+    /// without the server's response.
+    pub const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Blocks until `ready` holds as batches leave the window, or until the
+    /// stall timeout passes.
+    pub fn wait_until(&self, ready: impl Fn(&Self) -> bool) {
+        let deadline = Instant::now() + Self::STALL_TIMEOUT;
+        let mut frames = self.in_flight_frames.lock().unwrap();
+        while !ready(self) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            frames = self.batch_done.wait_timeout(frames, remaining).unwrap().0;
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.in_flight() >= self.max_in_flight
+    }
+
+    pub fn channel_in_flight(&self, channel: Channel) -> usize {
+        self.channel_in_flight[channel.id() as usize].load(Ordering::Acquire)
+    }
+
+    /// Whether a polling request on `channel` can be sent this frame.
+    pub fn has_room(&self, channel: Channel) -> bool {
+        !self.barrier
+            && !self.is_full()
+            && self.channel_limits[channel.id() as usize]
+                .is_none_or(|limit| self.channel_in_flight(channel) < limit)
     }
 }
 
@@ -91,16 +530,91 @@ impl Plugin for RapierPhysicsPlugin {
             .register_type::<Group>();
 
         // Insert all of our required resources. Don’t overwrite
-        // the `RapierConfiguration` if it already exists.
-        if app.world.get_resource::<RapierConfiguration>().is_none() {
+        // the `RapierConfiguration` if it already exists, unless a profile
+        // decides it.
+        if let Some(profile) = self.profile {
+            info!("Physics profile: {}", profile);
+            app.insert_resource(profile.config());
+        } else if app.world.get_resource::<RapierConfiguration>().is_none() {
             app.insert_resource(RapierConfiguration::default());
         }
 
+        // Likewise for the contact rules
+        if let Some(contact_rules) = &self.contact_rules {
+            app.insert_resource(contact_rules.clone());
+        } else {
+            app.init_resource::<ContactRules>();
+        }
+
         app.insert_resource(SimulationToRenderTime::default())
             .insert_resource(RapierContext::default());
 
-        app.insert_resource(RequestQueue::default());
-        app.insert_resource(RequestResult::default());
+        // The profile, layers, step pacing, impairment and scene are set up
+        // with the first requests sent, the profile by the prewarm if there is
+        // one
+        let initial_requests = self
+            .profile
+            .filter(|_| !self.prewarm)
+            .map(Request::UseProfile)
+            .into_iter()
+            .chain(self.layers.clone().map(Request::RegisterLayers))
+            .chain(self.step_pacing.map(Request::SetStepPacing))
+            .chain(
+                self.impairment
+                    .clone()
+                    .map(|(key, impairment)| Request::SetImpairment { key, impairment }),
+            )
+            .chain(self.scene.iter().map(|name| Request::LoadScene {
+                name: name.clone(),
+                hash: local_scene_hash(name),
+            }))
+            .collect();
+        app.insert_resource(RequestQueue(initial_requests));
+        app.insert_resource(BodyCommands::default());
+        app.insert_resource(PendingBodyCommands::default());
+        app.insert_resource(BodyTransforms::default());
+        app.init_resource::<ConfigPatches>();
+        app.insert_resource(SentColliderScales::default());
+        app.insert_resource(PhysicsIds::default());
+        app.insert_resource(RemoteRayCasts::default());
+        app.insert_resource(RemoteShapeQueries::default());
+        app.insert_resource(StateRequests::default());
+        app.insert_resource(SavedWorld::default());
+        app.insert_resource(SimulationControl::default());
+        app.insert_resource(RemoteDegradation::default());
+        app.insert_resource(ResultValidation {
+            max_distance: self.max_distance,
+            max_speed: self.max_speed,
+            ..default()
+        });
+        app.add_event::<QuarantinedBodies>();
+        app.insert_resource(self.layers.clone().unwrap_or_default());
+        app.insert_resource(self.writeback_target);
+        app.add_event::<RemotePoseUpdated>();
+        app.add_event::<RemoteReady>();
+        app.add_event::<PrewarmProgress>();
+        app.add_event::<RemoteRayHit>();
+        app.add_event::<RemoteShapeHit>();
+        app.add_event::<RemoteShapeIntersections>();
+        app.add_event::<RemoteScene>();
+        app.add_event::<RemoteSceneMismatch>();
+        app.add_event::<RemoteJointBreak>();
+        app.insert_resource(RemoteIntersections::default());
+        app.add_event::<RemoteIntersection>();
+        app.add_event::<RemoteWorldResponse>();
+        app.insert_resource(PendingTrajectories::default());
+        app.insert_resource(ClockSync::default());
+        app.add_event::<SessionStatus>();
+        // Sent from the server's events, as bevy_rapier would locally
+        app.add_event::<CollisionEvent>();
+        app.add_event::<ContactForceEvent>();
+        app.insert_resource(Backend::default());
+        app.insert_resource(LoadedScene::default());
+        app.add_event::<SwitchBackend>();
+        app.insert_resource(TemplateRegistry {
+            enabled: self.templates,
+            ..default()
+        });
 
         // Custom initialization
 
@@ -109,27 +623,921 @@ impl Plugin for RapierPhysicsPlugin {
             PhysicsStage::SyncBackend,
             SystemStage::parallel().with_system_set(
                 SystemSet::new()
+                    .with_system(systems::send_config_patches.before(systems::update_config))
                     .with_system(systems::update_config)
-                    .with_system(systems::init_rigid_bodies.after(systems::update_config))
+                    .with_system(systems::init_templated_bodies.after(systems::update_config))
+                    .with_system(systems::init_rigid_bodies.after(systems::init_templated_bodies))
                     .with_system(systems::init_colliders.after(systems::init_rigid_bodies))
-                    .with_system(systems::simulate_step.after(systems::init_colliders))
-                    .with_system(systems::process_requests.after(systems::simulate_step)),
+                    .with_system(systems::init_joints.after(systems::init_colliders))
+                    .with_system(systems::init_ragdolls.after(systems::init_joints))
+                    .with_system(systems::init_ropes.after(systems::init_ragdolls))
+                    .with_system(systems::init_fluid_volumes.after(systems::init_ropes))
+                    .with_system(systems::send_update_rates.after(systems::init_fluid_volumes))
+                    .with_system(systems::send_priorities.after(systems::send_update_rates))
+                    .with_system(systems::send_focus.after(systems::send_priorities))
+                    .with_system(systems::send_controllers.after(systems::send_focus))
+                    .with_system(systems::send_body_updates.after(systems::send_controllers))
+                    .with_system(systems::send_collider_updates.after(systems::send_body_updates))
+                    .with_system(systems::send_body_commands.after(systems::send_collider_updates))
+                    .with_system(systems::send_body_transforms.after(systems::send_body_commands))
+                    .with_system(systems::send_forces.after(systems::send_body_transforms))
+                    .with_system(systems::simulate_step.after(systems::send_forces))
+                    .with_system(systems::request_ropes.after(systems::simulate_step))
+                    .with_system(systems::request_joint_breaks.after(systems::simulate_step))
+                    .with_system(
+                        systems::request_events
+                            .after(systems::simulate_step)
+                            .before(systems::process_requests),
+                    )
+                    .with_system(
+                        clock::sync_clock
+                            .after(systems::simulate_step)
+                            .before(systems::process_requests),
+                    )
+                    .with_system(
+                        trajectory::request_trajectories
+                            .after(systems::simulate_step)
+                            .before(systems::process_requests),
+                    )
+                    .with_system(systems::send_ray_casts.after(systems::request_ropes))
+                    .with_system(systems::send_shape_queries.after(systems::send_ray_casts))
+                    .with_system(systems::process_requests.after(systems::send_shape_queries))
+                    .with_run_criteria(backend::remote_backend),
             ),
         );
 
         app.add_stage_before(
             PhysicsStage::SyncBackend,
             PhysicsStage::Writeback,
-            SystemStage::parallel().with_system(systems::writeback), //with_run_criteria(FixedTimestep::steps_per_second(1.0))
+            SystemStage::parallel()
+                .with_system(systems::writeback.with_run_criteria(backend::remote_backend)) //with_run_criteria(FixedTimestep::steps_per_second(1.0))
+                .with_system(
+                    validation::send_events
+                        .after(systems::writeback)
+                        .with_run_criteria(backend::remote_backend),
+                )
+                .with_system(
+                    backend::recover_from_reconnect
+                        .after(systems::writeback)
+                        .with_run_criteria(backend::remote_backend),
+                )
+                .with_system(backend::switch_backend.after(backend::recover_from_reconnect))
+                .with_system(backend::control_simulation.after(backend::switch_backend)),
         );
 
-        let url = Url::parse(format!("ws://{}:{}/socket", self.addr, self.port).as_str()).unwrap();
-        let client = PhysicsClient::new(url);
+        // The plain bevy_rapier plugin, stepping the world while the local
+        // backend is switched to
+        app.add_plugin(LocalPhysicsPlugin::default().with_default_system_setup(false))
+            .add_stage_after(
+                CoreStage::Update,
+                PhysicsStages::SyncBackend,
+                local_stage(PhysicsStages::SyncBackend),
+            )
+            .add_stage_after(
+                PhysicsStages::SyncBackend,
+                PhysicsStages::StepSimulation,
+                local_stage(PhysicsStages::StepSimulation),
+            )
+            .add_stage_after(
+                PhysicsStages::StepSimulation,
+                PhysicsStages::Writeback,
+                local_stage(PhysicsStages::Writeback),
+            )
+            .add_stage_before(
+                CoreStage::Last,
+                PhysicsStages::DetectDespawn,
+                local_stage(PhysicsStages::DetectDespawn),
+            );
+        app.add_system_set(
+            SystemSet::new()
+                .with_run_criteria(backend::local_backend)
+                .with_system(backend::apply_body_commands_locally)
+                .with_system(backend::cast_rays_locally)
+                .with_system(backend::query_shapes_locally),
+        );
+        app.add_system(backend::keep_scene);
+        app.add_system_to_stage(
+            CoreStage::Last,
+            systems::send_removals.with_run_criteria(backend::remote_backend),
+        );
+
+        let url = Url::parse(format!("ws://{}/socket", self.host()).as_str()).unwrap();
+        let mut client = PhysicsClient::connect(url, self.room.clone());
+        if let Some(member) = client.room_member() {
+            app.insert_resource(PhysicsIds::starting_at(member << ROOM_MEMBER_ID_SHIFT));
+        }
+        if let Some(threshold) = self.compression_threshold {
+            client.set_compression_threshold(threshold);
+        }
+        if let Some(level) = self.compression_level {
+            client.set_compression_level(level);
+        }
+        let handover = self
+            .handover
+            .map(|(kind, after, duration)| Handover::new(kind, after, duration));
+        if let Some(handover) = handover {
+            client.set_handover(handover);
+        }
+        app.insert_resource(Reconnects(client.reconnects()));
+
+        let mut metadata = self.metadata.clone();
+        let mut server_tick = None;
+        let handshake = Request::Handshake(
+            metadata.clone().unwrap_or_default(),
+            self.framing,
+            self.encoding,
+            self.compression,
+        );
+        match client.send_request(handshake) {
+            Ok(Response::Handshake(server, framing, encoding, compression, capabilities)) => {
+                // Going on would only fail later in ways harder to trace back
+                if let Some(incompatibility) = capabilities.incompatibility() {
+                    panic!(
+                        "Can't use the server at {}: {}",
+                        self.host(),
+                        incompatibility
+                    );
+                }
+                if framing != self.framing {
+                    warn!(
+                        "The server doesn't support {} framing, using {}",
+                        self.framing, framing
+                    );
+                }
+                if encoding != self.encoding {
+                    warn!(
+                        "The server doesn't support {} encoding, using {}",
+                        self.encoding, encoding
+                    );
+                }
+                if compression != self.compression {
+                    warn!(
+                        "The server doesn't support {} compression, using {}",
+                        self.compression, compression
+                    );
+                }
+                if framing != Framing::Binary {
+                    info!("Messages are framed as {}", framing);
+                } else {
+                    if encoding != Encoding::Bincode {
+                        info!("Messages are encoded as {}", encoding);
+                    }
+                    if compression != Compression::None {
+                        info!("Messages are compressed with {}", compression);
+                    }
+                }
+                if let Some(max_bodies) = capabilities.max_bodies {
+                    info!("The server takes up to {} bodies per world", max_bodies);
+                }
+                if !capabilities.queries {
+                    warn!("The server doesn't answer ray casts and shape queries");
+                }
+                if let Some(tick) = capabilities.tick {
+                    info!("The server steps every {:?} on its own", tick);
+                    client.expect_pushes();
+                    server_tick = Some(tick);
+                }
+                if let Some(metadata) = &mut metadata {
+                    metadata.peer = Some(Box::new(server));
+                }
+                app.insert_resource(ServerCapabilities(capabilities));
+            }
+            Ok(_) => error!("Unexpected handshake response"),
+            Err(err) => error!("Handshake failed: {}", err),
+        }
+        if let Some(metadata) = &metadata {
+            for line in metadata.preamble() {
+                info!("{}", line);
+            }
+        }
+        let preamble = metadata
+            .as_ref()
+            .map(RunMetadata::preamble)
+            .unwrap_or_default();
+
+        if self.prewarm {
+            app.insert_resource(ScenePrewarm {
+                profile: self.profile,
+            })
+            .add_startup_system_to_stage(
+                StartupStage::PostStartup,
+                prewarm::prewarm.after(bevy::transform::TransformSystem::TransformPropagate),
+            );
+        }
+
+        if self.calibration {
+            match calibration::calibrate(&mut client) {
+                Ok(calibration) => {
+                    info!(
+                        "Calibrated: {:?} round trips at {}, {:?} steps of {} bodies, {:?} of interpolation delay, {}",
+                        calibration.rtt,
+                        calibration.bandwidth.map_or("too fast to tell".to_string(), |bandwidth| {
+                            format!("{}/s", human_bytes::human_bytes(bandwidth))
+                        }),
+                        calibration.step_time,
+                        calibration::MEASURED_BODIES,
+                        calibration.interpolation_delay,
+                        if calibration.batch_requests {
+                            "batching requests"
+                        } else {
+                            "sending requests on their own"
+                        }
+                    );
+                    app.insert_resource(calibration);
+                }
+                Err(err) => error!("Calibration failed: {}", err),
+            }
+        }
+
+        if let Some(path) = &self.metrics_path {
+            let writer = CsvWriter::open_with_preamble(path, MetricsExport::HEADER, &preamble)
+                .expect("Can't open metrics file");
+            app.insert_resource(MetricsExport {
+                writer,
+                // Subscribed after calibration, whose traffic isn't part of
+                // the metrics
+                stats: client.subscribe_stats(),
+                energy: self.energy_sampling.then(EnergySampler::new),
+                handover,
+                last_export: Instant::now(),
+            })
+            .add_system(systems::export_metrics);
+        }
+
+        if self.diagnostics {
+            app.init_resource::<Diagnostics>()
+                .insert_resource(DiagnosticsStats {
+                    stats: client.subscribe_stats(),
+                    last_measurement: Instant::now(),
+                })
+                .add_startup_system(diagnostics::setup)
+                .add_system_to_stage(
+                    PhysicsStage::SyncBackend,
+                    diagnostics::request_step_time
+                        .after(systems::simulate_step)
+                        .before(systems::process_requests)
+                        .with_run_criteria(backend::remote_backend),
+                )
+                .add_system_to_stage(
+                    PhysicsStage::SyncBackend,
+                    diagnostics::measure
+                        .after(diagnostics::request_step_time)
+                        .after(systems::send_ray_casts)
+                        .before(systems::process_requests)
+                        .with_run_criteria(backend::remote_backend),
+                );
+        }
+
+        if self.console {
+            #[cfg(feature = "console")]
+            app.add_plugin(bevy_egui::EguiPlugin)
+                .insert_resource(ConsoleLog {
+                    server: self.host(),
+                    stats: client.subscribe_stats(),
+                    exchanges: Default::default(),
+                    pending: vec![],
+                    last_response: None,
+                })
+                .add_system_to_stage(
+                    PhysicsStage::SyncBackend,
+                    console::collect
+                        .after(systems::send_ray_casts)
+                        .before(systems::process_requests),
+                )
+                .add_system(console::show);
+            #[cfg(not(feature = "console"))]
+            warn!("The console needs the client built with the console feature");
+        }
+
+        if let Some(frames) = self.watchdog_frames {
+            app.insert_resource(Watchdog {
+                frames,
+                stats: client.subscribe_stats(),
+                control: client.control(),
+                silent_frames: 0,
+                last_response: None,
+            })
+            .add_system_to_stage(
+                PhysicsStage::SyncBackend,
+                watchdog::watch
+                    .after(systems::send_ray_casts)
+                    .before(systems::process_requests)
+                    .with_run_criteria(backend::remote_backend),
+            );
+        }
+
+        if let Some(period) = self.heartbeat_period {
+            app.insert_resource(Heartbeat { period })
+                .add_system_to_stage(
+                    PhysicsStage::SyncBackend,
+                    systems::send_heartbeat
+                        .after(systems::send_shape_queries)
+                        .before(systems::process_requests)
+                        .with_run_criteria(backend::remote_backend),
+                );
+        }
+
+        if self.frame_report {
+            app.insert_resource(FrameReport::new(client.subscribe_stats()))
+                .add_system_to_stage(
+                    CoreStage::Last,
+                    frame_report::count_frame.with_run_criteria(backend::remote_backend),
+                )
+                .add_system_to_stage(
+                    CoreStage::Last,
+                    frame_report::log_on_exit.after(frame_report::count_frame),
+                );
+        }
+
+        if let Some(path) = &self.snapshot_recording_path {
+            let recorder = Recorder::create(path).expect("Can't create snapshot recording");
+            app.insert_resource(SnapshotRecording::new(recorder))
+                .add_system_to_stage(CoreStage::Last, replay::record);
+        }
+
+        if let Some(path) = &self.placement_path {
+            let writer = CsvWriter::open_with_preamble(path, PlacementReport::HEADER, &preamble)
+                .expect("Can't open placement report file");
+            app.insert_resource(PlacementReport {
+                writer,
+                pending: HashMap::new(),
+            })
+            .add_system_to_stage(
+                PhysicsStage::SyncBackend,
+                systems::track_spawns
+                    .before(systems::process_requests)
+                    .with_run_criteria(backend::remote_backend),
+            );
+        }
+
+        if let Some(period) = self.mirror_period {
+            app.insert_resource(MirrorSync {
+                period,
+                last_sync: Instant::now(),
+                bodies: HashMap::new(),
+                colliders: HashMap::new(),
+            })
+            .add_system_to_stage(
+                PhysicsStage::SyncBackend,
+                systems::request_state
+                    .after(systems::simulate_step)
+                    .before(systems::process_requests)
+                    .with_run_criteria(backend::remote_backend),
+            );
+        }
+
+        if let Some(period) = self.compaction_period {
+            app.insert_resource(WorldCompaction {
+                period,
+                last: Instant::now(),
+            })
+            .add_system_to_stage(
+                PhysicsStage::SyncBackend,
+                systems::compact_world
+                    .after(systems::send_ray_casts)
+                    .before(systems::process_requests)
+                    .with_run_criteria(backend::remote_backend),
+            );
+        }
+
+        // After the calibration, so that pushes don't skew its round trips
+        if let Some(period) = self.streaming {
+            match client.send_request(Request::SetStreaming(Some(period))) {
+                Ok(Response::StreamingSet) => info!("The server pushes a step every {:?}", period),
+                Ok(_) => error!("Unexpected streaming response"),
+                Err(err) => error!("Failed to start streaming: {}", err),
+            }
+        }
+        let pushed = PushedResults {
+            results: client.pushed(),
+            // Steps aren't asked for either when the server ticks on its own
+            streaming: self.streaming.is_some() || server_tick.is_some(),
+        };
         let wrapper = PhysicsClientWrapper(Arc::new(Mutex::new(client)));
-        app.insert_resource(wrapper);
+        let result = RequestResult::default();
+        let window = RequestWindow {
+            max_in_flight: self.max_in_flight,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            in_flight_frames: Arc::new(Mutex::new(vec![])),
+            batch_done: Arc::new(Condvar::new()),
+            channel_in_flight: Arc::new(Default::default()),
+            channel_limits: self.channel_limits,
+            barrier: false,
+            frames: 0,
+            in_flight_total: 0,
+            full_frames: 0,
+            last_sent: Instant::now(),
+        };
+        let (sender, batches) = mpsc::channel();
+        let (client, responses, in_flight, in_flight_frames, batch_done, channel_in_flight) = (
+            wrapper.0.clone(),
+            result.0.clone(),
+            window.in_flight.clone(),
+            window.in_flight_frames.clone(),
+            window.batch_done.clone(),
+            window.channel_in_flight.clone(),
+        );
+        thread::spawn(move || {
+            systems::send_batches(
+                client,
+                batches,
+                responses,
+                in_flight,
+                in_flight_frames,
+                batch_done,
+                channel_in_flight,
+            )
+        });
+
+        app.insert_resource(wrapper)
+            .insert_resource(result)
+            .insert_resource(pushed)
+            .insert_resource(window)
+            .insert_resource(RequestSender(Mutex::new(sender)));
+    }
+}
+
+/// A stage of the local backend, skipped while the remote one runs.
+fn local_stage(stage: PhysicsStages) -> SystemStage {
+    SystemStage::parallel().with_system_set(
+        LocalPhysicsPlugin::get_systems(stage).with_run_criteria(backend::local_backend),
+    )
+}
+
+/// The content hash of the client's copy of a scene, if it has one.
+fn local_scene_hash(name: &str) -> Option<u64> {
+    let path = FileAssetIo::get_base_path()
+        .join("assets/scenes")
+        .join(name)
+        .with_extension("ron");
+    match std::fs::read(&path) {
+        Ok(contents) => Some(content_hash(&contents)),
+        Err(_) => {
+            warn!(
+                "No local copy of scene {} to verify the server's against",
+                name
+            );
+            None
+        }
     }
 }
 
 #[derive(Resource, Default)]
 
 pub struct RequestQueue(pub Vec<Request>);
+
+/// Forces, impulses and velocity changes gameplay code wants applied to
+/// bodies on the server.
+#[derive(Resource, Default)]
+pub struct BodyCommands(pub Vec<(Entity, BodyCommand)>);
+
+/// Fields of the configuration gameplay code wants changed without sending
+/// all of it, merged across the systems patching it during a frame. They are
+/// applied to the local `RapierConfiguration` too.
+#[derive(Resource, Default)]
+pub struct ConfigPatches(pub ConfigPatch);
+
+/// Positions gameplay code wants bodies moved to on the server, reached by
+/// kinematic position-based bodies at the next step and teleported to by the
+/// others.
+#[derive(Resource, Default)]
+pub struct BodyTransforms(pub Vec<(Entity, Transform)>);
+
+/// Commands targeting entities whose body hasn't been created on the server
+/// yet, sent as soon as its handle arrives.
+#[derive(Resource, Default)]
+pub struct PendingBodyCommands(pub HashMap<Entity, Vec<BodyCommand>>);
+
+/// Sent once the server has created an entity's body or collider, i.e. once
+/// its `RapierRigidBodyHandle` or `RapierColliderHandle` is inserted. Until then
+/// the server doesn't know about the entity and anything applied to it is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteReady {
+    Body(Entity),
+    Collider(Entity),
+}
+
+/// Keeps track of the body archetypes seen so far, so that repeated spawns
+/// of the same body+collider combination can be sent as template instances.
+#[derive(Resource, Default)]
+pub struct TemplateRegistry {
+    pub enabled: bool,
+    /// Number of times each serialized archetype has been spawned.
+    pub seen: HashMap<Vec<u8>, u32>,
+    /// Template ids of the archetypes registered on the server.
+    pub templates: HashMap<Vec<u8>, u64>,
+    /// Entities sent as template instances this frame.
+    pub claimed: HashSet<Entity>,
+    pub next_id: u64,
+}
+
+/// Appends one row of traffic statistics per second to a CSV file.
+#[derive(Resource)]
+pub struct MetricsExport {
+    pub writer: CsvWriter,
+    pub stats: Arc<Mutex<RequestStats>>,
+    pub energy: Option<EnergySampler>,
+    pub handover: Option<Handover>,
+    pub last_export: Instant,
+}
+
+impl MetricsExport {
+    pub const HEADER: &'static [&'static str] = &[
+        "timestamp",
+        "requests",
+        "rtt_p50_ms",
+        "rtt_p95_ms",
+        "rtt_p99_ms",
+        "rtt_max_ms",
+        "bytes_sent",
+        "bytes_received",
+        "bodies",
+        "cpu_ms",
+        "energy_j",
+        "in_flight_mean",
+        "window_full_frames",
+        "handover",
+        "server_degradation",
+    ];
+}
+
+/// Keeps an entity's body and collider off the server, for gizmos, ghosts
+/// and purely cosmetic colliders. They are only simulated while the local
+/// backend runs.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct LocalPhysicsOnly;
+
+/// What the bodies' states from the server are written to, for every body
+/// as a resource and for single bodies as a component overriding it. The local
+/// backend always writes `Transform` and `Velocity`.
+#[derive(Component, Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WritebackTarget {
+    /// `Transform` and `Velocity`, like bevy_rapier does.
+    #[default]
+    Transform,
+    /// `RemotePhysicsPose`, inserted if missing, leaving `Transform` to games
+    /// running their own smoothing.
+    Pose,
+    /// Nothing but `RemotePoseUpdated` events.
+    Events,
+}
+
+/// The latest state of the body on the server, written instead of
+/// `Transform` and `Velocity` by `WritebackTarget::Pose`.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq)]
+pub struct RemotePhysicsPose {
+    pub transform: Transform,
+    pub velocity: Velocity,
+}
+
+/// A body's state from the server, sent for bodies written back with
+/// `WritebackTarget::Events`.
+#[derive(Debug, Clone, Copy)]
+pub struct RemotePoseUpdated {
+    pub entity: Entity,
+    pub transform: Transform,
+    pub velocity: Velocity,
+}
+
+/// How important a body's updates are when the server's snapshot budget can't
+/// fit every body, 1 by default.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotPriority(pub f32);
+
+/// Marks the entity, usually the camera, that bodies closer to are updated
+/// first when the server's snapshot budget can't fit every body.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct SnapshotFocus;
+
+/// Builds a ragdoll on the server from the entity's transform. Every bone gets
+/// an entity of its own, with a `RagdollBone` pointing back here.
+#[derive(Component, Debug, Clone)]
+pub struct Ragdoll(pub Skeleton);
+
+#[derive(Component, Debug, Clone, Copy)]
+pub struct RagdollBone {
+    pub ragdoll: Entity,
+    /// The index of the bone in the ragdoll's skeleton.
+    pub index: usize,
+}
+
+/// A rope created on the server, stretched from `start` to `end`. Its joint
+/// positions are written to `RopePoints` every frame. Anchors tie it to bodies
+/// by the bits of their entities, sent as their physics ids.
+#[derive(Component, Debug, Clone)]
+pub struct Rope {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub start_anchor: RopeAnchor,
+    pub end_anchor: RopeAnchor,
+    pub segments: u32,
+    pub radius: Real,
+    pub stiffness: Real,
+    pub break_force: Option<Real>,
+}
+
+/// The joint positions of a rope from start to end, in world space.
+#[derive(Component, Debug, Clone, Default)]
+pub struct RopePoints(pub Vec<Vec3>);
+
+/// Ray casts waiting to be sent to the server.
+#[derive(Resource, Default)]
+pub struct RemoteRayCasts {
+    pub rays: Vec<RayCast>,
+    /// Sent one by one, as the server answers them without their id.
+    pub filtered_rays: Vec<(RayCast, SerializableQueryFilter)>,
+    /// The ids of the filtered rays sent, in the order of the answers.
+    pub pending: VecDeque<u64>,
+    pub next_id: u64,
+}
+
+/// The server's answer to a ray cast issued with `QueryMode::Remote`.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteRayHit {
+    pub id: u64,
+    pub hit: Option<(Entity, Real)>,
+    /// The normal at the hit, only known for rays cast with a filter.
+    pub normal: Option<Vect>,
+}
+
+/// Shape casts and intersection tests against the server's world waiting to
+/// be sent, answered a roundtrip later with `RemoteShapeHit` and
+/// `RemoteShapeIntersections` events carrying the id they were issued with.
+#[derive(Resource, Default)]
+pub struct RemoteShapeQueries {
+    /// `Request::CastShape` and `Request::IntersectionsWithShape`, by id.
+    pub queries: Vec<(u64, Request)>,
+    /// The ids of the queries sent, in the order of the answers.
+    pub pending: VecDeque<u64>,
+    pub next_id: u64,
+}
+
+impl RemoteShapeQueries {
+    pub fn cast_shape(
+        &mut self,
+        shape_pos: Vect,
+        shape_rot: Quat,
+        shape_vel: Vect,
+        shape: &Collider,
+        max_toi: Real,
+        filter: QueryFilter,
+    ) -> u64 {
+        self.push(Request::CastShape {
+            shape_pos,
+            shape_rot,
+            shape_vel,
+            shape: shape.raw.clone(),
+            max_toi,
+            filter: filter.into(),
+        })
+    }
+
+    pub fn intersections_with_shape(
+        &mut self,
+        shape_pos: Vect,
+        shape_rot: Quat,
+        shape: &Collider,
+        filter: QueryFilter,
+    ) -> u64 {
+        self.push(Request::IntersectionsWithShape {
+            shape_pos,
+            shape_rot,
+            shape: shape.raw.clone(),
+            filter: filter.into(),
+        })
+    }
+
+    fn push(&mut self, request: Request) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queries.push((id, request));
+        id
+    }
+}
+
+/// The server's answer to `RemoteShapeQueries::cast_shape`.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteShapeHit {
+    pub id: u64,
+    pub hit: Option<(Entity, ShapeCastHit)>,
+}
+
+/// The server's answer to `RemoteShapeQueries::intersections_with_shape`.
+#[derive(Debug, Clone)]
+pub struct RemoteShapeIntersections {
+    pub id: u64,
+    pub entities: Vec<Entity>,
+}
+
+/// A ragdoll or rope joint the server broke, between the bodies of the given
+/// entities. Rope joints are reported with the rope's entity on both sides.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteJointBreak {
+    pub entities: (Entity, Entity),
+    pub force: Real,
+}
+
+/// The entities of every pair of colliders where a sensor intersects the
+/// other one on the server, as of the latest step.
+#[derive(Resource, Debug, Default)]
+pub struct RemoteIntersections(pub HashSet<(Entity, Entity)>);
+
+/// A sensor started or stopped intersecting another collider on the server.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteIntersection {
+    pub entities: (Entity, Entity),
+    pub intersecting: bool,
+}
+
+/// The scales the shapes of colliders were last sent to the server with, so
+/// that they are sent again when their scale changes.
+#[derive(Resource, Debug, Default)]
+pub struct SentColliderScales(pub HashMap<Entity, Vect>);
+
+/// The physics ids the entities sent to the server go by there, in place of
+/// the bits of the entities. Ids are never given twice, so what the server
+/// says about an entity that is gone can't be taken for one spawned in its
+/// slot.
+#[derive(Resource, Debug, Default)]
+pub struct PhysicsIds {
+    ids: IdMap<Entity>,
+    next: u64,
+}
+
+impl PhysicsIds {
+    /// Giving ids from `next` on, as members of a room do not to collide with
+    /// the others.
+    pub fn starting_at(next: u64) -> Self {
+        Self {
+            ids: IdMap::default(),
+            next,
+        }
+    }
+
+    /// The id of the entity, given one if it doesn't have one yet.
+    pub fn assign(&mut self, entity: Entity) -> PhysicsId {
+        if let Some(id) = self.ids.id(entity) {
+            return id;
+        }
+        let id = PhysicsId(self.next);
+        self.next += 1;
+        self.ids.insert(id, entity);
+        id
+    }
+
+    pub fn id(&self, entity: Entity) -> Option<PhysicsId> {
+        self.ids.id(entity)
+    }
+
+    /// The entity of an id the server sent, `None` once it's released. Scene
+    /// colliders, which have no entity, keep being reported as the entity of
+    /// `SCENE_ENTITY`'s bits.
+    pub fn entity(&self, id: u64) -> Option<Entity> {
+        if id == SCENE_ENTITY {
+            return Some(Entity::from_bits(id));
+        }
+        self.ids.get(PhysicsId(id))
+    }
+
+    /// Forgets the id of an entity the server no longer has anything of.
+    pub fn release(&mut self, entity: Entity) -> Option<PhysicsId> {
+        self.ids.remove_value(entity)
+    }
+
+    /// The filter with the entities it excludes as their ids, `None` for
+    /// those without any as the server has nothing of them to exclude.
+    pub fn filter(&self, filter: SerializableQueryFilter) -> SerializableQueryFilter {
+        let id = |bits| self.id(Entity::from_bits(bits)).map(|id| id.0);
+        SerializableQueryFilter {
+            exclude_collider: filter.exclude_collider.and_then(id),
+            exclude_rigid_body: filter.exclude_rigid_body.and_then(id),
+            ..filter
+        }
+    }
+
+    /// The anchor with the body it's tied to as its id.
+    pub fn anchor(&mut self, anchor: RopeAnchor) -> RopeAnchor {
+        match anchor {
+            RopeAnchor::Body { id, offset } => RopeAnchor::Body {
+                id: self.assign(Entity::from_bits(id)).0,
+                offset,
+            },
+            anchor => anchor,
+        }
+    }
+}
+
+/// The response of one of the worlds the application created next to the one
+/// mirrored into the `RapierContext`, left for it to handle.
+#[derive(Debug, Clone)]
+pub struct RemoteWorldResponse {
+    pub world_id: u64,
+    pub response: Response,
+}
+
+/// The static colliders the server created from the scene it was asked to load.
+#[derive(Debug, Clone)]
+pub struct RemoteScene {
+    pub colliders: Vec<(ColliderHandle, SceneCollider)>,
+}
+
+/// Sent instead of `RemoteScene` when the server refused to load its version
+/// of the scene for differing from the client's copy, for the application to
+/// e.g. upload its own level instead.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteSceneMismatch {
+    /// The content hash of the client's copy.
+    pub expected: u64,
+    /// The content hash of the server's.
+    pub actual: u64,
+}
+
+/// How the server lowered the quality of its responses to keep up.
+#[derive(Resource, Debug, Default)]
+pub struct RemoteDegradation {
+    /// Of the latest step.
+    pub latest: Degradation,
+    /// Everything degraded since the last metrics row.
+    pub since_export: Degradation,
+    /// Of the response being handled.
+    pub current: Degradation,
+}
+
+impl RemoteDegradation {
+    pub fn step_received(&mut self) {
+        if self.current != self.latest {
+            info!("Server degradation: {}", self.current);
+        }
+        self.latest = self.current;
+    }
+}
+
+/// What the next full state the server sends is for, besides the mirror.
+#[derive(Resource, Debug, Default)]
+pub struct StateRequests {
+    /// Snaps every body to the server's state, including those its snapshot
+    /// budget or update rate left behind.
+    pub resync: bool,
+    /// Writes the state to `snapshot_<timestamp>.ron`.
+    pub export: bool,
+}
+
+/// Pauses, resumes and restarts the world of either backend, so that a level
+/// can be restarted without reconnecting.
+#[derive(Resource, Debug, Default)]
+pub struct SimulationControl {
+    pub state: SimulationState,
+    /// Drops the world, the bodies and colliders of the entities left being
+    /// created again. Set back once done.
+    pub reset: bool,
+}
+
+/// The latest copy of the server's world taken with `Request::TakeSnapshot`,
+/// for `Request::RestoreSnapshot` to load back.
+#[derive(Resource, Debug, Default)]
+pub struct SavedWorld(pub Option<WorldSnapshot>);
+
+/// Periodically has the server compact the world, so that long sessions that
+/// create and remove many bodies don't leave its handle arenas fragmented.
+#[derive(Resource)]
+pub struct WorldCompaction {
+    pub period: Duration,
+    pub last: Instant,
+}
+
+/// How long the client can go without sending a request before it pings the
+/// server.
+#[derive(Resource)]
+pub struct Heartbeat {
+    pub period: Duration,
+}
+
+impl Heartbeat {
+    pub const DEFAULT_PERIOD: Duration = Duration::from_secs(5);
+}
+
+/// Periodically mirrors the server's world into the client's `RapierContext`.
+#[derive(Resource)]
+pub struct MirrorSync {
+    pub period: Duration,
+    pub last_sync: Instant,
+    /// Maps the server's body handles to the mirror's.
+    pub bodies: HashMap<RigidBodyHandle, RigidBodyHandle>,
+    /// Maps the server's collider handles to the mirror's.
+    pub colliders: HashMap<ColliderHandle, ColliderHandle>,
+}
+
+/// Records how far every spawned body is from its intended position in the
+/// first snapshot the server sends back for it.
+#[derive(Resource)]
+pub struct PlacementReport {
+    pub writer: CsvWriter,
+    /// Intended position, spawn time and frames waited of bodies that haven't
+    /// been in a snapshot yet.
+    pub pending: HashMap<Entity, (Vec3, Instant, u32)>,
+}
+
+impl PlacementReport {
+    pub const HEADER: &'static [&'static str] =
+        &["timestamp", "entity", "elapsed_ms", "frames", "error_m"];
+}
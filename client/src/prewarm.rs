@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use shared::{profile::Profile, Request, Response};
+
+use crate::plugin::{
+    LocalPhysicsOnly, PhysicsClientWrapper, PhysicsIds, RemoteReady, SentColliderScales,
+};
+use crate::systems::{self, ColliderComponents};
+
+/// Static colliders sent per request while prewarming, so that progress can
+/// be reported on large levels.
+const CHUNK_SIZE: usize = 64;
+
+/// Uploads the static colliders that startup systems spawned before the first
+/// frame, so that bodies don't fall through a level still on its way to the
+/// server. Colliders of bodies are left to the first frames.
+#[derive(Resource)]
+pub struct ScenePrewarm {
+    /// Sent first, as it decides the restitution of colliders that don't set
+    /// their own.
+    pub profile: Option<Profile>,
+}
+
+/// How many of the static colliders the prewarm uploaded, sent after every
+/// request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrewarmProgress {
+    pub sent: usize,
+    pub total: usize,
+}
+
+/// Runs after startup, once the transforms of the level are propagated.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn prewarm(
+    mut commands: Commands,
+    prewarm: Res<ScenePrewarm>,
+    context: Res<RapierContext>,
+    client: Res<PhysicsClientWrapper>,
+    colliders: Query<
+        (ColliderComponents, Option<&GlobalTransform>),
+        (
+            Without<RapierColliderHandle>,
+            Without<RigidBody>,
+            Without<LocalPhysicsOnly>,
+        ),
+    >,
+    parents: Query<&Parent>,
+    bodies: Query<Option<&GlobalTransform>, With<RigidBody>>,
+    mut ready: EventWriter<RemoteReady>,
+    mut progress: EventWriter<PrewarmProgress>,
+    mut sent_scales: ResMut<SentColliderScales>,
+    mut ids: ResMut<PhysicsIds>,
+) {
+    let physics_scale = context.physics_scale();
+    let created: Vec<_> = colliders
+        .iter()
+        .filter(|(components, _)| systems::owning_body(components.0, &parents, &bodies).is_none())
+        .map(|(components, transform)| {
+            sent_scales.0.insert(
+                components.0,
+                systems::collider_scale(transform, components.2),
+            );
+            systems::created_collider(components, transform, None, physics_scale, &mut ids)
+        })
+        .collect();
+
+    let mut client = client.0.lock().unwrap();
+    if let Some(profile) = prewarm.profile {
+        if let Err(err) = client.send_request(Request::UseProfile(profile)) {
+            error!("Failed to use profile: {}", err);
+        }
+    }
+
+    let total = created.len();
+    let mut sent = 0;
+    for chunk in created.chunks(CHUNK_SIZE) {
+        match client.send_request(Request::CreateColliders(chunk.to_vec())) {
+            Ok(resp @ Response::ColliderHandles(_)) => {
+                systems::handle_init_colliders_response(Ok(resp), &mut commands, &mut ready, &ids);
+            }
+            Ok(resp) => {
+                error!("Unexpected prewarm response <{}>", resp.name());
+                break;
+            }
+            // The rest is sent in the first frames
+            Err(err) => {
+                error!("Failed to prewarm static colliders: {}", err);
+                break;
+            }
+        }
+        sent += chunk.len();
+        progress.send(PrewarmProgress { sent, total });
+        debug!("Prewarmed {}/{} static colliders", sent, total);
+    }
+    info!("Prewarmed {}/{} static colliders", sent, total);
+}
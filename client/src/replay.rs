@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use shared::recording::{read_recording, shape_mesh, Recorder, ReplayFrame, ReplayShape};
+
+/// Records what every rendered frame shows of the bodies and colliders, for
+/// `ReplayPlugin` to render again without a server.
+#[derive(Resource)]
+pub struct SnapshotRecording {
+    pub recorder: Recorder,
+    pub tick: u64,
+    /// Entities whose shape was recorded, whose despawning is recorded too.
+    pub recorded: HashSet<u64>,
+}
+
+impl SnapshotRecording {
+    pub fn new(recorder: Recorder) -> Self {
+        Self {
+            recorder,
+            tick: 0,
+            recorded: HashSet::new(),
+        }
+    }
+}
+
+/// Runs last in the frame, once the snapshot it shows is written back.
+#[allow(clippy::type_complexity)]
+pub fn record(
+    mut recording: ResMut<SnapshotRecording>,
+    new_colliders: Query<
+        (
+            Entity,
+            &Collider,
+            &Transform,
+            &GlobalTransform,
+            Option<&Parent>,
+            Option<&Handle<StandardMaterial>>,
+        ),
+        Added<RapierColliderHandle>,
+    >,
+    bodies: Query<(), With<RapierRigidBodyHandle>>,
+    moved: Query<
+        (Entity, &Transform),
+        (
+            With<RapierRigidBodyHandle>,
+            Or<(Changed<Transform>, Added<RapierRigidBodyHandle>)>,
+        ),
+    >,
+    materials: Res<Assets<StandardMaterial>>,
+    removed_colliders: RemovedComponents<Collider>,
+    removed_bodies: RemovedComponents<RigidBody>,
+) {
+    let tick = recording.tick;
+    recording.tick += 1;
+
+    let spawned: Vec<ReplayShape> = new_colliders
+        .iter()
+        .map(|(entity, collider, transform, global, parent, material)| {
+            let (parent, transform) = if bodies.contains(entity) {
+                (Some(entity), Transform::IDENTITY)
+            } else {
+                match parent.filter(|parent| bodies.contains(parent.get())) {
+                    Some(parent) => (Some(parent.get()), *transform),
+                    None => (None, global.compute_transform()),
+                }
+            };
+            ReplayShape {
+                id: entity.to_bits(),
+                parent: parent.map(Entity::to_bits),
+                transform,
+                shape: collider.clone(),
+                color: material
+                    .and_then(|material| materials.get(material))
+                    .map_or(Color::GRAY, |material| material.base_color),
+            }
+        })
+        .collect();
+    let moved: Vec<(u64, Transform)> = moved
+        .iter()
+        .map(|(entity, transform)| (entity.to_bits(), *transform))
+        .collect();
+    let despawned: Vec<u64> = removed_colliders
+        .iter()
+        .chain(removed_bodies.iter())
+        .map(Entity::to_bits)
+        .filter(|id| recording.recorded.remove(id))
+        .collect();
+    recording.recorded.extend(
+        spawned
+            .iter()
+            .flat_map(|shape| [Some(shape.id), shape.parent])
+            .flatten(),
+    );
+
+    if spawned.is_empty() && moved.is_empty() && despawned.is_empty() {
+        return;
+    }
+    let frame = ReplayFrame {
+        tick,
+        spawned,
+        moved,
+        despawned,
+    };
+    if let Err(err) = recording.recorder.record(&frame) {
+        error!("Failed to record snapshot: {}", err);
+    }
+}
+
+/// Renders a recording made with `with_snapshot_recording`, one recorded frame
+/// per rendered frame, without connecting to a server. Bodies are drawn as the
+/// shapes of their colliders, in the base color of their material.
+pub struct ReplayPlugin {
+    path: String,
+}
+
+impl ReplayPlugin {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let frames: VecDeque<ReplayFrame> = read_recording(&self.path)
+            .expect("Can't read recording")
+            .into();
+        info!(
+            "Loaded {} frames over {} ticks from {}",
+            frames.len(),
+            frames.back().map_or(0, |frame| frame.tick + 1),
+            self.path
+        );
+
+        app.insert_resource(Replay {
+            frames,
+            tick: 0,
+            bodies: HashMap::new(),
+            shapes: HashMap::new(),
+        })
+        .add_system(play);
+    }
+}
+
+#[derive(Resource)]
+struct Replay {
+    frames: VecDeque<ReplayFrame>,
+    tick: u64,
+    bodies: HashMap<u64, Entity>,
+    /// With the body they are attached to.
+    shapes: HashMap<u64, (Entity, Option<u64>)>,
+}
+
+fn play(
+    mut commands: Commands,
+    mut replay: ResMut<Replay>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut transforms: Query<&mut Transform>,
+) {
+    let tick = replay.tick;
+    replay.tick += 1;
+
+    while replay
+        .frames
+        .front()
+        .is_some_and(|frame| frame.tick <= tick)
+    {
+        let frame = replay.frames.pop_front().unwrap();
+        let replay = &mut *replay;
+
+        let despawned: HashSet<u64> = frame.despawned.into_iter().collect();
+        for id in &despawned {
+            if let Some((entity, parent)) = replay.shapes.remove(id) {
+                // Shapes of despawned bodies go with them
+                let with_body = parent.is_some_and(|parent| {
+                    despawned.contains(&parent) && replay.bodies.contains_key(&parent)
+                });
+                if !with_body {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+        }
+        for id in &despawned {
+            if let Some(entity) = replay.bodies.remove(id) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+
+        // Bodies first seen here are spawned where they are
+        for (id, transform) in frame.moved {
+            match replay.bodies.get(&id) {
+                Some(&entity) => {
+                    if let Ok(mut current) = transforms.get_mut(entity) {
+                        *current = transform;
+                    }
+                }
+                None => {
+                    let entity = commands
+                        .spawn(SpatialBundle::from_transform(transform))
+                        .id();
+                    replay.bodies.insert(id, entity);
+                }
+            }
+        }
+
+        // Colliders get new handles when the backend switches, so a shape can
+        // be recorded again
+        for shape in frame.spawned {
+            if let Some((entity, _)) = replay.shapes.remove(&shape.id) {
+                commands.entity(entity).despawn_recursive();
+            }
+            let entity = commands
+                .spawn(PbrBundle {
+                    mesh: meshes.add(shape_mesh(&shape.shape)),
+                    material: materials.add(StandardMaterial {
+                        base_color: shape.color,
+                        perceptual_roughness: 0.6,
+                        ..default()
+                    }),
+                    transform: shape.transform,
+                    ..default()
+                })
+                .id();
+            replay.shapes.insert(shape.id, (entity, shape.parent));
+
+            if let Some(parent) = shape.parent {
+                let body = *replay
+                    .bodies
+                    .entry(parent)
+                    .or_insert_with(|| commands.spawn(SpatialBundle::default()).id());
+                commands.entity(body).add_child(entity);
+            }
+        }
+    }
+}
@@ -1,13 +1,46 @@
-use std::thread;
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
 
-use bevy::prelude::*;
+use bevy::{diagnostic::Diagnostics, ecs::system::SystemParam, prelude::*, utils::Instant};
 use bevy_rapier3d::prelude::*;
 
 use bevy_rapier3d::plugin::systems::RigidBodyWritebackComponents;
+use bevy_rapier3d::rapier::prelude::{CollisionEventFlags, RigidBodyHandle};
 
+use crate::bulk;
+#[cfg(not(feature = "bulk-requests"))]
+use crate::calibration::Calibration;
+use crate::client::PhysicsClient;
+use crate::clock::ClockSync;
+use crate::diagnostics;
 use crate::error::Result;
-use crate::plugin::{PhysicsClientWrapper, RequestQueue, RequestResult};
-use shared::*;
+use crate::frame_report::FrameReport;
+use crate::mirror;
+use crate::plugin::{
+    BodyCommands, BodyTransforms, ConfigPatches, Heartbeat, LocalPhysicsOnly, MetricsExport,
+    MirrorSync, PendingBodyCommands, PhysicsIds, PlacementReport, PushedResults, Ragdoll,
+    RagdollBone, RemoteDegradation, RemoteIntersection, RemoteIntersections, RemoteJointBreak,
+    RemotePhysicsPose, RemotePoseUpdated, RemoteRayCasts, RemoteRayHit, RemoteReady, RemoteScene,
+    RemoteSceneMismatch, RemoteShapeHit, RemoteShapeIntersections, RemoteShapeQueries,
+    RemoteWorldResponse, RequestQueue, RequestResult, RequestSender, RequestWindow, Rope,
+    RopePoints, SavedWorld, SentColliderScales, SnapshotFocus, SnapshotPriority, StateRequests,
+    TemplateRegistry, WorldCompaction, WritebackTarget,
+};
+use crate::trajectory::RemoteTrajectories;
+use crate::validation::{Corruption, ResultValidation};
+use shared::{
+    arena::CompactedWorld, channel::Channel, clock::TimeSample, degradation::Degradation,
+    hooks::ContactRules, metrics::*, operator::SessionStatus, ragdoll::CreatedRagdoll,
+    rope::CreatedRope, scene::SceneError, serializable::SerializableJoint, *,
+};
 
 pub type RigidBodyComponents<'a> = (
     Entity,
@@ -15,27 +48,60 @@ pub type RigidBodyComponents<'a> = (
     Option<&'a GlobalTransform>,
     Option<&'a Velocity>,
     Option<&'a AdditionalMassProperties>,
+    Option<&'a Damping>,
+    Option<&'a GravityScale>,
+    Option<&'a Ccd>,
+    Option<&'a Dominance>,
+    Option<&'a LockedAxes>,
+    Option<&'a Tag>,
 );
 
 pub type ColliderComponents<'a> = (
     Entity,
     &'a Collider,
+    Option<&'a ColliderScale>,
     Option<&'a Sensor>,
     Option<&'a ColliderMassProperties>,
     Option<&'a Friction>,
     Option<&'a Restitution>,
+    Option<&'a CollisionGroups>,
+    Option<&'a SolverGroups>,
+    Option<&'a ActiveEvents>,
+    Option<&'a ActiveHooks>,
+    Option<&'a ActiveCollisionTypes>,
+    Option<&'a ContactForceEventThreshold>,
 );
 
-pub fn update_config(config: Res<RapierConfiguration>, mut request_queue: ResMut<RequestQueue>) {
-    if !config.is_changed() {
+pub fn update_config(
+    config: Res<RapierConfiguration>,
+    contact_rules: Res<ContactRules>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if !config.is_changed() && !contact_rules.is_changed() {
         return;
     }
 
-    let req = Request::UpdateConfig(config.clone().into());
+    let req = Request::UpdateConfig((*config).into(), contact_rules.clone());
 
     request_queue.0.push(req);
 }
 
+/// Sends the fields of the configuration patched during the frame, changing
+/// the local configuration without `update_config` sending all of it.
+pub fn send_config_patches(
+    mut patches: ResMut<ConfigPatches>,
+    mut config: ResMut<RapierConfiguration>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if patches.0.is_empty() {
+        return;
+    }
+
+    let patch = mem::take(&mut patches.0);
+    patch.clone().apply(config.bypass_change_detection());
+    request_queue.0.push(Request::PatchConfig(patch));
+}
+
 fn handle_update_config_response(resp: Result<Response>) {
     if let Err(err) = resp {
         error!("Failed to update config: {}", err);
@@ -46,224 +112,2350 @@ fn handle_update_config_response(resp: Result<Response>) {
     }
 }
 
+#[allow(clippy::type_complexity)]
+pub fn init_templated_bodies(
+    context: Res<RapierContext>,
+    bodies: Query<
+        (RigidBodyComponents, ColliderComponents),
+        (
+            Without<RapierRigidBodyHandle>,
+            Without<RapierColliderHandle>,
+            Without<LocalPhysicsOnly>,
+        ),
+    >,
+    mut registry: ResMut<TemplateRegistry>,
+    mut sent_scales: ResMut<SentColliderScales>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    registry.claimed.clear();
+
+    if !registry.enabled {
+        return;
+    }
+
+    let mut new_templates = vec![];
+    let mut instances = vec![];
+
+    let physics_scale = context.physics_scale();
+
+    for (
+        (
+            entity,
+            rb,
+            transform,
+            velocity,
+            additional_mass_properties,
+            damping,
+            gravity_scale,
+            ccd,
+            dominance,
+            locked_axes,
+            tag,
+        ),
+        (
+            _,
+            shape,
+            custom_scale,
+            sensor,
+            mprops,
+            friction,
+            restitution,
+            groups,
+            solver_groups,
+            active_events,
+            active_hooks,
+            collision_types,
+            threshold,
+        ),
+    ) in bodies.iter()
+    {
+        let scale = collider_scale(transform, custom_scale);
+        let template = BodyTemplate {
+            body: *rb,
+            additional_mass_properties: additional_mass_properties.map(|mprops| (*mprops).into()),
+            damping: damping.map(|damping| (*damping).into()),
+            gravity_scale: gravity_scale.map(|scale| scale.0),
+            ccd: ccd.is_some_and(|ccd| ccd.enabled),
+            dominance_group: dominance.map(|dominance| dominance.groups),
+            locked_axes: locked_axes.map(|axes| axes.bits()),
+            shape: shape.clone(),
+            scale: sent_scale(scale),
+            sensor: sensor.map(|sensor| (*sensor).into()),
+            mass_properties: mprops.map(|mprops| (*mprops).into()),
+            friction: friction.map(|friction| (*friction).into()),
+            restitution: restitution.map(|restitution| (*restitution).into()),
+            collision_groups: groups.map(|groups| (*groups).into()),
+            solver_groups: solver_groups.map(|groups| (*groups).into()),
+            active_events: active_events.copied(),
+            active_hooks: active_hooks.map(|hooks| (*hooks).into()),
+            active_collision_types: collision_types.map(|types| (*types).into()),
+            contact_force_event_threshold: threshold.map(|threshold| threshold.0),
+        };
+
+        // The serialized template doubles as the archetype key
+        let key = match bincode::serialize(&template) {
+            Ok(key) => key,
+            Err(err) => {
+                error!("Failed to serialize body template: {}", err);
+                continue;
+            }
+        };
+
+        let count = {
+            let count = registry.seen.entry(key.clone()).or_insert(0);
+            *count += 1;
+            *count
+        };
+
+        // Only archetypes spawned more than once are worth a template
+        if count < 2 {
+            continue;
+        }
+
+        let template_id = match registry.templates.get(&key) {
+            Some(&template_id) => template_id,
+            None => {
+                let template_id = registry.next_id;
+                registry.next_id += 1;
+                registry.templates.insert(key, template_id);
+                new_templates.push((template_id, template));
+                template_id
+            }
+        };
+
+        instances.push(TemplateInstance {
+            id: ids.assign(entity).0,
+            template_id,
+            transform: transform
+                .map(|transform| {
+                    shared::transform_to_iso(&transform.compute_transform(), physics_scale)
+                })
+                .unwrap_or_default(),
+            velocity: velocity.copied(),
+            tag: tag.map(|tag| tag.as_str().to_string()),
+        });
+        registry.claimed.insert(entity);
+        sent_scales.0.insert(entity, scale);
+    }
+
+    if !new_templates.is_empty() {
+        request_queue
+            .0
+            .push(Request::RegisterTemplates(new_templates));
+    }
+
+    if !instances.is_empty() {
+        request_queue.0.push(Request::SpawnInstances(instances));
+    }
+}
+
+fn handle_register_templates_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to register templates: {}", err);
+    } else if let Ok(Response::TemplatesRegistered) = resp {
+        debug!("Templates registered");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+fn handle_spawn_instances_response(
+    resp: Result<Response>,
+    commands: &mut Commands,
+    ready: &mut EventWriter<RemoteReady>,
+    ids: &PhysicsIds,
+    registry: &mut TemplateRegistry,
+) {
+    if let Ok(Response::InstanceHandles(handles, unknown)) = resp {
+        // Registered again with the entities left without handles next frame
+        for (id, template_id) in unknown {
+            warn!("Template {} of {} unknown to the server", template_id, id);
+            registry
+                .templates
+                .retain(|_, &mut known| known != template_id);
+        }
+        for (id, body_handle, collider_handle) in handles {
+            let entity = match ids.entity(id) {
+                Some(entity) => entity,
+                None => continue,
+            };
+            commands.entity(entity).insert((
+                RapierRigidBodyHandle(body_handle),
+                RapierColliderHandle(collider_handle),
+            ));
+            ready.send(RemoteReady::Body(entity));
+            ready.send(RemoteReady::Collider(entity));
+        }
+    }
+}
+
 pub fn init_rigid_bodies(
     context: Res<RapierContext>,
-    rigid_bodies: Query<RigidBodyComponents, Without<RapierRigidBodyHandle>>,
+    rigid_bodies: Query<
+        RigidBodyComponents,
+        (Without<RapierRigidBodyHandle>, Without<LocalPhysicsOnly>),
+    >,
+    registry: Res<TemplateRegistry>,
+    mut ids: ResMut<PhysicsIds>,
     mut request_queue: ResMut<RequestQueue>,
 ) {
     let mut created_bodies = vec![];
 
     let physics_scale = context.physics_scale();
 
-    for (entity, rb, transform, velocity, additional_mass_properties) in rigid_bodies.iter() {
+    for (
+        entity,
+        rb,
+        transform,
+        _,
+        additional_mass_properties,
+        damping,
+        gravity_scale,
+        ccd,
+        dominance,
+        locked_axes,
+        tag,
+    ) in rigid_bodies.iter()
+    {
+        if registry.claimed.contains(&entity) {
+            continue;
+        }
+
         created_bodies.push(CreatedBody {
-            id: entity.to_bits(),
+            id: ids.assign(entity).0,
             body: *rb,
             transform: transform.map(|transform| {
                 shared::transform_to_iso(&transform.compute_transform(), physics_scale)
             }),
-            additional_mass_properties: additional_mass_properties
-                .map(|mprops| mprops.clone().into()),
+            additional_mass_properties: additional_mass_properties.map(|mprops| (*mprops).into()),
+            damping: damping.map(|damping| (*damping).into()),
+            gravity_scale: gravity_scale.map(|scale| scale.0),
+            ccd: ccd.is_some_and(|ccd| ccd.enabled),
+            dominance_group: dominance.map(|dominance| dominance.groups),
+            locked_axes: locked_axes.map(|axes| axes.bits()),
+            tag: tag.map(|tag| tag.as_str().to_string()),
         });
     }
 
-    if created_bodies.is_empty() {
-        return;
-    }
-
-    request_queue.0.push(Request::CreateBodies(created_bodies));
+    bulk::BulkRequestBuilder::new()
+        .create_bodies(created_bodies)
+        .queue(&mut request_queue);
 }
 
-fn handle_init_rigid_bodies_response(resp: Result<Response>, commands: &mut Commands) {
+fn handle_init_rigid_bodies_response(
+    resp: Result<Response>,
+    commands: &mut Commands,
+    ready: &mut EventWriter<RemoteReady>,
+    ids: &PhysicsIds,
+) {
     if let Ok(Response::RigidBodyHandles(handles)) = resp {
         for handle in handles {
+            let entity = match ids.entity(handle.0) {
+                Some(entity) => entity,
+                None => continue,
+            };
             commands
-                .entity(Entity::from_bits(handle.0))
+                .entity(entity)
                 .insert(RapierRigidBodyHandle(handle.1));
+            ready.send(RemoteReady::Body(entity));
         }
     }
 }
 
+/// What the colliders sent to the server are recorded in and queued with.
+#[derive(SystemParam)]
+pub struct ColliderCreation<'w, 's> {
+    context: Res<'w, RapierContext>,
+    registry: Res<'w, TemplateRegistry>,
+    sent_scales: ResMut<'w, SentColliderScales>,
+    ids: ResMut<'w, PhysicsIds>,
+    request_queue: ResMut<'w, RequestQueue>,
+    #[system_param(ignore)]
+    marker: std::marker::PhantomData<&'s ()>,
+}
+
+#[allow(clippy::type_complexity)]
 pub fn init_colliders(
-    context: Res<RapierContext>,
-    colliders: Query<(ColliderComponents, Option<&GlobalTransform>), Without<RapierColliderHandle>>,
-    mut request_queue: ResMut<RequestQueue>,
+    colliders: Query<
+        (ColliderComponents, Option<&GlobalTransform>),
+        (Without<RapierColliderHandle>, Without<LocalPhysicsOnly>),
+    >,
+    parents: Query<&Parent>,
+    bodies: Query<Option<&GlobalTransform>, With<RigidBody>>,
+    mut creation: ColliderCreation,
 ) {
     let mut created_colliders = vec![];
 
-    let physics_scale = context.physics_scale();
+    let physics_scale = creation.context.physics_scale();
 
-    for ((entity, shape, sensor, mprops, friction, restitution), transform) in colliders.iter() {
-        created_colliders.push(CreatedCollider {
-            id: entity.to_bits(),
-            shape: shape.clone(),
-            transform: transform.map(|transform| {
-                shared::transform_to_iso(&transform.compute_transform(), physics_scale)
+    for (components, transform) in colliders.iter() {
+        if creation.registry.claimed.contains(&components.0) {
+            continue;
+        }
+
+        let parent = if bodies.contains(components.0) {
+            None
+        } else {
+            owning_body(components.0, &parents, &bodies)
+                .map(|(body, body_transform)| (body, relative_transform(transform, body_transform)))
+        };
+
+        creation
+            .sent_scales
+            .0
+            .insert(components.0, collider_scale(transform, components.2));
+        created_colliders.push(created_collider(
+            components,
+            transform,
+            parent,
+            physics_scale,
+            &mut creation.ids,
+        ));
+    }
+
+    bulk::BulkRequestBuilder::new()
+        .create_colliders(created_colliders)
+        .queue(&mut creation.request_queue);
+}
+
+/// The nearest ancestor of the entity that is a body, as bevy_rapier attaches
+/// colliders to, with its transform.
+pub fn owning_body<'a>(
+    entity: Entity,
+    parents: &Query<&Parent>,
+    bodies: &'a Query<Option<&GlobalTransform>, With<RigidBody>>,
+) -> Option<(Entity, Option<&'a GlobalTransform>)> {
+    let mut ancestor = entity;
+    while let Ok(parent) = parents.get(ancestor) {
+        ancestor = parent.get();
+        if let Ok(transform) = bodies.get(ancestor) {
+            return Some((ancestor, transform));
+        }
+    }
+    None
+}
+
+/// The transform of a collider relative to the body it is attached to,
+/// without scale which is sent with the shape.
+fn relative_transform(
+    transform: Option<&GlobalTransform>,
+    body_transform: Option<&GlobalTransform>,
+) -> Transform {
+    let (transform, body_transform) = match (transform, body_transform) {
+        (Some(transform), Some(body_transform)) => (transform, body_transform),
+        _ => return Transform::IDENTITY,
+    };
+    let (_, rotation, translation) = transform.to_scale_rotation_translation();
+    let (_, body_rotation, body_translation) = body_transform.to_scale_rotation_translation();
+    let inverse_body_rotation = body_rotation.inverse();
+    Transform {
+        translation: inverse_body_rotation * (translation - body_translation),
+        rotation: inverse_body_rotation * rotation,
+        scale: Vec3::ONE,
+    }
+}
+
+/// Sends the joints whose bodies were both created, the child body being that
+/// of the joint's entity or of its parent as with bevy_rapier. Joints of other
+/// kinds than fixed, revolute, prismatic and spherical are left out.
+#[allow(clippy::type_complexity)]
+pub fn init_joints(
+    impulse_joints: Query<
+        (Entity, &ImpulseJoint, Option<&Parent>),
+        (Without<RapierImpulseJointHandle>, Without<LocalPhysicsOnly>),
+    >,
+    multibody_joints: Query<
+        (Entity, &MultibodyJoint, Option<&Parent>),
+        (
+            Without<RapierMultibodyJointHandle>,
+            Without<LocalPhysicsOnly>,
+        ),
+    >,
+    handles: Query<&RapierRigidBodyHandle>,
+    mut unsupported: Local<HashSet<Entity>>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let joints = impulse_joints
+        .iter()
+        .map(|(entity, joint, parent)| (entity, joint.parent, &joint.data, parent, false))
+        .chain(
+            multibody_joints
+                .iter()
+                .map(|(entity, joint, parent)| (entity, joint.parent, &joint.data, parent, true)),
+        );
+
+    let mut created_joints = vec![];
+    for (entity, parent_body, data, parent, multibody) in joints {
+        if unsupported.contains(&entity) {
+            continue;
+        }
+        let body2 = handles
+            .get(entity)
+            .ok()
+            .or_else(|| parent.and_then(|parent| handles.get(parent.get()).ok()));
+        let (body1, body2) = match (handles.get(parent_body).ok(), body2) {
+            (Some(body1), Some(body2)) => (body1.0, body2.0),
+            // Until both bodies are created
+            _ => continue,
+        };
+        match SerializableJoint::try_from(data) {
+            Ok(joint) => created_joints.push(CreatedJoint {
+                id: ids.assign(entity).0,
+                body1,
+                body2,
+                joint,
+                multibody,
             }),
-            sensor: sensor.map(|sensor| sensor.clone().into()),
-            mass_properties: mprops.map(|mprops| mprops.clone().into()),
-            friction: friction.map(|friction| friction.clone().into()),
-            restitution: restitution.map(|restitution| restitution.clone().into()),
-        });
+            Err(locked_axes) => {
+                warn!(
+                    "Joint of {:?} with locked axes {:?} isn't supported by the server",
+                    entity, locked_axes
+                );
+                unsupported.insert(entity);
+            }
+        }
     }
 
-    if created_colliders.is_empty() {
+    if created_joints.is_empty() {
         return;
     }
 
-    request_queue
-        .0
-        .push(Request::CreateColliders(created_colliders));
+    request_queue.0.push(Request::CreateJoints(created_joints));
 }
 
-fn handle_init_colliders_response(resp: Result<Response>, commands: &mut Commands) {
-    if let Ok(Response::ColliderHandles(handles)) = resp {
-        for handle in handles {
-            commands
-                .entity(Entity::from_bits(handle.0))
-                .insert(RapierColliderHandle(handle.1));
+fn handle_init_joints_response(resp: Result<Response>, commands: &mut Commands, ids: &PhysicsIds) {
+    if let Ok(Response::JointHandles(handles)) = resp {
+        for (id, handle) in handles {
+            let mut entity = match ids.entity(id) {
+                Some(entity) => commands.entity(entity),
+                None => continue,
+            };
+            match handle {
+                JointHandle::Impulse(handle) => entity.insert(RapierImpulseJointHandle(handle)),
+                JointHandle::Multibody(handle) => entity.insert(RapierMultibodyJointHandle(handle)),
+            };
         }
     }
 }
 
-pub fn simulate_step(time: Res<Time>, mut request_queue: ResMut<RequestQueue>) {
-    request_queue
-        .0
-        .push(Request::SimulateStep(time.delta_seconds()));
+pub fn init_ragdolls(
+    mut commands: Commands,
+    context: Res<RapierContext>,
+    ragdolls: Query<(Entity, &Ragdoll, &Transform), Added<Ragdoll>>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let physics_scale = context.physics_scale();
+
+    for (entity, ragdoll, transform) in ragdolls.iter() {
+        let ids = ragdoll
+            .0
+            .bone_transforms()
+            .into_iter()
+            .enumerate()
+            .map(|(index, bone_transform)| {
+                let bone = commands.spawn((
+                    TransformBundle::from_transform(transform.mul_transform(bone_transform)),
+                    RagdollBone {
+                        ragdoll: entity,
+                        index,
+                    },
+                ));
+                ids.assign(bone.id()).0
+            })
+            .collect();
+
+        request_queue.0.push(Request::CreateRagdoll(CreatedRagdoll {
+            ids,
+            transform: shared::transform_to_iso(transform, physics_scale),
+            skeleton: ragdoll.0.clone(),
+        }));
+    }
 }
 
-fn handle_simulate_step_response(
+fn handle_create_ragdoll_response(
     resp: Result<Response>,
-    rigid_bodies: &mut Query<(RigidBodyWritebackComponents, &RapierRigidBodyHandle)>,
+    commands: &mut Commands,
+    ready: &mut EventWriter<RemoteReady>,
+    ids: &PhysicsIds,
 ) {
-    if let Ok(Response::SimulationResult(result)) = resp {
-        for ((entity, parent, transform, mut interpolation, mut velocity, mut sleeping), handle) in
-            rigid_bodies.iter_mut()
-        {
-            let (new_transform, new_velocity) = result.get(&handle.0).unwrap();
+    if let Ok(Response::RagdollHandles(handles)) = resp {
+        for (id, body_handle, collider_handle) in handles {
+            let entity = match ids.entity(id) {
+                Some(entity) => entity,
+                None => continue,
+            };
+            commands.entity(entity).insert((
+                RigidBody::Dynamic,
+                RapierRigidBodyHandle(body_handle),
+                RapierColliderHandle(collider_handle),
+            ));
+            ready.send(RemoteReady::Body(entity));
+            ready.send(RemoteReady::Collider(entity));
+        }
+    } else if let Err(err) = resp {
+        error!("Failed to create ragdoll: {}", err);
+    }
+}
+
+pub fn init_ropes(
+    mut commands: Commands,
+    ropes: Query<(Entity, &Rope), Added<Rope>>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    for (entity, rope) in ropes.iter() {
+        commands.entity(entity).insert(RopePoints::default());
+        request_queue.0.push(Request::CreateRope(CreatedRope {
+            id: ids.assign(entity).0,
+            start: rope.start,
+            end: rope.end,
+            start_anchor: ids.anchor(rope.start_anchor),
+            end_anchor: ids.anchor(rope.end_anchor),
+            segments: rope.segments,
+            radius: rope.radius,
+            stiffness: rope.stiffness,
+            break_force: rope.break_force,
+        }));
+    }
+}
+
+fn handle_create_rope_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to create rope: {}", err);
+    } else if let Ok(Response::RopeCreated) = resp {
+        debug!("Rope created");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+pub fn request_ropes(
+    ropes: Query<(), With<Rope>>,
+    window: Res<RequestWindow>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if !ropes.is_empty() && window.has_room(Channel::Snapshots) {
+        request_queue.0.push(Request::GetRopes);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn request_joint_breaks(
+    breakable: Query<(), Or<(With<Ragdoll>, With<Rope>)>>,
+    window: Res<RequestWindow>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if !breakable.is_empty() && window.has_room(Channel::Queries) {
+        request_queue.0.push(Request::TakeJointBreaks);
+    }
+}
 
-            if let Some(mut transform) = transform {
-                transform.translation = new_transform.translation;
-                transform.rotation = new_transform.rotation;
+fn handle_joint_breaks_response(
+    resp: Result<Response>,
+    joint_breaks: &mut EventWriter<RemoteJointBreak>,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::JointBreaks(breaks)) = resp {
+        for joint_break in breaks {
+            if let (Some(entity1), Some(entity2)) = (
+                ids.entity(joint_break.entity1),
+                ids.entity(joint_break.entity2),
+            ) {
+                joint_breaks.send(RemoteJointBreak {
+                    entities: (entity1, entity2),
+                    force: joint_break.force,
+                });
             }
+        }
+    }
+}
 
-            if let Some(velocity) = &mut velocity {
-                // NOTE: we write the new value only if there was an
-                //       actual change, in order to not trigger bevy’s
-                //       change tracking when the values didn’t change.
-                if **velocity != *new_velocity {
-                    **velocity = *new_velocity;
-                }
+fn handle_ropes_response(
+    resp: Result<Response>,
+    rope_points: &mut Query<&mut RopePoints>,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::Ropes(snapshots)) = resp {
+        for snapshot in snapshots {
+            let entity = match ids.entity(snapshot.id) {
+                Some(entity) => entity,
+                None => continue,
+            };
+            if let Ok(mut points) = rope_points.get_mut(entity) {
+                points.0 = snapshot.decode();
             }
         }
     }
 }
 
-pub fn process_requests(
+pub fn init_fluid_volumes(
+    volumes: Query<&FluidVolume, Added<FluidVolume>>,
     mut request_queue: ResMut<RequestQueue>,
-    client: Res<PhysicsClientWrapper>,
-    result: Res<RequestResult>,
-    rigid_bodies: Query<RigidBodyComponents>,
-    mut frame_count: Local<u64>,
 ) {
-    let client = client.0.clone();
-    let result = result.0.clone();
-    let object_count = rigid_bodies.iter().count();
-    *frame_count += 1;
-    let frame_count = *frame_count;
+    let volumes: Vec<_> = volumes.iter().copied().collect();
 
-    #[cfg(feature = "bulk-requests")]
-    {
-        let req = Request::BulkRequest(request_queue.0.drain(..).collect());
+    if volumes.is_empty() {
+        return;
+    }
 
-        thread::spawn(move || {
-            let span = tracing::debug_span!("process_requests", object_count, frame_count);
-            let _guard = span.enter();
-            let resp = client.lock().unwrap().send_request(req);
-            result.lock().unwrap().replace(resp);
-        });
+    request_queue.0.push(Request::AddFluidVolumes(volumes));
+}
+
+fn handle_add_fluid_volumes_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to add fluid volumes: {}", err);
+    } else if let Ok(Response::FluidVolumesAdded) = resp {
+        debug!("Fluid volumes added");
+    } else {
+        error!("Unexpected response");
     }
-    #[cfg(not(feature = "bulk-requests"))]
-    {
-        let request_queue = request_queue.0.drain(..).collect::<Vec<_>>();
-
-        thread::spawn(move || {
-            let span = tracing::debug_span!("process_requests", object_count, frame_count);
-            let _guard = span.enter();
-            let mut result = result.lock().unwrap();
-            for req in request_queue {
-                let resp = client.lock().unwrap().send_request(req);
-                result.push(resp);
-            }
-        });
+}
+
+fn handle_register_layers_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to register collision layers: {}", err);
+    } else if let Ok(Response::LayersRegistered) = resp {
+        debug!("Collision layers registered");
+    } else {
+        error!("Unexpected response");
     }
 }
 
-pub fn writeback(
-    mut commands: Commands,
-    mut rigid_bodies: Query<(RigidBodyWritebackComponents, &RapierRigidBodyHandle)>,
-    result: Res<RequestResult>,
-    mut init: Local<bool>,
+fn handle_set_step_pacing_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to set step pacing: {}", err);
+    } else if let Ok(Response::StepPacingSet(step_pacing)) = resp {
+        info!("Server step pacing: {}", step_pacing);
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+fn handle_compact_world_response(
+    compacted: CompactedWorld,
+    commands: &mut Commands,
+    mirror: &mut Option<ResMut<MirrorSync>>,
+    ids: &PhysicsIds,
 ) {
-    if !*init {
-        *init = true;
+    if compacted.bodies.is_empty() && compacted.colliders.is_empty() {
+        debug!("Server world needs no compaction: {}", compacted.before);
         return;
     }
-
-    #[cfg(feature = "bulk-requests")]
-    {
-        while result.0.lock().unwrap().is_none() {}
-        let resp = result.0.lock().unwrap().take().unwrap();
-        if let Err(err) = resp {
-            error!("Failed to send request: {}", err);
-            return;
+    info!(
+        "Server world compacted from {} to {}",
+        compacted.before, compacted.after
+    );
+    for (id, handle) in compacted.bodies {
+        if let Some(entity) = ids.entity(id) {
+            commands
+                .entity(entity)
+                .insert(RapierRigidBodyHandle(handle));
         }
-
-        if let Response::BulkResponse(responses) = resp.unwrap() {
-            for resp in responses {
-                handle_response(resp, &mut commands, &mut rigid_bodies);
-            }
-        } else {
-            error!("Unexpected response");
+    }
+    for (id, handle) in compacted.colliders {
+        if let Some(entity) = ids.entity(id) {
+            commands.entity(entity).insert(RapierColliderHandle(handle));
         }
     }
-    #[cfg(not(feature = "bulk-requests"))]
+    // The mirror's maps are rebuilt from the next state
+    if let Some(mirror) = mirror {
+        mirror.bodies.clear();
+        mirror.colliders.clear();
+    }
+}
+
+pub fn compact_world(
+    mut compaction: ResMut<WorldCompaction>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if compaction.last.elapsed() < compaction.period {
+        return;
+    }
+    compaction.last = Instant::now();
+    request_queue.0.push(Request::CompactWorld);
+}
+
+/// Pings the server when no request was sent for a heartbeat period.
+pub fn send_heartbeat(
+    heartbeat: Res<Heartbeat>,
+    window: Res<RequestWindow>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if window.last_sent.elapsed() < heartbeat.period
+        || !request_queue.0.is_empty()
+        || !window.has_room(Channel::Queries)
     {
-        while result.0.lock().unwrap().is_empty() {}
-        while let Some(resp) = result.0.lock().unwrap().pop() {
-            match resp {
-                Ok(resp) => {
-                    handle_response(resp, &mut commands, &mut rigid_bodies);
-                }
-                Err(err) => {
-                    error!("Failed to send request: {}", err);
-                    continue;
-                }
-            }
+        return;
+    }
+    request_queue.0.push(Request::Ping {
+        payload: vec![],
+        reply_len: 0,
+    });
+}
+
+fn handle_snapshot_response(resp: Result<Response>, saved_world: &mut SavedWorld) {
+    match resp {
+        Ok(Response::Snapshot(Ok(snapshot))) => {
+            info!("Saved a world of {} bytes", snapshot.context.len());
+            saved_world.0 = Some(snapshot);
         }
+        Ok(Response::Snapshot(Err(err))) => error!("Failed to save the world: {}", err),
+        Err(err) => error!("Failed to save the world: {}", err),
+        _ => error!("Unexpected response"),
     }
 }
 
-fn handle_response(
-    resp: Response,
-    mut commands: &mut Commands,
-    mut rigid_bodies: &mut Query<(RigidBodyWritebackComponents, &RapierRigidBodyHandle)>,
-) {
+fn handle_restore_snapshot_response(resp: Result<Response>) {
     match resp {
-        Response::ConfigUpdated => {
-            handle_update_config_response(Ok(resp));
+        Ok(Response::SnapshotRestored(Ok(bodies))) => {
+            info!("Restored a world of {} bodies", bodies)
         }
-        Response::RigidBodyHandles(_) => {
-            handle_init_rigid_bodies_response(Ok(resp), &mut commands);
+        Ok(Response::SnapshotRestored(Err(err))) => {
+            error!("Failed to restore the world: {}", err)
         }
-        Response::ColliderHandles(_) => {
-            handle_init_colliders_response(Ok(resp), &mut commands);
+        Err(err) => error!("Failed to restore the world: {}", err),
+        _ => error!("Unexpected response"),
+    }
+}
+
+fn handle_set_simulation_state_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to set the simulation state: {}", err);
+    } else if let Ok(Response::SimulationStateSet) = resp {
+        debug!("Simulation state set");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+fn handle_reset_world_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to reset world: {}", err);
+    } else if let Ok(Response::WorldReset) = resp {
+        debug!("World reset");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+/// How far apart two scales can be and still count as the same.
+const SCALE_EPSILON: Real = 1.0e-4;
+
+/// The scale bevy_rapier would give the shape of a collider.
+pub fn collider_scale(
+    transform: Option<&GlobalTransform>,
+    collider_scale: Option<&ColliderScale>,
+) -> Vect {
+    let transform_scale =
+        transform.map_or(Vect::ONE, |transform| transform.compute_transform().scale);
+    match collider_scale {
+        Some(ColliderScale::Absolute(scale)) => *scale,
+        Some(ColliderScale::Relative(scale)) => *scale * transform_scale,
+        None => transform_scale,
+    }
+}
+
+/// As scales are sent, `None` if it's one.
+fn sent_scale(scale: Vect) -> Option<Vect> {
+    (!scale.abs_diff_eq(Vect::ONE, SCALE_EPSILON)).then_some(scale)
+}
+
+/// `parent` is the body the collider is attached to, if its entity isn't a
+/// body itself, with the collider's transform relative to it.
+pub fn created_collider(
+    (
+        entity,
+        shape,
+        custom_scale,
+        sensor,
+        mprops,
+        friction,
+        restitution,
+        groups,
+        solver_groups,
+        active_events,
+        active_hooks,
+        collision_types,
+        threshold,
+    ): ColliderComponents,
+    transform: Option<&GlobalTransform>,
+    parent: Option<(Entity, Transform)>,
+    physics_scale: Real,
+    ids: &mut PhysicsIds,
+) -> CreatedCollider {
+    let iso = |transform: &Transform| shared::transform_to_iso(transform, physics_scale);
+    CreatedCollider {
+        id: ids.assign(entity).0,
+        shape: shape.clone(),
+        scale: sent_scale(collider_scale(transform, custom_scale)),
+        parent_id: parent.map(|(body, _)| ids.assign(body).0),
+        transform: match parent {
+            Some((_, relative)) => Some(iso(&relative)),
+            None => transform.map(|transform| iso(&transform.compute_transform())),
+        },
+        sensor: sensor.map(|sensor| (*sensor).into()),
+        mass_properties: mprops.map(|mprops| (*mprops).into()),
+        friction: friction.map(|friction| (*friction).into()),
+        restitution: restitution.map(|restitution| (*restitution).into()),
+        collision_groups: groups.map(|groups| (*groups).into()),
+        solver_groups: solver_groups.map(|groups| (*groups).into()),
+        active_events: active_events.copied(),
+        active_hooks: active_hooks.map(|hooks| (*hooks).into()),
+        active_collision_types: collision_types.map(|types| (*types).into()),
+        contact_force_event_threshold: threshold.map(|threshold| threshold.0),
+    }
+}
+
+pub fn handle_init_colliders_response(
+    resp: Result<Response>,
+    commands: &mut Commands,
+    ready: &mut EventWriter<RemoteReady>,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::ColliderHandles(handles)) = resp {
+        for handle in handles {
+            let entity = match ids.entity(handle.0) {
+                Some(entity) => entity,
+                None => continue,
+            };
+            commands
+                .entity(entity)
+                .insert(RapierColliderHandle(handle.1));
+            ready.send(RemoteReady::Collider(entity));
         }
-        Response::SimulationResult(_) => {
-            handle_simulate_step_response(Ok(resp), &mut rigid_bodies);
+    }
+}
+
+pub fn send_body_commands(
+    mut body_commands: ResMut<BodyCommands>,
+    mut pending: ResMut<PendingBodyCommands>,
+    mut ready: EventReader<RemoteReady>,
+    handles: Query<&RapierRigidBodyHandle>,
+    rigid_bodies: Query<(), With<RigidBody>>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let mut commands = vec![];
+
+    // Bodies created since the last frame get their buffered commands first
+    for event in ready.iter() {
+        if let RemoteReady::Body(entity) = event {
+            if let (Some(buffered), Ok(handle)) = (pending.0.remove(entity), handles.get(*entity)) {
+                commands.extend(buffered.into_iter().map(|command| (handle.0, command)));
+            }
         }
-        _ => {
-            error!("Unexpected response");
+    }
+
+    for (entity, command) in body_commands.0.drain(..) {
+        match handles.get(entity) {
+            Ok(handle) => commands.push((handle.0, command)),
+            Err(_) => pending.0.entry(entity).or_default().push(command),
         }
     }
+
+    // Entities despawned before their body was created never get a handle
+    pending.0.retain(|&entity, _| rigid_bodies.contains(entity));
+
+    bulk::BulkRequestBuilder::new()
+        .apply_commands(commands)
+        .queue(&mut request_queue);
+}
+
+fn handle_apply_commands_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to apply body commands: {}", err);
+    } else if let Ok(Response::CommandsApplied) = resp {
+        debug!("Body commands applied");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+/// Sends the positions gameplay code moved bodies to. They are sent by physics
+/// id, so bodies still being created are moved once they are.
+pub fn send_body_transforms(
+    mut body_transforms: ResMut<BodyTransforms>,
+    context: Res<RapierContext>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if body_transforms.0.is_empty() {
+        return;
+    }
+
+    let physics_scale = context.physics_scale();
+    let transforms = body_transforms
+        .0
+        .drain(..)
+        .map(|(entity, transform)| {
+            (
+                ids.assign(entity).0,
+                shared::transform_to_iso(&transform, physics_scale),
+            )
+        })
+        .collect();
+
+    request_queue.0.push(Request::SetBodyTransforms(transforms));
+}
+
+fn handle_set_body_transforms_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to set body transforms: {}", err);
+    } else if let Ok(Response::BodyTransformsSet) = resp {
+        debug!("Body transforms set");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+/// Sends the force components that changed, and those of bodies created since
+/// the last frame. Impulses are reset once sent, as bevy_rapier does once it
+/// applied them.
+#[allow(clippy::type_complexity)]
+pub fn send_forces(
+    mut forces: Query<
+        (
+            &RapierRigidBodyHandle,
+            ChangeTrackers<RapierRigidBodyHandle>,
+            Option<(&ExternalForce, ChangeTrackers<ExternalForce>)>,
+            Option<&mut ExternalImpulse>,
+        ),
+        Or<(
+            Changed<ExternalForce>,
+            Changed<ExternalImpulse>,
+            Added<RapierRigidBodyHandle>,
+        )>,
+    >,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let mut applied = vec![];
+    for (handle, handle_trackers, force, impulse) in forces.iter_mut() {
+        let force = force
+            .filter(|(_, trackers)| handle_trackers.is_added() || trackers.is_changed())
+            .map(|(&force, _)| force.into());
+        let impulse = impulse
+            .filter(|impulse| **impulse != ExternalImpulse::default())
+            .map(|mut impulse| {
+                // Without marking it changed, so that the reset isn't sent
+                mem::take(impulse.bypass_change_detection()).into()
+            });
+        if force.is_some() || impulse.is_some() {
+            applied.push((handle.0, force, impulse));
+        }
+    }
+
+    bulk::BulkRequestBuilder::new()
+        .apply_forces(applied)
+        .queue(&mut request_queue);
+}
+
+fn handle_apply_forces_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to apply forces: {}", err);
+    } else if let Ok(Response::ForcesApplied) = resp {
+        debug!("Forces applied");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+/// Sends the components of bodies that changed after the bodies were created.
+#[allow(clippy::type_complexity)]
+pub fn send_body_updates(
+    bodies: Query<
+        (
+            &RapierRigidBodyHandle,
+            &RigidBody,
+            ChangeTrackers<RigidBody>,
+            Option<(
+                &AdditionalMassProperties,
+                ChangeTrackers<AdditionalMassProperties>,
+            )>,
+        ),
+        (
+            Or<(Changed<RigidBody>, Changed<AdditionalMassProperties>)>,
+            Without<LocalPhysicsOnly>,
+        ),
+    >,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let updates: Vec<_> = bodies
+        .iter()
+        .map(|(handle, rb, rb_trackers, mprops)| {
+            let update = BodyUpdate {
+                body: rb_trackers.is_changed().then_some(*rb),
+                additional_mass_properties: mprops
+                    .filter(|(_, trackers)| trackers.is_changed())
+                    .map(|(mprops, _)| (*mprops).into()),
+            };
+            (handle.0, update)
+        })
+        .collect();
+
+    if updates.is_empty() {
+        return;
+    }
+
+    request_queue.0.push(Request::UpdateBodies(updates));
+}
+
+fn handle_update_bodies_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to update bodies: {}", err);
+    } else if let Ok(Response::BodiesUpdated) = resp {
+        debug!("Bodies updated");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+/// Sends the components of colliders that changed after the colliders were
+/// created, and their shapes again when their scale changed.
+#[allow(clippy::type_complexity)]
+pub fn send_collider_updates(
+    colliders: Query<
+        (
+            Entity,
+            &RapierColliderHandle,
+            &Collider,
+            ChangeTrackers<Collider>,
+            Option<(
+                &ColliderMassProperties,
+                ChangeTrackers<ColliderMassProperties>,
+            )>,
+            Option<(&Friction, ChangeTrackers<Friction>)>,
+            Option<(&Restitution, ChangeTrackers<Restitution>)>,
+            Option<&GlobalTransform>,
+            Option<&ColliderScale>,
+        ),
+        (
+            Or<(
+                Changed<Collider>,
+                Changed<ColliderMassProperties>,
+                Changed<Friction>,
+                Changed<Restitution>,
+                Changed<GlobalTransform>,
+                Changed<ColliderScale>,
+            )>,
+            Without<LocalPhysicsOnly>,
+        ),
+    >,
+    removed: RemovedComponents<Collider>,
+    mut sent_scales: ResMut<SentColliderScales>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    for entity in removed.iter() {
+        sent_scales.0.remove(&entity);
+    }
+
+    let mut updates = vec![];
+    for (entity, handle, shape, shape_trackers, mprops, friction, restitution, transform, scale) in
+        colliders.iter()
+    {
+        let scale = collider_scale(transform, scale);
+        let sent = sent_scales.0.entry(entity).or_insert(Vect::ONE);
+        let rescaled = !scale.abs_diff_eq(*sent, SCALE_EPSILON);
+        let shape = (shape_trackers.is_changed() || rescaled).then(|| shape.clone());
+        if shape.is_some() {
+            *sent = scale;
+        }
+        let update = ColliderUpdate {
+            scale: shape.as_ref().and_then(|_| sent_scale(scale)),
+            shape,
+            mass_properties: mprops
+                .filter(|(_, trackers)| trackers.is_changed())
+                .map(|(mprops, _)| (*mprops).into()),
+            friction: friction
+                .filter(|(_, trackers)| trackers.is_changed())
+                .map(|(friction, _)| (*friction).into()),
+            restitution: restitution
+                .filter(|(_, trackers)| trackers.is_changed())
+                .map(|(restitution, _)| (*restitution).into()),
+        };
+        // Most colliders here only moved
+        if update.shape.is_some()
+            || update.mass_properties.is_some()
+            || update.friction.is_some()
+            || update.restitution.is_some()
+        {
+            updates.push((handle.0, update));
+        }
+    }
+
+    if updates.is_empty() {
+        return;
+    }
+
+    request_queue.0.push(Request::UpdateColliders(updates));
+}
+
+fn handle_update_colliders_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to update colliders: {}", err);
+    } else if let Ok(Response::CollidersUpdated) = resp {
+        debug!("Colliders updated");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+pub fn send_update_rates(
+    update_rates: Query<(Entity, &UpdateRate), Changed<UpdateRate>>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let rates: Vec<_> = update_rates
+        .iter()
+        .map(|(entity, rate)| (ids.assign(entity).0, *rate))
+        .collect();
+
+    if rates.is_empty() {
+        return;
+    }
+
+    request_queue.0.push(Request::SetUpdateRates(rates));
+}
+
+fn handle_set_update_rates_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to set update rates: {}", err);
+    } else if let Ok(Response::UpdateRatesSet) = resp {
+        debug!("Update rates set");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+pub fn send_priorities(
+    priorities: Query<(Entity, &SnapshotPriority), Changed<SnapshotPriority>>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let priorities: Vec<_> = priorities
+        .iter()
+        .map(|(entity, priority)| (ids.assign(entity).0, priority.0))
+        .collect();
+
+    if priorities.is_empty() {
+        return;
+    }
+
+    request_queue.0.push(Request::SetPriorities(priorities));
+}
+
+fn handle_set_priorities_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to set priorities: {}", err);
+    } else if let Ok(Response::PrioritiesSet) = resp {
+        debug!("Priorities set");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+pub fn send_controllers(
+    changed: Query<(Entity, &Controller), Changed<Controller>>,
+    controlled: Query<Entity, With<Controller>>,
+    mut attached: Local<HashSet<Entity>>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    // Removals of the previous frame's update are already forgotten by now,
+    // so detached controllers are found by comparing with the last frame
+    let current: HashSet<_> = controlled.iter().collect();
+    let detached: Vec<_> = attached
+        .difference(&current)
+        .filter_map(|&entity| ids.id(entity))
+        .map(|id| (id.0, None))
+        .collect();
+
+    let controllers: Vec<_> = changed
+        .iter()
+        .map(|(entity, controller)| (ids.assign(entity).0, Some(controller.clone())))
+        .chain(detached)
+        .collect();
+    *attached = current;
+
+    if controllers.is_empty() {
+        return;
+    }
+
+    request_queue.0.push(Request::SetControllers(controllers));
+}
+
+fn handle_set_controllers_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to set controllers: {}", err);
+    } else if let Ok(Response::ControllersSet) = resp {
+        debug!("Controllers set");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+pub fn send_focus(
+    focus: Query<&GlobalTransform, (With<SnapshotFocus>, Changed<GlobalTransform>)>,
+    window: Res<RequestWindow>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    // The focus only needs to be roughly up to date
+    if !window.has_room(Channel::Control) {
+        return;
+    }
+
+    if let Some(transform) = focus.iter().next() {
+        request_queue
+            .0
+            .push(Request::SetFocus(transform.translation()));
+    }
+}
+
+fn handle_set_focus_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to set focus: {}", err);
+    } else if let Ok(Response::FocusSet) = resp {
+        debug!("Focus set");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+/// Runs last in the frame, as removed components are only seen in the frame
+/// they were removed in. Handles are also taken off entities that keep their
+/// body or collider when the world is sent again, which aren't removed. The
+/// ids of entities left with neither are released.
+pub fn send_removals(
+    removed_bodies: RemovedComponents<RapierRigidBodyHandle>,
+    removed_colliders: RemovedComponents<RapierColliderHandle>,
+    bodies: Query<(), With<RigidBody>>,
+    colliders: Query<(), With<Collider>>,
+    mut ids: ResMut<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let removed_bodies: Vec<u64> = removed_bodies
+        .iter()
+        .filter(|&entity| !bodies.contains(entity))
+        .filter_map(|entity| ids.id(entity))
+        .map(|id| id.0)
+        .collect();
+    // Colliders of removed bodies go with them
+    let removed_colliders: Vec<u64> = removed_colliders
+        .iter()
+        .filter(|&entity| !colliders.contains(entity))
+        .filter_map(|entity| ids.id(entity))
+        .map(|id| id.0)
+        .filter(|id| !removed_bodies.contains(id))
+        .collect();
+
+    for &id in removed_bodies.iter().chain(&removed_colliders) {
+        if let Some(entity) = ids.entity(id) {
+            if !bodies.contains(entity) && !colliders.contains(entity) {
+                ids.release(entity);
+            }
+        }
+    }
+
+    if !removed_bodies.is_empty() {
+        request_queue.0.push(Request::RemoveBodies(removed_bodies));
+    }
+    if !removed_colliders.is_empty() {
+        request_queue
+            .0
+            .push(Request::RemoveColliders(removed_colliders));
+    }
+}
+
+fn handle_remove_bodies_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to remove bodies: {}", err);
+    } else if let Ok(Response::BodiesRemoved) = resp {
+        debug!("Bodies removed");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+fn handle_remove_colliders_response(resp: Result<Response>) {
+    if let Err(err) = resp {
+        error!("Failed to remove colliders: {}", err);
+    } else if let Ok(Response::CollidersRemoved) = resp {
+        debug!("Colliders removed");
+    } else {
+        error!("Unexpected response");
+    }
+}
+
+/// Steps are coalesced while the request window is full, the next step making
+/// up for the time of the skipped ones.
+pub fn simulate_step(
+    time: Res<Time>,
+    window: Res<RequestWindow>,
+    pushed: Res<PushedResults>,
+    mut skipped_time: Local<f32>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    // The server steps on its own
+    if pushed.streaming {
+        return;
+    }
+    *skipped_time += time.delta_seconds();
+    if !window.has_room(Channel::Snapshots) {
+        return;
+    }
+
+    bulk::BulkRequestBuilder::new()
+        .step(mem::take(&mut *skipped_time))
+        .queue(&mut request_queue);
+}
+
+fn handle_simulate_step_response(
+    resp: Result<Response>,
+    bodies: &mut BodyWriteback,
+    placement: &mut Option<ResMut<PlacementReport>>,
+    context: &mut RapierContext,
+    mirror: &Option<ResMut<MirrorSync>>,
+    validation: &mut ResultValidation,
+) {
+    if let Ok(Response::SimulationResult(mut result, _, fell_asleep)) = resp {
+        let fell_asleep: HashSet<RigidBodyHandle> = fell_asleep.into_iter().collect();
+        // Corrupt states would spread into rendering and every other system
+        let corrupt: HashMap<RigidBodyHandle, Corruption> = result
+            .iter()
+            .filter_map(|(&handle, (transform, velocity))| {
+                validation
+                    .check(transform, velocity)
+                    .err()
+                    .map(|corruption| (handle, corruption))
+            })
+            .collect();
+        result.retain(|handle, _| !corrupt.contains_key(handle));
+
+        // Keeps local queries close to the server's state between syncs
+        if let Some(mirror) = mirror {
+            mirror::update_positions(context, mirror, &result);
+        }
+
+        for ((entity, parent, transform, _, mut velocity, mut sleeping), handle, target, pose) in
+            bodies.rigid_bodies.iter_mut()
+        {
+            if let Some(&corruption) = corrupt.get(&handle.0) {
+                validation.record(entity, Err(corruption));
+                continue;
+            }
+            // Bodies with a lower update rate aren't part of every step
+            let (new_transform, new_velocity) = match result.get(&handle.0) {
+                Some(body_result) => body_result,
+                None => continue,
+            };
+            validation.record(entity, Ok(()));
+
+            // The server leaves sleeping bodies out once it reported them
+            // asleep, so the others of the step are awake
+            let asleep = fell_asleep.contains(&handle.0);
+            match &mut sleeping {
+                Some(sleeping) if sleeping.sleeping != asleep => sleeping.sleeping = asleep,
+                None if asleep => {
+                    bodies.commands.entity(entity).insert(Sleeping {
+                        sleeping: true,
+                        ..default()
+                    });
+                }
+                _ => {}
+            }
+
+            if let Some(placement) = placement {
+                report_placement(placement, entity, new_transform.translation);
+            }
+
+            match target.copied().unwrap_or(*bodies.target) {
+                WritebackTarget::Transform => {
+                    if let Some(mut transform) = transform {
+                        // The server's transforms are in world space, so bodies
+                        // that are children get theirs relative to the parent,
+                        // like bevy_rapier's own writeback
+                        match parent.and_then(|parent| bodies.global_transforms.get(**parent).ok())
+                        {
+                            Some(parent_global_transform) => {
+                                let (_, inverse_parent_rotation, inverse_parent_translation) =
+                                    parent_global_transform
+                                        .affine()
+                                        .inverse()
+                                        .to_scale_rotation_translation();
+                                transform.translation = inverse_parent_rotation
+                                    * new_transform.translation
+                                    + inverse_parent_translation;
+                                transform.rotation =
+                                    inverse_parent_rotation * new_transform.rotation;
+                            }
+                            None => {
+                                transform.translation = new_transform.translation;
+                                transform.rotation = new_transform.rotation;
+                            }
+                        }
+                    }
+
+                    if let Some(velocity) = &mut velocity {
+                        // NOTE: we write the new value only if there was an
+                        //       actual change, in order to not trigger bevy’s
+                        //       change tracking when the values didn’t change.
+                        if **velocity != *new_velocity {
+                            **velocity = *new_velocity;
+                        }
+                    }
+                }
+                WritebackTarget::Pose => {
+                    let new_pose = RemotePhysicsPose {
+                        transform: *new_transform,
+                        velocity: *new_velocity,
+                    };
+                    match pose {
+                        Some(mut pose) => *pose = new_pose,
+                        None => {
+                            bodies.commands.entity(entity).insert(new_pose);
+                        }
+                    }
+                }
+                WritebackTarget::Events => bodies.poses.send(RemotePoseUpdated {
+                    entity,
+                    transform: *new_transform,
+                    velocity: *new_velocity,
+                }),
+            }
+        }
+    }
+}
+
+/// Replaces the intersections of the previous step, sending an event for
+/// every pair that started or stopped intersecting.
+fn handle_intersections(
+    pairs: &[(u64, u64)],
+    intersections: &mut RemoteIntersections,
+    events: &mut EventWriter<RemoteIntersection>,
+    ids: &PhysicsIds,
+) {
+    let pairs: HashSet<(Entity, Entity)> = pairs
+        .iter()
+        .filter_map(|&(id1, id2)| Some((ids.entity(id1)?, ids.entity(id2)?)))
+        .collect();
+    for &entities in pairs.difference(&intersections.0) {
+        events.send(RemoteIntersection {
+            entities,
+            intersecting: true,
+        });
+    }
+    for &entities in intersections.0.difference(&pairs) {
+        events.send(RemoteIntersection {
+            entities,
+            intersecting: false,
+        });
+    }
+    intersections.0 = pairs;
+}
+
+pub fn request_state(
+    mut mirror: ResMut<MirrorSync>,
+    window: Res<RequestWindow>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if mirror.last_sync.elapsed() < mirror.period || !window.has_room(Channel::Snapshots) {
+        return;
+    }
+    mirror.last_sync = Instant::now();
+
+    request_queue.0.push(Request::GetState);
+}
+
+fn handle_state_response(
+    resp: Result<Response>,
+    bodies: &mut BodyWriteback,
+    context: &mut RapierContext,
+    mirror: &mut Option<ResMut<MirrorSync>>,
+    state_requests: &mut StateRequests,
+    validation: &mut ResultValidation,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::State(state)) = resp {
+        if mem::take(&mut state_requests.export) {
+            export_state(&state);
+        }
+
+        if mem::take(&mut state_requests.resync) {
+            info!("Resyncing {} bodies", state.bodies.len());
+            let scale = context.physics_scale();
+            let result = state
+                .bodies
+                .iter()
+                .map(|body| {
+                    let transform = bevy_rapier3d::utils::iso_to_transform(&body.position, scale);
+                    let velocity = Velocity {
+                        linvel: body.linvel * scale,
+                        angvel: body.angvel,
+                    };
+                    (body.handle, (transform, velocity))
+                })
+                .collect();
+            handle_simulate_step_response(
+                Ok(Response::SimulationResult(result, vec![], vec![])),
+                bodies,
+                &mut None,
+                context,
+                &None,
+                validation,
+            );
+        }
+
+        if let Some(mirror) = mirror {
+            mirror::rebuild(context, mirror, state, ids);
+        }
+    }
+}
+
+fn export_state(state: &shared::mirror::WorldState) {
+    let path = format!("snapshot_{}.ron", unix_timestamp());
+    let result = ron::ser::to_string_pretty(state, ron::ser::PrettyConfig::default())
+        .map_err(|err| err.to_string())
+        .and_then(|contents| std::fs::write(&path, contents).map_err(|err| err.to_string()));
+    match result {
+        Ok(()) => info!("Exported {} bodies to {}", state.bodies.len(), path),
+        Err(err) => error!("Failed to export snapshot: {}", err),
+    }
+}
+
+pub fn send_ray_casts(
+    mut ray_casts: ResMut<RemoteRayCasts>,
+    ids: Res<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let ray_casts = &mut *ray_casts;
+    for (ray, filter) in ray_casts.filtered_rays.drain(..) {
+        request_queue.0.push(Request::CastRay {
+            origin: ray.origin,
+            dir: ray.dir,
+            max_toi: ray.max_toi,
+            solid: ray.solid,
+            filter: ids.filter(filter),
+        });
+        ray_casts.pending.push_back(ray.id);
+    }
+
+    if ray_casts.rays.is_empty() {
+        return;
+    }
+
+    let rays = ray_casts.rays.drain(..).collect();
+    request_queue.0.push(Request::CastRays(rays));
+}
+
+fn handle_ray_hits_response(
+    resp: Result<Response>,
+    ray_hits: &mut EventWriter<RemoteRayHit>,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::RayHits(hits)) = resp {
+        for (id, hit) in hits {
+            ray_hits.send(RemoteRayHit {
+                id,
+                hit: hit.and_then(|(entity, toi)| Some((ids.entity(entity)?, toi))),
+                normal: None,
+            });
+        }
+    }
+}
+
+fn handle_ray_hit_response(
+    resp: Result<Response>,
+    ray_casts: &mut RemoteRayCasts,
+    ray_hits: &mut EventWriter<RemoteRayHit>,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::RayHit(hit)) = resp {
+        if let Some(id) = ray_casts.pending.pop_front() {
+            ray_hits.send(RemoteRayHit {
+                id,
+                hit: hit.and_then(|(entity, toi, _)| Some((ids.entity(entity)?, toi))),
+                normal: hit.map(|(_, _, normal)| normal),
+            });
+        }
+    }
+}
+
+pub fn send_shape_queries(
+    mut shape_queries: ResMut<RemoteShapeQueries>,
+    ids: Res<PhysicsIds>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    let shape_queries = &mut *shape_queries;
+    for (id, mut request) in shape_queries.queries.drain(..) {
+        if let Request::CastShape { filter, .. } | Request::IntersectionsWithShape { filter, .. } =
+            &mut request
+        {
+            *filter = ids.filter(*filter);
+        }
+        request_queue.0.push(request);
+        shape_queries.pending.push_back(id);
+    }
+}
+
+fn handle_shape_hit_response(
+    resp: Result<Response>,
+    shape_queries: &mut RemoteShapeQueries,
+    shape_hits: &mut EventWriter<RemoteShapeHit>,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::ShapeHit(hit)) = resp {
+        if let Some(id) = shape_queries.pending.pop_front() {
+            shape_hits.send(RemoteShapeHit {
+                id,
+                hit: hit.and_then(|(entity, hit)| Some((ids.entity(entity)?, hit))),
+            });
+        }
+    }
+}
+
+fn handle_shape_intersections_response(
+    resp: Result<Response>,
+    shape_queries: &mut RemoteShapeQueries,
+    shape_intersections: &mut EventWriter<RemoteShapeIntersections>,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::ShapeIntersections(entities)) = resp {
+        if let Some(id) = shape_queries.pending.pop_front() {
+            shape_intersections.send(RemoteShapeIntersections {
+                id,
+                entities: entities
+                    .into_iter()
+                    .filter_map(|entity| ids.entity(entity))
+                    .collect(),
+            });
+        }
+    }
+}
+
+pub fn request_events(
+    active: Query<(), With<ActiveEvents>>,
+    window: Res<RequestWindow>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    if !active.is_empty() && window.has_room(Channel::Queries) {
+        request_queue.0.push(Request::TakeEvents);
+    }
+}
+
+fn handle_events_response(
+    resp: Result<Response>,
+    collisions: &mut EventWriter<CollisionEvent>,
+    contact_forces: &mut EventWriter<ContactForceEvent>,
+    ids: &PhysicsIds,
+) {
+    if let Ok(Response::Events(events)) = resp {
+        for collision in events.collisions {
+            let (entity1, entity2) =
+                match (ids.entity(collision.entity1), ids.entity(collision.entity2)) {
+                    (Some(entity1), Some(entity2)) => (entity1, entity2),
+                    _ => continue,
+                };
+            let flags = CollisionEventFlags::from_bits_truncate(collision.flags);
+            collisions.send(if collision.started {
+                CollisionEvent::Started(entity1, entity2, flags)
+            } else {
+                CollisionEvent::Stopped(entity1, entity2, flags)
+            });
+        }
+        for contact_force in events.contact_forces {
+            let (collider1, collider2) = match (
+                ids.entity(contact_force.entity1),
+                ids.entity(contact_force.entity2),
+            ) {
+                (Some(collider1), Some(collider2)) => (collider1, collider2),
+                _ => continue,
+            };
+            contact_forces.send(ContactForceEvent {
+                collider1,
+                collider2,
+                total_force: contact_force.total_force,
+                total_force_magnitude: contact_force.total_force_magnitude,
+                max_force_direction: contact_force.max_force_direction,
+                max_force_magnitude: contact_force.max_force_magnitude,
+            });
+        }
+    }
+}
+
+fn handle_load_scene_response(resp: Result<Response>, scenes: &mut RemoteScenes) {
+    match resp {
+        Err(err) => error!("Failed to load scene: {}", err),
+        Ok(Response::SceneLoaded(Err(err))) => {
+            error!("Failed to load scene: {}", err);
+            if let SceneError::HashMismatch { expected, actual } = err {
+                scenes
+                    .mismatches
+                    .send(RemoteSceneMismatch { expected, actual });
+            }
+        }
+        Ok(Response::SceneLoaded(Ok(colliders))) => {
+            info!("Scene loaded with {} colliders", colliders.len());
+            scenes.loaded.send(RemoteScene { colliders });
+        }
+        Ok(_) => error!("Unexpected response"),
+    }
+}
+
+pub fn track_spawns(
+    mut placement: ResMut<PlacementReport>,
+    spawned: Query<(Entity, &Transform), Added<RigidBody>>,
+) {
+    for (_, _, frames) in placement.pending.values_mut() {
+        *frames += 1;
+    }
+
+    for (entity, transform) in spawned.iter() {
+        placement
+            .pending
+            .insert(entity, (transform.translation, Instant::now(), 0));
+    }
+}
+
+fn report_placement(placement: &mut PlacementReport, entity: Entity, translation: Vec3) {
+    let (intended, spawned_at, frames) = match placement.pending.remove(&entity) {
+        Some(pending) => pending,
+        None => return,
+    };
+
+    let row = [
+        unix_timestamp(),
+        entity.to_bits().to_string(),
+        millis(spawned_at.elapsed()),
+        frames.to_string(),
+        format!("{:.4}", intended.distance(translation)),
+    ];
+
+    if let Err(err) = placement.writer.write_row(&row) {
+        error!("Failed to write placement report: {}", err);
+    }
+}
+
+/// The requests of one frame.
+pub struct RequestBatch {
+    requests: Vec<Request>,
+    object_count: usize,
+    frame_count: u64,
+    /// Whether the batch has requests on each channel.
+    channels: [bool; 3],
+}
+
+fn handle_time_sync_response(sample: TimeSample, clock: &mut ClockSync) {
+    clock.add(sample);
+    if let (Some(offset), Some(rtt), Some((uplink, downlink))) =
+        (clock.offset, clock.rtt, clock.one_way_latencies())
+    {
+        debug!(
+            "Server clock {} µs ahead, round trip {:?}, {} µs there and {} µs back",
+            offset, rtt, uplink, downlink
+        );
+    }
+}
+
+/// How often results the server pushes are read while no batch is sent.
+const PUSH_POLL_PERIOD: Duration = Duration::from_millis(5);
+
+/// Sends batches one after another on a thread of its own, so that they reach
+/// the server in order however many are in flight, reading the results the
+/// server pushes in between.
+pub fn send_batches(
+    client: Arc<Mutex<PhysicsClient>>,
+    batches: Receiver<RequestBatch>,
+    result: Arc<Mutex<Vec<Result<Response>>>>,
+    in_flight: Arc<AtomicUsize>,
+    in_flight_frames: Arc<Mutex<Vec<u64>>>,
+    batch_done: Arc<Condvar>,
+    channel_in_flight: Arc<[AtomicUsize; 3]>,
+) {
+    loop {
+        let batch = match batches.recv_timeout(PUSH_POLL_PERIOD) {
+            Ok(batch) => batch,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(err) = client.lock().unwrap().poll_pushed() {
+                    error!("Failed to read pushed results: {}", err);
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let RequestBatch {
+            requests,
+            object_count,
+            frame_count,
+            channels,
+        } = batch;
+        let span = tracing::debug_span!("process_requests", object_count, frame_count);
+        let _guard = span.enter();
+
+        #[cfg(feature = "bulk-requests")]
+        let requests = bulk::bundle(requests);
+        let responses = client.lock().unwrap().send_requests(requests);
+        result.lock().unwrap().extend(responses);
+
+        // Under the lock, for frames waiting on the window not to miss it
+        let mut frames = in_flight_frames.lock().unwrap();
+        frames.retain(|&frame| frame != frame_count);
+        for (count, _) in channel_in_flight
+            .iter()
+            .zip(channels)
+            .filter(|(_, used)| *used)
+        {
+            count.fetch_sub(1, Ordering::AcqRel);
+        }
+        in_flight.fetch_sub(1, Ordering::AcqRel);
+        drop(frames);
+        batch_done.notify_all();
+    }
+}
+
+/// Keys the requests that create something, so that the server answers them
+/// with the handles of the first try if they reach it again.
+fn with_idempotency_key(request: Request, next_key: &mut u64) -> Request {
+    match request {
+        Request::BulkRequest(requests) => Request::BulkRequest(
+            requests
+                .into_iter()
+                .map(|request| with_idempotency_key(request, next_key))
+                .collect(),
+        ),
+        Request::InWorld { world_id, request } => Request::InWorld {
+            world_id,
+            request: Box::new(with_idempotency_key(*request, next_key)),
+        },
+        Request::CreateBodies(_)
+        | Request::CreateColliders(_)
+        | Request::CreateJoints(_)
+        | Request::SpawnInstances(_)
+        | Request::CreateRagdoll(_)
+        | Request::CreateRope(_)
+        | Request::AddFluidVolumes(_)
+        | Request::Transaction(_) => {
+            let key = *next_key;
+            *next_key += 1;
+            Request::Idempotent {
+                key,
+                request: Box::new(request),
+            }
+        }
+        request => request,
+    }
+}
+
+pub fn process_requests(
+    mut request_queue: ResMut<RequestQueue>,
+    sender: Res<RequestSender>,
+    mut window: ResMut<RequestWindow>,
+    rigid_bodies: Query<RigidBodyComponents>,
+    #[cfg(not(feature = "bulk-requests"))] calibration: Option<Res<Calibration>>,
+    mut frame_count: Local<u64>,
+    mut next_key: Local<u64>,
+) {
+    *frame_count += 1;
+
+    let in_flight = window.in_flight();
+    let full = in_flight >= window.max_in_flight;
+    window.frames += 1;
+    window.in_flight_total += in_flight;
+    window.full_frames += full as usize;
+
+    // Requests stay queued until the window has room again
+    if full || window.barrier || request_queue.0.is_empty() {
+        return;
+    }
+
+    // Compaction waits for every other batch and goes last, so that no request
+    // with the old handles reaches the server after it
+    let compaction = request_queue
+        .0
+        .iter()
+        .any(|request| matches!(request, Request::CompactWorld));
+    if compaction && in_flight > 0 {
+        return;
+    }
+
+    let mut requests = request_queue
+        .0
+        .drain(..)
+        .map(|request| with_idempotency_key(request, &mut next_key))
+        .collect::<Vec<_>>();
+    if compaction {
+        requests.sort_by_key(|request| matches!(request, Request::CompactWorld));
+        window.barrier = true;
+    }
+    let mut channels = [false; 3];
+    for request in &requests {
+        channels[Channel::of(request).id() as usize] = true;
+    }
+    #[cfg(not(feature = "bulk-requests"))]
+    {
+        if calibration.is_some_and(|calibration| calibration.batch_requests) {
+            requests = bulk::bundle(requests);
+        }
+    }
+
+    window.in_flight.fetch_add(1, Ordering::AcqRel);
+    window.in_flight_frames.lock().unwrap().push(*frame_count);
+    for (count, _) in window
+        .channel_in_flight
+        .iter()
+        .zip(channels)
+        .filter(|(_, used)| *used)
+    {
+        count.fetch_add(1, Ordering::AcqRel);
+    }
+    let batch = RequestBatch {
+        requests,
+        object_count: rigid_bodies.iter().count(),
+        frame_count: *frame_count,
+        channels,
+    };
+    if sender.0.lock().unwrap().send(batch).is_err() {
+        error!("The request sender thread is gone");
+    }
+    window.last_sent = Instant::now();
+}
+
+/// The bodies the server's states are written back to, and where to.
+#[derive(SystemParam)]
+pub struct BodyWriteback<'w, 's> {
+    commands: Commands<'w, 's>,
+    rigid_bodies: Query<
+        'w,
+        's,
+        (
+            RigidBodyWritebackComponents<'static>,
+            &'static RapierRigidBodyHandle,
+            Option<&'static WritebackTarget>,
+            Option<&'static mut RemotePhysicsPose>,
+        ),
+    >,
+    /// Of the parents of bodies that are children.
+    global_transforms: Query<'w, 's, &'static GlobalTransform>,
+    target: Res<'w, WritebackTarget>,
+    poses: EventWriter<'w, 's, RemotePoseUpdated>,
+}
+
+/// The events sent for what the server reports of its steps, and the queries
+/// and copies of the world it answers with, with the ids to find the entities
+/// they are about by and the templates the server was sent.
+#[derive(SystemParam)]
+pub struct RemoteEvents<'w, 's> {
+    saved_world: ResMut<'w, SavedWorld>,
+    ray_casts: ResMut<'w, RemoteRayCasts>,
+    ray_hits: EventWriter<'w, 's, RemoteRayHit>,
+    shape_queries: ResMut<'w, RemoteShapeQueries>,
+    shape_hits: EventWriter<'w, 's, RemoteShapeHit>,
+    shape_intersections: EventWriter<'w, 's, RemoteShapeIntersections>,
+    joint_breaks: EventWriter<'w, 's, RemoteJointBreak>,
+    collisions: EventWriter<'w, 's, CollisionEvent>,
+    contact_forces: EventWriter<'w, 's, ContactForceEvent>,
+    intersections: EventWriter<'w, 's, RemoteIntersection>,
+    status: EventWriter<'w, 's, SessionStatus>,
+    clock: ResMut<'w, ClockSync>,
+    worlds: EventWriter<'w, 's, RemoteWorldResponse>,
+    ids: Res<'w, PhysicsIds>,
+    templates: ResMut<'w, TemplateRegistry>,
+}
+
+/// What the server answers a request to load a scene with.
+#[derive(SystemParam)]
+pub struct RemoteScenes<'w, 's> {
+    loaded: EventWriter<'w, 's, RemoteScene>,
+    mismatches: EventWriter<'w, 's, RemoteSceneMismatch>,
+}
+
+/// Everything the handlers of the server's responses write to.
+#[derive(SystemParam)]
+pub struct ResponseTargets<'w, 's> {
+    commands: Commands<'w, 's>,
+    bodies: BodyWriteback<'w, 's>,
+    placement: Option<ResMut<'w, PlacementReport>>,
+    ready: EventWriter<'w, 's, RemoteReady>,
+    context: ResMut<'w, RapierContext>,
+    mirror: Option<ResMut<'w, MirrorSync>>,
+    events: RemoteEvents<'w, 's>,
+    intersections: ResMut<'w, RemoteIntersections>,
+    trajectories: RemoteTrajectories<'w, 's>,
+    scenes: RemoteScenes<'w, 's>,
+    rope_points: Query<'w, 's, &'static mut RopePoints>,
+    diagnostics: Option<ResMut<'w, Diagnostics>>,
+    state_requests: ResMut<'w, StateRequests>,
+    frame_report: Option<ResMut<'w, FrameReport>>,
+    degradation: ResMut<'w, RemoteDegradation>,
+    validation: ResMut<'w, ResultValidation>,
+}
+
+pub fn writeback(
+    mut targets: ResponseTargets,
+    result: Res<RequestResult>,
+    pushed: Res<PushedResults>,
+    mut window: ResMut<RequestWindow>,
+) {
+    // A full window waits for the oldest batch's responses, unless the server
+    // stalls, in which case the frame goes on and the window pushes back
+    window.wait_until(|window| !window.is_full());
+
+    // Responses are stored before the batch leaves the window, so with none in
+    // flight they are all about to be handled
+    let drained = window.in_flight() == 0;
+    let mut responses = mem::take(&mut *result.0.lock().unwrap());
+    // Pushed steps oldest first, so that the latest state is the one left
+    responses.extend(
+        mem::take(&mut *pushed.results.lock().unwrap())
+            .into_iter()
+            .map(Ok),
+    );
+    for resp in responses {
+        match resp {
+            Ok(resp) => {
+                handle_response(resp, &mut targets);
+            }
+            Err(err) => {
+                error!("Failed to send request: {}", err);
+            }
+        }
+    }
+    if drained {
+        window.barrier = false;
+    }
+}
+
+fn handle_response(resp: Response, targets: &mut ResponseTargets) {
+    match resp {
+        // Requests are batched when calibration found round trips costly
+        Response::BulkResponse(responses) => {
+            for resp in responses {
+                handle_response(resp, targets);
+            }
+        }
+        Response::Transaction(Ok(responses)) => {
+            for resp in responses {
+                handle_response(resp, targets);
+            }
+        }
+        Response::Transaction(Err(err)) => {
+            error!("Transaction rolled back: {}", err);
+        }
+        Response::Forbidden(reason) => {
+            warn!("Request forbidden in the room: {}", reason);
+        }
+        Response::Degraded(degradation, resp) => {
+            targets.degradation.current = degradation;
+            targets.degradation.since_export |= degradation;
+            handle_response(*resp, targets);
+            targets.degradation.current = Degradation::NONE;
+        }
+        Response::Pushed(resp) => {
+            handle_response(*resp, targets);
+        }
+        Response::Status(status, resp) => {
+            info!("The server's operator set the session {}", status);
+            targets.events.status.send(status);
+            handle_response(*resp, targets);
+        }
+        Response::Paced(report, resp) => {
+            if report.deferred {
+                debug!("Step deferred by step pacing {}", report.pacing);
+            } else if report.caught_up > 0 || !report.delay.is_zero() {
+                debug!(
+                    "Step paced by {}: made up for {} deferred steps after waiting {:?}",
+                    report.pacing, report.caught_up, report.delay
+                );
+            }
+            handle_response(*resp, targets);
+        }
+        Response::ConfigUpdated => {
+            handle_update_config_response(Ok(resp));
+        }
+        Response::RigidBodyHandles(_) => {
+            handle_init_rigid_bodies_response(
+                Ok(resp),
+                &mut targets.commands,
+                &mut targets.ready,
+                &targets.events.ids,
+            );
+        }
+        Response::ColliderHandles(_) => {
+            handle_init_colliders_response(
+                Ok(resp),
+                &mut targets.commands,
+                &mut targets.ready,
+                &targets.events.ids,
+            );
+        }
+        Response::TemplatesRegistered => {
+            handle_register_templates_response(Ok(resp));
+        }
+        Response::InstanceHandles(..) => {
+            handle_spawn_instances_response(
+                Ok(resp),
+                &mut targets.commands,
+                &mut targets.ready,
+                &targets.events.ids,
+                &mut targets.events.templates,
+            );
+        }
+        Response::ForcesApplied => {
+            handle_apply_forces_response(Ok(resp));
+        }
+        Response::CommandsApplied => {
+            handle_apply_commands_response(Ok(resp));
+        }
+        Response::BodyTransformsSet => {
+            handle_set_body_transforms_response(Ok(resp));
+        }
+        Response::SimulationResult(_, ref pairs, _) => {
+            handle_intersections(
+                pairs,
+                &mut targets.intersections,
+                &mut targets.events.intersections,
+                &targets.events.ids,
+            );
+            handle_simulate_step_response(
+                Ok(resp),
+                &mut targets.bodies,
+                &mut targets.placement,
+                &mut targets.context,
+                &targets.mirror,
+                &mut targets.validation,
+            );
+            if let Some(frame_report) = &mut targets.frame_report {
+                frame_report.snapshot_arrived = true;
+            }
+            targets.degradation.step_received();
+        }
+        Response::State(_) => {
+            handle_state_response(
+                Ok(resp),
+                &mut targets.bodies,
+                &mut targets.context,
+                &mut targets.mirror,
+                &mut targets.state_requests,
+                &mut targets.validation,
+                &targets.events.ids,
+            );
+        }
+        Response::RayHits(_) => {
+            handle_ray_hits_response(Ok(resp), &mut targets.events.ray_hits, &targets.events.ids);
+        }
+        Response::ShapeHit(_) => {
+            handle_shape_hit_response(
+                Ok(resp),
+                &mut targets.events.shape_queries,
+                &mut targets.events.shape_hits,
+                &targets.events.ids,
+            );
+        }
+        Response::ShapeIntersections(_) => {
+            handle_shape_intersections_response(
+                Ok(resp),
+                &mut targets.events.shape_queries,
+                &mut targets.events.shape_intersections,
+                &targets.events.ids,
+            );
+        }
+        Response::RayHit(_) => {
+            handle_ray_hit_response(
+                Ok(resp),
+                &mut targets.events.ray_casts,
+                &mut targets.events.ray_hits,
+                &targets.events.ids,
+            );
+        }
+        Response::Events(_) => {
+            handle_events_response(
+                Ok(resp),
+                &mut targets.events.collisions,
+                &mut targets.events.contact_forces,
+                &targets.events.ids,
+            );
+        }
+        Response::UpdateRatesSet => {
+            handle_set_update_rates_response(Ok(resp));
+        }
+        Response::PrioritiesSet => {
+            handle_set_priorities_response(Ok(resp));
+        }
+        Response::FocusSet => {
+            handle_set_focus_response(Ok(resp));
+        }
+        Response::ControllersSet => {
+            handle_set_controllers_response(Ok(resp));
+        }
+        Response::JointHandles(_) => {
+            handle_init_joints_response(Ok(resp), &mut targets.commands, &targets.events.ids);
+        }
+        Response::RagdollHandles(_) => {
+            handle_create_ragdoll_response(
+                Ok(resp),
+                &mut targets.commands,
+                &mut targets.ready,
+                &targets.events.ids,
+            );
+        }
+        Response::RopeCreated => {
+            handle_create_rope_response(Ok(resp));
+        }
+        Response::Ropes(_) => {
+            handle_ropes_response(Ok(resp), &mut targets.rope_points, &targets.events.ids);
+        }
+        Response::Trajectory(points) => {
+            targets.trajectories.draw(&points);
+        }
+        Response::FluidVolumesAdded => {
+            handle_add_fluid_volumes_response(Ok(resp));
+        }
+        Response::JointBreaks(_) => {
+            handle_joint_breaks_response(
+                Ok(resp),
+                &mut targets.events.joint_breaks,
+                &targets.events.ids,
+            );
+        }
+        Response::StepTime(_) => {
+            diagnostics::handle_step_time_response(Ok(resp), &mut targets.diagnostics);
+        }
+        Response::Snapshot(_) => {
+            handle_snapshot_response(Ok(resp), &mut targets.events.saved_world);
+        }
+        Response::SnapshotRestored(_) => {
+            handle_restore_snapshot_response(Ok(resp));
+        }
+        Response::SimulationStateSet => {
+            handle_set_simulation_state_response(Ok(resp));
+        }
+        Response::WorldReset => {
+            handle_reset_world_response(Ok(resp));
+        }
+        Response::BodiesRemoved => {
+            handle_remove_bodies_response(Ok(resp));
+        }
+        Response::CollidersRemoved => {
+            handle_remove_colliders_response(Ok(resp));
+        }
+        Response::BodiesUpdated => {
+            handle_update_bodies_response(Ok(resp));
+        }
+        Response::CollidersUpdated => {
+            handle_update_colliders_response(Ok(resp));
+        }
+        Response::LayersRegistered => {
+            handle_register_layers_response(Ok(resp));
+        }
+        Response::StepPacingSet(_) => {
+            handle_set_step_pacing_response(Ok(resp));
+        }
+        Response::Stats(stats) => {
+            info!("Server world: {}", stats);
+        }
+        Response::ImpairmentSet(Ok(impairment)) => {
+            info!("Server impairment: {}", impairment);
+        }
+        Response::ImpairmentSet(Err(err)) => {
+            error!("Failed to set impairment: {}", err);
+        }
+        Response::WorldCompacted(compacted) => {
+            handle_compact_world_response(
+                compacted,
+                &mut targets.commands,
+                &mut targets.mirror,
+                &targets.events.ids,
+            );
+        }
+        Response::SceneLoaded(_) => {
+            handle_load_scene_response(Ok(resp), &mut targets.scenes);
+        }
+        Response::StreamingSet => {
+            debug!("Streaming set");
+        }
+        Response::Pong(_) => {
+            debug!("Heartbeat answered");
+        }
+        // Only the default world is mirrored, the others are the application's
+        Response::InWorld(world_id, Ok(response)) => {
+            targets.events.worlds.send(RemoteWorldResponse {
+                world_id,
+                response: *response,
+            });
+        }
+        Response::InWorld(world_id, Err(err)) => {
+            error!("Request to world {} failed: {}", world_id, err);
+        }
+        Response::WorldCreated(Ok(world_id)) => {
+            info!("Created world {}", world_id);
+        }
+        Response::WorldDestroyed(Ok(world_id)) => {
+            info!("Destroyed world {}", world_id);
+        }
+        Response::WorldCreated(Err(err)) | Response::WorldDestroyed(Err(err)) => {
+            error!("{}", err);
+        }
+        Response::TimeSync(sample) => {
+            handle_time_sync_response(sample, &mut targets.events.clock);
+        }
+        _ => {
+            error!("Unexpected response");
+        }
+    }
+}
+
+pub fn export_metrics(
+    mut export: ResMut<MetricsExport>,
+    mut window: ResMut<RequestWindow>,
+    mut degradation: ResMut<RemoteDegradation>,
+    rigid_bodies: Query<&RigidBody>,
+) {
+    if export.last_export.elapsed() < Duration::from_secs(1) {
+        return;
+    }
+    let since = mem::replace(&mut export.last_export, Instant::now());
+
+    let stats = mem::take(&mut *export.stats.lock().unwrap());
+    let mut latencies = stats.latencies;
+    latencies.sort();
+
+    let (cpu_time, energy) = match &mut export.energy {
+        Some(sampler) => sampler.sample(),
+        None => (None, None),
+    };
+
+    let row = [
+        unix_timestamp(),
+        latencies.len().to_string(),
+        millis(percentile(&latencies, 0.5)),
+        millis(percentile(&latencies, 0.95)),
+        millis(percentile(&latencies, 0.99)),
+        millis(latencies.last().copied().unwrap_or_default()),
+        stats.bytes_sent.to_string(),
+        stats.bytes_received.to_string(),
+        rigid_bodies.iter().count().to_string(),
+        cpu_time.map(millis).unwrap_or_default(),
+        energy
+            .map(|energy| format!("{:.3}", energy))
+            .unwrap_or_default(),
+        format!(
+            "{:.3}",
+            window.in_flight_total as f64 / window.frames.max(1) as f64
+        ),
+        window.full_frames.to_string(),
+        export
+            .handover
+            .filter(|handover| handover.overlaps(since))
+            .map(|handover| handover.kind.name().to_string())
+            .unwrap_or_default(),
+        mem::take(&mut degradation.since_export).to_string(),
+    ];
+
+    window.frames = 0;
+    window.in_flight_total = 0;
+    window.full_frames = 0;
+
+    if let Err(err) = export.writer.write_row(&row) {
+        error!("Failed to write metrics: {}", err);
+    }
 }
@@ -0,0 +1,192 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier3d::prelude::*;
+
+use shared::{channel::Channel, BodyTemplate, Request};
+
+use crate::plugin::{RequestQueue, RequestWindow};
+
+/// The time between the predicted points.
+const STEP: f32 = 1.0 / 60.0;
+
+/// The longest stretch predicted, in steps.
+const MAX_STEPS: usize = 180;
+
+/// Below this speed after a bounce, the ball is taken to have come to rest.
+const REST_SPEED: f32 = 0.5;
+
+/// A line strip showing where a ball dropped from `start` would fall and
+/// bounce, predicted locally so that it shows without waiting on the server.
+///
+/// Only the fixed colliders of the local context are bounced off, which are
+/// the server's when its world is mirrored, and the ground plane otherwise.
+/// Other bodies are ignored.
+///
+/// With `remote`, the server steps the ball against its own fixed colliders
+/// instead, and the line follows a round trip behind.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct TrajectoryPreview {
+    pub start: Vec3,
+    pub velocity: Vec3,
+    pub radius: Real,
+    pub restitution: Real,
+    pub remote: bool,
+}
+
+impl TrajectoryPreview {
+    fn template(&self) -> BodyTemplate {
+        BodyTemplate {
+            body: RigidBody::Dynamic,
+            additional_mass_properties: None,
+            damping: None,
+            gravity_scale: None,
+            ccd: false,
+            dominance_group: None,
+            locked_axes: None,
+            shape: Collider::ball(self.radius),
+            scale: None,
+            sensor: None,
+            mass_properties: None,
+            friction: None,
+            restitution: Some(Restitution::coefficient(self.restitution).into()),
+            collision_groups: None,
+            solver_groups: None,
+            active_events: None,
+            active_hooks: None,
+            active_collision_types: None,
+            contact_force_event_threshold: None,
+        }
+    }
+}
+
+/// The remote previews whose trajectory the server was asked for, in the
+/// order of its answers.
+#[derive(Resource, Debug, Default)]
+pub struct PendingTrajectories(VecDeque<Entity>);
+
+/// Integrates the ball's fall, reflecting its velocity off whatever it hits.
+pub fn predict(context: &RapierContext, gravity: Vect, preview: &TrajectoryPreview) -> Vec<Vec3> {
+    let mut position = preview.start;
+    let mut velocity = preview.velocity;
+    let mut points = vec![position];
+
+    for _ in 0..MAX_STEPS {
+        velocity += gravity * STEP;
+        let motion = velocity * STEP;
+        let distance = motion.length();
+        if distance == 0.0 {
+            break;
+        }
+        let direction = motion / distance;
+
+        let hit = context
+            .cast_ray_and_get_normal(
+                position,
+                direction,
+                distance + preview.radius,
+                true,
+                QueryFilter::only_fixed(),
+            )
+            .map(|(_, hit)| (hit.toi - preview.radius, hit.normal))
+            .or_else(|| {
+                // The ground plane, when the server's world isn't mirrored
+                let toi = (position.y - preview.radius) / -direction.y;
+                (direction.y < 0.0 && toi <= distance).then_some((toi, Vec3::Y))
+            });
+
+        match hit {
+            Some((toi, normal)) => {
+                position += direction * toi.max(0.0);
+                velocity = (velocity - 2.0 * velocity.dot(normal) * normal) * preview.restitution;
+                points.push(position);
+                if velocity.length() < REST_SPEED {
+                    break;
+                }
+            }
+            None => {
+                position += motion;
+                points.push(position);
+            }
+        }
+    }
+
+    points
+}
+
+fn set_points(mesh: &mut Mesh, points: &[Vec3]) {
+    let positions: Vec<[f32; 3]> = points.iter().map(|point| point.to_array()).collect();
+    let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+}
+
+/// Predicts the trajectory again when the preview moves.
+pub fn update_previews(
+    context: Res<RapierContext>,
+    config: Res<RapierConfiguration>,
+    previews: Query<(&TrajectoryPreview, &Handle<Mesh>), Changed<TrajectoryPreview>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (preview, mesh) in previews.iter().filter(|(preview, _)| !preview.remote) {
+        if let Some(mesh) = meshes.get_mut(mesh) {
+            set_points(mesh, &predict(&context, config.gravity, preview));
+        }
+    }
+}
+
+/// Asks the server for the trajectories of the remote previews that moved,
+/// once there is room on the query channel.
+pub fn request_trajectories(
+    previews: Query<(
+        Entity,
+        &TrajectoryPreview,
+        ChangeTrackers<TrajectoryPreview>,
+    )>,
+    window: Res<RequestWindow>,
+    mut stale: Local<HashSet<Entity>>,
+    mut pending: ResMut<PendingTrajectories>,
+    mut request_queue: ResMut<RequestQueue>,
+) {
+    stale.extend(
+        previews
+            .iter()
+            .filter(|(_, preview, tracker)| preview.remote && tracker.is_changed())
+            .map(|(entity, _, _)| entity),
+    );
+    if stale.is_empty() || !window.has_room(Channel::Queries) {
+        return;
+    }
+    for entity in stale.drain() {
+        if let Ok((_, preview, _)) = previews.get(entity) {
+            request_queue.0.push(Request::PredictTrajectory {
+                body_template: preview.template(),
+                origin: preview.start,
+                velocity: preview.velocity,
+                steps: MAX_STEPS as u32,
+            });
+            pending.0.push_back(entity);
+        }
+    }
+}
+
+/// Where the server's trajectories are drawn.
+#[derive(SystemParam)]
+pub struct RemoteTrajectories<'w, 's> {
+    pending: ResMut<'w, PendingTrajectories>,
+    previews: Query<'w, 's, &'static Handle<Mesh>, With<TrajectoryPreview>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+}
+
+impl RemoteTrajectories<'_, '_> {
+    /// Draws the answer to the oldest request.
+    pub fn draw(&mut self, points: &[Vec3]) {
+        if let Some(entity) = self.pending.0.pop_front() {
+            if let Ok(mesh) = self.previews.get(entity) {
+                if let Some(mesh) = self.meshes.get_mut(mesh) {
+                    set_points(mesh, points);
+                }
+            }
+        }
+    }
+}
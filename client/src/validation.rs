@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+/// What was wrong with a body's state in a step's result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    /// A position, rotation or velocity was NaN or infinite.
+    NonFinite,
+    OutOfBounds,
+    TooFast,
+}
+
+/// Bodies whose state in a step's result was corrupt, kept where they were
+/// instead. Sent once per step they are left out of.
+#[derive(Debug, Clone)]
+pub struct QuarantinedBodies(pub Vec<(Entity, Corruption)>);
+
+/// The limits a body's state from the server has to be within to be written
+/// back.
+#[derive(Resource)]
+pub struct ResultValidation {
+    /// How far from the origin bodies can be, in meters.
+    pub max_distance: f32,
+    /// In meters and radians per second.
+    pub max_speed: f32,
+    /// The bodies whose latest state was corrupt.
+    pub quarantined: HashSet<Entity>,
+    /// Bodies left out since the last event, with what was wrong.
+    pub unreported: Vec<(Entity, Corruption)>,
+}
+
+impl Default for ResultValidation {
+    fn default() -> Self {
+        Self {
+            max_distance: 1.0e4,
+            max_speed: 1.0e3,
+            quarantined: HashSet::new(),
+            unreported: vec![],
+        }
+    }
+}
+
+impl ResultValidation {
+    pub fn check(&self, transform: &Transform, velocity: &Velocity) -> Result<(), Corruption> {
+        let finite = transform.translation.is_finite()
+            && transform.rotation.is_finite()
+            && velocity.linvel.is_finite()
+            && velocity.angvel.is_finite();
+        if !finite {
+            Err(Corruption::NonFinite)
+        } else if transform.translation.length() > self.max_distance {
+            Err(Corruption::OutOfBounds)
+        } else if velocity.linvel.length() > self.max_speed
+            || velocity.angvel.length() > self.max_speed
+        {
+            Err(Corruption::TooFast)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Keeps track of which bodies are quarantined after a body's state was
+    /// checked.
+    pub fn record(&mut self, entity: Entity, checked: Result<(), Corruption>) {
+        match checked {
+            Ok(()) => {
+                if self.quarantined.remove(&entity) {
+                    info!("Body {:?} left quarantine", entity);
+                }
+            }
+            Err(corruption) => {
+                if self.quarantined.insert(entity) {
+                    warn!(
+                        "Body {:?} quarantined, its state was {:?}",
+                        entity, corruption
+                    );
+                }
+                self.unreported.push((entity, corruption));
+            }
+        }
+    }
+}
+
+/// Runs right after the responses were written back.
+pub fn send_events(
+    mut validation: ResMut<ResultValidation>,
+    mut events: EventWriter<QuarantinedBodies>,
+) {
+    if !validation.unreported.is_empty() {
+        events.send(QuarantinedBodies(std::mem::take(
+            &mut validation.unreported,
+        )));
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt::Write;
+use std::mem;
+use std::sync::{atomic::Ordering, Arc, Mutex};
+
+use bevy::{prelude::*, utils::Instant};
+use shared::{metrics::unix_timestamp, Request};
+
+use crate::{
+    backend::Reconnects,
+    client::{ConnectionControl, RequestStats},
+    plugin::{RequestQueue, RequestWindow},
+};
+
+/// Frames without a response while requests are in flight after which the
+/// connection is reset.
+pub const DEFAULT_FRAMES: u32 = 60;
+
+/// Resets the connection when the server stops answering, after writing what
+/// the request pipeline looked like to `watchdog_<timestamp>.txt`. The world
+/// is then created again like after a handover reconnect.
+#[derive(Resource)]
+pub struct Watchdog {
+    pub frames: u32,
+    pub stats: Arc<Mutex<RequestStats>>,
+    pub control: ConnectionControl,
+    /// Frames in a row that requests were in flight and no response arrived.
+    pub silent_frames: u32,
+    /// When the latest response arrived and what it was.
+    pub last_response: Option<(Instant, &'static str)>,
+}
+
+/// Runs right before the frame's requests are sent.
+pub fn watch(
+    mut watchdog: ResMut<Watchdog>,
+    window: Res<RequestWindow>,
+    request_queue: Res<RequestQueue>,
+    reconnects: Res<Reconnects>,
+) {
+    let stats = mem::take(&mut *watchdog.stats.lock().unwrap());
+    if let Some(exchange) = stats.exchanges.last() {
+        watchdog.last_response = Some((Instant::now(), exchange.response));
+        watchdog.silent_frames = 0;
+        return;
+    }
+    if window.in_flight() == 0 {
+        watchdog.silent_frames = 0;
+        return;
+    }
+
+    watchdog.silent_frames += 1;
+    if watchdog.silent_frames < watchdog.frames {
+        return;
+    }
+    watchdog.silent_frames = 0;
+
+    let dump = dump(&watchdog, &window, &request_queue, &reconnects);
+    let path = format!("watchdog_{}.txt", unix_timestamp());
+    match std::fs::write(&path, &dump) {
+        Ok(()) => error!(
+            "No response for {} frames, resetting the connection, pipeline state written to {}",
+            watchdog.frames, path
+        ),
+        Err(err) => error!(
+            "No response for {} frames, resetting the connection, failed to write {}: {}\n{}",
+            watchdog.frames, path, err, dump
+        ),
+    }
+    watchdog.control.reset();
+}
+
+fn dump(
+    watchdog: &Watchdog,
+    window: &RequestWindow,
+    request_queue: &RequestQueue,
+    reconnects: &Reconnects,
+) -> String {
+    let mut dump = String::new();
+    let _ = writeln!(dump, "No response for {} frames", watchdog.frames);
+    let _ = writeln!(
+        dump,
+        "In flight: {}/{} batches, of frames {:?}",
+        window.in_flight(),
+        window.max_in_flight,
+        window.in_flight_frames.lock().unwrap()
+    );
+    let queued: Vec<&str> = request_queue.0.iter().map(Request::name).collect();
+    let _ = writeln!(dump, "Queued: {:?}", queued);
+    match watchdog.last_response {
+        Some((at, response)) => {
+            let _ = writeln!(dump, "Last response: {} {:?} ago", response, at.elapsed());
+        }
+        None => {
+            let _ = writeln!(dump, "Last response: none");
+        }
+    }
+    let _ = writeln!(dump, "Socket: {}", watchdog.control.status());
+    let _ = writeln!(
+        dump,
+        "Reconnects so far: {}",
+        reconnects.0.load(Ordering::SeqCst)
+    );
+    dump
+}
@@ -0,0 +1,397 @@
+//! Runs the real server on a free port and drives a session through it the
+//! way a client would: a ground and a row of balls are created and stepped
+//! until they rest, removed, and the client connects again. Fails at the first
+//! check that doesn't hold, so that a single command validates the whole
+//! networked pipeline after any change.
+//!
+//! The session speaks the protocol over a raw WebSocket rather than through
+//! the client's plugin in a headless app, the plugin being part of the
+//! client's binary, which this one can't depend on. What the plugin does on
+//! its side, such as sending entities as template instances, batching its
+//! requests or writing the results back to the entities, isn't covered.
+
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::Isometry;
+use bincode::{deserialize, serialize};
+use clap::{arg, command, value_parser};
+use tungstenite::{
+    client::IntoClientRequest,
+    connect,
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+    stream::MaybeTlsStream,
+    Message, WebSocket,
+};
+
+use shared::{
+    channel::{self, Channel},
+    envelope,
+    hooks::ContactRules,
+    protocol::ProtocolVersion,
+    CreatedBody, CreatedCollider, Request, Response,
+};
+
+const GROUND_ID: u64 = 1;
+/// The top of the ground, which the balls come to rest on.
+const GROUND_HEIGHT: Real = 0.5;
+const BALLS: u64 = 10;
+const BALL_RADIUS: Real = 0.25;
+const DROP_HEIGHT: Real = 3.0;
+/// Enough for the balls to fall and settle.
+const TICKS: usize = 300;
+const TICK: f32 = 1.0 / 60.0;
+/// How far from resting on the ground a ball may end up.
+const REST_TOLERANCE: Real = 0.05;
+const REST_SPEED: Real = 0.1;
+
+/// The server takes a moment to start listening.
+const CONNECT_ATTEMPTS: usize = 20;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Kills the server when dropped, whether the scenario passed or not.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+struct Session {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    /// Of every exchange.
+    round_trips: Vec<Duration>,
+}
+
+impl Session {
+    fn connect(port: u16) -> Result<Self> {
+        let mut attempts = 0;
+        loop {
+            let mut request =
+                format!("ws://{}:{}", Ipv4Addr::LOCALHOST, port).into_client_request()?;
+            request.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(ProtocolVersion::CURRENT.name()),
+            );
+            match connect(request) {
+                Ok((socket, _)) => {
+                    return Ok(Self {
+                        socket,
+                        round_trips: vec![],
+                    })
+                }
+                Err(err) if attempts < CONNECT_ATTEMPTS => {
+                    println!("Waiting for the server: {}", err);
+                    attempts += 1;
+                    sleep(Duration::from_millis(250));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn exchange(&mut self, request: Request) -> Result<Response> {
+        let channel = Channel::of(&request);
+        let serialized = serialize(&request)?;
+
+        let start = Instant::now();
+        self.socket
+            .write_message(Message::binary(envelope::seal(&channel::wrap(
+                channel,
+                &serialized,
+            ))))?;
+        // Pings of the server's idle check come in between
+        let msg = loop {
+            let msg = self.socket.read_message()?;
+            if msg.is_binary() {
+                break msg;
+            }
+        };
+        self.round_trips.push(start.elapsed());
+
+        let msg_data = msg.into_data();
+        let (_, msg_data) = channel::unwrap(envelope::open(&msg_data)?)?;
+        let response = deserialize(msg_data)?;
+        Ok(unwrap(response))
+    }
+
+    fn close(mut self) -> Result<()> {
+        self.socket.close(None)?;
+        Ok(())
+    }
+}
+
+/// The response inside the wrappers the server adds when it degrades, paces
+/// or pauses the session.
+fn unwrap(response: Response) -> Response {
+    match response {
+        Response::Degraded(_, response)
+        | Response::Paced(_, response)
+        | Response::Status(_, response) => unwrap(*response),
+        response => response,
+    }
+}
+
+fn unexpected(expected: &str, response: &Response) -> Box<dyn std::error::Error> {
+    format!("expected {}, got {}", expected, response.name()).into()
+}
+
+fn check(holds: bool, what: String) -> Result<()> {
+    if !holds {
+        return Err(what.into());
+    }
+    println!("ok: {}", what);
+    Ok(())
+}
+
+fn percentile(durations: &[Duration], percentile: usize) -> Duration {
+    let mut sorted = durations.to_vec();
+    sorted.sort();
+    sorted
+        .get((sorted.len() * percentile / 100).min(sorted.len().saturating_sub(1)))
+        .copied()
+        .unwrap_or_default()
+}
+
+fn ball_ids() -> impl Iterator<Item = u64> {
+    (0..BALLS).map(|i| GROUND_ID + 1 + i)
+}
+
+fn created_body(id: u64, body: RigidBody, position: Vect) -> CreatedBody {
+    CreatedBody {
+        id,
+        body,
+        transform: Some(Isometry::translation(position.x, position.y, position.z)),
+        additional_mass_properties: None,
+        damping: None,
+        gravity_scale: None,
+        ccd: false,
+        dominance_group: None,
+        locked_axes: None,
+        tag: None,
+    }
+}
+
+fn created_collider(id: u64, shape: Collider) -> CreatedCollider {
+    CreatedCollider {
+        id,
+        shape,
+        scale: None,
+        parent_id: None,
+        transform: None,
+        sensor: None,
+        mass_properties: None,
+        friction: None,
+        restitution: None,
+        collision_groups: None,
+        solver_groups: None,
+        active_events: None,
+        active_hooks: None,
+        active_collision_types: None,
+        contact_force_event_threshold: None,
+    }
+}
+
+/// The scenario, against the server listening on `port`.
+fn run(port: u16, max_round_trip: Duration, max_step_time: Duration) -> Result<()> {
+    let mut session = Session::connect(port)?;
+
+    match session.exchange(Request::UpdateConfig(
+        RapierConfiguration::default().into(),
+        ContactRules::default(),
+    ))? {
+        Response::ConfigUpdated => {}
+        other => return Err(unexpected("ConfigUpdated", &other)),
+    }
+
+    let mut bodies = vec![created_body(GROUND_ID, RigidBody::Fixed, Vect::ZERO)];
+    let mut colliders = vec![created_collider(
+        GROUND_ID,
+        Collider::cuboid(BALLS as Real, GROUND_HEIGHT, BALLS as Real),
+    )];
+    for (i, id) in ball_ids().enumerate() {
+        let x = i as Real - BALLS as Real / 2.0;
+        bodies.push(created_body(
+            id,
+            RigidBody::Dynamic,
+            Vect::new(x, DROP_HEIGHT, 0.0),
+        ));
+        colliders.push(created_collider(id, Collider::ball(BALL_RADIUS)));
+    }
+    match session.exchange(Request::CreateBodies(bodies))? {
+        Response::RigidBodyHandles(handles) => check(
+            handles.len() == BALLS as usize + 1,
+            format!("created {} bodies", handles.len()),
+        )?,
+        other => return Err(unexpected("RigidBodyHandles", &other)),
+    }
+    match session.exchange(Request::CreateColliders(colliders))? {
+        Response::ColliderHandles(handles) => check(
+            handles.len() == BALLS as usize + 1,
+            format!("created {} colliders", handles.len()),
+        )?,
+        other => return Err(unexpected("ColliderHandles", &other)),
+    }
+
+    let steps_start = session.round_trips.len();
+    for _ in 0..TICKS {
+        match session.exchange(Request::SimulateStep(TICK))? {
+            Response::SimulationResult(..) => {}
+            other => return Err(unexpected("SimulationResult", &other)),
+        }
+    }
+    let round_trip = percentile(&session.round_trips[steps_start..], 95);
+    check(
+        round_trip <= max_round_trip,
+        format!(
+            "95th percentile step round trip of {:?}, at most {:?}",
+            round_trip, max_round_trip
+        ),
+    )?;
+
+    match session.exchange(Request::TakeStepTime)? {
+        Response::StepTime(step_time) => check(
+            step_time <= max_step_time,
+            format!(
+                "mean step time of {:?}, at most {:?}",
+                step_time, max_step_time
+            ),
+        )?,
+        other => return Err(unexpected("StepTime", &other)),
+    }
+
+    let state = match session.exchange(Request::GetState)? {
+        Response::State(state) => state,
+        other => return Err(unexpected("State", &other)),
+    };
+    for id in ball_ids() {
+        let ball = state
+            .bodies
+            .iter()
+            .find(|body| body.id == id)
+            .ok_or_else(|| format!("ball {} is missing from the state", id))?;
+        let height = ball.position.translation.y;
+        let speed = ball.linvel.length();
+        check(
+            (height - GROUND_HEIGHT - BALL_RADIUS).abs() <= REST_TOLERANCE && speed <= REST_SPEED,
+            format!(
+                "ball {} rests on the ground at {:.3} m, moving at {:.3} m/s",
+                id, height, speed
+            ),
+        )?;
+    }
+
+    match session.exchange(Request::RemoveBodies(ball_ids().collect()))? {
+        Response::BodiesRemoved => {}
+        other => return Err(unexpected("BodiesRemoved", &other)),
+    }
+    match session.exchange(Request::GetStats)? {
+        Response::Stats(stats) => check(
+            stats.bodies.live == 1 && stats.colliders.live == 1,
+            format!(
+                "only the ground is left after removing the balls: {}",
+                stats
+            ),
+        )?,
+        other => return Err(unexpected("Stats", &other)),
+    }
+    session.close()?;
+
+    let mut session = Session::connect(port)?;
+    match session.exchange(Request::GetStats)? {
+        Response::Stats(stats) => check(
+            stats.bodies.live == 0 && stats.colliders.live == 0,
+            format!("the session after reconnecting starts empty: {}", stats),
+        )?,
+        other => return Err(unexpected("Stats", &other)),
+    }
+    session.close()?;
+
+    Ok(())
+}
+
+/// A port nothing listens on, as picked by the OS.
+fn free_port() -> std::io::Result<u16> {
+    Ok(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port())
+}
+
+fn spawn_server(bin: &Path, port: u16) -> std::io::Result<Server> {
+    let server = Command::new(bin.join("server"))
+        .args([
+            "-p",
+            &port.to_string(),
+            "--bind",
+            &Ipv4Addr::LOCALHOST.to_string(),
+        ])
+        .stdout(Stdio::null())
+        .spawn()?;
+    Ok(Server(server))
+}
+
+fn main() {
+    let matches = command!()
+        .arg(
+            arg!(
+                --bin <DIR> "The directory containing the server binary"
+            )
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(
+                --"max-round-trip" <MS> "The slowest 95th percentile step round trip that passes"
+            )
+            .required(false)
+            .default_value("50")
+            .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(
+                --"max-step-time" <MS> "The slowest mean step time on the server that passes"
+            )
+            .required(false)
+            .default_value("5")
+            .value_parser(value_parser!(u64)),
+        )
+        .get_matches();
+
+    let bin = match matches.get_one::<PathBuf>("bin") {
+        Some(bin) => bin.clone(),
+        // The binaries of the workspace end up next to each other
+        None => std::env::current_exe()
+            .expect("The path of the running binary")
+            .parent()
+            .unwrap()
+            .to_path_buf(),
+    };
+    let max_round_trip = Duration::from_millis(*matches.get_one::<u64>("max-round-trip").unwrap());
+    let max_step_time = Duration::from_millis(*matches.get_one::<u64>("max-step-time").unwrap());
+
+    let result = free_port().and_then(|port| Ok((port, spawn_server(&bin, port)?)));
+    let (port, server) = match result {
+        Ok(started) => started,
+        Err(err) => {
+            eprintln!("Failed to start the server from {}: {}", bin.display(), err);
+            exit(1);
+        }
+    };
+    println!("Server listening on port {}", port);
+
+    let result = run(port, max_round_trip, max_step_time);
+    // Exiting wouldn't drop it
+    drop(server);
+    if let Err(err) = result {
+        eprintln!("FAILED: {}", err);
+        exit(1);
+    }
+    println!("Passed");
+}
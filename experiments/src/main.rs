@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+use clap::{arg, command, value_parser};
+
+use shared::{metadata::RunMetadata, metrics::CsvWriter};
+
+const RESULTS_HEADER: &[&str] = &[
+    "latency_ms",
+    "bandwidth_kbps",
+    "spawn_frames",
+    "seconds",
+    "requests",
+    "rtt_p50_ms",
+    "rtt_p95_ms",
+    "rtt_p99_ms",
+    "bytes_sent",
+    "bytes_received",
+    "final_bodies",
+    "step_mean_ms",
+    "step_max_ms",
+    "client_cpu_ms",
+    "client_energy_j",
+    "placement_error_mean_m",
+    "placement_error_max_m",
+    "placement_elapsed_mean_ms",
+];
+
+/// The first port handed out to a run; every run gets its own port so that
+/// lingering sockets of a previous run can't interfere.
+const BASE_PORT: u16 = 9000;
+
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    latency: u64,
+    bandwidth: u64,
+    spawn: u64,
+}
+
+type CsvRows = Vec<HashMap<String, String>>;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = command!()
+        .arg(
+            arg!(
+                --latencies <LIST> "Comma separated simulated latencies in milliseconds"
+            )
+            .required(false)
+            .default_value("0"),
+        )
+        .arg(
+            arg!(
+                --bandwidths <LIST> "Comma separated simulated bandwidths in kbps, 0 for unlimited"
+            )
+            .required(false)
+            .default_value("0"),
+        )
+        .arg(
+            arg!(
+                --spawn <LIST> "Comma separated ball spawn periods in frames"
+            )
+            .required(false)
+            .default_value("10"),
+        )
+        .arg(
+            arg!(
+                -d --duration <SECONDS> "How long every combination runs"
+            )
+            .required(false)
+            .default_value("30")
+            .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(
+                -o --out <DIR> "The directory the per-run and aggregated results are written to"
+            )
+            .required(false)
+            .default_value("experiment_results")
+            .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(
+                --bin <DIR> "The directory containing the server and client binaries"
+            )
+            .required(false)
+            .value_parser(value_parser!(PathBuf)),
+        )
+        .get_matches();
+
+    let latencies = parse_list(matches.get_one::<String>("latencies").unwrap())?;
+    let bandwidths = parse_list(matches.get_one::<String>("bandwidths").unwrap())?;
+    let spawns = parse_list(matches.get_one::<String>("spawn").unwrap())?;
+    let duration = Duration::from_secs(*matches.get_one::<u64>("duration").unwrap());
+    let out = matches.get_one::<PathBuf>("out").unwrap();
+    let bin = match matches.get_one::<PathBuf>("bin") {
+        Some(bin) => bin.clone(),
+        // The binaries of the workspace end up next to each other
+        None => std::env::current_exe()?.parent().unwrap().to_path_buf(),
+    };
+
+    let mut runs = vec![];
+    for &latency in &latencies {
+        for &bandwidth in &bandwidths {
+            for &spawn in &spawns {
+                runs.push(Run {
+                    latency,
+                    bandwidth,
+                    spawn,
+                });
+            }
+        }
+    }
+
+    fs::create_dir_all(out)?;
+    let metadata = RunMetadata::collect("experiments", env!("CARGO_PKG_VERSION"), &[]);
+    let mut results = CsvWriter::open_with_preamble(
+        out.join("results.csv"),
+        RESULTS_HEADER,
+        &metadata.preamble(),
+    )?;
+
+    for (i, run) in runs.iter().enumerate() {
+        println!("Run {}/{}: {:?}", i + 1, runs.len(), run);
+
+        let dir = out.join(format!(
+            "latency{}_bw{}_spawn{}",
+            run.latency, run.bandwidth, run.spawn
+        ));
+        fs::create_dir_all(&dir)?;
+        // The writers append, which would mix in the rows of an earlier sweep
+        for name in ["client.csv", "server.csv", "placement.csv"] {
+            match fs::remove_file(dir.join(name)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        execute_run(*run, &bin, &dir, BASE_PORT + i as u16, duration)?;
+
+        let client_rows = read_csv(&dir.join("client.csv"))?;
+        let server_rows = read_csv(&dir.join("server.csv"))?;
+        let placement_rows = read_csv(&dir.join("placement.csv"))?;
+        results.write_row(&aggregate(
+            *run,
+            &client_rows,
+            &server_rows,
+            &placement_rows,
+        ))?;
+    }
+
+    println!("Results written to {}", out.join("results.csv").display());
+
+    Ok(())
+}
+
+fn parse_list(list: &str) -> Result<Vec<u64>, std::num::ParseIntError> {
+    list.split(',').map(|value| value.trim().parse()).collect()
+}
+
+fn execute_run(
+    run: Run,
+    bin: &Path,
+    dir: &Path,
+    port: u16,
+    duration: Duration,
+) -> std::io::Result<()> {
+    let mut server = Command::new(bin.join("server"));
+    server
+        .current_dir(dir)
+        .args(["-p", &port.to_string(), "--metrics", "server.csv"])
+        .stdout(Stdio::null());
+    if run.latency > 0 {
+        server.args(["-l", &run.latency.to_string()]);
+    }
+    if run.bandwidth > 0 {
+        server.args(["-b", &run.bandwidth.to_string()]);
+    }
+    let mut server = server.spawn()?;
+
+    // Give the server time to start listening
+    sleep(Duration::from_secs(1));
+
+    // Headless, so that sweeps also run on machines without a display
+    let client = Command::new(bin.join("client"))
+        .current_dir(dir)
+        // Bevy resolves the asset folder relative to the manifest directory
+        .env(
+            "CARGO_MANIFEST_DIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../client"),
+        )
+        .args([
+            "-p",
+            &port.to_string(),
+            "-s",
+            &run.spawn.to_string(),
+            "--metrics",
+            "client.csv",
+            "--energy",
+            "--placement",
+            "placement.csv",
+            "--headless",
+        ])
+        .spawn();
+
+    let mut client = match client {
+        Ok(client) => client,
+        Err(err) => {
+            stop(&mut server)?;
+            return Err(err);
+        }
+    };
+
+    sleep(duration);
+
+    if let Some(status) = client.try_wait()? {
+        println!("Client exited early with {}", status);
+    }
+
+    stop(&mut client)?;
+    stop(&mut server)?;
+
+    Ok(())
+}
+
+fn stop(child: &mut Child) -> std::io::Result<()> {
+    // The child may already have exited on its own
+    let _ = child.kill();
+    child.wait()?;
+    Ok(())
+}
+
+fn read_csv(path: &Path) -> std::io::Result<CsvRows> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            println!("Can't read {}: {}", path.display(), err);
+            return Ok(vec![]);
+        }
+    };
+
+    // Skip the metadata preambles
+    let mut lines = content.lines().filter(|line| !line.starts_with('#'));
+    let header: Vec<&str> = match lines.next() {
+        Some(header) => header.split(',').collect(),
+        None => return Ok(vec![]),
+    };
+
+    Ok(lines
+        .map(|line| {
+            header
+                .iter()
+                .map(|name| name.to_string())
+                .zip(line.split(',').map(|value| value.to_string()))
+                .collect()
+        })
+        .collect())
+}
+
+fn column(rows: &CsvRows, name: &str) -> Vec<f64> {
+    rows.iter()
+        .filter_map(|row| row.get(name)?.parse().ok())
+        .collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn sum(values: &[f64]) -> f64 {
+    values.iter().sum()
+}
+
+fn max(values: &[f64]) -> f64 {
+    values.iter().copied().fold(0.0, f64::max)
+}
+
+fn aggregate(
+    run: Run,
+    client_rows: &CsvRows,
+    server_rows: &CsvRows,
+    placement_rows: &CsvRows,
+) -> Vec<String> {
+    // Rows of seconds without any traffic would drag the percentiles to zero
+    let active_rows: CsvRows = client_rows
+        .iter()
+        .filter(|row| row.get("requests").is_some_and(|requests| requests != "0"))
+        .cloned()
+        .collect();
+
+    vec![
+        run.latency.to_string(),
+        run.bandwidth.to_string(),
+        run.spawn.to_string(),
+        client_rows.len().to_string(),
+        sum(&column(client_rows, "requests")).to_string(),
+        format!("{:.3}", mean(&column(&active_rows, "rtt_p50_ms"))),
+        format!("{:.3}", mean(&column(&active_rows, "rtt_p95_ms"))),
+        format!("{:.3}", mean(&column(&active_rows, "rtt_p99_ms"))),
+        sum(&column(client_rows, "bytes_sent")).to_string(),
+        sum(&column(client_rows, "bytes_received")).to_string(),
+        column(client_rows, "bodies")
+            .last()
+            .copied()
+            .unwrap_or_default()
+            .to_string(),
+        format!("{:.3}", mean(&column(server_rows, "step_mean_ms"))),
+        format!("{:.3}", max(&column(server_rows, "step_max_ms"))),
+        format!("{:.3}", sum(&column(client_rows, "cpu_ms"))),
+        format!("{:.3}", sum(&column(client_rows, "energy_j"))),
+        format!("{:.4}", mean(&column(placement_rows, "error_m"))),
+        format!("{:.4}", max(&column(placement_rows, "error_m"))),
+        format!("{:.3}", mean(&column(placement_rows, "elapsed_ms"))),
+    ]
+}
@@ -0,0 +1,29 @@
+struct Aabb {
+    mins: vec4<f32>,
+    maxs: vec4<f32>,
+};
+
+@group(0) @binding(0) var<storage, read> aabbs: array<Aabb>;
+@group(0) @binding(1) var<storage, read_write> pair_count: atomic<u32>;
+@group(0) @binding(2) var<storage, read_write> pairs: array<vec2<u32>>;
+
+// Brute force all-pairs test, one invocation per AABB against every AABB after it
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let count = arrayLength(&aabbs);
+    let i = id.x;
+    if (i >= count) {
+        return;
+    }
+
+    let a = aabbs[i];
+    for (var j = i + 1u; j < count; j = j + 1u) {
+        let b = aabbs[j];
+        if (all(a.mins.xyz <= b.maxs.xyz) && all(b.mins.xyz <= a.maxs.xyz)) {
+            let slot = atomicAdd(&pair_count, 1u);
+            if (slot < arrayLength(&pairs)) {
+                pairs[slot] = vec2<u32>(i, j);
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use shared::operator::SessionStatus;
+
+use crate::rooms::Rooms;
+
+struct Entry {
+    peer: String,
+    status: Arc<Mutex<SessionStatus>>,
+}
+
+/// The running sessions, by the id operators address them with.
+pub struct Sessions {
+    next_id: AtomicU64,
+    sessions: Mutex<BTreeMap<u64, Entry>>,
+    /// Whose worlds operators address by room name, with rooms enabled.
+    rooms: Option<Arc<Rooms>>,
+}
+
+/// What a command acts on.
+enum Target {
+    Session(u64),
+    /// The world a room's members share.
+    Room(String),
+}
+
+/// A session's place in `Sessions`, given back when dropped.
+pub struct SessionControl {
+    id: u64,
+    status: Arc<Mutex<SessionStatus>>,
+    sessions: Arc<Sessions>,
+}
+
+impl Sessions {
+    pub fn new(rooms: Option<Arc<Rooms>>) -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            sessions: Mutex::new(BTreeMap::new()),
+            rooms,
+        }
+    }
+
+    pub fn register(self: &Arc<Self>, peer: String) -> SessionControl {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let status = Arc::new(Mutex::new(SessionStatus::default()));
+        println!("Session {} is {}", id, peer);
+        self.sessions.lock().unwrap().insert(
+            id,
+            Entry {
+                peer,
+                status: status.clone(),
+            },
+        );
+        SessionControl {
+            id,
+            status,
+            sessions: self.clone(),
+        }
+    }
+
+    fn update(
+        &self,
+        target: Target,
+        update: impl FnOnce(&mut SessionStatus),
+    ) -> Result<String, String> {
+        let id = match target {
+            Target::Session(id) => id,
+            Target::Room(name) => return self.update_room(&name, update),
+        };
+        let sessions = self.sessions.lock().unwrap();
+        let entry = sessions
+            .get(&id)
+            .ok_or_else(|| format!("no session {}", id))?;
+        let mut status = entry.status.lock().unwrap();
+        update(&mut status);
+        println!("Operator set session {} ({}) {}", id, entry.peer, status);
+        Ok(format!("session {} {}", id, status))
+    }
+
+    fn update_room(
+        &self,
+        name: &str,
+        update: impl FnOnce(&mut SessionStatus),
+    ) -> Result<String, String> {
+        let rooms = self.rooms.as_ref().ok_or("rooms aren't enabled")?;
+        let status = rooms
+            .update_status(name, update)
+            .ok_or_else(|| format!("no room {}", name))?;
+        println!("Operator set room {} {}", name, status);
+        Ok(format!("room {} {}", name, status))
+    }
+
+    /// Runs a line of the admin protocol and returns the answer:
+    ///
+    /// - `list` lists the sessions with their id, peer and status, then the
+    ///   rooms with their name, number of members and status
+    /// - `pause <TARGET>` and `resume <TARGET>` stop and restart the clock of
+    ///   a session, given by its id, or of the world a room's members share,
+    ///   given as `room <NAME>`
+    /// - `scale <TARGET> <FACTOR>` runs it at the given speed, 0.5 being half
+    fn command(&self, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let mut target = || {
+            match words.next() {
+                Some("room") => words.next().map(|room| Target::Room(room.to_string())),
+                Some(id) => id.parse::<u64>().ok().map(Target::Session),
+                None => None,
+            }
+            .ok_or_else(|| format!("expected {} <ID> or {} room <NAME>", name, name))
+        };
+        match name {
+            "list" => {
+                let sessions = self.sessions.lock().unwrap();
+                let mut lines: Vec<String> = sessions
+                    .iter()
+                    .map(|(id, entry)| {
+                        format!("{} {} {}", id, entry.peer, entry.status.lock().unwrap())
+                    })
+                    .collect();
+                for (room, members, status) in self.rooms.iter().flat_map(|rooms| rooms.list()) {
+                    lines.push(format!("room {} {} members {}", room, members, status));
+                }
+                Ok(lines.join("\n"))
+            }
+            "pause" => self.update(target()?, |status| status.paused = true),
+            "resume" => self.update(target()?, |status| status.paused = false),
+            "scale" => {
+                let target = target()?;
+                let time_scale = words
+                    .next()
+                    .and_then(|factor| factor.parse::<f32>().ok())
+                    .filter(|factor| factor.is_finite() && *factor > 0.0)
+                    .ok_or("expected scale <TARGET> <FACTOR>, the factor above 0")?;
+                self.update(target, |status| status.time_scale = time_scale)
+            }
+            _ => Err(format!("unknown command {}", name)),
+        }
+    }
+}
+
+impl SessionControl {
+    pub fn status(&self) -> SessionStatus {
+        *self.status.lock().unwrap()
+    }
+}
+
+impl Drop for SessionControl {
+    fn drop(&mut self) {
+        self.sessions.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Answers operators' commands, one per line, on localhost only as they
+/// aren't authenticated.
+pub fn listen(port: u16, sessions: Arc<Sessions>) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    println!("Admin commands on {}", listener.local_addr()?);
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let sessions = sessions.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, &sessions) {
+                            println!("Admin error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => println!("Error: {}", e),
+            }
+        }
+    }))
+}
+
+fn serve(stream: TcpStream, sessions: &Sessions) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let answer = match sessions.command(&line) {
+            Ok(answer) => answer,
+            Err(err) => format!("error: {}", err),
+        };
+        writeln!(writer, "{}", answer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use shared::{impairment::Impairment, metadata::RunMetadata, pacing::StepPacing};
+
+    use super::*;
+    use crate::SessionOptions;
+
+    fn options() -> SessionOptions {
+        SessionOptions {
+            impairment: Impairment::default(),
+            impairment_key: None,
+            record: None,
+            metrics: None,
+            snapshot_budget: None,
+            scenes_dir: PathBuf::new(),
+            seed: 0,
+            pool: None,
+            ground: false,
+            default_scene: None,
+            profile: None,
+            step_pacing: StepPacing::default(),
+            idle_timeout: Duration::from_secs(60),
+            metadata: Arc::new(RunMetadata::collect("server", "test", &[])),
+            compression_threshold: 0,
+            compression_level: None,
+            coalesce: false,
+            sessions: None,
+            max_worlds: 1,
+            max_bodies: None,
+            rooms: None,
+            tick: None,
+            #[cfg(feature = "parallel")]
+            threads: 1,
+        }
+    }
+
+    /// The clock every member's turns in the room run with.
+    fn statuses(memberships: &[&crate::rooms::Membership]) -> Vec<SessionStatus> {
+        memberships
+            .iter()
+            .map(|membership| {
+                let mut state = membership.room.state.lock().unwrap();
+                let turn = state.turn(membership.member);
+                let status = turn.status;
+                state.end_turn(turn);
+                status
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pausing_a_room_pauses_its_world_for_every_member() {
+        let rooms = Arc::new(Rooms::default());
+        let first = rooms.join("lobby", &options());
+        let second = rooms.join("lobby", &options());
+        let sessions = Sessions::new(Some(rooms.clone()));
+
+        assert_eq!(
+            sessions.command("pause room lobby"),
+            Ok("room lobby paused".to_string())
+        );
+        assert!(statuses(&[&first, &second])
+            .iter()
+            .all(|status| status.paused));
+        assert_eq!(
+            sessions.command("list"),
+            Ok("room lobby 2 members paused".to_string())
+        );
+
+        sessions.command("resume room lobby").unwrap();
+        assert!(statuses(&[&first, &second])
+            .iter()
+            .all(|status| !status.paused));
+    }
+
+    #[test]
+    fn unknown_rooms_are_refused() {
+        let sessions = Sessions::new(Some(Arc::new(Rooms::default())));
+        assert_eq!(
+            sessions.command("pause room lobby"),
+            Err("no room lobby".to_string())
+        );
+        assert_eq!(
+            Sessions::new(None).command("scale room lobby 2"),
+            Err("rooms aren't enabled".to_string())
+        );
+    }
+}
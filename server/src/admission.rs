@@ -0,0 +1,90 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::http::{header::RETRY_AFTER, StatusCode};
+
+#[derive(Default)]
+struct Occupancy {
+    active: usize,
+    queued: usize,
+}
+
+/// Limits how many sessions run at once. A few connections beyond the limit
+/// wait for a session to end, the rest are turned away with a hint of when to
+/// retry and where else to go.
+pub struct Admission {
+    max_sessions: Option<usize>,
+    max_queued: usize,
+    occupancy: Mutex<Occupancy>,
+    freed: Condvar,
+}
+
+/// A running session's place, given back when dropped.
+pub struct SessionSlot(Arc<Admission>);
+
+impl Admission {
+    pub fn new(max_sessions: Option<usize>, max_queued: usize) -> Self {
+        Self {
+            max_sessions,
+            max_queued,
+            occupancy: Mutex::new(Occupancy::default()),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Blocks while queued, `None` if the queue is full too.
+    pub fn admit(self: &Arc<Self>) -> Option<SessionSlot> {
+        let mut occupancy = self.occupancy.lock().unwrap();
+        let max_sessions = match self.max_sessions {
+            Some(max_sessions) => max_sessions,
+            None => {
+                occupancy.active += 1;
+                return Some(SessionSlot(self.clone()));
+            }
+        };
+
+        if occupancy.active >= max_sessions {
+            if occupancy.queued >= self.max_queued {
+                return None;
+            }
+            occupancy.queued += 1;
+            println!("Server full, {} connections queued", occupancy.queued);
+            while occupancy.active >= max_sessions {
+                occupancy = self.freed.wait(occupancy).unwrap();
+            }
+            occupancy.queued -= 1;
+        }
+        occupancy.active += 1;
+        Some(SessionSlot(self.clone()))
+    }
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        self.0.occupancy.lock().unwrap().active -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// How a connection is turned away when the server is full.
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    pub retry_after: u64,
+    /// Another server to try in the meantime.
+    pub alternative: Option<String>,
+}
+
+/// Answers the WebSocket handshake with `503 Service Unavailable`.
+impl Callback for Rejection {
+    fn on_request(self, _: &Request, _: Response) -> Result<Response, ErrorResponse> {
+        let mut message = format!("The server is full, retry in {} seconds", self.retry_after);
+        let mut response = tungstenite::http::Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(RETRY_AFTER, self.retry_after);
+        if let Some(alternative) = self.alternative {
+            message += &format!(" or connect to {}", alternative);
+            response = response.header("X-Alternative-Server", alternative);
+        }
+        Err(response.body(Some(message)).unwrap())
+    }
+}
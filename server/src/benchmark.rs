@@ -0,0 +1,270 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{ColliderBuilder, RigidBodyBuilder};
+use bevy_rapier3d::utils;
+use bincode::serialize;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
+
+use shared::{
+    codec::Encoding,
+    compression::{self, Compression, Compressor},
+    Request, Response,
+};
+
+const DELTA_TIME: f32 = 1.0 / 60.0;
+
+const BENCHMARK_STEPS: u32 = 5;
+
+/// How many times every message is encoded and decoded.
+const ENCODING_ROUNDS: u32 = 1000;
+
+/// Creates a world of randomly placed, partly overlapping unit balls, the same
+/// for the same seed.
+pub fn random_ball_world(bodies: usize, seed: u64) -> RapierContext {
+    let mut context = RapierContext::default();
+
+    // Roughly one ball per 8 cubic units
+    let extent = (bodies as f32).cbrt();
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..bodies {
+        let position = Vec3::new(
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+        );
+        let body = context
+            .bodies
+            .insert(RigidBodyBuilder::dynamic().translation(position.into()));
+        context
+            .colliders
+            .insert_with_parent(ColliderBuilder::ball(0.5), body, &mut context.bodies);
+    }
+
+    context
+}
+
+/// Returns the mean duration of a step, not counting the first step which
+/// initializes the broad-phase from scratch.
+pub fn mean_step_time(context: &mut RapierContext) -> Duration {
+    let mut sim_to_render_time = SimulationToRenderTime::default();
+    let timestep_mode = TimestepMode::Variable {
+        max_dt: DELTA_TIME,
+        time_scale: 1.0,
+        substeps: 1,
+    };
+
+    crate::step_context(
+        context,
+        Vect::ZERO,
+        timestep_mode,
+        None,
+        &(),
+        DELTA_TIME,
+        &mut sim_to_render_time,
+    );
+
+    let mut total = Duration::ZERO;
+    for _ in 0..BENCHMARK_STEPS {
+        let start = Instant::now();
+        crate::step_context(
+            context,
+            Vect::ZERO,
+            timestep_mode,
+            None,
+            &(),
+            DELTA_TIME,
+            &mut sim_to_render_time,
+        );
+        total += start.elapsed();
+    }
+    total / BENCHMARK_STEPS
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn run(bodies: usize, seed: u64) {
+    let mut context = random_ball_world(bodies, seed);
+    println!(
+        "{} bodies: {:?} per step",
+        bodies,
+        mean_step_time(&mut context)
+    );
+}
+
+/// Measures the step time of the same world with doubling thread pool sizes
+/// up to `max_threads`.
+#[cfg(feature = "parallel")]
+pub fn run_scaling(
+    bodies: usize,
+    max_threads: usize,
+    seed: u64,
+) -> Result<(), rayon::ThreadPoolBuildError> {
+    let mut thread_counts: Vec<usize> = std::iter::successors(Some(1), |threads| Some(threads * 2))
+        .take_while(|&threads| threads < max_threads)
+        .collect();
+    thread_counts.push(max_threads);
+
+    let mut single_threaded = None;
+    for threads in thread_counts {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()?;
+        let mut context = random_ball_world(bodies, seed);
+        let step_time = pool.install(|| mean_step_time(&mut context));
+        let baseline = *single_threaded.get_or_insert(step_time);
+        println!(
+            "{} bodies, {} threads: {:?} per step ({:.2}x)",
+            bodies,
+            threads,
+            step_time,
+            baseline.as_secs_f64() / step_time.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+/// The results of a step of worlds of growing size.
+fn step_results(seed: u64) -> Vec<(String, Response)> {
+    let mut results = vec![];
+    for bodies in [10, 100, 1000] {
+        let mut context = random_ball_world(bodies, seed);
+        // Overlapping balls pushing each other apart, for realistic velocities
+        mean_step_time(&mut context);
+        let result = context
+            .bodies
+            .iter()
+            .map(|(handle, rb)| {
+                let velocity = Velocity {
+                    linvel: (*rb.linvel()).into(),
+                    angvel: (*rb.angvel()).into(),
+                };
+                (
+                    handle,
+                    (utils::iso_to_transform(rb.position(), 1.0), velocity),
+                )
+            })
+            .collect();
+        results.push((
+            format!("SimulationResult of {} bodies", bodies),
+            Response::SimulationResult(result, vec![], vec![]),
+        ));
+    }
+    results
+}
+
+/// Compares the encoding and decoding time and size of typical messages in
+/// every compression at `level`, compressed from `threshold` bytes on and
+/// always compressed.
+pub fn compression(
+    threshold: usize,
+    level: Option<i32>,
+    seed: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut messages = vec![
+        (
+            "SimulateStep request".to_string(),
+            serialize(&Request::SimulateStep(DELTA_TIME))?,
+        ),
+        (
+            "ConfigUpdated response".to_string(),
+            serialize(&Response::ConfigUpdated)?,
+        ),
+    ];
+    for (name, result) in step_results(seed) {
+        messages.push((name, serialize(&result)?));
+    }
+
+    for (name, serialized) in messages {
+        for compression in Compression::ALL
+            .into_iter()
+            .filter(|&compression| compression != Compression::None)
+        {
+            let compressor = Compressor {
+                compression,
+                level,
+                threshold,
+            };
+            let (thresholded_len, thresholded_time) = measure_encoding(&serialized, compressor)?;
+            let (compressed_len, compressed_time) = measure_encoding(
+                &serialized,
+                Compressor {
+                    threshold: 0,
+                    ..compressor
+                },
+            )?;
+            println!(
+                "{} of {} bytes in {}: {} bytes in {:?} with a threshold of {}, {} bytes in {:?} always compressed",
+                name,
+                serialized.len(),
+                compression,
+                thresholded_len,
+                thresholded_time,
+                threshold,
+                compressed_len,
+                compressed_time
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Compares the size of typical messages and the time to encode and decode
+/// them in every encoding.
+pub fn codecs(seed: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut messages = vec![(
+        "ConfigUpdated response".to_string(),
+        Response::ConfigUpdated,
+    )];
+    messages.extend(step_results(seed));
+
+    let request = Request::SimulateStep(DELTA_TIME);
+    for encoding in Encoding::ALL {
+        let (len, time) = measure_codec(encoding, &request)?;
+        println!(
+            "SimulateStep request in {}: {} bytes in {:?}",
+            encoding, len, time
+        );
+    }
+    for (name, response) in messages {
+        for encoding in Encoding::ALL {
+            let (len, time) = measure_codec(encoding, &response)?;
+            println!("{} in {}: {} bytes in {:?}", name, encoding, len, time);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the encoded size of a message and the mean time to encode and
+/// decode it.
+fn measure_codec<T: Serialize + DeserializeOwned>(
+    encoding: Encoding,
+    message: &T,
+) -> io::Result<(usize, Duration)> {
+    let mut len = 0;
+    let start = Instant::now();
+    for _ in 0..ENCODING_ROUNDS {
+        let encoded = encoding.encode(message)?;
+        len = encoded.len();
+        encoding.decode::<T>(&encoded)?;
+    }
+    Ok((len, start.elapsed() / ENCODING_ROUNDS))
+}
+
+/// Returns the framed size of a message and the mean time to encode and
+/// decode it.
+fn measure_encoding(serialized: &[u8], compressor: Compressor) -> io::Result<(usize, Duration)> {
+    let mut len = 0;
+    let start = Instant::now();
+    for _ in 0..ENCODING_ROUNDS {
+        let framed = compressor.encode(serialized)?;
+        len = framed.len();
+        compression::decode(compressor.compression, &framed)?;
+    }
+    Ok((len, start.elapsed() / ENCODING_ROUNDS))
+}
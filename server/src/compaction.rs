@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem;
+
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{
+    BroadPhase, ColliderHandle, ColliderSet, ImpulseJointHandle, ImpulseJointSet, IslandManager,
+    NarrowPhase, RigidBodyHandle, RigidBodySet,
+};
+
+use shared::arena::{ArenaStats, WorldStats};
+
+/// The new handle of everything that was in the world before compaction.
+#[derive(Default)]
+pub struct Remap {
+    pub bodies: HashMap<RigidBodyHandle, RigidBodyHandle>,
+    pub colliders: HashMap<ColliderHandle, ColliderHandle>,
+    pub impulse_joints: HashMap<ImpulseJointHandle, ImpulseJointHandle>,
+}
+
+pub fn stats(context: &RapierContext, compactions: u32) -> WorldStats {
+    WorldStats {
+        bodies: ArenaStats::of(
+            context
+                .bodies
+                .iter()
+                .map(|(handle, _)| handle.into_raw_parts().0),
+        ),
+        colliders: ArenaStats::of(
+            context
+                .colliders
+                .iter()
+                .map(|(handle, _)| handle.into_raw_parts().0),
+        ),
+        impulse_joints: ArenaStats::of(
+            context
+                .impulse_joints
+                .iter()
+                .map(|(handle, _)| handle.into_raw_parts().0),
+        ),
+        compactions,
+    }
+}
+
+/// Moves every body, collider and impulse joint into new sets without free
+/// slots, oldest first. Contacts and islands are found again in the next step.
+/// Multibody joints are left alone, so worlds with them mustn't be compacted.
+pub fn compact(context: &mut RapierContext) -> Remap {
+    let bodies = mem::replace(&mut context.bodies, RigidBodySet::new());
+    let colliders = mem::replace(&mut context.colliders, ColliderSet::new());
+    let impulse_joints = mem::replace(&mut context.impulse_joints, ImpulseJointSet::new());
+    context.islands = IslandManager::new();
+    context.broad_phase = BroadPhase::new();
+    context.narrow_phase = NarrowPhase::new();
+
+    let mut remap = Remap::default();
+    for (old, body) in in_slot_order(bodies.iter(), |handle| handle.into_raw_parts()) {
+        let new = context.bodies.insert(body.clone());
+        remap.bodies.insert(old, new);
+    }
+    for (old, collider) in in_slot_order(colliders.iter(), |handle| handle.into_raw_parts()) {
+        let new = match collider
+            .parent()
+            .and_then(|parent| remap.bodies.get(&parent))
+        {
+            Some(&parent) => {
+                context
+                    .colliders
+                    .insert_with_parent(collider.clone(), parent, &mut context.bodies)
+            }
+            None => context.colliders.insert(collider.clone()),
+        };
+        remap.colliders.insert(old, new);
+    }
+    for (old, joint) in in_slot_order(impulse_joints.iter(), |handle| handle.into_raw_parts()) {
+        let (body1, body2) = match (
+            remap.bodies.get(&joint.body1),
+            remap.bodies.get(&joint.body2),
+        ) {
+            (Some(&body1), Some(&body2)) => (body1, body2),
+            _ => continue,
+        };
+        let new = context
+            .impulse_joints
+            .insert(body1, body2, joint.data, true);
+        remap.impulse_joints.insert(old, new);
+    }
+
+    context
+        .query_pipeline
+        .update(&context.bodies, &context.colliders);
+    remap
+}
+
+fn in_slot_order<H: Copy, T>(
+    items: impl Iterator<Item = (H, T)>,
+    raw_parts: impl Fn(H) -> (u32, u32),
+) -> Vec<(H, T)> {
+    let mut items: Vec<(H, T)> = items.collect();
+    items.sort_by_key(|(handle, _)| raw_parts(*handle).0);
+    items
+}
+
+/// Re-keys `map` by the new handles, dropping what is no longer in the world.
+pub fn remap_keys<K: Copy + Eq + Hash, V>(map: &mut HashMap<K, V>, remap: &HashMap<K, K>) {
+    *map = mem::take(map)
+        .into_iter()
+        .filter_map(|(old, value)| Some((*remap.get(&old)?, value)))
+        .collect();
+}
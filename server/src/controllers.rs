@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::RigidBodyHandle;
+use rand::{rngs::StdRng, Rng};
+
+use shared::Controller;
+
+use crate::compaction::remap_keys;
+
+/// How close to a patrol waypoint a body has to get before heading to the next.
+const WAYPOINT_RADIUS: Real = 1.0;
+/// How far ahead of a wandering body its unit heading circle is.
+const WANDER_DISTANCE: Real = 1.0;
+/// The most the wander heading turns per second, in radians.
+const WANDER_JITTER: Real = 2.0;
+
+struct ControlledBody {
+    controller: Controller,
+    /// The waypoint a patrol is heading to.
+    waypoint: usize,
+    /// The direction a wander is heading to, in radians around the y axis.
+    heading: Real,
+}
+
+/// Runs the controllers attached to bodies, steering them before every step.
+pub struct Controllers {
+    bodies: HashMap<RigidBodyHandle, ControlledBody>,
+    /// Drives wandering, seeded so that runs can be repeated.
+    rng: StdRng,
+}
+
+impl Controllers {
+    pub fn new(rng: StdRng) -> Self {
+        Self {
+            bodies: HashMap::new(),
+            rng,
+        }
+    }
+
+    pub fn set(&mut self, handle: RigidBodyHandle, controller: Option<Controller>) {
+        match controller {
+            Some(controller) => {
+                self.bodies.insert(
+                    handle,
+                    ControlledBody {
+                        controller,
+                        waypoint: 0,
+                        heading: self.rng.gen_range(0.0..std::f32::consts::TAU),
+                    },
+                );
+            }
+            None => {
+                self.bodies.remove(&handle);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bodies.clear();
+    }
+
+    pub fn remap(&mut self, bodies: &HashMap<RigidBodyHandle, RigidBodyHandle>) {
+        remap_keys(&mut self.bodies, bodies);
+    }
+
+    /// Applies the steering force of every controller over `delta_time` as an
+    /// impulse, so that it doesn't add up with forces set by the client.
+    pub fn apply(&mut self, context: &mut RapierContext, delta_time: f32) {
+        let scale = context.physics_scale();
+        let rng = &mut self.rng;
+        self.bodies
+            .retain(|handle, controlled| match context.bodies.get_mut(*handle) {
+                Some(rb) => {
+                    let position = Vect::from(*rb.translation()) * scale;
+                    let velocity = Vect::from(*rb.linvel()) * scale;
+                    let force = controlled.steer(position, velocity, delta_time, rng);
+                    if force != Vect::ZERO {
+                        rb.apply_impulse((force * delta_time / scale).into(), true);
+                    }
+                    true
+                }
+                // The body was removed
+                None => false,
+            });
+    }
+}
+
+impl ControlledBody {
+    fn steer(&mut self, position: Vect, velocity: Vect, delta_time: f32, rng: &mut StdRng) -> Vect {
+        let (desired, max_force) = match &self.controller {
+            Controller::Seek {
+                target,
+                max_speed,
+                max_force,
+            } => (seek(position, *target, *max_speed), *max_force),
+            Controller::Wander {
+                max_speed,
+                max_force,
+            } => {
+                self.heading += rng.gen_range(-1.0..=1.0) * WANDER_JITTER * delta_time;
+                let ahead = horizontal(velocity).normalize_or_zero() * WANDER_DISTANCE;
+                let around = Vect::new(self.heading.cos(), 0.0, self.heading.sin());
+                (
+                    (ahead + around).normalize_or_zero() * *max_speed,
+                    *max_force,
+                )
+            }
+            Controller::Patrol {
+                waypoints,
+                max_speed,
+                max_force,
+            } => {
+                if waypoints.is_empty() {
+                    return Vect::ZERO;
+                }
+                let mut target = waypoints[self.waypoint % waypoints.len()];
+                if horizontal(target - position).length() < WAYPOINT_RADIUS {
+                    self.waypoint = (self.waypoint + 1) % waypoints.len();
+                    target = waypoints[self.waypoint];
+                }
+                (seek(position, target, *max_speed), *max_force)
+            }
+        };
+        (desired - horizontal(velocity)).clamp_length_max(max_force)
+    }
+}
+
+fn seek(position: Vect, target: Vect, max_speed: Real) -> Vect {
+    horizontal(target - position).normalize_or_zero() * max_speed
+}
+
+/// Controllers only steer along the ground, gravity takes care of the rest.
+fn horizontal(v: Vect) -> Vect {
+    Vect::new(v.x, 0.0, v.z)
+}
@@ -0,0 +1,166 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{ColliderBuilder, RigidBodyBuilder};
+
+use shared::scene::content_hash;
+
+use crate::scene;
+
+const DELTA_TIME: f32 = 1.0 / 60.0;
+
+const GRAVITY: Vect = Vect::new(0.0, -9.81, 0.0);
+
+/// The side of the square pyramid of boxes in the canonical scene.
+const PYRAMID_BASE: usize = 6;
+
+/// The side of the square grid of balls dropped onto it.
+const BALL_GRID: usize = 5;
+
+/// Builds the scene whose steps are hashed: a pyramid of boxes on the ground
+/// hit by a grid of falling balls, placed without randomness so that it
+/// doesn't change with the rand crate.
+pub fn canonical_world() -> RapierContext {
+    let mut context = RapierContext::default();
+    scene::create_ground(&mut context);
+
+    for layer in 0..PYRAMID_BASE {
+        let side = PYRAMID_BASE - layer;
+        let offset = (side as f32 - 1.0) / 2.0;
+        for x in 0..side {
+            for z in 0..side {
+                let position = Vec3::new(x as f32 - offset, layer as f32 + 0.5, z as f32 - offset);
+                insert_body(
+                    &mut context,
+                    position,
+                    ColliderBuilder::cuboid(0.5, 0.5, 0.5),
+                );
+            }
+        }
+    }
+
+    let offset = (BALL_GRID as f32 - 1.0) / 2.0;
+    for x in 0..BALL_GRID {
+        for z in 0..BALL_GRID {
+            // Slightly off the grid of boxes, so that the pyramid topples
+            let position = Vec3::new(
+                (x as f32 - offset) * 1.3 + 0.1,
+                PYRAMID_BASE as f32 + 3.0 + (x + z) as f32 * 0.5,
+                (z as f32 - offset) * 1.3 - 0.2,
+            );
+            insert_body(&mut context, position, ColliderBuilder::ball(0.4));
+        }
+    }
+
+    context
+}
+
+fn insert_body(context: &mut RapierContext, position: Vec3, collider: ColliderBuilder) {
+    let body = context
+        .bodies
+        .insert(RigidBodyBuilder::dynamic().translation(position.into()));
+    context
+        .colliders
+        .insert_with_parent(collider, body, &mut context.bodies);
+}
+
+/// Hashes the exact bits of the position and velocity of every body, in handle
+/// order.
+pub fn hash_world(context: &RapierContext) -> u64 {
+    let mut bodies: Vec<_> = context.bodies.iter().collect();
+    bodies.sort_by_key(|(handle, _)| handle.into_raw_parts());
+
+    let mut bytes = vec![];
+    for (_, rb) in bodies {
+        let position = rb.position();
+        let values = position
+            .translation
+            .vector
+            .iter()
+            .chain(position.rotation.coords.iter())
+            .chain(rb.linvel().iter())
+            .chain(rb.angvel().iter());
+        for value in values {
+            bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+    }
+    content_hash(&bytes)
+}
+
+/// Steps the canonical scene and returns the hash of the world after every
+/// tick.
+pub fn run(ticks: u32) -> Vec<u64> {
+    let mut context = canonical_world();
+    let mut sim_to_render_time = SimulationToRenderTime::default();
+    let timestep_mode = TimestepMode::Fixed {
+        dt: DELTA_TIME,
+        substeps: 1,
+    };
+
+    (0..ticks)
+        .map(|_| {
+            crate::step_context(
+                &mut context,
+                GRAVITY,
+                timestep_mode,
+                None,
+                &(),
+                DELTA_TIME,
+                &mut sim_to_render_time,
+            );
+            hash_world(&context)
+        })
+        .collect()
+}
+
+/// One `<TICK> <HASH>` line per tick after comment lines starting with `#`,
+/// which name what the hashes were made with.
+pub fn write(path: &Path, hashes: &[u64], features: &[&str]) -> io::Result<()> {
+    let mut file = String::new();
+    let _ = writeln!(
+        file,
+        "# {} ticks of the canonical scene, server {}, features: {}",
+        hashes.len(),
+        env!("CARGO_PKG_VERSION"),
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(",")
+        }
+    );
+    for (tick, hash) in hashes.iter().enumerate() {
+        let _ = writeln!(file, "{} {:016x}", tick, hash);
+    }
+    fs::write(path, file)
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<u64>> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected <TICK> <HASH>, got {}", line),
+        )
+    };
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (_, hash) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            u64::from_str_radix(hash, 16).map_err(|_| invalid(line))
+        })
+        .collect()
+}
+
+/// Compares the hashes of a run with golden ones, returning the first tick
+/// where they differ. A run longer or shorter than the golden hashes is only
+/// compared over the ticks both have.
+pub fn first_divergence(hashes: &[u64], golden: &[u64]) -> Option<usize> {
+    hashes
+        .iter()
+        .zip(golden)
+        .position(|(hash, golden)| hash != golden)
+}
@@ -0,0 +1,112 @@
+use std::mem;
+
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use shared::{CollisionChange, ContactForce, StepEvents};
+
+type Writers = (
+    EventWriter<'static, 'static, CollisionEvent>,
+    EventWriter<'static, 'static, ContactForceEvent>,
+);
+
+/// Collects the events bevy_rapier sends while stepping, into a world of its
+/// own as bevy_rapier only sends them to bevy events.
+///
+/// Collecting only starts once the client asks for events the first time, so
+/// sessions that never do don't buffer them forever.
+pub struct EventCollector {
+    enabled: bool,
+    world: World,
+    writers: SystemState<Writers>,
+    events: StepEvents,
+}
+
+impl Default for EventCollector {
+    fn default() -> Self {
+        let mut world = World::new();
+        world.init_resource::<Events<CollisionEvent>>();
+        world.init_resource::<Events<ContactForceEvent>>();
+        let writers = SystemState::new(&mut world);
+        Self {
+            enabled: false,
+            world,
+            writers,
+            events: StepEvents::default(),
+        }
+    }
+}
+
+impl EventCollector {
+    /// Collecting from the start, for the events to be handed on.
+    pub fn enabled() -> Self {
+        Self {
+            enabled: true,
+            ..Self::default()
+        }
+    }
+
+    /// What to pass to a step, `None` until collecting starts.
+    pub fn writers(
+        &mut self,
+    ) -> Option<(
+        EventWriter<'_, '_, CollisionEvent>,
+        EventWriter<'_, '_, ContactForceEvent>,
+    )> {
+        if !self.enabled {
+            return None;
+        }
+        Some(self.writers.get_mut(&mut self.world))
+    }
+
+    /// Moves the events of a step into those to be taken.
+    pub fn record(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        let mut collisions = self.world.resource_mut::<Events<CollisionEvent>>();
+        self.events
+            .collisions
+            .extend(collisions.drain().map(|event| match event {
+                CollisionEvent::Started(entity1, entity2, flags) => CollisionChange {
+                    entity1: entity1.to_bits(),
+                    entity2: entity2.to_bits(),
+                    started: true,
+                    flags: flags.bits(),
+                },
+                CollisionEvent::Stopped(entity1, entity2, flags) => CollisionChange {
+                    entity1: entity1.to_bits(),
+                    entity2: entity2.to_bits(),
+                    started: false,
+                    flags: flags.bits(),
+                },
+            }));
+        let mut contact_forces = self.world.resource_mut::<Events<ContactForceEvent>>();
+        self.events
+            .contact_forces
+            .extend(contact_forces.drain().map(|event| ContactForce {
+                entity1: event.collider1.to_bits(),
+                entity2: event.collider2.to_bits(),
+                total_force: event.total_force,
+                total_force_magnitude: event.total_force_magnitude,
+                max_force_direction: event.max_force_direction,
+                max_force_magnitude: event.max_force_magnitude,
+            }));
+    }
+
+    /// Adds the events of steps collected elsewhere, as the host's steps of
+    /// a room are for its members.
+    pub fn extend(&mut self, events: StepEvents) {
+        if !self.enabled {
+            return;
+        }
+        self.events.collisions.extend(events.collisions);
+        self.events.contact_forces.extend(events.contact_forces);
+    }
+
+    pub fn take(&mut self) -> StepEvents {
+        self.enabled = true;
+        mem::take(&mut self.events)
+    }
+}
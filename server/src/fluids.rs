@@ -0,0 +1,75 @@
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{Point, Vector};
+
+use shared::FluidVolume;
+
+/// Applies buoyancy and drag to the bodies in fluid volumes. Bodies are
+/// approximated by their bounding box, how much of it is in a volume is how
+/// submerged they are.
+#[derive(Default)]
+pub struct FluidVolumes {
+    volumes: Vec<FluidVolume>,
+}
+
+impl FluidVolumes {
+    pub fn add(&mut self, volumes: Vec<FluidVolume>) {
+        self.volumes.extend(volumes);
+    }
+
+    /// Pushes the bodies in fluids for `delta_time` before a step, with
+    /// impulses so that it doesn't add up with forces set by the client.
+    pub fn apply(&self, context: &mut RapierContext, gravity: Vect, delta_time: f32) {
+        if self.volumes.is_empty() {
+            return;
+        }
+
+        let scale = context.physics_scale();
+        let gravity = gravity / scale;
+        let RapierContext {
+            bodies, colliders, ..
+        } = context;
+
+        for volume in &self.volumes {
+            let min = volume.min / scale;
+            let max = volume.max / scale;
+
+            for (_, collider) in colliders.iter() {
+                let rb = match collider.parent().and_then(|parent| bodies.get_mut(parent)) {
+                    Some(rb) if rb.is_dynamic() => rb,
+                    _ => continue,
+                };
+
+                let aabb = collider.compute_aabb();
+                let aabb_min = Vect::from(aabb.mins.coords);
+                let aabb_max = Vect::from(aabb.maxs.coords);
+                let overlap_min = aabb_min.max(min);
+                let overlap_max = aabb_max.min(max);
+                let overlap = overlap_max - overlap_min;
+                if overlap.min_element() <= 0.0 {
+                    continue;
+                }
+                let size = aabb_max - aabb_min;
+                let submerged = (overlap.x * overlap.y * overlap.z)
+                    / (size.x * size.y * size.z).max(Real::EPSILON);
+
+                // The mass of a shape at density 1 is its volume
+                let displaced = collider.shape().mass_properties(1.0).mass() * submerged;
+                let buoyancy = -gravity * volume.density * displaced * delta_time;
+                let center = (overlap_min + overlap_max) / 2.0;
+                rb.apply_impulse_at_point(
+                    Vector::from(buoyancy),
+                    Point::from(Vector::from(center)),
+                    true,
+                );
+
+                // Damping velocities directly can't overshoot and reverse them
+                let linear_damping = (volume.linear_drag * submerged * delta_time).min(1.0);
+                let angular_damping = (volume.angular_drag * submerged * delta_time).min(1.0);
+                let linvel = *rb.linvel() * (1.0 - linear_damping);
+                let angvel = *rb.angvel() * (1.0 - angular_damping);
+                rb.set_linvel(linvel, true);
+                rb.set_angvel(angvel, true);
+            }
+        }
+    }
+}
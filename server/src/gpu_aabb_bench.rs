@@ -0,0 +1,248 @@
+//! Standalone benchmark of AABB overlap tests on the GPU.
+//!
+//! This is not a broad-phase: rapier's `BroadPhase` is a concrete type that
+//! can't be swapped out of its physics pipeline, and a pair filter in the
+//! physics hooks could only discard the pairs rapier already found, so the
+//! pairs found here are never fed into the simulation. The `--bench-gpu-aabbs`
+//! mode only measures whether offloading the AABB overlap tests to an edge
+//! node's GPU would be worth a custom pipeline for very large worlds.
+//!
+//! A GPU broad-phase with a CPU fallback stays to be done. It would take a
+//! fork of rapier: a step of our own in place of `PhysicsPipeline::step`
+//! would have to hand the pairs found here to the narrow phase as they appear
+//! and disappear, which `NarrowPhase::register_pairs` only lets rapier do. The
+//! fork would keep rapier's `BroadPhase` for worlds below `MIN_COLLIDERS` and
+//! for servers without a GPU.
+
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+use bevy_rapier3d::prelude::*;
+use wgpu::util::DeviceExt;
+
+/// Below this many colliders rapier's own broad-phase always wins.
+pub const MIN_COLLIDERS: usize = 50_000;
+
+const WORKGROUP_SIZE: usize = 64;
+
+/// Space reserved for overlapping pairs per collider.
+const PAIRS_PER_COLLIDER: usize = 16;
+
+const BENCHMARK_STEPS: u32 = 5;
+
+pub type AabbBounds = ([f32; 3], [f32; 3]);
+
+pub struct GpuAabbOverlaps {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuAabbOverlaps {
+    /// Returns `None` if no suitable GPU is available.
+    pub fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        }))?;
+        println!("Using GPU adapter {:?}", adapter.get_info().name);
+
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("aabb_overlaps_device"),
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::default(),
+            },
+            None,
+        ))
+        .ok()?;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("aabb_overlaps_shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("aabb_overlaps.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("aabb_overlaps_pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: "main",
+        });
+
+        Some(Self {
+            device,
+            queue,
+            pipeline,
+        })
+    }
+
+    /// Finds the indices of every pair of overlapping AABBs.
+    pub fn find_pairs(&self, aabbs: &[AabbBounds]) -> Vec<(u32, u32)> {
+        if aabbs.len() < 2 {
+            return vec![];
+        }
+
+        let capacity = aabbs.len() * PAIRS_PER_COLLIDER;
+
+        let aabb_data: Vec<u8> = aabbs
+            .iter()
+            .flat_map(|(mins, maxs)| {
+                [
+                    mins[0], mins[1], mins[2], 0.0, maxs[0], maxs[1], maxs[2], 0.0,
+                ]
+            })
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+
+        let aabb_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("aabbs"),
+                contents: &aabb_data,
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let count_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("pair_count"),
+                contents: &0u32.to_ne_bytes(),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            });
+        let pairs_size = (capacity * 2 * std::mem::size_of::<u32>()) as u64;
+        let pairs_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pairs"),
+            size: pairs_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size: 4 + pairs_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("aabb_overlaps_bind_group"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: aabb_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: count_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: pairs_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            let workgroups = aabbs.len().div_ceil(WORKGROUP_SIZE);
+            pass.dispatch_workgroups(workgroups as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&count_buffer, 0, &staging_buffer, 0, 4);
+        encoder.copy_buffer_to_buffer(&pairs_buffer, 0, &staging_buffer, 4, pairs_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = staging_buffer.slice(..);
+        let (sender, receiver) = channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        match receiver.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                println!("Failed to read AABB overlap results: {}", err);
+                return vec![];
+            }
+            Err(_) => {
+                println!("AABB overlap results were never mapped");
+                return vec![];
+            }
+        }
+
+        let data = slice.get_mapped_range();
+        let words: Vec<u32> = data
+            .chunks_exact(4)
+            .map(|bytes| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        drop(data);
+        staging_buffer.unmap();
+
+        let found = words[0] as usize;
+        if found > capacity {
+            println!("Found {} overlapping pairs, keeping {}", found, capacity);
+        }
+
+        words[1..]
+            .chunks_exact(2)
+            .take(found.min(capacity))
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
+}
+
+pub fn collider_aabbs(context: &RapierContext) -> Vec<AabbBounds> {
+    context
+        .colliders
+        .iter()
+        .map(|(_, collider)| {
+            let aabb = collider.compute_aabb();
+            (
+                [aabb.mins.x, aabb.mins.y, aabb.mins.z],
+                [aabb.maxs.x, aabb.maxs.y, aabb.maxs.z],
+            )
+        })
+        .collect()
+}
+
+/// Compares rapier's step time against the GPU pair search on a world of
+/// randomly placed balls.
+pub fn benchmark(colliders: usize, seed: u64) {
+    if colliders < MIN_COLLIDERS {
+        println!(
+            "Note: GPU overlap tests only pay off in worlds with at least {} colliders",
+            MIN_COLLIDERS
+        );
+    }
+
+    let mut context = crate::benchmark::random_ball_world(colliders, seed);
+    let cpu_time = crate::benchmark::mean_step_time(&mut context);
+    let cpu_pairs = context.narrow_phase.contact_pairs().count();
+    println!("rapier: {:?} per step, {} pairs", cpu_time, cpu_pairs);
+
+    let gpu = match GpuAabbOverlaps::new() {
+        Some(gpu) => gpu,
+        None => {
+            println!("No GPU available to benchmark");
+            return;
+        }
+    };
+
+    let aabbs = collider_aabbs(&context);
+    let mut gpu_time = Duration::ZERO;
+    let mut gpu_pairs = 0;
+    for _ in 0..BENCHMARK_STEPS {
+        let start = Instant::now();
+        gpu_pairs = gpu.find_pairs(&aabbs).len();
+        gpu_time += start.elapsed();
+    }
+    println!(
+        "GPU AABB overlaps: {:?} per pass, {} pairs",
+        gpu_time / BENCHMARK_STEPS,
+        gpu_pairs
+    );
+}
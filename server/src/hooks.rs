@@ -0,0 +1,79 @@
+use bevy_rapier3d::rapier::prelude::{
+    ActiveHooks, Collider, ColliderSet, ContactModificationContext, PairFilterContext,
+    PhysicsHooks, SolverFlags, Vector,
+};
+
+use shared::hooks::{ContactRules, ONE_WAY_ALLOWED_ANGLE};
+
+/// The physics hooks of a world, following the contact rules its client set.
+pub struct RuleHooks<'a>(pub &'a ContactRules);
+
+fn memberships(collider: &Collider) -> u32 {
+    collider.collision_groups().memberships.bits()
+}
+
+impl PhysicsHooks for RuleHooks<'_> {
+    fn filter_contact_pair(&self, context: &PairFilterContext) -> Option<SolverFlags> {
+        let collider1 = &context.colliders[context.collider1];
+        let collider2 = &context.colliders[context.collider2];
+        self.0
+            .allows(memberships(collider1), memberships(collider2))
+            .then_some(SolverFlags::COMPUTE_IMPULSES)
+    }
+
+    fn filter_intersection_pair(&self, context: &PairFilterContext) -> bool {
+        let collider1 = &context.colliders[context.collider1];
+        let collider2 = &context.colliders[context.collider2];
+        self.0
+            .allows(memberships(collider1), memberships(collider2))
+    }
+
+    fn modify_solver_contacts(&self, context: &mut ContactModificationContext) {
+        let collider1 = &context.colliders[context.collider1];
+        let collider2 = &context.colliders[context.collider2];
+        // The platform's up, in the frame of the first collider whose normals
+        // the contacts have
+        let allowed_local_n1 = if self.0.is_one_way_platform(memberships(collider1)) {
+            Vector::y()
+        } else if self.0.is_one_way_platform(memberships(collider2)) {
+            let up = collider2.position().rotation * Vector::y();
+            collider1.position().rotation.inverse() * -up
+        } else {
+            return;
+        };
+        context.update_as_oneway_platform(&allowed_local_n1, ONE_WAY_ALLOWED_ANGLE);
+    }
+}
+
+/// Has rapier call the hooks for the colliders of the world that the rules
+/// apply to, which it only does for colliders asking for them. Only the
+/// colliders that don't ask yet are changed, so that the others aren't
+/// marked as modified.
+pub fn activate(rules: &ContactRules, colliders: &mut ColliderSet) {
+    if rules.is_empty() {
+        return;
+    }
+    let filter = if rules.rules.is_empty() {
+        ActiveHooks::empty()
+    } else {
+        ActiveHooks::FILTER_CONTACT_PAIRS | ActiveHooks::FILTER_INTERSECTION_PAIR
+    };
+    let needed = |collider: &Collider| {
+        if rules.is_one_way_platform(memberships(collider)) {
+            filter | ActiveHooks::MODIFY_SOLVER_CONTACTS
+        } else {
+            filter
+        }
+    };
+    let inactive: Vec<_> = colliders
+        .iter()
+        .filter(|(_, collider)| !collider.active_hooks().contains(needed(collider)))
+        .map(|(handle, _)| handle)
+        .collect();
+    for handle in inactive {
+        if let Some(collider) = colliders.get_mut(handle) {
+            let hooks = collider.active_hooks() | needed(collider);
+            collider.set_active_hooks(hooks);
+        }
+    }
+}
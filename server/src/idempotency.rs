@@ -0,0 +1,36 @@
+use std::collections::VecDeque;
+
+use shared::Response;
+
+/// How many responses to keyed requests a session remembers. A retry comes
+/// within a few frames of the first try, so only the latest are needed.
+const CAPACITY: usize = 256;
+
+/// The responses to the latest `Request::Idempotent`s, so that a request sent
+/// again gets the handles of what it created the first time.
+#[derive(Default)]
+pub struct RecentResults {
+    results: VecDeque<(u64, Response)>,
+}
+
+impl RecentResults {
+    pub fn get(&self, key: u64) -> Option<&Response> {
+        self.results
+            .iter()
+            .find(|(recent, _)| *recent == key)
+            .map(|(_, response)| response)
+    }
+
+    pub fn insert(&mut self, key: u64, response: Response) {
+        if self.results.len() == CAPACITY {
+            self.results.pop_front();
+        }
+        self.results.push_back((key, response));
+    }
+
+    /// Forgets every response, whose handles are no longer valid after the
+    /// world is reset or compacted.
+    pub fn clear(&mut self) {
+        self.results.clear();
+    }
+}
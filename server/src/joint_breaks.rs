@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::ImpulseJointHandle;
+
+use shared::JointBreak;
+
+use crate::compaction::remap_keys;
+
+/// Removes joints pulled harder than their break force after every step and
+/// keeps them until the client takes them.
+#[derive(Default)]
+pub struct JointBreaks {
+    break_forces: HashMap<ImpulseJointHandle, Real>,
+    broken: Vec<JointBreak>,
+}
+
+impl JointBreaks {
+    pub fn insert(&mut self, handle: ImpulseJointHandle, break_force: Option<Real>) {
+        if let Some(break_force) = break_force {
+            self.break_forces.insert(handle, break_force);
+        }
+    }
+
+    pub fn remap(&mut self, impulse_joints: &HashMap<ImpulseJointHandle, ImpulseJointHandle>) {
+        remap_keys(&mut self.break_forces, impulse_joints);
+    }
+
+    pub fn record(&mut self, context: &mut RapierContext) {
+        if self.break_forces.is_empty() {
+            return;
+        }
+
+        let scale = context.physics_scale();
+        let dt = context.integration_parameters.dt;
+        let mut broken = vec![];
+        self.break_forces.retain(|&handle, &mut break_force| {
+            match context.impulse_joints.get(handle) {
+                Some(joint) => {
+                    let force = joint.impulses.fixed_rows::<3>(0).norm() / dt * scale;
+                    if force > break_force {
+                        broken.push((handle, force));
+                        false
+                    } else {
+                        true
+                    }
+                }
+                // Removed along with one of its bodies
+                None => false,
+            }
+        });
+
+        for (handle, force) in broken {
+            let joint = match context.impulse_joints.remove(handle, true) {
+                Some(joint) => joint,
+                None => continue,
+            };
+            let entity = |body| {
+                context
+                    .bodies
+                    .get(body)
+                    .map(|rb| rb.user_data as u64)
+                    .unwrap_or_default()
+            };
+            self.broken.push(JointBreak {
+                entity1: entity(joint.body1),
+                entity2: entity(joint.body2),
+                force,
+            });
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<JointBreak> {
+        std::mem::take(&mut self.broken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_rapier3d::rapier::prelude::{
+        ColliderBuilder, Point, RigidBodyBuilder, SphericalJointBuilder,
+    };
+
+    use super::*;
+
+    #[test]
+    fn joint_pulled_past_its_break_force_is_removed_and_reported_once() {
+        let mut context = RapierContext::default();
+        let anchor = context
+            .bodies
+            .insert(RigidBodyBuilder::fixed().user_data(1));
+        let load = context.bodies.insert(
+            RigidBodyBuilder::dynamic()
+                .translation([0.0, -1.0, 0.0].into())
+                .user_data(2),
+        );
+        context.colliders.insert_with_parent(
+            ColliderBuilder::ball(0.5).density(10.0),
+            load,
+            &mut context.bodies,
+        );
+        let joint = SphericalJointBuilder::new().local_anchor2(Point::new(0.0, 1.0, 0.0));
+        let handle = context.impulse_joints.insert(anchor, load, joint, true);
+
+        let mut joint_breaks = JointBreaks::default();
+        // Well under the weight of the load
+        joint_breaks.insert(handle, Some(10.0));
+
+        let delta_time = 1.0 / 60.0;
+        let mut sim_to_render_time = SimulationToRenderTime::default();
+        for _ in 0..10 {
+            crate::step_context(
+                &mut context,
+                Vect::new(0.0, -9.81, 0.0),
+                TimestepMode::Fixed {
+                    dt: delta_time,
+                    substeps: 1,
+                },
+                None,
+                &(),
+                delta_time,
+                &mut sim_to_render_time,
+            );
+            joint_breaks.record(&mut context);
+        }
+
+        assert!(context.impulse_joints.get(handle).is_none());
+        let broken = joint_breaks.take();
+        assert_eq!(broken.len(), 1);
+        assert_eq!((broken[0].entity1, broken[0].entity2), (1, 2));
+        assert!(broken[0].force > 10.0);
+        assert!(joint_breaks.take().is_empty());
+    }
+}
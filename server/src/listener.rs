@@ -0,0 +1,165 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+#[cfg(unix)]
+use std::{
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+#[cfg(unix)]
+use tokio::{
+    io::ReadBuf,
+    net::{UnixListener, UnixStream},
+};
+
+/// A connection a session can be served on.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {
+    /// Who is on the other end, for the logs.
+    fn peer(&self) -> io::Result<String>;
+}
+
+impl Connection for TcpStream {
+    fn peer(&self) -> io::Result<String> {
+        Ok(self.peer_addr()?.to_string())
+    }
+}
+
+/// Clients of Unix domain sockets usually have no address, so they are told
+/// apart by the order they connected in.
+#[cfg(unix)]
+struct UnixConnection {
+    stream: UnixStream,
+    number: usize,
+}
+
+#[cfg(unix)]
+impl AsyncRead for UnixConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+#[cfg(unix)]
+impl AsyncWrite for UnixConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixConnection {
+    fn peer(&self) -> io::Result<String> {
+        Ok(format!("unix#{}", self.number))
+    }
+}
+
+/// An address to listen on, given with `--bind`.
+#[derive(Debug, Clone)]
+pub enum BindAddr {
+    /// An IPv4 or IPv6 address, on `--port` unless it has its own.
+    Tcp(IpAddr, Option<u16>),
+    /// The path of a Unix domain socket, for clients on the same machine.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(ip, Some(port)) => write!(f, "{}", SocketAddr::new(*ip, *port)),
+            Self::Tcp(ip, None) => write!(f, "{}", ip),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Parses `<IP>`, `<IP>:<PORT>`, `[<IPv6>]`, `[<IPv6>]:<PORT>` or
+/// `unix:<PATH>`.
+pub fn parse_bind(addr: &str) -> Result<BindAddr, String> {
+    if let Some(path) = addr.strip_prefix("unix:") {
+        #[cfg(unix)]
+        return Ok(BindAddr::Unix(PathBuf::from(path)));
+        #[cfg(not(unix))]
+        return Err(format!(
+            "Unix domain sockets aren't supported here, can't bind {}",
+            path
+        ));
+    }
+    if let Ok(socket_addr) = addr.parse::<SocketAddr>() {
+        return Ok(BindAddr::Tcp(socket_addr.ip(), Some(socket_addr.port())));
+    }
+    let ip = addr.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>()
+        .map(|ip| BindAddr::Tcp(ip, None))
+        .map_err(|_| format!("expected an IP address or unix:<PATH>, got {}", addr))
+}
+
+/// Accepts connections on `addr` on a task of its own, handing each to
+/// `serve`.
+pub async fn listen<F>(addr: &BindAddr, port: u16, serve: Arc<F>) -> io::Result<JoinHandle<()>>
+where
+    F: Fn(Box<dyn Connection>) + Send + Sync + 'static,
+{
+    match addr {
+        BindAddr::Tcp(ip, own_port) => {
+            let listener =
+                TcpListener::bind(SocketAddr::new(*ip, own_port.unwrap_or(port))).await?;
+            println!("Listening on {}", listener.local_addr()?);
+            Ok(tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => serve(Box::new(stream)),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+            }))
+        }
+        #[cfg(unix)]
+        BindAddr::Unix(path) => {
+            // A socket left behind by an earlier run would fail the bind
+            if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)?;
+            println!("Listening on unix:{}", path.display());
+            let connections = AtomicUsize::new(0);
+            Ok(tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => serve(Box::new(UnixConnection {
+                            stream,
+                            number: connections.fetch_add(1, Ordering::Relaxed),
+                        })),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+            }))
+        }
+    }
+}
@@ -1,29 +1,654 @@
 use bevy::prelude::*;
-use bevy_rapier3d::rapier::prelude::{ColliderBuilder, RigidBodyBuilder, RigidBodyHandle};
+use bevy_rapier3d::rapier::prelude::{
+    ColliderBuilder, ColliderHandle, ImpulseJointHandle, Isometry, PhysicsHooks, RigidBodyBuilder,
+    RigidBodyHandle, RigidBodyType,
+};
 use bevy_rapier3d::{prelude::*, utils};
 
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::thread::sleep;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use bincode::{deserialize, serialize};
-use clap::{arg, command, value_parser};
-use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
-use rand::{thread_rng, Rng};
-use tungstenite::{accept, Message};
+use clap::{arg, builder::RangedU64ValueParser, command, value_parser};
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use tokio_tungstenite::accept_hdr_async;
+use tungstenite::Message;
 
-use shared::*;
+use shared::{
+    capabilities::Capabilities,
+    codec::Encoding,
+    compression::{Compression, Compressor},
+    degradation::Degradation,
+    framing::Framing,
+    hooks::ContactRules,
+    ids::{IdMap, PhysicsId},
+    impairment::{Impairment, ImpairmentError, SimulatedLatency},
+    layers::LayerRegistry,
+    metadata::RunMetadata,
+    metrics::*,
+    mirror::*,
+    operator::SessionStatus,
+    protocol::ProtocolVersion,
+    recording::*,
+    serializable::{SerializableExternalForce, SerializableExternalImpulse},
+    transaction::TransactionError,
+    worlds::{WorldError, DEFAULT_WORLD},
+    *,
+};
 
-#[derive(Debug, Clone, Copy)]
-enum SimulatedLatency {
-    None,
-    Fixed(u64),
-    Random { min: u64, mean: u64 },
+mod admin;
+mod admission;
+mod benchmark;
+mod compaction;
+mod controllers;
+mod determinism;
+mod events;
+mod fluids;
+#[cfg(feature = "gpu-aabb-bench")]
+mod gpu_aabb_bench;
+mod hooks;
+mod idempotency;
+mod joint_breaks;
+mod listener;
+mod pacing;
+mod partition;
+mod pool;
+mod protocol;
+mod queries;
+mod ragdoll;
+mod rooms;
+mod rope;
+mod scene;
+mod snapshot;
+mod store;
+mod streaming;
+mod tags;
+mod trajectory;
+mod transaction;
+mod worlds;
+
+/// How many times the client is pinged before an idle session is torn down.
+const PINGS_PER_IDLE_TIMEOUT: u32 = 3;
+
+/// The largest ping reply and calibration world a client can ask for.
+const MAX_PING_REPLY: usize = 1 << 20;
+const MAX_MEASURED_BODIES: usize = 10_000;
+
+/// The minimum retransmission timeout of TCP, by which lost responses are
+/// delayed.
+const RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
+
+const METRICS_HEADER: &[&str] = &[
+    "timestamp",
+    "peer",
+    "requests",
+    "bytes_received",
+    "bytes_sent",
+    "bodies",
+    "steps",
+    "step_mean_ms",
+    "step_max_ms",
+    "degradation",
+];
+
+/// Per-session traffic and step statistics since the last metrics row.
+#[derive(Debug, Default)]
+struct SessionStats {
+    requests: usize,
+    bytes_received: usize,
+    bytes_sent: usize,
+    step_times: Vec<Duration>,
+    degradation: Degradation,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Settings given on the command line that apply to every session.
+#[derive(Clone)]
+struct SessionOptions {
+    /// Of sessions whose client doesn't set its own.
+    impairment: Impairment,
+    /// What clients setting their own impairment have to send, which they
+    /// can't without.
+    impairment_key: Option<String>,
+    record: Option<String>,
+    metrics: Option<Arc<Mutex<CsvWriter>>>,
+    snapshot_budget: Option<usize>,
+    scenes_dir: PathBuf,
+    /// Seeds the random number generator of every session.
+    seed: u64,
+    pool: Option<Arc<pool::WorldPool>>,
+    /// Whether new worlds start with a large fixed ground.
+    ground: bool,
+    /// A scene every new world starts with.
+    default_scene: Option<String>,
+    /// The profile of sessions whose client doesn't pick one.
+    profile: Option<profile::Profile>,
+    /// The step pacing of sessions whose client doesn't pick one.
+    step_pacing: shared::pacing::StepPacing,
+    /// How long a session can go without hearing from its client.
+    idle_timeout: Duration,
+    /// Sent to clients in the handshake.
+    metadata: Arc<RunMetadata>,
+    /// Responses shorter than this many bytes aren't compressed.
+    compression_threshold: usize,
+    /// Of the responses, the compression's default if `None`.
+    compression_level: Option<i32>,
+    /// Whether requests that arrive together are answered together.
+    coalesce: bool,
+    /// Sessions operators can control, with the admin port open.
+    sessions: Option<Arc<admin::Sessions>>,
+    /// How many worlds a session can have, its first one included.
+    max_worlds: usize,
+    /// How many bodies a world can have, unlimited if `None`.
+    max_bodies: Option<usize>,
+    /// That sessions can join to share a world, with rooms enabled.
+    rooms: Option<Arc<rooms::Rooms>>,
+    /// The period every world is stepped at on its own, the results pushed
+    /// to its client, if the server steps them rather than its clients.
+    tick: Option<Duration>,
+    #[cfg(feature = "parallel")]
+    threads: usize,
+}
+
+/// Everything a connection's requests act on.
+struct Session {
+    context: RapierContext,
+    config: Option<RapierConfiguration>,
+    /// Run by the world's physics hooks.
+    contact_rules: ContactRules,
+    sim_to_render_time: SimulationToRenderTime,
+    id2body: IdMap<RigidBodyHandle>,
+    templates: HashMap<u64, BodyTemplate>,
+    tags: tags::Tags,
+    stats: SessionStats,
+    events: events::EventCollector,
+    snapshot_filter: snapshot::SnapshotFilter,
+    controllers: controllers::Controllers,
+    ropes: rope::Ropes,
+    fluids: fluids::FluidVolumes,
+    joint_breaks: joint_breaks::JointBreaks,
+    recent_results: idempotency::RecentResults,
+    scenes_dir: PathBuf,
+    /// A scene the world came with from the pool, until the client asks for it.
+    preloaded_scene: Option<scene::PreloadedScene>,
+    profile: Option<profile::Profile>,
+    /// The names of collision group bits used in the logs.
+    layers: LayerRegistry,
+    /// The total time and count of steps since the client last asked.
+    unreported_steps: (Duration, u32),
+    /// How the response to the request being handled was degraded.
+    degradation: Degradation,
+    pacer: pacing::Pacer,
+    /// How many times the world was compacted.
+    compactions: u32,
+    /// Of the messages after the handshake.
+    framing: Framing,
+    encoding: Encoding,
+    compression: Compression,
+    metadata: Arc<RunMetadata>,
+    impairment: Impairment,
+    impairment_key: Option<String>,
+    /// Drives the simulated latency and calibration, seeded so that runs can
+    /// be repeated.
+    rng: StdRng,
+    /// Where operators pause and rescale the session's clock, with the admin
+    /// port open.
+    control: Option<admin::SessionControl>,
+    /// As operators last set the clock of the session's room, taken with
+    /// every turn in it.
+    room_status: SessionStatus,
+    /// The status the client was last told about.
+    reported_status: SessionStatus,
+    /// As the client last set it.
+    simulation_state: SimulationState,
+    /// Set while the client has the results pushed to it.
+    stream: Option<streaming::Stream>,
+    /// When the latest messages were read, in microseconds since the Unix
+    /// epoch.
+    received_at: u64,
+    /// Of the world requests act on, whose state is in the fields above.
+    world_id: u64,
+    /// The session's other worlds.
+    worlds: HashMap<u64, worlds::World>,
+    /// That new worlds are made from.
+    options: SessionOptions,
+    /// Set while a request of a room member is handled, its world being the
+    /// room's.
+    turn: Option<rooms::Turn>,
+}
+
+impl Session {
+    fn new(options: &SessionOptions) -> Self {
+        let mut rng = StdRng::seed_from_u64(options.seed);
+        let world = worlds::World::new(options, &mut rng);
+        Self {
+            context: world.context,
+            config: world.config,
+            contact_rules: world.contact_rules,
+            sim_to_render_time: world.sim_to_render_time,
+            id2body: world.id2body,
+            templates: world.templates,
+            tags: world.tags,
+            stats: SessionStats::default(),
+            events: world.events,
+            snapshot_filter: world.snapshot_filter,
+            controllers: world.controllers,
+            ropes: world.ropes,
+            fluids: world.fluids,
+            joint_breaks: world.joint_breaks,
+            recent_results: world.recent_results,
+            scenes_dir: options.scenes_dir.clone(),
+            preloaded_scene: world.preloaded_scene,
+            profile: options.profile,
+            layers: LayerRegistry::default(),
+            unreported_steps: world.unreported_steps,
+            degradation: Degradation::NONE,
+            pacer: pacing::Pacer::new(options.step_pacing),
+            compactions: world.compactions,
+            framing: Framing::Binary,
+            encoding: Encoding::Bincode,
+            compression: Compression::None,
+            metadata: options.metadata.clone(),
+            impairment: options.impairment,
+            impairment_key: options.impairment_key.clone(),
+            rng,
+            control: None,
+            room_status: SessionStatus::default(),
+            reported_status: SessionStatus::default(),
+            simulation_state: world.simulation_state,
+            stream: world.stream,
+            received_at: 0,
+            world_id: DEFAULT_WORLD,
+            worlds: HashMap::new(),
+            options: options.clone(),
+            turn: None,
+        }
+    }
+
+    /// Swaps the world requests act on with `world`.
+    fn swap_world(&mut self, world: &mut worlds::World) {
+        use std::mem::swap;
+        swap(&mut self.context, &mut world.context);
+        swap(&mut self.config, &mut world.config);
+        swap(&mut self.contact_rules, &mut world.contact_rules);
+        swap(&mut self.sim_to_render_time, &mut world.sim_to_render_time);
+        swap(&mut self.id2body, &mut world.id2body);
+        swap(&mut self.templates, &mut world.templates);
+        swap(&mut self.tags, &mut world.tags);
+        swap(&mut self.events, &mut world.events);
+        swap(&mut self.snapshot_filter, &mut world.snapshot_filter);
+        swap(&mut self.controllers, &mut world.controllers);
+        swap(&mut self.ropes, &mut world.ropes);
+        swap(&mut self.fluids, &mut world.fluids);
+        swap(&mut self.joint_breaks, &mut world.joint_breaks);
+        swap(&mut self.recent_results, &mut world.recent_results);
+        swap(&mut self.preloaded_scene, &mut world.preloaded_scene);
+        swap(&mut self.unreported_steps, &mut world.unreported_steps);
+        swap(&mut self.compactions, &mut world.compactions);
+        swap(&mut self.simulation_state, &mut world.simulation_state);
+        swap(&mut self.stream, &mut world.stream);
+    }
+
+    /// Swaps what a member of a room keeps apart from the others with what
+    /// the session holds.
+    fn swap_member_state(&mut self, state: &mut rooms::MemberState) {
+        use std::mem::swap;
+        swap(&mut self.recent_results, &mut state.recent_results);
+        swap(&mut self.events, &mut state.events);
+        swap(&mut self.joint_breaks, &mut state.joint_breaks);
+    }
+
+    /// Makes the world of the id the one requests act on.
+    fn enter_world(&mut self, id: u64) -> Result<(), WorldError> {
+        if id == self.world_id {
+            return Ok(());
+        }
+        let mut world = self.worlds.remove(&id).ok_or(WorldError::Unknown(id))?;
+        self.swap_world(&mut world);
+        self.worlds.insert(self.world_id, world);
+        self.world_id = id;
+        Ok(())
+    }
+
+    fn create_world(&mut self, id: u64) -> Result<u64, WorldError> {
+        if id == self.world_id || self.worlds.contains_key(&id) {
+            return Err(WorldError::Exists(id));
+        }
+        if self.worlds.len() + 1 >= self.options.max_worlds {
+            return Err(WorldError::TooMany(self.options.max_worlds));
+        }
+        let world = worlds::World::new(&self.options, &mut self.rng);
+        self.worlds.insert(id, world);
+        Ok(id)
+    }
+
+    fn destroy_world(&mut self, id: u64) -> Result<u64, WorldError> {
+        if id == DEFAULT_WORLD {
+            return Err(WorldError::Default);
+        }
+        if id == self.world_id {
+            return Err(WorldError::InUse(id));
+        }
+        self.worlds
+            .remove(&id)
+            .map(|_| id)
+            .ok_or(WorldError::Unknown(id))
+    }
+
+    /// The worlds whose streamed step is due and its delta time, scheduling
+    /// the steps after them.
+    fn take_due_streams(&mut self) -> Vec<(u64, f32)> {
+        let current = std::iter::once((self.world_id, &mut self.stream));
+        let parked = self
+            .worlds
+            .iter_mut()
+            .map(|(&id, world)| (id, &mut world.stream));
+        current
+            .chain(parked)
+            .filter_map(|(id, stream)| {
+                let stream = stream.as_mut()?;
+                stream
+                    .take_due()
+                    .then_some((id, stream.period.as_secs_f32()))
+            })
+            .collect()
+    }
+
+    /// How long until the next streamed step of any world is due.
+    fn until_next_stream(&self) -> Option<Duration> {
+        std::iter::once(&self.stream)
+            .chain(self.worlds.values().map(|world| &world.stream))
+            .flatten()
+            .map(streaming::Stream::until_next)
+            .min()
+    }
+
+    /// Into how many segments balls, cylinders, cones and capsules are cut
+    /// when scaled unevenly, which makes them convex polyhedra.
+    fn scaled_shape_subdivision(&self) -> u32 {
+        self.config.unwrap_or_default().scaled_shape_subdivision
+    }
+
+    /// As the operator last set it and the clock of its room, and paused
+    /// while the client paused it.
+    fn status(&self) -> SessionStatus {
+        let mut status = self
+            .control
+            .as_ref()
+            .map_or_else(SessionStatus::default, admin::SessionControl::status);
+        status.paused |= self.room_status.paused;
+        status.time_scale *= self.room_status.time_scale;
+        status.paused |= self.simulation_state == SimulationState::Paused;
+        status
+    }
+
+    /// Drops every body and collider the client created, keeping the scene
+    /// and fluid volumes.
+    fn reset_world(&mut self) {
+        let context = &mut self.context;
+        let bodies: Vec<RigidBodyHandle> =
+            context.bodies.iter().map(|(handle, _)| handle).collect();
+        for handle in bodies {
+            context.bodies.remove(
+                handle,
+                &mut context.islands,
+                &mut context.colliders,
+                &mut context.impulse_joints,
+                &mut context.multibody_joints,
+                true,
+            );
+        }
+        let colliders: Vec<ColliderHandle> = context
+            .colliders
+            .iter()
+            .filter(|(_, collider)| collider.user_data != shared::scene::SCENE_ENTITY as u128)
+            .map(|(handle, _)| handle)
+            .collect();
+        for handle in colliders {
+            context
+                .colliders
+                .remove(handle, &mut context.islands, &mut context.bodies, false);
+        }
+
+        self.templates.clear();
+        self.forget_bodies();
+    }
+
+    /// Drops what is kept about the bodies of the world, once they are gone.
+    fn forget_bodies(&mut self) {
+        self.id2body.clear();
+        self.tags.clear();
+        self.events = events::EventCollector::default();
+        self.snapshot_filter.clear();
+        self.controllers.clear();
+        self.ropes = rope::Ropes::default();
+        self.joint_breaks = joint_breaks::JointBreaks::default();
+        self.recent_results.clear();
+    }
+
+    fn take_snapshot(&self) -> Result<WorldSnapshot, String> {
+        Ok(WorldSnapshot {
+            context: serialize(&self.context).map_err(|err| err.to_string())?,
+            config: self.config.map(Into::into),
+            tags: self.tags.entries(),
+        })
+    }
+
+    /// Replaces the world with the snapshot's, returning how many bodies it
+    /// has. The world is left as it was if the snapshot can't be read.
+    fn restore_snapshot(&mut self, snapshot: WorldSnapshot) -> Result<usize, String> {
+        let context: RapierContext =
+            deserialize(&snapshot.context).map_err(|err| err.to_string())?;
+        self.context = context;
+        self.forget_bodies();
+
+        for (handle, rb) in self.context.bodies.iter() {
+            if rb.user_data != shared::scene::SCENE_ENTITY as u128 {
+                self.id2body.insert(PhysicsId(rb.user_data as u64), handle);
+            }
+        }
+        for (id, tag) in snapshot.tags {
+            self.tags.insert(id, Some(tag));
+        }
+        if let Some(config) = snapshot.config {
+            self.config = Some(config.into());
+        }
+
+        Ok(self.context.bodies.len())
+    }
+
+    /// What the server supports, as told to clients in the handshake.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            framings: Framing::ALL.to_vec(),
+            encodings: Encoding::ALL.to_vec(),
+            compressions: Compression::ALL.to_vec(),
+            max_bodies: self.options.max_bodies,
+            dimensions: Capabilities::DIMENSIONS,
+            real_bits: Capabilities::REAL_BITS,
+            queries: true,
+            tick: self.options.tick,
+        }
+    }
+
+    /// Drops the bodies that would take the world over its limit.
+    fn limit_bodies<T>(&self, bodies: &mut Vec<T>) {
+        if let Some(max_bodies) = self.options.max_bodies {
+            let room = max_bodies.saturating_sub(self.context.bodies.len());
+            if bodies.len() > room {
+                println!(
+                    "Dropping {} bodies over the limit of {}",
+                    bodies.len() - room,
+                    max_bodies
+                );
+                bodies.truncate(room);
+            }
+        }
+    }
+
+    fn savepoint(&self) -> Result<transaction::Savepoint, String> {
+        Ok(transaction::Savepoint {
+            context: serialize(&self.context).map_err(|err| err.to_string())?,
+            id2body: self.id2body.clone(),
+            tags: self.tags.clone(),
+            templates: self.templates.clone(),
+            owners: self.turn.as_ref().map(|turn| turn.owners().clone()),
+        })
+    }
+
+    /// Puts the world back as it was at the savepoint, leaving it as it is if
+    /// the savepoint can't be read.
+    fn roll_back(&mut self, savepoint: transaction::Savepoint) -> Result<(), String> {
+        self.context = deserialize(&savepoint.context).map_err(|err| err.to_string())?;
+        self.id2body = savepoint.id2body;
+        self.tags = savepoint.tags;
+        self.templates = savepoint.templates;
+        if let (Some(turn), Some(owners)) = (&mut self.turn, savepoint.owners) {
+            turn.restore_owners(owners);
+        }
+        Ok(())
+    }
+
+    /// Removes the bodies of the given physics ids with their colliders and
+    /// joints, returning how many there were.
+    fn remove_bodies(&mut self, ids: &[u64]) -> usize {
+        let context = &mut self.context;
+        let mut removed = 0;
+        for &id in ids {
+            let handle = match self.id2body.remove(PhysicsId(id)) {
+                Some(handle) => handle,
+                None => continue,
+            };
+            if context
+                .bodies
+                .remove(
+                    handle,
+                    &mut context.islands,
+                    &mut context.colliders,
+                    &mut context.impulse_joints,
+                    &mut context.multibody_joints,
+                    true,
+                )
+                .is_some()
+            {
+                removed += 1;
+            }
+            self.tags.insert(id, None);
+        }
+        self.forget_removed();
+        removed
+    }
+
+    /// Removes the colliders of the given physics ids, never those of scenes,
+    /// returning how many there were.
+    fn remove_colliders(&mut self, ids: &[u64]) -> usize {
+        let ids: HashSet<u128> = ids.iter().map(|&id| id as u128).collect();
+        let context = &mut self.context;
+        let colliders: Vec<ColliderHandle> = context
+            .colliders
+            .iter()
+            .filter(|(_, collider)| {
+                collider.user_data != shared::scene::SCENE_ENTITY as u128
+                    && ids.contains(&collider.user_data)
+            })
+            .map(|(handle, _)| handle)
+            .collect();
+        for &handle in &colliders {
+            context
+                .colliders
+                .remove(handle, &mut context.islands, &mut context.bodies, true);
+        }
+        self.forget_removed();
+        colliders.len()
+    }
+
+    /// Drops what the session knows about bodies, colliders and joints that
+    /// are no longer in the world, by moving everything left to itself.
+    fn forget_removed(&mut self) {
+        let bodies: HashMap<RigidBodyHandle, RigidBodyHandle> = self
+            .context
+            .bodies
+            .iter()
+            .map(|(handle, _)| (handle, handle))
+            .collect();
+        let impulse_joints: HashMap<ImpulseJointHandle, ImpulseJointHandle> = self
+            .context
+            .impulse_joints
+            .iter()
+            .map(|(handle, _)| (handle, handle))
+            .collect();
+        self.controllers.remap(&bodies);
+        self.snapshot_filter.remap(&bodies);
+        self.ropes.remap(&bodies);
+        self.joint_breaks.remap(&impulse_joints);
+    }
+
+    /// Compacts the world and moves everything the session knows about it to
+    /// the new handles.
+    fn compact_world(&mut self) -> shared::arena::CompactedWorld {
+        let before = compaction::stats(&self.context, self.compactions);
+        let fragmented = [before.bodies, before.colliders, before.impulse_joints]
+            .iter()
+            .any(|arena| arena.free() > 0);
+        // Multibodies can't be moved to new body handles
+        let multibodies = self.context.multibody_joints.multibodies().next().is_some();
+        if !fragmented || multibodies {
+            return shared::arena::CompactedWorld {
+                bodies: vec![],
+                colliders: vec![],
+                before,
+                after: before,
+            };
+        }
+        let remap = compaction::compact(&mut self.context);
+        self.compactions += 1;
+
+        self.id2body
+            .remap(|handle| remap.bodies.get(&handle).copied());
+        self.controllers.remap(&remap.bodies);
+        self.snapshot_filter.remap(&remap.bodies);
+        self.ropes.remap(&remap.bodies);
+        self.joint_breaks.remap(&remap.impulse_joints);
+        self.recent_results.clear();
+        if let Some(scene) = &mut self.preloaded_scene {
+            scene.remap(&remap.colliders);
+        }
+
+        // Only the handles the client was given, not those of scenes and rope
+        // segments
+        let bodies: Vec<(u64, RigidBodyHandle)> = self
+            .id2body
+            .iter()
+            .map(|(id, handle)| (id.0, handle))
+            .collect();
+        let client_bodies: HashSet<RigidBodyHandle> =
+            bodies.iter().map(|&(_, handle)| handle).collect();
+        let colliders = self
+            .context
+            .colliders
+            .iter()
+            .filter(|(_, collider)| {
+                collider.user_data != shared::scene::SCENE_ENTITY as u128
+                    && collider
+                        .parent()
+                        .is_none_or(|parent| client_bodies.contains(&parent))
+            })
+            .map(|(handle, collider)| (collider.user_data as u64, handle))
+            .collect();
+        shared::arena::CompactedWorld {
+            bodies,
+            colliders,
+            before,
+            after: compaction::stats(&self.context, self.compactions),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = command!()
         .arg(
             arg!(
@@ -33,6 +658,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .default_value("8080")
             .value_parser(value_parser!(u16).range(1..=65535)),
         )
+        .arg(
+            arg!(
+                --bind <ADDR> "An address to listen on, IPv4, IPv6 ([::] takes both where the system allows it) with an optional port, or unix:<PATH>, 0.0.0.0 by default, repeatable"
+            )
+            .required(false)
+            .action(clap::ArgAction::Append)
+            .value_parser(listener::parse_bind),
+        )
         .arg(
             arg!(
                 -l --latency <LATENCY> "The simulated latency in milliseconds, mean latency if min is specified"
@@ -47,9 +680,428 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .required(false)
             .requires("latency")
             .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(
+                -b --bandwidth <KBPS> "The simulated bandwidth in kilobits per second"
+            )
+            .required(false)
+            .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(
+                --loss <SHARE> "The share of responses lost and sent again after a retransmission timeout, from 0 to 1"
+            )
+            .required(false)
+            .value_parser(parse_loss),
+        )
+        .arg(
+            arg!(
+                --"impairment-key" <KEY> "Let clients sending this key set the simulated latency, bandwidth and loss of their own session"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --"admin-port" <PORT> "Take commands pausing, resuming and rescaling the clock of running sessions and rooms on the given port of localhost"
+            )
+            .required(false)
+            .value_parser(value_parser!(u16).range(1..=65535)),
+        )
+        .arg(
+            arg!(
+                -r --record <PREFIX> "Record every session's ticks to <PREFIX>_<peer>.rec"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --metrics <PATH> "Append per-second session metrics to the given CSV file"
+            )
+            .required(false)
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --"snapshot-budget" <BYTES> "The most bytes of bodies sent per step, the most important ones first"
+            )
+            .required(false)
+            .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            arg!(
+                --scenes <DIR> "The directory scenes requested by clients are loaded from"
+            )
+            .required(false)
+            .default_value(concat!(env!("CARGO_MANIFEST_DIR"), "/scenes"))
+            .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(
+                --profile <NAME> "The physics profile of sessions whose client doesn't pick one"
+            )
+            .required(false)
+            .value_parser(profile::Profile::ALL.map(profile::Profile::name)),
+        )
+        .arg(
+            arg!(
+                --"step-pacing" <PACING> "How sessions whose client doesn't pick one run steps arriving in a burst: immediate, cap:<STEPS>/<MILLISECONDS> or collapse:<MILLISECONDS>"
+            )
+            .required(false)
+            .default_value("immediate")
+            .value_parser(shared::pacing::StepPacing::parse),
+        )
+        .arg(
+            arg!(
+                --ground "Start every world with a large fixed ground at y = 0"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --"default-scene" <NAME> "A scene from the scenes directory every world starts with"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --"max-connections" <SESSIONS> "The most sessions run at once, unlimited by default"
+            )
+            .required(false)
+            .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            arg!(
+                --"accept-queue" <CONNECTIONS> "How many connections beyond the maximum wait for a session to end instead of being turned away"
+            )
+            .required(false)
+            .requires("max-connections")
+            .default_value("4")
+            .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(
+                --"retry-after" <SECONDS> "When turned away connections are told to retry"
+            )
+            .required(false)
+            .requires("max-connections")
+            .default_value("10")
+            .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(
+                --alternative <ADDR> "Another server turned away connections are pointed to"
+            )
+            .required(false)
+            .requires("max-connections")
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --pool <WORLDS> "Keep the given number of worlds ready for new sessions"
+            )
+            .required(false)
+            .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            arg!(
+                --"pool-scene" <NAME> "Load the given scene into pooled worlds ahead of the sessions asking for it"
+            )
+            .required(false)
+            .requires("pool")
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --"pool-refill" <POLICY> "When worlds taken from the pool are replaced"
+            )
+            .required(false)
+            .requires("pool")
+            .default_value("eager")
+            .value_parser(["eager", "never"]),
+        )
+        .arg(
+            arg!(
+                --"compression-threshold" <BYTES> "Responses shorter than this aren't compressed, once the client picked a compression"
+            )
+            .required(false)
+            .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(
+                --"compression-level" <LEVEL> "Compress responses at this level of the compression the client picked instead of its default"
+            )
+            .required(false)
+            .allow_hyphen_values(true)
+            .value_parser(value_parser!(i32)),
+        )
+        .arg(
+            arg!(
+                --"compression-benchmark" "Measure the encoding time and size of typical messages in every compression, compressed from the threshold on or always, and exit"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --"codec-benchmark" "Measure the size and encoding time of typical messages in every encoding, and exit"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --coalesce "Answer requests that arrive together at once, paying the simulated latency once"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --"idle-timeout" <SECONDS> "Tear down sessions that haven't heard from their client for the given number of seconds"
+            )
+            .required(false)
+            .default_value("30")
+            .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(
+                --"resume-grace" <SECONDS> "Keep the sessions of lost connections for the given number of seconds, for their client to resume, 0 to end them right away"
+            )
+            .required(false)
+            .default_value("30")
+            .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(
+                --"max-worlds" <COUNT> "Let every session have up to this many worlds, its first one included"
+            )
+            .required(false)
+            .default_value("8")
+            .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            arg!(
+                --rooms "Let clients join rooms by name to share a world with the other members"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --"tick-rate" <HZ> "Step every world this many times a second on its own and push the results, rather than when clients ask; the world's timestep mode divides every tick into steps"
+            )
+            .required(false)
+            .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            arg!(
+                --"max-bodies" <BODIES> "Let every world have up to this many bodies, unlimited by default"
+            )
+            .required(false)
+            .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            arg!(
+                --seed <SEED> "Seeds every random behavior of the server so that runs can be repeated, random by default"
+            )
+            .required(false)
+            .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(
+                --benchmark <BODIES> "Measure the step time of a world with the given number of bodies and exit"
+            )
+            .required(false)
+            .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(
+                --determinism <TICKS> "Step a canonical scene for the given number of ticks, hashing the world after every tick, and exit"
+            )
+            .required(false)
+            .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            arg!(
+                --"write-hashes" <PATH> "Write the hash of every tick of the determinism run to a file"
+            )
+            .required(false)
+            .requires("determinism")
+            .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(
+                --golden <PATH> "Fail the determinism run if its hashes differ from those of a file written with --write-hashes"
+            )
+            .required(false)
+            .requires("determinism")
+            .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(
+                --region <RANGE> "Simulate only the x range <MIN>:<MAX> of a world partitioned across servers"
+            )
+            .required(false)
+            .value_parser(parse_region),
+        )
+        .arg(
+            arg!(
+                --"partition-index" <INDEX> "The index of this server's region, keeps body ids unique"
+            )
+            .required(false)
+            .requires("region")
+            .default_value("0")
+            .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            arg!(
+                --"partition-port" <PORT> "The port the left neighbour region connects to"
+            )
+            .required(false)
+            .requires("region")
+            .value_parser(value_parser!(u16).range(1..=65535)),
+        )
+        .arg(
+            arg!(
+                --right <ADDR> "The address of the right neighbour region's partition port"
+            )
+            .required(false)
+            .requires("region")
+            .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(
+                --"ghost-margin" <METERS> "How close to a boundary bodies are mirrored in the neighbour region"
+            )
+            .required(false)
+            .requires("region")
+            .default_value("1.0")
+            .value_parser(value_parser!(f32)),
+        )
+        .arg(
+            arg!(
+                --"region-bodies" <BODIES> "The number of bodies seeded in this region"
+            )
+            .required(false)
+            .requires("region")
+            .default_value("500")
+            .value_parser(value_parser!(usize)),
+        );
+
+    #[cfg(feature = "parallel")]
+    {
+        cmd = cmd.arg(
+            arg!(
+                --threads <THREADS> "The size of every world's stepping thread pool, all cores by default"
+            )
+            .required(false)
+            .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        );
+    }
+
+    #[cfg(feature = "gpu-aabb-bench")]
+    {
+        cmd = cmd.arg(
+            arg!(
+                --"bench-gpu-aabbs" <COLLIDERS> "Time AABB overlap tests on the GPU against rapier's step and exit"
+            )
+            .required(false)
+            .value_parser(value_parser!(usize)),
         );
+    }
+
+    let matches = cmd.get_matches_mut();
+
+    #[cfg(feature = "parallel")]
+    let threads = match matches.get_one::<usize>("threads") {
+        Some(&threads) => threads,
+        None => std::thread::available_parallelism()?.get(),
+    };
+
+    let seed = match matches.get_one::<u64>("seed") {
+        Some(&seed) => seed,
+        None => thread_rng().gen(),
+    };
+    println!("Seed: {}", seed);
+
+    let metadata = RunMetadata::collect("server", env!("CARGO_PKG_VERSION"), &features());
+    for line in metadata.preamble() {
+        println!("{}", line);
+    }
+
+    let compression_threshold = matches
+        .get_one::<usize>("compression-threshold")
+        .copied()
+        .unwrap_or(compression::DEFAULT_THRESHOLD);
+    let compression_level = matches.get_one::<i32>("compression-level").copied();
+    if matches.get_flag("compression-benchmark") {
+        return benchmark::compression(compression_threshold, compression_level, seed);
+    }
+    if matches.get_flag("codec-benchmark") {
+        return benchmark::codecs(seed);
+    }
+
+    if let Some(&bodies) = matches.get_one::<usize>("benchmark") {
+        #[cfg(feature = "parallel")]
+        benchmark::run_scaling(bodies, threads, seed)?;
+        #[cfg(not(feature = "parallel"))]
+        benchmark::run(bodies, seed);
+        return Ok(());
+    }
+
+    if let Some(&ticks) = matches.get_one::<u32>("determinism") {
+        let hashes = determinism::run(ticks);
+        println!(
+            "{} ticks of the canonical scene, final hash {:016x}",
+            ticks,
+            hashes.last().unwrap()
+        );
+        if let Some(path) = matches.get_one::<PathBuf>("write-hashes") {
+            determinism::write(path, &hashes, &features())?;
+            println!("Wrote the hashes to {}", path.display());
+        }
+        if let Some(path) = matches.get_one::<PathBuf>("golden") {
+            let golden = determinism::read(path)?;
+            if let Some(tick) = determinism::first_divergence(&hashes, &golden) {
+                return Err(format!(
+                    "the world diverges from {} at tick {}: {:016x} instead of {:016x}",
+                    path.display(),
+                    tick,
+                    hashes[tick],
+                    golden[tick]
+                )
+                .into());
+            }
+            if golden.len() < hashes.len() {
+                return Err(format!(
+                    "{} only has {} of the {} ticks",
+                    path.display(),
+                    golden.len(),
+                    ticks
+                )
+                .into());
+            }
+            println!("Matches {}", path.display());
+        }
+        return Ok(());
+    }
 
-    let matches = cmd.get_matches_mut();
+    #[cfg(feature = "gpu-aabb-bench")]
+    if let Some(&colliders) = matches.get_one::<usize>("bench-gpu-aabbs") {
+        gpu_aabb_bench::benchmark(colliders, seed);
+        return Ok(());
+    }
+
+    if let Some(&region) = matches.get_one::<shared::partition::Region>("region") {
+        return partition::run(partition::PartitionOptions {
+            index: *matches.get_one::<u32>("partition-index").unwrap(),
+            region,
+            ghost_margin: *matches.get_one::<f32>("ghost-margin").unwrap(),
+            listen_port: matches.get_one::<u16>("partition-port").copied(),
+            right: matches.get_one::<String>("right").cloned(),
+            bodies: *matches.get_one::<usize>("region-bodies").unwrap(),
+            seed,
+        });
+    }
 
     let simulated_latency = match (
         matches.get_one::<u64>("latency"),
@@ -69,149 +1121,1507 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ => unreachable!(),
     };
 
-    let port = matches.get_one::<u16>("port").unwrap();
-    let server = TcpListener::bind(format!("0.0.0.0:{}", port))?;
-    println!("Listening on port {}", port);
+    let metrics = match matches.get_one::<String>("metrics") {
+        Some(path) => Some(Arc::new(Mutex::new(CsvWriter::open_with_preamble(
+            path,
+            METRICS_HEADER,
+            &metadata.preamble(),
+        )?))),
+        None => None,
+    };
 
-    for stream in server.incoming() {
-        match stream {
-            Ok(stream) => {
-                std::thread::spawn(move || {
-                    if let Err(e) = handle_connection(stream, simulated_latency) {
-                        println!("Error: {}", e);
-                    }
-                });
+    let scenes_dir = matches.get_one::<PathBuf>("scenes").unwrap().clone();
+    let default_scene = matches.get_one::<String>("default-scene").cloned();
+    let profile = matches
+        .get_one::<String>("profile")
+        .and_then(|name| profile::Profile::from_name(name));
+    if let Some(profile) = profile {
+        println!("Default profile: {}", profile);
+    }
+    let step_pacing = *matches
+        .get_one::<shared::pacing::StepPacing>("step-pacing")
+        .unwrap();
+    println!("Default step pacing: {}", step_pacing);
+    if let Some(name) = &default_scene {
+        // Fail now rather than in every session
+        scene::load_default(name, &scenes_dir, &mut RapierContext::default())?;
+    }
+    let pool = matches.get_one::<usize>("pool").map(|&size| {
+        let refill = match matches.get_one::<String>("pool-refill").unwrap().as_str() {
+            "never" => pool::Refill::Never,
+            _ => pool::Refill::Eager,
+        };
+        pool::WorldPool::new(
+            size,
+            matches.get_one::<String>("pool-scene").cloned(),
+            scenes_dir.clone(),
+            refill,
+        )
+    });
+
+    let mut options = SessionOptions {
+        impairment: Impairment {
+            latency: simulated_latency,
+            bandwidth: matches.get_one::<u64>("bandwidth").copied(),
+            loss: matches.get_one::<f32>("loss").copied().unwrap_or(0.0),
+        },
+        impairment_key: matches.get_one::<String>("impairment-key").cloned(),
+        record: matches.get_one::<String>("record").cloned(),
+        metrics,
+        snapshot_budget: matches.get_one::<usize>("snapshot-budget").copied(),
+        scenes_dir,
+        seed,
+        pool,
+        ground: matches.get_flag("ground"),
+        default_scene,
+        profile,
+        step_pacing,
+        idle_timeout: Duration::from_secs(*matches.get_one::<u64>("idle-timeout").unwrap()),
+        metadata: Arc::new(metadata),
+        compression_threshold,
+        compression_level,
+        coalesce: matches.get_flag("coalesce"),
+        sessions: None,
+        max_worlds: *matches.get_one::<usize>("max-worlds").unwrap(),
+        max_bodies: matches.get_one::<usize>("max-bodies").copied(),
+        rooms: matches
+            .get_flag("rooms")
+            .then(|| Arc::new(rooms::Rooms::default())),
+        tick: matches
+            .get_one::<u32>("tick-rate")
+            .map(|&rate| Duration::from_secs(1) / rate),
+        #[cfg(feature = "parallel")]
+        threads,
+    };
+    let admin = match matches.get_one::<u16>("admin-port") {
+        Some(&port) => {
+            let sessions = Arc::new(admin::Sessions::new(options.rooms.clone()));
+            options.sessions = Some(sessions.clone());
+            Some(admin::listen(port, sessions)?)
+        }
+        None => None,
+    };
+
+    let admission = Arc::new(admission::Admission::new(
+        matches.get_one::<usize>("max-connections").copied(),
+        *matches.get_one::<usize>("accept-queue").unwrap(),
+    ));
+    let rejection = admission::Rejection {
+        retry_after: *matches.get_one::<u64>("retry-after").unwrap(),
+        alternative: matches.get_one::<String>("alternative").cloned(),
+    };
+
+    let port = *matches.get_one::<u16>("port").unwrap();
+    let binds: Vec<listener::BindAddr> = match matches.get_many::<listener::BindAddr>("bind") {
+        Some(binds) => binds.cloned().collect(),
+        None => vec![listener::BindAddr::Tcp(
+            std::net::Ipv4Addr::UNSPECIFIED.into(),
+            None,
+        )],
+    };
+
+    let store = Arc::new(store::SessionStore::new(Duration::from_secs(
+        *matches.get_one::<u64>("resume-grace").unwrap(),
+    )));
+    if !store.grace().is_zero() {
+        let store = store.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                for (id, simulation) in store.expire() {
+                    println!("Session {} wasn't resumed in time, ending it", id);
+                    log_session_summary(&simulation);
+                }
             }
-            Err(e) => {
+        });
+    }
+
+    let serve = Arc::new(move |stream: Box<dyn listener::Connection>| {
+        let options = options.clone();
+        let store = store.clone();
+        let admission = admission.clone();
+        let rejection = rejection.clone();
+        tokio::spawn(async move {
+            // Connections wait in the queue on a blocking thread
+            let admitted = tokio::task::spawn_blocking(move || admission.admit()).await;
+            let _slot = match admitted {
+                Ok(Some(slot)) => slot,
+                _ => {
+                    println!("Server full, turning away {:?}", stream.peer());
+                    let _ = accept_hdr_async(stream, rejection).await;
+                    return;
+                }
+            };
+            if let Err(e) = handle_connection(stream, options, store).await {
                 println!("Error: {}", e);
             }
-        }
+        });
+    });
+
+    let mut listeners = vec![];
+    for bind in &binds {
+        listeners.push(listener::listen(bind, port, serve.clone()).await?);
+    }
+    for listener in listeners {
+        let _ = listener.await;
+    }
+    if let Some(admin) = admin {
+        let _ = tokio::task::spawn_blocking(move || admin.join()).await;
     }
 
     Ok(())
 }
 
-fn handle_connection(
-    stream: TcpStream,
-    simulated_latency: SimulatedLatency,
+fn parse_loss(loss: &str) -> Result<f32, String> {
+    loss.parse::<f32>()
+        .ok()
+        .filter(|loss| (0.0..=1.0).contains(loss))
+        .ok_or_else(|| format!("expected a share from 0 to 1, got {}", loss))
+}
+
+fn parse_region(range: &str) -> Result<shared::partition::Region, String> {
+    let (min_x, max_x) = range.split_once(':').ok_or("expected <MIN>:<MAX>")?;
+    let min_x: f32 = min_x
+        .parse()
+        .map_err(|err| format!("invalid min: {}", err))?;
+    let max_x: f32 = max_x
+        .parse()
+        .map_err(|err| format!("invalid max: {}", err))?;
+    if min_x >= max_x {
+        return Err("min must be less than max".to_string());
+    }
+    Ok(shared::partition::Region { min_x, max_x })
+}
+
+/// What a connection's requests are handled with, moved to a blocking thread
+/// for every batch of them so that steps never hold up the runtime.
+struct Simulation {
+    session: Session,
+    recorder: Option<Recorder>,
+    /// The bodies recorded and not removed yet.
+    recorded_bodies: HashSet<RigidBodyHandle>,
+    /// Of the latest connection, the session having outlived the others.
+    peer_addr: String,
+    started: Instant,
+    /// By channel.
+    requests: [usize; 3],
+    /// Of the room the session joined, if any.
+    room: Option<rooms::Membership>,
+    #[cfg(feature = "parallel")]
+    thread_pool: rayon::ThreadPool,
+}
+
+impl Simulation {
+    /// Runs `f` with the simulation on a blocking thread, handing it back
+    /// with the result.
+    async fn run<T: Send + 'static>(
+        mut self,
+        f: impl FnOnce(&mut Self) -> T + Send + 'static,
+    ) -> Result<(Self, T), tokio::task::JoinError> {
+        tokio::task::spawn_blocking(move || {
+            let result = f(&mut self);
+            (self, result)
+        })
+        .await
+    }
+
+    fn handle(&mut self, req: Request) -> Result<Response, String> {
+        // Members of a room act on its world, one request at a time
+        let room = self
+            .room
+            .as_ref()
+            .map(|membership| (membership.room.clone(), membership.member));
+        let mut state = room.as_ref().map(|(room, _)| room.state.lock().unwrap());
+        if let (Some(state), Some((_, member))) = (&mut state, &room) {
+            let mut turn = state.turn(*member);
+            self.session.room_status = turn.status;
+            self.session.swap_world(&mut state.world);
+            self.session.swap_member_state(&mut turn.state);
+            self.session.turn = Some(turn);
+        }
+
+        let session = &mut self.session;
+        let handle = || handle_request(req, session);
+
+        // Rapier's parallel solver runs on the thread pool it's called from
+        #[cfg(feature = "parallel")]
+        let response = self.thread_pool.install(handle);
+        #[cfg(not(feature = "parallel"))]
+        let response = handle();
+
+        let recorded = match &mut self.recorder {
+            Some(recorder) => record_response(
+                recorder,
+                &mut self.recorded_bodies,
+                &response,
+                &self.session.context,
+            )
+            .map_err(|err| err.to_string()),
+            None => Ok(()),
+        };
+        if let Some(state) = &mut state {
+            if let Some(mut turn) = self.session.turn.take() {
+                self.session.swap_member_state(&mut turn.state);
+                self.session.swap_world(&mut state.world);
+                state.end_turn(turn);
+            }
+        }
+        recorded?;
+        Ok(report(response, &mut self.session))
+    }
+
+    /// Runs the streamed steps that are due, answered with the messages
+    /// pushing their results.
+    fn push_due_streams(&mut self, version: ProtocolVersion) -> Result<Vec<Message>, String> {
+        let due = match &self.room {
+            // The room's world, whose results are pushed to every member
+            Some(room) => room
+                .take_due_tick()
+                .map(|delta_time| (DEFAULT_WORLD, delta_time))
+                .into_iter()
+                .collect(),
+            None => self.session.take_due_streams(),
+        };
+        let mut pushed = vec![];
+        for (world_id, delta_time) in due {
+            let step = Request::SimulateStep(delta_time);
+            let request = if world_id == DEFAULT_WORLD {
+                step
+            } else {
+                Request::InWorld {
+                    world_id,
+                    request: Box::new(step),
+                }
+            };
+            let response = Response::Pushed(Box::new(self.handle(request)?));
+            let session = &mut self.session;
+            let msg = encode_response(
+                channel::Channel::Snapshots,
+                &response,
+                version,
+                session.framing,
+                session.encoding,
+                session.compression,
+                &session.options,
+            )
+            .map_err(|err| err.to_string())?;
+            session.stats.bytes_sent += msg.len();
+            pushed.push(msg);
+        }
+        Ok(pushed)
+    }
+
+    /// How long until the next streamed step of the session is due, or of its
+    /// room if it's the host.
+    fn until_next_stream(&self) -> Option<Duration> {
+        match &self.room {
+            Some(room) => room.until_next_tick(),
+            None => self.session.until_next_stream(),
+        }
+    }
+
+    /// Handles the requests of the messages, answered with the replies in
+    /// their order.
+    fn answer(
+        &mut self,
+        messages: Vec<Message>,
+        version: ProtocolVersion,
+    ) -> Result<Vec<Message>, String> {
+        let mut replies = vec![];
+        for msg in messages {
+            self.session.stats.requests += 1;
+            self.session.stats.bytes_received += msg.len();
+            // The handshake is answered the way it came in
+            let (framing, encoding, compression) = (
+                self.session.framing,
+                self.session.encoding,
+                self.session.compression,
+            );
+            let (channel, req) = decode_request(msg, version, framing, encoding, compression)
+                .map_err(|err| err.to_string())?;
+            self.requests[channel.id() as usize] += 1;
+
+            let response = self.handle(req)?;
+
+            // Responses travel on the channel of their request
+            replies.push(
+                encode_response(
+                    channel,
+                    &response,
+                    version,
+                    framing,
+                    encoding,
+                    compression,
+                    &self.session.options,
+                )
+                .map_err(|err| err.to_string())?,
+            );
+        }
+        Ok(replies)
+    }
+}
+
+impl Drop for Simulation {
+    /// Leaves the session's room, removing what the member created, which its
+    /// client creates again if it comes back in a new session.
+    fn drop(&mut self) {
+        if let Some(room) = self.room.take() {
+            let mut state = room.room.state.lock().unwrap();
+            let owned = state.disown(room.member);
+            self.session.swap_world(&mut state.world);
+            self.session.remove_colliders(&owned);
+            let removed = self.session.remove_bodies(&owned);
+            self.session.swap_world(&mut state.world);
+            println!(
+                "Removed {} bodies of member {} from the room",
+                removed, room.member
+            );
+            // Before leaving, which takes the lock again
+            drop(state);
+        }
+    }
+}
+
+/// Keeps the session of a lost connection for its client to resume, ending
+/// it if there's no grace period.
+fn park(store: &store::SessionStore<Simulation>, id: u64, simulation: Simulation) {
+    let peer_addr = simulation.peer_addr.clone();
+    // Another member hosts its room meanwhile
+    if let Some(room) = &simulation.room {
+        room.set_parked(true);
+    }
+    match store.park(id, simulation) {
+        Some(simulation) => log_session_summary(&simulation),
+        None => println!(
+            "Keeping session {} of {} for {:?}",
+            id,
+            peer_addr,
+            store.grace()
+        ),
+    }
+}
+
+/// Keeps the session of a connection that failed while reading or writing,
+/// lost rather than closed, for its client to come back for it.
+fn lost(
+    store: &store::SessionStore<Simulation>,
+    id: u64,
+    simulation: Simulation,
+    peer_addr: &str,
+    err: tungstenite::Error,
+) {
+    println!("Lost connection with {}: {}", peer_addr, err);
+    park(store, id, simulation);
+}
+
+/// What the loop of a connection wakes up to.
+enum Wakeup {
+    Message(Option<Result<Message, tungstenite::Error>>),
+    /// A result pushed by the host of the session's room.
+    Pushed(Response),
+}
+
+#[allow(clippy::result_large_err)]
+async fn handle_connection(
+    stream: Box<dyn listener::Connection>,
+    options: SessionOptions,
+    store: Arc<store::SessionStore<Simulation>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let peer_addr = stream.peer_addr()?;
+    let peer_addr = stream.peer()?;
 
-    let mut websocket = accept(stream)?;
+    let mut version = ProtocolVersion::CURRENT;
+    let new_id = store.new_id();
+    let mut resumed = None;
+    let mut joined = None;
+    let accepted = accept_hdr_async(stream, |request: &_, response| {
+        let mut response = protocol::negotiate(request, response, &mut version)?;
+        resumed = store::requested(request).and_then(|id| Some((id, store.resume(id)?)));
+        let id = resumed.as_ref().map_or(new_id, |(id, _)| *id);
+        store::answer(&mut response, id, resumed.is_some());
+        // Resumed sessions are still in the room they joined
+        let member = match &resumed {
+            Some((_, simulation)) => simulation.room.as_ref().map(|room| room.member),
+            None => {
+                joined = options
+                    .rooms
+                    .as_ref()
+                    .filter(|_| version == ProtocolVersion::CURRENT)
+                    .zip(rooms::requested(request))
+                    .map(|(rooms, name)| rooms.join(&name, &options));
+                joined.as_ref().map(|room| room.member)
+            }
+        };
+        if let Some(member) = member {
+            rooms::answer(&mut response, member);
+        }
+        Ok(response)
+    })
+    .await;
+    let mut websocket = match accepted {
+        Ok(websocket) => websocket,
+        Err(err) => {
+            if let Some((id, simulation)) = resumed {
+                park(&store, id, simulation);
+            }
+            return Err(err.into());
+        }
+    };
+    // Wakes the loop up to ping the client and to notice when it's gone
+    let ping_period = options.idle_timeout / PINGS_PER_IDLE_TIMEOUT;
+    let mut last_ping = Instant::now();
 
-    println!("Connection from {}", peer_addr);
+    println!("Connection from {} speaking {}", peer_addr, version);
+    let mut last_seen = Instant::now();
+
+    let (id, mut simulation) = match resumed {
+        Some((id, mut simulation)) => {
+            println!(
+                "Resuming session {} of {} for {}",
+                id, simulation.peer_addr, peer_addr
+            );
+            simulation.peer_addr = peer_addr.clone();
+            // Until the client sends the handshake again
+            let session = &mut simulation.session;
+            session.framing = Framing::Binary;
+            session.encoding = Encoding::Bincode;
+            session.compression = Compression::None;
+            // The room's steps it missed are stale
+            if let Some(room) = &mut simulation.room {
+                while room.pushes.try_recv().is_ok() {}
+                room.set_parked(false);
+            }
+            (id, simulation)
+        }
+        None => {
+            let recorder = match &options.record {
+                Some(prefix) => {
+                    let path = format!("{}_{}.rec", prefix, peer_addr).replace([':', '/'], "-");
+                    println!("Recording session to {}", path);
+                    Some(Recorder::create(path)?)
+                }
+                None => None,
+            };
 
-    let mut context = RapierContext::default();
-    let mut config: Option<RapierConfiguration> = None;
-    let mut sim_to_render_time = SimulationToRenderTime::default();
-    let mut entity2body = HashMap::new();
+            let mut session = Session::new(&options);
+            // Members step the room's world rather than their own
+            if joined.is_some() {
+                session.stream = None;
+            }
+            session.control = options
+                .sessions
+                .as_ref()
+                .map(|sessions| sessions.register(peer_addr.clone()));
 
-    // dummy physics hooks
-    #[allow(clippy::let_unit_value)]
-    let physics_hooks = ();
+            println!("Starting session {} for {}", new_id, peer_addr);
+            let simulation = Simulation {
+                session,
+                recorder,
+                recorded_bodies: HashSet::new(),
+                peer_addr: peer_addr.clone(),
+                started: Instant::now(),
+                requests: [0; 3],
+                room: joined,
+                #[cfg(feature = "parallel")]
+                thread_pool: rayon::ThreadPoolBuilder::new()
+                    .num_threads(options.threads)
+                    .build()?,
+            };
+            (new_id, simulation)
+        }
+    };
+    let mut last_export = Instant::now();
 
     loop {
+        // Streamed steps are due whether the client sent anything or not
+        let (returned, pushed) = simulation
+            .run(move |simulation| simulation.push_due_streams(version))
+            .await?;
+        simulation = returned;
+        // Without the simulated impairment, which would hold the tick back
+        for msg in pushed? {
+            if let Err(err) = websocket.send(msg).await {
+                lost(&store, id, simulation, &peer_addr, err);
+                return Ok(());
+            }
+        }
+        // Wakes up for the next streamed step too
+        let timeout = simulation
+            .until_next_stream()
+            .map_or(ping_period, |until_next| {
+                until_next.clamp(Duration::from_millis(1), ping_period)
+            });
+
         println!("Waiting for message...");
-        let msg = websocket.read_message()?;
-        println!("Received message of length {:?}", msg.len());
-        if msg.is_binary() {
+        // Or for the steps of the host of the session's room
+        let pushes = simulation.room.as_mut().map(|room| &mut room.pushes);
+        let next = tokio::time::timeout(timeout, async {
+            match pushes {
+                Some(pushes) => tokio::select! {
+                    msg = websocket.next() => Wakeup::Message(msg),
+                    Some(pushed) = pushes.recv() => Wakeup::Pushed(pushed),
+                },
+                None => Wakeup::Message(websocket.next().await),
+            }
+        })
+        .await;
+        let msg = match next {
+            Ok(Wakeup::Pushed(pushed)) => {
+                let session = &mut simulation.session;
+                let msg = encode_response(
+                    channel::Channel::Snapshots,
+                    &pushed,
+                    version,
+                    session.framing,
+                    session.encoding,
+                    session.compression,
+                    &session.options,
+                )?;
+                session.stats.bytes_sent += msg.len();
+                if let Err(err) = websocket.send(msg).await {
+                    lost(&store, id, simulation, &peer_addr, err);
+                    return Ok(());
+                }
+                continue;
+            }
+            Ok(Wakeup::Message(Some(Ok(msg)))) => msg,
+            // Lost rather than closed, the client may come back for the session
+            Ok(Wakeup::Message(Some(Err(err)))) => {
+                lost(&store, id, simulation, &peer_addr, err);
+                return Ok(());
+            }
+            Ok(Wakeup::Message(None)) => {
+                println!("Lost connection with {}", peer_addr);
+                park(&store, id, simulation);
+                return Ok(());
+            }
+            Err(_) => {
+                if last_seen.elapsed() >= options.idle_timeout {
+                    println!(
+                        "Session with {} idle for {:?}, dropping the connection",
+                        peer_addr,
+                        last_seen.elapsed()
+                    );
+                    park(&store, id, simulation);
+                    return Ok(());
+                }
+                if last_ping.elapsed() >= ping_period {
+                    last_ping = Instant::now();
+                    if let Err(err) = websocket.send(Message::Ping(Vec::new())).await {
+                        lost(&store, id, simulation, &peer_addr, err);
+                        return Ok(());
+                    }
+                }
+                continue;
+            }
+        };
+        last_seen = Instant::now();
+        simulation.session.received_at = clock::now_micros();
+
+        // Requests that arrived while this one was on its way are answered
+        // together, paying the simulated latency and bandwidth once
+        let mut messages = vec![msg];
+        if options.coalesce {
+            // Without waiting for more
+            while !messages.last().is_some_and(Message::is_close) {
+                match websocket.next().now_or_never() {
+                    Some(Some(Ok(msg))) => messages.push(msg),
+                    Some(Some(Err(err))) => {
+                        lost(&store, id, simulation, &peer_addr, err);
+                        return Ok(());
+                    }
+                    _ => break,
+                }
+            }
+            if messages.len() > 1 {
+                println!("Coalescing {} messages", messages.len());
+            }
+        }
+
+        let mut requests = vec![];
+        let mut closed = false;
+        for msg in messages {
+            println!("Received message of length {:?}", msg.len());
+            if msg.is_binary() || msg.is_text() {
+                requests.push(msg);
+            } else if msg.is_close() {
+                closed = true;
+                break;
+            } else if msg.is_ping() || msg.is_pong() {
+                // Pings are answered by tungstenite, both only show the client is alive
+                continue;
+            } else {
+                return Err(format!("Unexpected message: {:?}", msg).into());
+            }
+        }
+        let replies = if requests.is_empty() {
+            vec![]
+        } else {
+            let (returned, replies) = simulation
+                .run(move |simulation| simulation.answer(requests, version))
+                .await?;
+            simulation = returned;
+            replies?
+        };
+        if closed {
+            println!("Closing connection with {}", peer_addr);
+            log_session_summary(&simulation);
+            return Ok(());
+        }
+        if replies.is_empty() {
+            continue;
+        }
+
+        let session = &mut simulation.session;
+        let impairment = session.impairment;
+        let delay = latency_delay(impairment.latency, &mut session.rng)
+            + bandwidth_delay(impairment.bandwidth, replies.iter().map(Message::len).sum())
+            + loss_delay(impairment.loss, &mut session.rng);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        // In the order of their requests
+        for msg in replies {
+            simulation.session.stats.bytes_sent += msg.len();
+            if let Err(err) = websocket.send(msg).await {
+                lost(&store, id, simulation, &peer_addr, err);
+                return Ok(());
+            }
+        }
+
+        if let Some(metrics) = &options.metrics {
+            if last_export.elapsed() >= Duration::from_secs(1) {
+                last_export = Instant::now();
+                let stats = std::mem::take(&mut simulation.session.stats);
+                let row = metrics_row(&stats, &peer_addr, &simulation.session.context);
+                metrics.lock().unwrap().write_row(&row)?;
+            }
+        }
+    }
+}
+
+/// Wraps a response in how it was degraded and the session's status, if they
+/// weren't reported yet.
+fn report(response: Response, session: &mut Session) -> Response {
+    let degradation = std::mem::take(&mut session.degradation);
+    let response = if degradation.is_empty() {
+        response
+    } else {
+        session.stats.degradation |= degradation;
+        Response::Degraded(degradation, Box::new(response))
+    };
+    let status = session.status();
+    if status == session.reported_status {
+        response
+    } else {
+        session.reported_status = status;
+        Response::Status(status, Box::new(response))
+    }
+}
+
+fn decode_request(
+    msg: Message,
+    version: ProtocolVersion,
+    framing: Framing,
+    encoding: Encoding,
+    compression: Compression,
+) -> Result<(channel::Channel, Request), Box<dyn std::error::Error>> {
+    match version {
+        ProtocolVersion::V1 => {
+            let (channel, req) =
+                decode::<protocol::v1::Request>(msg, version, framing, encoding, compression)?;
+            Ok((channel, req.into()))
+        }
+        ProtocolVersion::V2 => decode(msg, version, framing, encoding, compression),
+    }
+}
+
+fn decode<T: DeserializeOwned>(
+    msg: Message,
+    version: ProtocolVersion,
+    framing: Framing,
+    encoding: Encoding,
+    compression: Compression,
+) -> Result<(channel::Channel, T), Box<dyn std::error::Error>> {
+    match framing {
+        Framing::Binary => {
             let msg_data = msg.into_data();
+            let msg_data = if version.seals_messages() {
+                envelope::open(&msg_data)?
+            } else {
+                &msg_data
+            };
+            let (channel, msg_data) = channel::unwrap(msg_data)?;
+            let req = encoding.decode(&compression::decode(compression, msg_data)?)?;
+            Ok((channel, req))
+        }
+        Framing::Json => Ok(framing::from_json(msg.to_text()?)?),
+    }
+}
 
-            let req = {
-                #[cfg(feature = "compression")]
-                {
-                    let mut decoder = ZlibDecoder::new(msg_data.as_slice());
-                    let mut decompressed = Vec::new();
-                    decoder.read_to_end(&mut decompressed)?;
+fn encode_response(
+    channel: channel::Channel,
+    response: &Response,
+    version: ProtocolVersion,
+    framing: Framing,
+    encoding: Encoding,
+    compression: Compression,
+    options: &SessionOptions,
+) -> Result<Message, Box<dyn std::error::Error>> {
+    let versioned = protocol::Versioned::new(version, response);
+    match framing {
+        Framing::Binary => {
+            let serialized = encoding.encode(&versioned)?;
+            let serialized = Compressor {
+                compression,
+                level: options.compression_level,
+                threshold: options.compression_threshold,
+            }
+            .encode(&serialized)?;
+            let wrapped = channel::wrap(channel, &serialized);
+            if version.seals_messages() {
+                Ok(Message::binary(envelope::seal(&wrapped)))
+            } else {
+                Ok(Message::binary(wrapped))
+            }
+        }
+        Framing::Json => Ok(Message::text(framing::to_json(
+            channel,
+            response.name(),
+            &versioned,
+        )?)),
+    }
+}
+
+fn log_session_summary(simulation: &Simulation) {
+    let Simulation {
+        session,
+        peer_addr,
+        started,
+        requests,
+        ..
+    } = simulation;
+    let profile = session.profile.map_or("none", profile::Profile::name);
+    let by_channel: Vec<String> = channel::Channel::ALL
+        .iter()
+        .map(|channel| format!("{} {}", requests[channel.id() as usize], channel.name()))
+        .collect();
+    println!(
+        "Session with {} (profile {}) lasted {:?}: {} requests ({}), {} bodies, {} colliders",
+        peer_addr,
+        profile,
+        started.elapsed(),
+        requests.iter().sum::<usize>(),
+        by_channel.join(", "),
+        session.context.bodies.len(),
+        session.context.colliders.len()
+    );
+    if !session.context.bodies.is_empty() {
+        let ids = session
+            .context
+            .bodies
+            .iter()
+            .map(|(_, rb)| rb.user_data as u64);
+        println!("Bodies by tag: {}", session.tags.counts(ids));
+    }
+    println!(
+        "World: {}",
+        compaction::stats(&session.context, session.compactions)
+    );
+    if !session.worlds.is_empty() {
+        let mut ids: Vec<u64> = session.worlds.keys().copied().collect();
+        ids.sort_unstable();
+        println!("Worlds created by the client: {:?}", ids);
+    }
+    if session.pacer.pacing() != shared::pacing::StepPacing::Immediate {
+        println!(
+            "Step pacing {}: {} steps deferred, {:?} waited",
+            session.pacer.pacing(),
+            session.pacer.total_deferred,
+            session.pacer.total_delay
+        );
+    }
+}
+
+/// Handles the requests in order, checking each against the world as the
+/// ones before left it, and puts the world back as it was before the first
+/// if one fails.
+fn handle_transaction(
+    reqs: Vec<Request>,
+    session: &mut Session,
+) -> Result<Vec<Response>, TransactionError> {
+    if let Some((index, req)) = reqs
+        .iter()
+        .enumerate()
+        .find(|(_, req)| !shared::transaction::allowed(req))
+    {
+        return Err(TransactionError::NotAllowed {
+            index,
+            request: req.name().to_string(),
+        });
+    }
+    let savepoint = session.savepoint().map_err(TransactionError::Savepoint)?;
+    let mut responses = vec![];
+    for (index, req) in reqs.into_iter().enumerate() {
+        let checked = transaction::check(
+            &req,
+            &session.context,
+            &session.id2body,
+            &session.templates,
+            session.options.max_bodies,
+        )
+        .and_then(|()| match &session.turn {
+            Some(turn) => turn.check(&req, &session.context),
+            None => Ok(()),
+        });
+        if let Err(reason) = checked {
+            if let Err(err) = session.roll_back(savepoint) {
+                println!("Failed to roll back transaction: {}", err);
+            }
+            return Err(TransactionError::Failed { index, reason });
+        }
+        responses.push(handle_request(req, session));
+    }
+    Ok(responses)
+}
+
+/// Handles the request, unless the session is the member of a room that
+/// isn't allowed to make it.
+fn handle_request(req: Request, session: &mut Session) -> Response {
+    if let Some(turn) = &mut session.turn {
+        if let Err(reason) = turn.check(&req, &session.context) {
+            println!("Forbidden {}: {}", req.name(), reason);
+            return Response::Forbidden(reason);
+        }
+        turn.apply(&req);
+        if let Request::SimulateStep(_) = req {
+            if !turn.host {
+                // The host steps the room, its results pushed to everyone
+                return Response::SimulationResult(
+                    HashMap::new(),
+                    intersections(&session.context),
+                    vec![],
+                );
+            }
+            // The events of the step are collected for every member
+            if let Some(turn) = &mut session.turn {
+                std::mem::swap(&mut session.events, &mut turn.step_events);
+            }
+            let response = handle_allowed_request(req, session);
+            if let Some(turn) = &mut session.turn {
+                std::mem::swap(&mut session.events, &mut turn.step_events);
+                let events = turn.step_events.take();
+                session.events.extend(events.clone());
+                turn.steps.push((response.clone(), events));
+            }
+            return response;
+        }
+    }
+    handle_allowed_request(req, session)
+}
 
-                    deserialize(&decompressed)?
+fn handle_allowed_request(req: Request, session: &mut Session) -> Response {
+    match req {
+        Request::BulkRequest(reqs) => {
+            let mut responses = vec![];
+            for req in reqs {
+                responses.push(handle_request(req, session));
+            }
+            Response::BulkResponse(responses)
+        }
+        Request::Transaction(reqs) => {
+            let result = handle_transaction(reqs, session);
+            if let Err(err) = &result {
+                println!("Transaction rolled back: {}", err);
+            }
+            Response::Transaction(result)
+        }
+        Request::Idempotent { key, request } => {
+            if let Some(response) = session.recent_results.get(key) {
+                println!("Answering {} {} again", request.name(), key);
+                return response.clone();
+            }
+            let response = handle_request(*request, session);
+            session.recent_results.insert(key, response.clone());
+            response
+        }
+        Request::CreateWorld(id) => {
+            let created = session.create_world(id);
+            match created {
+                Ok(_) => println!(
+                    "Created world {}, {} in the session",
+                    id,
+                    session.worlds.len() + 1
+                ),
+                Err(err) => println!("Failed to create world {}: {}", id, err),
+            }
+            Response::WorldCreated(created)
+        }
+        Request::DestroyWorld(id) => {
+            let destroyed = session.destroy_world(id);
+            match destroyed {
+                Ok(_) => println!("Destroyed world {}", id),
+                Err(err) => println!("Failed to destroy world {}: {}", id, err),
+            }
+            Response::WorldDestroyed(destroyed)
+        }
+        Request::InWorld { world_id, request } => {
+            let previous = session.world_id;
+            if let Err(err) = session.enter_world(world_id) {
+                println!("Not handling {}: {}", request.name(), err);
+                return Response::InWorld(world_id, Err(err));
+            }
+            let response = handle_request(*request, session);
+            // Requests that aren't wrapped act on the world they did before
+            session.enter_world(previous).unwrap();
+            Response::InWorld(world_id, Ok(Box::new(response)))
+        }
+        Request::UpdateConfig(new_config, contact_rules) => {
+            if contact_rules != session.contact_rules {
+                println!("Contact rules: {}", contact_rules);
+                session.contact_rules = contact_rules;
+            }
+            update_config(new_config.into(), &mut session.config)
+        }
+        Request::PatchConfig(patch) => {
+            let mut config = session.config.unwrap_or_default();
+            patch.apply(&mut config);
+            update_config(config, &mut session.config)
+        }
+        Request::CreateBodies(mut bodies) => {
+            session.limit_bodies(&mut bodies);
+            create_bodies(
+                bodies,
+                &mut session.context,
+                &mut session.id2body,
+                &mut session.tags,
+            )
+        }
+        Request::CreateColliders(mut colliders) => {
+            if let Some(restitution) = session.profile.and_then(profile::Profile::restitution) {
+                for collider in colliders.iter_mut().filter(|c| c.restitution.is_none()) {
+                    collider.restitution = Some(restitution.clone());
                 }
-                #[cfg(not(feature = "compression"))]
+            }
+            let subdivisions = session.scaled_shape_subdivision();
+            for collider in &mut colliders {
+                scale_shape(&mut collider.shape, collider.scale, subdivisions);
+            }
+            create_colliders(
+                colliders,
+                &mut session.context,
+                &session.id2body,
+                &session.layers,
+            )
+        }
+        Request::CreateJoints(joints) => create_joints(joints, &mut session.context),
+        Request::RegisterTemplates(mut new_templates) => {
+            if let Some(restitution) = session.profile.and_then(profile::Profile::restitution) {
+                for (_, template) in new_templates
+                    .iter_mut()
+                    .filter(|(_, t)| t.restitution.is_none())
                 {
-                    deserialize(&msg_data)?
+                    template.restitution = Some(restitution.clone());
+                }
+            }
+            // Once, rather than for every instance
+            let subdivisions = session.scaled_shape_subdivision();
+            for (_, template) in &mut new_templates {
+                scale_shape(&mut template.shape, template.scale, subdivisions);
+            }
+            register_templates(new_templates, &mut session.templates)
+        }
+        Request::SpawnInstances(mut instances) => {
+            session.limit_bodies(&mut instances);
+            spawn_instances(
+                instances,
+                &mut session.context,
+                &mut session.id2body,
+                &session.templates,
+                &mut session.tags,
+            )
+        }
+        Request::ApplyCommands(commands) => apply_commands(commands, &mut session.context),
+        Request::ApplyForces(forces) => apply_forces(forces, &mut session.context),
+        Request::SimulateStep(delta_time) => {
+            let status = session.status();
+            if status.paused {
+                return Response::SimulationResult(
+                    HashMap::new(),
+                    intersections(&session.context),
+                    vec![],
+                );
+            }
+            let delta_time = delta_time * status.time_scale;
+            let (delta_time, report) = match session.pacer.pace(delta_time) {
+                pacing::Pace::Run(delta_time, report) => (delta_time, report),
+                pacing::Pace::Defer(report) => {
+                    return Response::Paced(
+                        report,
+                        Box::new(Response::SimulationResult(
+                            HashMap::new(),
+                            intersections(&session.context),
+                            vec![],
+                        )),
+                    )
                 }
             };
-
-            let response = handle_request(
-                req,
-                &mut context,
-                &mut config,
-                &mut sim_to_render_time,
-                &mut entity2body,
-                physics_hooks,
+            let start = Instant::now();
+            let config = match session.config {
+                Some(config) => config,
+                // The server's ticks can come before the client configured the world
+                None => {
+                    return Response::SimulationResult(
+                        HashMap::new(),
+                        intersections(&session.context),
+                        vec![],
+                    )
+                }
+            };
+            // Scenes are loaded with the first requests, before any step
+            if let Some(scene) = session.preloaded_scene.take() {
+                scene::unload(scene, &mut session.context);
+            }
+            session.controllers.apply(&mut session.context, delta_time);
+            session
+                .fluids
+                .apply(&mut session.context, config.gravity, delta_time);
+            hooks::activate(&session.contact_rules, &mut session.context.colliders);
+            let response = simulate_step(
+                &mut session.context,
+                config.gravity,
+                config.timestep_mode,
+                session.events.writers(),
+                &hooks::RuleHooks(&session.contact_rules),
+                delta_time,
+                &mut session.sim_to_render_time,
+                &mut session.snapshot_filter,
+            );
+            if session.snapshot_filter.left_out() > 0 {
+                session.degradation |= Degradation::SNAPSHOT_BUDGET;
+            }
+            let step_time = start.elapsed();
+            session.stats.step_times.push(step_time);
+            session.unreported_steps.0 += step_time;
+            session.unreported_steps.1 += 1;
+            session.events.record();
+            session.joint_breaks.record(&mut session.context);
+            if report.pacing == shared::pacing::StepPacing::Immediate {
+                response
+            } else {
+                Response::Paced(report, Box::new(response))
+            }
+        }
+        Request::GetState => get_state(&session.context, &session.tags),
+        Request::CastRays(rays) => cast_rays(rays, &session.context),
+        Request::CastRay {
+            origin,
+            dir,
+            max_toi,
+            solid,
+            filter,
+        } => Response::RayHit(queries::cast_ray(
+            origin,
+            dir,
+            max_toi,
+            solid,
+            filter,
+            &session.id2body,
+            &session.context,
+        )),
+        Request::CastShape {
+            shape_pos,
+            shape_rot,
+            shape_vel,
+            shape,
+            max_toi,
+            filter,
+        } => Response::ShapeHit(queries::cast_shape(
+            shape_pos,
+            shape_rot,
+            shape_vel,
+            shape,
+            max_toi,
+            filter,
+            &session.id2body,
+            &session.context,
+        )),
+        Request::IntersectionsWithShape {
+            shape_pos,
+            shape_rot,
+            shape,
+            filter,
+        } => Response::ShapeIntersections(queries::intersections_with_shape(
+            shape_pos,
+            shape_rot,
+            shape,
+            filter,
+            &session.id2body,
+            &session.context,
+        )),
+        Request::TakeEvents => Response::Events(session.events.take()),
+        Request::SetUpdateRates(rates) => set_update_rates(
+            rates,
+            &session.id2body,
+            &session.tags,
+            &mut session.snapshot_filter,
+        ),
+        Request::SetPriorities(priorities) => set_priorities(
+            priorities,
+            &session.id2body,
+            &session.tags,
+            &mut session.snapshot_filter,
+        ),
+        Request::SetFocus(focus) => {
+            session.snapshot_filter.set_focus(focus);
+            Response::FocusSet
+        }
+        Request::LoadScene { name, hash } => scene::load(
+            &name,
+            hash,
+            &session.scenes_dir,
+            &mut session.context,
+            &mut session.preloaded_scene,
+        ),
+        Request::SetControllers(controllers) => set_controllers(
+            controllers,
+            &session.id2body,
+            &session.tags,
+            &mut session.controllers,
+        ),
+        Request::CreateRagdoll(created) => ragdoll::create(
+            created,
+            &mut session.context,
+            &mut session.id2body,
+            &mut session.joint_breaks,
+        ),
+        Request::CreateRope(created) => session.ropes.create(
+            created,
+            &mut session.context,
+            &session.id2body,
+            &mut session.snapshot_filter,
+            &mut session.joint_breaks,
+        ),
+        Request::GetRopes => Response::Ropes(session.ropes.snapshots(&session.context)),
+        Request::AddFluidVolumes(volumes) => {
+            session.fluids.add(volumes);
+            Response::FluidVolumesAdded
+        }
+        Request::TakeJointBreaks => {
+            let breaks = session.joint_breaks.take();
+            for joint_break in &breaks {
+                println!(
+                    "Joint between {} and {} broke under {}",
+                    session.tags.describe(joint_break.entity1),
+                    session.tags.describe(joint_break.entity2),
+                    joint_break.force
+                );
+            }
+            Response::JointBreaks(breaks)
+        }
+        Request::PredictTrajectory {
+            body_template,
+            origin,
+            velocity,
+            steps,
+        } => {
+            println!("Predicting a trajectory of {} steps", steps);
+            let gravity = session.config.unwrap_or_default().gravity;
+            Response::Trajectory(trajectory::predict(
+                &session.context,
+                gravity,
+                &body_template,
+                origin,
+                velocity,
+                steps,
+            ))
+        }
+        Request::Ping { reply_len, .. } => {
+            if reply_len > MAX_PING_REPLY {
+                session.degradation |= Degradation::REQUEST_LIMITED;
+            }
+            let mut reply = vec![0; reply_len.min(MAX_PING_REPLY)];
+            // Random bytes, so that compression doesn't shrink them
+            session.rng.fill(&mut reply[..]);
+            Response::Pong(reply)
+        }
+        Request::TimeSync(client_sent) => Response::TimeSync(clock::TimeSample {
+            client_sent,
+            server_received: session.received_at,
+            server_sent: clock::now_micros(),
+            client_received: 0,
+        }),
+        Request::MeasureStep(bodies) => {
+            if bodies > MAX_MEASURED_BODIES {
+                session.degradation |= Degradation::REQUEST_LIMITED;
+            }
+            let mut context =
+                benchmark::random_ball_world(bodies.min(MAX_MEASURED_BODIES), session.rng.gen());
+            Response::StepMeasured(benchmark::mean_step_time(&mut context))
+        }
+        Request::RemoveBodies(ids) => {
+            let removed = session.remove_bodies(&ids);
+            println!("Removed {} of {} bodies", removed, ids.len());
+            Response::BodiesRemoved
+        }
+        Request::RemoveColliders(ids) => {
+            let removed = session.remove_colliders(&ids);
+            println!("Removed {} colliders of {} entities", removed, ids.len());
+            Response::CollidersRemoved
+        }
+        Request::UpdateBodies(updates) => update_bodies(updates, &mut session.context),
+        Request::UpdateColliders(mut updates) => {
+            let subdivisions = session.scaled_shape_subdivision();
+            for (_, update) in &mut updates {
+                if let Some(shape) = &mut update.shape {
+                    scale_shape(shape, update.scale, subdivisions);
+                }
+            }
+            update_colliders(updates, &mut session.context)
+        }
+        Request::SetBodyTransforms(transforms) => set_body_transforms(
+            transforms,
+            &session.id2body,
+            &session.tags,
+            &mut session.context,
+        ),
+        Request::ResetWorld => {
+            println!("Resetting world");
+            session.reset_world();
+            Response::WorldReset
+        }
+        Request::SetSimulationState(state) => {
+            println!("Simulation {:?} by the client", state);
+            session.simulation_state = state;
+            Response::SimulationStateSet
+        }
+        Request::SetStreaming(period) => {
+            match period {
+                Some(period) => println!("Streaming a step every {:?}", period),
+                None => println!("Streaming stopped"),
+            }
+            session.stream = period
+                .filter(|period| !period.is_zero())
+                .map(streaming::Stream::new);
+            Response::StreamingSet
+        }
+        Request::TakeSnapshot => {
+            let snapshot = session.take_snapshot();
+            match &snapshot {
+                Ok(snapshot) => println!("Took a snapshot of {} bytes", snapshot.context.len()),
+                Err(err) => println!("Failed to take a snapshot: {}", err),
+            }
+            Response::Snapshot(snapshot)
+        }
+        Request::RestoreSnapshot(snapshot) => {
+            let restored = session.restore_snapshot(snapshot);
+            match &restored {
+                Ok(bodies) => println!("Restored a snapshot of {} bodies", bodies),
+                Err(err) => println!("Failed to restore a snapshot: {}", err),
+            }
+            Response::SnapshotRestored(restored)
+        }
+        Request::UseProfile(profile) => {
+            println!("Using profile {}", profile);
+            session.profile = Some(profile);
+            update_config(profile.config(), &mut session.config)
+        }
+        Request::TakeStepTime => {
+            let (total, steps) = std::mem::take(&mut session.unreported_steps);
+            Response::StepTime(total.checked_div(steps).unwrap_or_default())
+        }
+        Request::Handshake(client, framing, encoding, compression) => {
+            println!("Client metadata: {}", client);
+            // What the server doesn't support is left as it is, which the
+            // client reads from the answer
+            let capabilities = session.capabilities();
+            let framing = Some(framing)
+                .filter(|framing| capabilities.framings.contains(framing))
+                .unwrap_or(session.framing);
+            let encoding = Some(encoding)
+                .filter(|encoding| capabilities.encodings.contains(encoding))
+                .unwrap_or(session.encoding);
+            let compression = Some(compression)
+                .filter(|compression| capabilities.compressions.contains(compression))
+                .unwrap_or(session.compression);
+            if framing != session.framing {
+                println!("Switching to {} framing", framing);
+            }
+            if encoding != session.encoding {
+                println!("Switching to {} encoding", encoding);
+            }
+            if compression != session.compression {
+                println!("Switching to {} compression", compression);
+            }
+            session.framing = framing;
+            session.encoding = encoding;
+            session.compression = compression;
+            Response::Handshake(
+                RunMetadata::clone(&session.metadata),
+                framing,
+                encoding,
+                compression,
+                capabilities,
+            )
+        }
+        Request::RegisterLayers(layers) => {
+            let names: Vec<String> = layers
+                .layers()
+                .map(|(name, bits)| format!("{} ({:#x})", name, bits))
+                .collect();
+            println!("Registering collision layers {}", names.join(", "));
+            session.layers = layers;
+            Response::LayersRegistered
+        }
+        Request::GetStats => {
+            Response::Stats(compaction::stats(&session.context, session.compactions))
+        }
+        Request::CompactWorld => {
+            let compacted = session.compact_world();
+            println!(
+                "Compacted world from {} to {}",
+                compacted.before, compacted.after
             );
-
-            simulate_latency(simulated_latency);
-
-            let serialized = serialize(&response)?;
-            let msg = {
-                #[cfg(feature = "compression")]
-                {
-                    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-                    encoder.write_all(&serialized)?;
-                    let compressed = encoder.finish()?;
-                    Message::binary(compressed)
-                }
-                #[cfg(not(feature = "compression"))]
-                {
-                    Message::binary(serialized)
+            Response::WorldCompacted(compacted)
+        }
+        Request::SetStepPacing(step_pacing) => {
+            println!("Using step pacing {}", step_pacing);
+            session.pacer.set_pacing(step_pacing);
+            Response::StepPacingSet(session.pacer.pacing())
+        }
+        Request::SetImpairment { key, impairment } => {
+            let result = match &session.impairment_key {
+                None => Err(ImpairmentError::Disabled),
+                Some(expected) if *expected != key => Err(ImpairmentError::WrongKey),
+                Some(_) => {
+                    println!("Using impairment {}", impairment);
+                    session.impairment = impairment;
+                    Ok(impairment)
                 }
             };
-            websocket.write_message(msg)?;
-        } else if msg.is_close() {
-            println!("Closing connection with {}", peer_addr);
-            return Ok(());
-        } else {
-            return Err(format!("Unexpected message: {:?}", msg).into());
+            if let Err(err) = result {
+                println!("Refused to set impairment: {}", err);
+            }
+            Response::ImpairmentSet(result)
         }
     }
 }
 
-fn handle_request(
-    req: Request,
-    mut context: &mut RapierContext,
-    mut config: &mut Option<RapierConfiguration>,
-    mut sim_to_render_time: &mut SimulationToRenderTime,
-    mut entity2body: &mut HashMap<Entity, RigidBodyHandle>,
-    physics_hooks: (),
-) -> Response {
-    match req {
-        Request::BulkRequest(reqs) => {
-            let mut responses = vec![];
-            for req in reqs {
-                responses.push(handle_request(
-                    req,
-                    &mut context,
-                    &mut config,
-                    &mut sim_to_render_time,
-                    &mut entity2body,
-                    physics_hooks,
-                ));
+/// The cargo features the server was built with.
+fn features() -> Vec<&'static str> {
+    [
+        ("gpu-aabb-bench", cfg!(feature = "gpu-aabb-bench")),
+        ("parallel", cfg!(feature = "parallel")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature)
+    .collect()
+}
+
+fn metrics_row(stats: &SessionStats, peer_addr: &str, context: &RapierContext) -> Vec<String> {
+    let steps = stats.step_times.len();
+    let step_mean = if steps > 0 {
+        stats.step_times.iter().sum::<Duration>() / steps as u32
+    } else {
+        Duration::ZERO
+    };
+    let step_max = stats.step_times.iter().max().copied().unwrap_or_default();
+
+    vec![
+        unix_timestamp(),
+        peer_addr.to_string(),
+        stats.requests.to_string(),
+        stats.bytes_received.to_string(),
+        stats.bytes_sent.to_string(),
+        context.bodies.len().to_string(),
+        steps.to_string(),
+        millis(step_mean),
+        millis(step_max),
+        stats.degradation.to_string(),
+    ]
+}
+
+fn record_response(
+    recorder: &mut Recorder,
+    bodies: &mut HashSet<RigidBodyHandle>,
+    response: &Response,
+    context: &RapierContext,
+) -> bincode::Result<()> {
+    let record_colliders = |recorder: &mut Recorder, handles: Vec<(u64, ColliderHandle)>| {
+        let scale = context.physics_scale();
+        let colliders = handles
+            .into_iter()
+            .filter_map(|(id, handle)| {
+                let collider = context.colliders.get(handle)?;
+                let iso = collider
+                    .position_wrt_parent()
+                    .unwrap_or_else(|| collider.position());
+                Some(RecordedCollider {
+                    id,
+                    parent: collider.parent(),
+                    transform: utils::iso_to_transform(iso, scale),
+                    shape: collider.shared_shape().clone().into(),
+                })
+            })
+            .collect();
+        recorder.record(&RecordEntry::Colliders(colliders))
+    };
+
+    match response {
+        Response::BulkResponse(responses) | Response::Transaction(Ok(responses)) => {
+            for response in responses {
+                record_response(recorder, bodies, response, context)?;
             }
-            Response::BulkResponse(responses)
         }
-        Request::UpdateConfig(new_config) => update_config(new_config.into(), &mut config),
-        Request::CreateBodies(bodies) => create_bodies(bodies, &mut context, &mut entity2body),
-        Request::CreateColliders(colliders) => {
-            create_colliders(colliders, &mut context, &entity2body)
-        }
-        Request::SimulateStep(delta_time) => simulate_step(
-            &mut context,
-            config.unwrap().gravity,
-            config.unwrap().timestep_mode,
-            physics_hooks,
-            delta_time,
-            &mut sim_to_render_time,
-        ),
+        Response::RigidBodyHandles(handles) => {
+            bodies.extend(handles.iter().map(|&(_, handle)| handle));
+            recorder.record(&RecordEntry::Bodies(handles.clone()))?;
+        }
+        Response::ColliderHandles(handles) => {
+            record_colliders(recorder, handles.clone())?;
+        }
+        Response::InstanceHandles(handles, _) => {
+            bodies.extend(handles.iter().map(|&(_, body, _)| body));
+            let created = handles.iter().map(|&(id, body, _)| (id, body)).collect();
+            recorder.record(&RecordEntry::Bodies(created))?;
+            let colliders = handles
+                .iter()
+                .map(|&(id, _, collider)| (id, collider))
+                .collect();
+            record_colliders(recorder, colliders)?;
+        }
+        Response::SimulationResult(results, ..) => {
+            // The step leaves out bodies that are still there, so those that
+            // are gone are recorded apart for the viewer to hide them
+            let removed: Vec<RigidBodyHandle> = bodies
+                .iter()
+                .filter(|&&handle| !context.bodies.contains(handle))
+                .copied()
+                .collect();
+            if !removed.is_empty() {
+                for handle in &removed {
+                    bodies.remove(handle);
+                }
+                recorder.record(&RecordEntry::Removed(removed))?;
+            }
+            recorder.record(&RecordEntry::Step(results.clone()))?;
+        }
+        Response::Paced(report, response) if !report.deferred => {
+            record_response(recorder, bodies, response, context)?;
+        }
+        _ => {}
     }
+    Ok(())
 }
 
-fn simulate_latency(simulated_latency: SimulatedLatency) {
+/// How long the simulated latency holds a response back.
+fn latency_delay(simulated_latency: SimulatedLatency, rng: &mut StdRng) -> Duration {
     let latency = match simulated_latency {
-        SimulatedLatency::None => return,
+        SimulatedLatency::None => return Duration::ZERO,
         SimulatedLatency::Fixed(latency) => latency,
         SimulatedLatency::Random { min, mean } => {
-            let mut rng = thread_rng();
             let expovariate = -rng.gen::<f64>().ln() * (mean - min) as f64;
             (min as f64 + expovariate) as u64
         }
@@ -219,7 +2629,30 @@ fn simulate_latency(simulated_latency: SimulatedLatency) {
 
     let latency = Duration::from_millis(latency);
     println!("Simulated Latency: {:?}", latency);
-    sleep(latency);
+    latency
+}
+
+/// Delays a lost message by the time it takes TCP to send it again.
+fn loss_delay(loss: f32, rng: &mut StdRng) -> Duration {
+    if loss > 0.0 && rng.gen::<f32>() < loss {
+        println!("Simulated Loss: {:?}", RETRANSMISSION_TIMEOUT);
+        RETRANSMISSION_TIMEOUT
+    } else {
+        Duration::ZERO
+    }
+}
+
+/// Delays a message of the given size by its transmission time at the
+/// simulated bandwidth.
+fn bandwidth_delay(bandwidth: Option<u64>, len: usize) -> Duration {
+    let kbps = match bandwidth {
+        Some(kbps) => kbps,
+        None => return Duration::ZERO,
+    };
+
+    let delay = Duration::from_secs_f64((len * 8) as f64 / (kbps * 1000) as f64);
+    println!("Simulated Transmission Time: {:?}", delay);
+    delay
 }
 
 fn update_config(
@@ -233,132 +2666,659 @@ fn update_config(
 fn create_bodies(
     bodies: Vec<CreatedBody>,
     context: &mut RapierContext,
-    entity2body: &mut HashMap<Entity, RigidBodyHandle>,
+    id2body: &mut IdMap<RigidBodyHandle>,
+    tags: &mut tags::Tags,
 ) -> Response {
-    println!("Creating bodies");
+    for body in &bodies {
+        tags.insert(body.id, body.tag.clone());
+    }
+    println!(
+        "Creating {} bodies, by tag {}",
+        bodies.len(),
+        tags.counts(bodies.iter().map(|body| body.id))
+    );
     let mut rbs = vec![];
     for body in bodies {
-        let mut builder = RigidBodyBuilder::new(body.body.into());
+        let id = body.id;
+        let handle = create_body(body, context, id2body);
+        rbs.push((id, handle));
+    }
+    Response::RigidBodyHandles(rbs)
+}
 
-        if let Some(transform) = body.transform {
-            builder = builder.position(transform);
-        }
+fn create_body(
+    body: CreatedBody,
+    context: &mut RapierContext,
+    id2body: &mut IdMap<RigidBodyHandle>,
+) -> RigidBodyHandle {
+    let mut builder = RigidBodyBuilder::new(body.body.into());
 
-        if let Some(mprops) = body.additional_mass_properties {
-            builder = match mprops.into() {
-                AdditionalMassProperties::MassProperties(mprops) => {
-                    builder.additional_mass_properties(mprops.into_rapier(context.physics_scale()))
-                }
-                AdditionalMassProperties::Mass(mass) => builder.additional_mass(mass),
-            };
-        }
+    if let Some(transform) = body.transform {
+        builder = builder.position(transform);
+    }
+
+    if let Some(mprops) = body.additional_mass_properties {
+        builder = match mprops.into() {
+            AdditionalMassProperties::MassProperties(mprops) => {
+                builder.additional_mass_properties(mprops.into_rapier(context.physics_scale()))
+            }
+            AdditionalMassProperties::Mass(mass) => builder.additional_mass(mass),
+        };
+    }
 
-        builder = builder.user_data(body.id.into());
+    if let Some(damping) = body.damping {
+        builder = builder
+            .linear_damping(damping.linear_damping)
+            .angular_damping(damping.angular_damping);
+    }
 
-        let handle = context.bodies.insert(builder);
+    if let Some(gravity_scale) = body.gravity_scale {
+        builder = builder.gravity_scale(gravity_scale);
+    }
 
-        entity2body.insert(Entity::from_bits(body.id), handle);
+    if let Some(group) = body.dominance_group {
+        builder = builder.dominance_group(group);
+    }
 
-        rbs.push((body.id, handle));
+    if let Some(locked_axes) = body.locked_axes {
+        builder = builder.locked_axes(
+            bevy_rapier3d::rapier::prelude::LockedAxes::from_bits_truncate(locked_axes),
+        );
+    }
+
+    builder = builder.ccd_enabled(body.ccd).user_data(body.id.into());
+
+    let handle = context.bodies.insert(builder);
+
+    id2body.insert(PhysicsId(body.id), handle);
+
+    handle
+}
+
+/// Scales a shape as bevy_rapier does, from its unscaled shape, so that a
+/// shape sent again at another scale isn't scaled twice.
+fn scale_shape(shape: &mut Collider, scale: Option<Vect>, subdivisions: u32) {
+    if let Some(scale) = scale {
+        shape.set_scale(scale, subdivisions);
     }
-    Response::RigidBodyHandles(rbs)
 }
 
 fn create_colliders(
     colliders: Vec<CreatedCollider>,
     context: &mut RapierContext,
-    entity2body: &HashMap<Entity, RigidBodyHandle>,
+    id2body: &IdMap<RigidBodyHandle>,
+    layers: &LayerRegistry,
 ) -> Response {
-    println!("Creating colliders");
+    println!(
+        "Creating {} colliders, by layer {}",
+        colliders.len(),
+        layer_counts(&colliders, layers)
+    );
     let mut cols = vec![];
     for collider in colliders {
-        let mut builder = ColliderBuilder::new(collider.shape.raw);
+        let id = collider.id;
+        let handle = create_collider(collider, context, id2body);
 
-        if let Some(mprops) = collider.mass_properties {
-            builder = match mprops.into() {
-                ColliderMassProperties::Density(density) => builder.density(density),
-                ColliderMassProperties::Mass(mass) => builder.mass(mass),
-                ColliderMassProperties::MassProperties(mprops) => {
-                    builder.mass_properties(mprops.into_rapier(context.physics_scale()))
+        // entity2collider.insert(Entity::from_bits(collider.id), handle);
+
+        cols.push((id, handle));
+    }
+    Response::ColliderHandles(cols)
+}
+
+fn create_joints(joints: Vec<CreatedJoint>, context: &mut RapierContext) -> Response {
+    println!("Creating {} joints", joints.len());
+    let scale = context.physics_scale();
+    let mut handles = vec![];
+    for joint in joints {
+        if !context.bodies.contains(joint.body1) || !context.bodies.contains(joint.body2) {
+            println!("Joint {} between unknown bodies", joint.id);
+            continue;
+        }
+        let data = GenericJoint::from(joint.joint).into_rapier(scale);
+        let handle = if joint.multibody {
+            match context
+                .multibody_joints
+                .insert(joint.body1, joint.body2, data, true)
+            {
+                Some(handle) => JointHandle::Multibody(handle),
+                None => {
+                    println!("Multibody joint {} would close a loop", joint.id);
+                    continue;
                 }
-            };
+            }
+        } else {
+            JointHandle::Impulse(context.impulse_joints.insert(
+                joint.body1,
+                joint.body2,
+                data,
+                true,
+            ))
+        };
+        handles.push((joint.id, handle));
+    }
+    Response::JointHandles(handles)
+}
+
+/// How many of the colliders are members of every combination of layers, by
+/// name.
+fn layer_counts(colliders: &[CreatedCollider], layers: &LayerRegistry) -> String {
+    let mut counts: Vec<(u32, usize)> = vec![];
+    for collider in colliders {
+        // Colliders without groups are members of every group
+        let memberships = collider
+            .collision_groups
+            .map_or(u32::MAX, |groups| groups.memberships);
+        match counts.iter_mut().find(|(bits, _)| *bits == memberships) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((memberships, 1)),
+        }
+    }
+    let counts: Vec<String> = counts
+        .into_iter()
+        .map(|(bits, count)| format!("{}: {}", layers.describe(bits), count))
+        .collect();
+    counts.join(", ")
+}
+
+fn create_collider(
+    collider: CreatedCollider,
+    context: &mut RapierContext,
+    id2body: &IdMap<RigidBodyHandle>,
+) -> ColliderHandle {
+    let mut builder = ColliderBuilder::new(collider.shape.raw);
+
+    if let Some(mprops) = collider.mass_properties {
+        builder = match mprops.into() {
+            ColliderMassProperties::Density(density) => builder.density(density),
+            ColliderMassProperties::Mass(mass) => builder.mass(mass),
+            ColliderMassProperties::MassProperties(mprops) => {
+                builder.mass_properties(mprops.into_rapier(context.physics_scale()))
+            }
+        };
+    }
+
+    if let Some(friction) = collider.friction {
+        builder = builder
+            .friction(friction.coefficient)
+            .friction_combine_rule(friction.combine_rule.into());
+    }
+
+    if let Some(restitution) = collider.restitution {
+        builder = builder
+            .restitution(restitution.coefficient)
+            .restitution_combine_rule(restitution.combine_rule.into());
+    }
+
+    if let Some(groups) = collider.collision_groups {
+        builder = builder.collision_groups(CollisionGroups::from(groups).into());
+    }
+
+    if let Some(groups) = collider.solver_groups {
+        builder = builder.solver_groups(SolverGroups::from(groups).into());
+    }
+
+    if collider.sensor.is_some() {
+        builder = builder.sensor(true);
+    }
+
+    if let Some(events) = collider.active_events {
+        builder = builder.active_events(events.into());
+    }
+
+    if let Some(hooks) = collider.active_hooks {
+        builder = builder.active_hooks(ActiveHooks::from(hooks).into());
+    }
+
+    if let Some(types) = collider.active_collision_types {
+        builder = builder.active_collision_types(ActiveCollisionTypes::from(types).into());
+    }
+
+    if let Some(threshold) = collider.contact_force_event_threshold {
+        builder = builder.contact_force_event_threshold(threshold);
+    }
+
+    let body_handle = id2body.get(PhysicsId(collider.parent_id.unwrap_or(collider.id)));
+
+    builder = builder.user_data(collider.id.into());
+
+    if let Some(body_handle) = body_handle {
+        // The transform of colliders on the body's own entity is the body's
+        if collider.parent_id.is_some() {
+            builder = builder.position(collider.transform.unwrap_or_default());
+        }
+        context
+            .colliders
+            .insert_with_parent(builder, body_handle, &mut context.bodies)
+    } else {
+        let transform = collider.transform.unwrap_or_default();
+        builder = builder.position(transform);
+        context.colliders.insert(builder)
+    }
+}
+
+fn register_templates(
+    new_templates: Vec<(u64, BodyTemplate)>,
+    templates: &mut HashMap<u64, BodyTemplate>,
+) -> Response {
+    println!("Registering {} templates", new_templates.len());
+    templates.extend(new_templates);
+    Response::TemplatesRegistered
+}
+
+fn spawn_instances(
+    instances: Vec<TemplateInstance>,
+    context: &mut RapierContext,
+    id2body: &mut IdMap<RigidBodyHandle>,
+    templates: &HashMap<u64, BodyTemplate>,
+    tags: &mut tags::Tags,
+) -> Response {
+    for instance in &instances {
+        tags.insert(instance.id, instance.tag.clone());
+    }
+    println!(
+        "Spawning {} instances, by tag {}",
+        instances.len(),
+        tags.counts(instances.iter().map(|instance| instance.id))
+    );
+    let mut handles = vec![];
+    let mut unknown = vec![];
+    for instance in instances {
+        let template = match templates.get(&instance.template_id) {
+            Some(template) => template,
+            None => {
+                println!(
+                    "Unknown template {} of {}",
+                    instance.template_id,
+                    tags.describe(instance.id)
+                );
+                unknown.push((instance.id, instance.template_id));
+                continue;
+            }
+        };
+
+        let (body, collider) = template.instantiate(&instance);
+        let body_handle = create_body(body, context, id2body);
+        let collider_handle = create_collider(collider, context, id2body);
+
+        if let Some(velocity) = instance.velocity {
+            let scale = context.physics_scale();
+            if let Some(rb) = context.bodies.get_mut(body_handle) {
+                rb.set_linvel((velocity.linvel / scale).into(), true);
+                rb.set_angvel(velocity.angvel.into(), true);
+            }
+        }
+
+        handles.push((instance.id, body_handle, collider_handle));
+    }
+    Response::InstanceHandles(handles, unknown)
+}
+
+fn apply_commands(
+    commands: Vec<(RigidBodyHandle, BodyCommand)>,
+    context: &mut RapierContext,
+) -> Response {
+    let scale = context.physics_scale();
+    for (handle, command) in commands {
+        let rb = match context.bodies.get_mut(handle) {
+            Some(rb) => rb,
+            None => {
+                println!("Command for unknown body {:?}", handle);
+                continue;
+            }
+        };
+
+        match command {
+            BodyCommand::Force { force, torque } => {
+                rb.reset_forces(true);
+                rb.reset_torques(true);
+                rb.add_force((force / scale).into(), true);
+                rb.add_torque(torque.into(), true);
+            }
+            BodyCommand::Impulse {
+                impulse,
+                torque_impulse,
+            } => {
+                rb.apply_impulse((impulse / scale).into(), true);
+                rb.apply_torque_impulse(torque_impulse.into(), true);
+            }
+            BodyCommand::SetVelocity(velocity) => {
+                rb.set_linvel((velocity.linvel / scale).into(), true);
+                rb.set_angvel(velocity.angvel.into(), true);
+            }
         }
+    }
+    Response::CommandsApplied
+}
 
-        if let Some(friction) = collider.friction {
-            builder = builder
-                .friction(friction.coefficient)
-                .friction_combine_rule(friction.combine_rule.into());
+fn update_bodies(
+    updates: Vec<(RigidBodyHandle, BodyUpdate)>,
+    context: &mut RapierContext,
+) -> Response {
+    println!("Updating {} bodies", updates.len());
+    let scale = context.physics_scale();
+    for (handle, update) in updates {
+        let rb = match context.bodies.get_mut(handle) {
+            Some(rb) => rb,
+            None => {
+                println!("Update of unknown body {:?}", handle);
+                continue;
+            }
+        };
+
+        if let Some(body) = update.body {
+            rb.set_body_type(body.into(), true);
+        }
+        if let Some(mprops) = update.additional_mass_properties {
+            match mprops.into() {
+                AdditionalMassProperties::MassProperties(mprops) => {
+                    rb.set_additional_mass_properties(mprops.into_rapier(scale), true)
+                }
+                AdditionalMassProperties::Mass(mass) => rb.set_additional_mass(mass, true),
+            }
         }
+    }
+    Response::BodiesUpdated
+}
+
+fn update_colliders(
+    updates: Vec<(ColliderHandle, ColliderUpdate)>,
+    context: &mut RapierContext,
+) -> Response {
+    println!("Updating {} colliders", updates.len());
+    let scale = context.physics_scale();
+    for (handle, update) in updates {
+        let collider = match context.colliders.get_mut(handle) {
+            Some(collider) => collider,
+            None => {
+                println!("Update of unknown collider {:?}", handle);
+                continue;
+            }
+        };
 
-        if let Some(restitution) = collider.restitution {
-            builder = builder
-                .restitution(restitution.coefficient)
-                .restitution_combine_rule(restitution.combine_rule.into());
+        if let Some(shape) = update.shape {
+            collider.set_shape(shape.raw);
+        }
+        if let Some(mprops) = update.mass_properties {
+            match mprops.into() {
+                ColliderMassProperties::Density(density) => collider.set_density(density),
+                ColliderMassProperties::Mass(mass) => collider.set_mass(mass),
+                ColliderMassProperties::MassProperties(mprops) => {
+                    collider.set_mass_properties(mprops.into_rapier(scale))
+                }
+            }
+        }
+        if let Some(friction) = update.friction {
+            collider.set_friction(friction.coefficient);
+            collider.set_friction_combine_rule(friction.combine_rule.into());
+        }
+        if let Some(restitution) = update.restitution {
+            collider.set_restitution(restitution.coefficient);
+            collider.set_restitution_combine_rule(restitution.combine_rule.into());
         }
 
-        let body_entity = Entity::from_bits(collider.id);
-        let body_handle = entity2body.get(&body_entity).copied();
-        let child_transform = Transform::default();
+        // A changed collider doesn't wake its body up on its own
+        if let Some(rb) = collider
+            .parent()
+            .and_then(|parent| context.bodies.get_mut(parent))
+        {
+            rb.wake_up(true);
+        }
+    }
+    Response::CollidersUpdated
+}
 
-        builder = builder.user_data(collider.id.into());
+fn set_body_transforms(
+    transforms: Vec<(u64, Isometry<Real>)>,
+    id2body: &IdMap<RigidBodyHandle>,
+    tags: &tags::Tags,
+    context: &mut RapierContext,
+) -> Response {
+    for (id, transform) in transforms {
+        let rb = match id2body
+            .get(PhysicsId(id))
+            .and_then(|handle| context.bodies.get_mut(handle))
+        {
+            Some(rb) => rb,
+            None => {
+                println!("Transform for unknown body {}", tags.describe(id));
+                continue;
+            }
+        };
 
-        let handle = if let Some(body_handle) = body_handle {
-            builder = builder.position(transform_to_iso(&child_transform, context.physics_scale()));
-            context
-                .colliders
-                .insert_with_parent(builder, body_handle, &mut context.bodies)
+        // Setting the position of a kinematic body directly would skip the
+        // velocity it moves its contacts with
+        if rb.body_type() == RigidBodyType::KinematicPositionBased {
+            rb.set_next_kinematic_position(transform);
         } else {
-            let transform = collider.transform.unwrap_or_default();
-            builder = builder.position(transform);
-            context.colliders.insert(builder)
+            rb.set_position(transform, true);
+        }
+    }
+    Response::BodyTransformsSet
+}
+
+/// Applies forces and impulses the way bevy_rapier applies its components.
+fn apply_forces(
+    forces: Vec<(
+        RigidBodyHandle,
+        Option<SerializableExternalForce>,
+        Option<SerializableExternalImpulse>,
+    )>,
+    context: &mut RapierContext,
+) -> Response {
+    let scale = context.physics_scale();
+    for (handle, force, impulse) in forces {
+        let rb = match context.bodies.get_mut(handle) {
+            Some(rb) => rb,
+            None => {
+                println!("Forces for unknown body {:?}", handle);
+                continue;
+            }
         };
 
-        // entity2collider.insert(Entity::from_bits(collider.id), handle);
+        if let Some(force) = force {
+            rb.reset_forces(true);
+            rb.reset_torques(true);
+            rb.add_force((force.force / scale).into(), true);
+            rb.add_torque(force.torque.into(), true);
+        }
+        if let Some(impulse) = impulse {
+            rb.apply_impulse((impulse.impulse / scale).into(), true);
+            rb.apply_torque_impulse(impulse.torque_impulse.into(), true);
+        }
+    }
+    Response::ForcesApplied
+}
+
+fn set_update_rates(
+    rates: Vec<(u64, UpdateRate)>,
+    id2body: &IdMap<RigidBodyHandle>,
+    tags: &tags::Tags,
+    snapshot_filter: &mut snapshot::SnapshotFilter,
+) -> Response {
+    for (id, rate) in rates {
+        match id2body.get(PhysicsId(id)) {
+            Some(handle) => snapshot_filter.set_rate(handle, rate),
+            None => println!("Update rate for unknown body {}", tags.describe(id)),
+        }
+    }
+    Response::UpdateRatesSet
+}
 
-        cols.push((collider.id, handle));
+fn set_controllers(
+    controllers: Vec<(u64, Option<Controller>)>,
+    id2body: &IdMap<RigidBodyHandle>,
+    tags: &tags::Tags,
+    session_controllers: &mut controllers::Controllers,
+) -> Response {
+    for (id, controller) in controllers {
+        match id2body.get(PhysicsId(id)) {
+            Some(handle) => session_controllers.set(handle, controller),
+            None => println!("Controller for unknown body {}", tags.describe(id)),
+        }
     }
-    Response::ColliderHandles(cols)
+    Response::ControllersSet
+}
+
+fn set_priorities(
+    priorities: Vec<(u64, f32)>,
+    id2body: &IdMap<RigidBodyHandle>,
+    tags: &tags::Tags,
+    snapshot_filter: &mut snapshot::SnapshotFilter,
+) -> Response {
+    for (id, priority) in priorities {
+        match id2body.get(PhysicsId(id)) {
+            Some(handle) => snapshot_filter.set_priority(handle, priority),
+            None => println!("Priority for unknown body {}", tags.describe(id)),
+        }
+    }
+    Response::PrioritiesSet
 }
 
+#[allow(clippy::too_many_arguments)]
 fn simulate_step(
     context: &mut RapierContext,
     gravity: Vect,
     timestep_mode: TimestepMode,
-    physics_hooks: (),
+    events: Option<(EventWriter<CollisionEvent>, EventWriter<ContactForceEvent>)>,
+    physics_hooks: &dyn PhysicsHooks,
     delta_time: f32,
     sim_to_render_time: &mut SimulationToRenderTime,
+    snapshot_filter: &mut snapshot::SnapshotFilter,
 ) -> Response {
     println!("Simulating step");
 
-    // Hack to get delta time into rapier
-    let now = Instant::now();
-    let then = now - Duration::from_secs_f32(delta_time);
-    let mut time = Time::new(then);
-    time.update_with_instant(then);
-    time.update_with_instant(now);
-
-    context.step_simulation(
+    step_context(
+        context,
         gravity,
         timestep_mode,
-        None,
-        &physics_hooks,
-        &time,
+        events,
+        physics_hooks,
+        delta_time,
         sim_to_render_time,
-        None,
     );
 
     let scale = context.physics_scale();
 
-    let mut results = HashMap::new();
+    let mut entries = vec![];
 
+    snapshot_filter.next_step();
     for (handle, rb) in context.bodies.iter() {
+        if !snapshot_filter.includes(handle, rb) {
+            continue;
+        }
+
         let transform = utils::iso_to_transform(rb.position(), scale);
         let velocity = Velocity {
             linvel: (rb.linvel() * scale).into(),
             angvel: (*rb.angvel()).into(),
         };
 
-        results.insert(handle, (transform, velocity));
+        entries.push((handle, (transform, velocity)));
     }
-    Response::SimulationResult(results)
+
+    let entries = snapshot_filter.fit_budget(entries);
+    let fell_asleep = snapshot_filter.fell_asleep(&entries, &context.bodies);
+    Response::SimulationResult(
+        entries.into_iter().collect(),
+        intersections(context),
+        fell_asleep,
+    )
+}
+
+/// The physics ids of the colliders intersecting sensors, leaving out those of
+/// scenes, which the client has no entity for.
+fn intersections(context: &RapierContext) -> Vec<(u64, u64)> {
+    let entity = |handle| {
+        context
+            .colliders
+            .get(handle)
+            .map(|collider| collider.user_data as u64)
+            .filter(|&entity| entity != shared::scene::SCENE_ENTITY)
+    };
+    context
+        .narrow_phase
+        .intersection_pairs()
+        .filter(|&(_, _, intersecting)| intersecting)
+        .filter_map(|(handle1, handle2, _)| Some((entity(handle1)?, entity(handle2)?)))
+        .collect()
+}
+
+fn get_state(context: &RapierContext, tags: &tags::Tags) -> Response {
+    let bodies = context
+        .bodies
+        .iter()
+        .map(|(handle, rb)| MirroredBody {
+            handle,
+            id: rb.user_data as u64,
+            body: rb.body_type().into(),
+            position: *rb.position(),
+            linvel: (*rb.linvel()).into(),
+            angvel: (*rb.angvel()).into(),
+            tag: tags.get(rb.user_data as u64).map(String::from),
+        })
+        .collect();
+
+    let colliders = context
+        .colliders
+        .iter()
+        .map(|(handle, collider)| MirroredCollider {
+            handle,
+            id: collider.user_data as u64,
+            parent: collider.parent(),
+            position: *collider
+                .position_wrt_parent()
+                .unwrap_or_else(|| collider.position()),
+            shape: collider.shared_shape().clone().into(),
+            sensor: collider.is_sensor(),
+        })
+        .collect();
+
+    Response::State(WorldState { bodies, colliders })
+}
+
+fn cast_rays(rays: Vec<RayCast>, context: &RapierContext) -> Response {
+    let hits = rays
+        .into_iter()
+        .map(|ray| {
+            let hit = context
+                .cast_ray(
+                    ray.origin,
+                    ray.dir,
+                    ray.max_toi,
+                    ray.solid,
+                    QueryFilter::default(),
+                )
+                .map(|(entity, toi)| (entity.to_bits(), toi));
+            (ray.id, hit)
+        })
+        .collect();
+    Response::RayHits(hits)
+}
+
+fn step_context(
+    context: &mut RapierContext,
+    gravity: Vect,
+    timestep_mode: TimestepMode,
+    events: Option<(EventWriter<CollisionEvent>, EventWriter<ContactForceEvent>)>,
+    physics_hooks: &dyn PhysicsHooks,
+    delta_time: f32,
+    sim_to_render_time: &mut SimulationToRenderTime,
+) {
+    // Hack to get delta time into rapier
+    let now = Instant::now();
+    let then = now - Duration::from_secs_f32(delta_time);
+    let mut time = Time::new(then);
+    time.update_with_instant(then);
+    time.update_with_instant(now);
+
+    context.step_simulation(
+        gravity,
+        timestep_mode,
+        events,
+        physics_hooks,
+        &time,
+        sim_to_render_time,
+        None,
+    );
 }
@@ -0,0 +1,94 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use shared::pacing::{PacingReport, StepPacing};
+
+/// What to do with a step that arrived.
+pub enum Pace {
+    /// Simulate this much time, the step's own and that of deferred steps.
+    Run(f32, PacingReport),
+    Defer(PacingReport),
+}
+
+/// Applies a session's step pacing.
+#[derive(Default)]
+pub struct Pacer {
+    pacing: StepPacing,
+    /// When the current interval of capped pacing started and how many steps
+    /// ran in it.
+    interval: Option<(Instant, u32)>,
+    last_step: Option<Instant>,
+    deferred_time: f32,
+    deferred_steps: u32,
+    /// Totals over the session, for its summary.
+    pub total_deferred: u32,
+    pub total_delay: Duration,
+}
+
+impl Pacer {
+    pub fn new(pacing: StepPacing) -> Self {
+        Self {
+            pacing,
+            ..Default::default()
+        }
+    }
+
+    pub fn pacing(&self) -> StepPacing {
+        self.pacing
+    }
+
+    /// Switches to another pacing, running steps deferred so far with the
+    /// next one.
+    pub fn set_pacing(&mut self, pacing: StepPacing) {
+        self.pacing = pacing;
+        self.interval = None;
+        self.last_step = None;
+    }
+
+    /// Waits for the step's turn if capped pacing says so.
+    pub fn pace(&mut self, delta_time: f32) -> Pace {
+        let mut report = PacingReport {
+            pacing: self.pacing,
+            deferred: false,
+            caught_up: 0,
+            delay: Duration::ZERO,
+        };
+        match self.pacing {
+            StepPacing::Immediate => {}
+            StepPacing::Capped {
+                max_steps,
+                interval,
+            } => {
+                let now = Instant::now();
+                let (start, steps) = match self.interval {
+                    Some((start, steps)) if now.duration_since(start) < interval => (start, steps),
+                    _ => (now, 0),
+                };
+                self.interval = if steps < max_steps {
+                    Some((start, steps + 1))
+                } else {
+                    report.delay = (start + interval).saturating_duration_since(now);
+                    self.total_delay += report.delay;
+                    sleep(report.delay);
+                    Some((Instant::now(), 1))
+                };
+            }
+            StepPacing::Collapsed { min_interval } => {
+                let now = Instant::now();
+                if self
+                    .last_step
+                    .is_some_and(|last| now.duration_since(last) < min_interval)
+                {
+                    self.deferred_time += delta_time;
+                    self.deferred_steps += 1;
+                    self.total_deferred += 1;
+                    report.deferred = true;
+                    return Pace::Defer(report);
+                }
+                self.last_step = Some(now);
+            }
+        }
+        report.caught_up = std::mem::take(&mut self.deferred_steps);
+        Pace::Run(delta_time + std::mem::take(&mut self.deferred_time), report)
+    }
+}
@@ -0,0 +1,563 @@
+//! Experimental partitioning of one world across server processes.
+//!
+//! Space is split along the x axis into [`Region`]s, each simulated by its own
+//! process. Neighbouring processes are linked by a websocket and exchange a
+//! [`PartitionMessage`] after every tick: bodies that left a region are handed
+//! off to the neighbour that now owns them, and bodies close to a boundary are
+//! mirrored on the other side as kinematic ghosts so that contacts across the
+//! boundary are resolved. Regions step in lockstep since every tick waits for
+//! the neighbours' messages.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{
+    ColliderBuilder, Isometry, RigidBodyBuilder, RigidBodyHandle,
+};
+use bincode::{deserialize, serialize};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tungstenite::{accept, connect, Message, WebSocket};
+
+use shared::partition::*;
+
+/// Half of the extent of the world along the y and z axes.
+const WORLD_HALF_EXTENT: Real = 10.0;
+
+const BALL_RADIUS: Real = 0.5;
+
+const CONNECT_ATTEMPTS: u32 = 20;
+
+const TICK_RATE: u32 = 60;
+
+pub struct PartitionOptions {
+    /// Distinguishes the ids of bodies seeded by different processes.
+    pub index: u32,
+    pub region: Region,
+    pub ghost_margin: Real,
+    /// The port the left neighbour connects to, if there is one.
+    pub listen_port: Option<u16>,
+    /// The address of the right neighbour's partition port, if there is one.
+    pub right: Option<String>,
+    pub bodies: usize,
+    /// Seeds the bodies' placement, mixed with `index` so that regions differ.
+    pub seed: u64,
+}
+
+enum Side {
+    Left,
+    Right,
+}
+
+struct RegionWorld {
+    region: Region,
+    context: RapierContext,
+    sim_to_render_time: SimulationToRenderTime,
+    owned: HashMap<u64, RigidBodyHandle>,
+    ghosts: HashMap<u64, RigidBodyHandle>,
+}
+
+#[derive(Default)]
+struct TickStats {
+    handed_in: usize,
+    handed_out: usize,
+    step_time: Duration,
+}
+
+pub fn run(options: PartitionOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let region = options.region;
+    println!(
+        "Simulating region [{}, {}) as partition {}",
+        region.min_x, region.max_x, options.index
+    );
+
+    // Every process binds before connecting to the right, and accepts from the
+    // left only once connected, so the chain is established from its right end.
+    let listener = match options.listen_port {
+        Some(port) => Some(TcpListener::bind(format!("0.0.0.0:{}", port))?),
+        None => None,
+    };
+
+    let mut right = match &options.right {
+        Some(addr) => Some(connect_with_retries(addr)?),
+        None => None,
+    };
+
+    let mut left = match listener {
+        Some(listener) => {
+            let (stream, peer_addr) = listener.accept()?;
+            println!("Left neighbour connected from {}", peer_addr);
+            Some(accept(stream)?)
+        }
+        None => None,
+    };
+
+    let mut world = RegionWorld::new(region, left.is_none(), right.is_none());
+    world.seed(options.index, options.bodies, options.seed);
+
+    let delta_time = 1.0 / TICK_RATE as f32;
+    let tick = Duration::from_secs_f32(delta_time);
+    let mut stats = TickStats::default();
+    let mut last_report = Instant::now();
+    let mut ticks = 0;
+
+    loop {
+        let tick_start = Instant::now();
+
+        let start = Instant::now();
+        world.step(delta_time);
+        stats.step_time += start.elapsed();
+
+        let to_left = world.outgoing(Side::Left, options.ghost_margin, left.is_some());
+        let to_right = world.outgoing(Side::Right, options.ghost_margin, right.is_some());
+
+        let from_left = match &mut left {
+            Some(link) => Some(exchange(link, &to_left)?),
+            None => None,
+        };
+        let from_right = match &mut right {
+            Some(link) => Some(exchange(link, &to_right)?),
+            None => None,
+        };
+
+        stats.handed_out += world.release(&to_left.handoff) + world.release(&to_right.handoff);
+
+        // Ghosts are replaced wholesale by the neighbours' latest view
+        let mut ghosts = vec![];
+        for message in from_left.into_iter().chain(from_right) {
+            stats.handed_in += message.handoff.len();
+            for state in message.handoff {
+                world.adopt(state);
+            }
+            ghosts.extend(message.ghosts);
+        }
+        world.update_ghosts(ghosts);
+
+        debug_assert!(world.owned.keys().all(|id| !world.ghosts.contains_key(id)));
+
+        ticks += 1;
+        if last_report.elapsed() >= Duration::from_secs(1) {
+            println!(
+                "owned: {}, ghosts: {}, handed in: {}, handed out: {}, step: {:?}",
+                world.owned.len(),
+                world.ghosts.len(),
+                stats.handed_in,
+                stats.handed_out,
+                stats.step_time / ticks
+            );
+            stats = TickStats::default();
+            last_report = Instant::now();
+            ticks = 0;
+        }
+
+        if let Some(remaining) = tick.checked_sub(tick_start.elapsed()) {
+            sleep(remaining);
+        }
+    }
+}
+
+fn connect_with_retries(
+    addr: &str,
+) -> Result<
+    WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>,
+    Box<dyn std::error::Error>,
+> {
+    let url = format!("ws://{}/partition", addr);
+    let mut attempts = 0;
+    loop {
+        match connect(url.as_str()) {
+            Ok((socket, _)) => {
+                println!("Connected to right neighbour {}", addr);
+                return Ok(socket);
+            }
+            Err(err) if attempts < CONNECT_ATTEMPTS => {
+                println!("Waiting for right neighbour {}: {}", addr, err);
+                attempts += 1;
+                sleep(Duration::from_millis(500));
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Sends this tick's message to a neighbour and waits for the neighbour's.
+fn exchange<S: Read + Write>(
+    link: &mut WebSocket<S>,
+    message: &PartitionMessage,
+) -> Result<PartitionMessage, Box<dyn std::error::Error>> {
+    link.write_message(Message::binary(serialize(message)?))?;
+    loop {
+        let msg = link.read_message()?;
+        if msg.is_binary() {
+            return Ok(deserialize(&msg.into_data())?);
+        } else if msg.is_close() {
+            return Err("Neighbour closed the partition link".into());
+        }
+    }
+}
+
+impl RegionWorld {
+    fn new(region: Region, left_wall: bool, right_wall: bool) -> Self {
+        let mut world = Self {
+            region,
+            context: RapierContext::default(),
+            sim_to_render_time: SimulationToRenderTime::default(),
+            owned: HashMap::new(),
+            ghosts: HashMap::new(),
+        };
+
+        // Keep bodies from drifting away along y and z
+        let half_width = (region.max_x - region.min_x) / 2.0;
+        let center_x = region.min_x + half_width;
+        for (position, half_extents) in [
+            (
+                Vec3::new(center_x, -WORLD_HALF_EXTENT - 0.5, 0.0),
+                Vec3::new(half_width, 0.5, WORLD_HALF_EXTENT),
+            ),
+            (
+                Vec3::new(center_x, WORLD_HALF_EXTENT + 0.5, 0.0),
+                Vec3::new(half_width, 0.5, WORLD_HALF_EXTENT),
+            ),
+            (
+                Vec3::new(center_x, 0.0, -WORLD_HALF_EXTENT - 0.5),
+                Vec3::new(half_width, WORLD_HALF_EXTENT, 0.5),
+            ),
+            (
+                Vec3::new(center_x, 0.0, WORLD_HALF_EXTENT + 0.5),
+                Vec3::new(half_width, WORLD_HALF_EXTENT, 0.5),
+            ),
+        ] {
+            world.add_wall(position, half_extents);
+        }
+
+        // Only the outermost regions are closed along x
+        let side_half_extents = Vec3::new(0.5, WORLD_HALF_EXTENT, WORLD_HALF_EXTENT);
+        if left_wall {
+            world.add_wall(Vec3::new(region.min_x - 0.5, 0.0, 0.0), side_half_extents);
+        }
+        if right_wall {
+            world.add_wall(Vec3::new(region.max_x + 0.5, 0.0, 0.0), side_half_extents);
+        }
+
+        world
+    }
+
+    fn add_wall(&mut self, position: Vec3, half_extents: Vec3) {
+        self.context.colliders.insert(
+            ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+                .translation(position.into())
+                .restitution(1.0),
+        );
+    }
+
+    fn seed(&mut self, index: u32, bodies: usize, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed ^ index as u64);
+        let margin = BALL_RADIUS;
+        for i in 0..bodies {
+            let state = BodyState {
+                id: ((index as u64) << 32) | i as u64,
+                position: Isometry::translation(
+                    rng.gen_range(self.region.min_x + margin..self.region.max_x - margin),
+                    rng.gen_range(-WORLD_HALF_EXTENT + margin..WORLD_HALF_EXTENT - margin),
+                    rng.gen_range(-WORLD_HALF_EXTENT + margin..WORLD_HALF_EXTENT - margin),
+                ),
+                linvel: Vec3::new(
+                    rng.gen_range(-5.0..5.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                ),
+                angvel: Vec3::ZERO,
+                shape: Collider::ball(BALL_RADIUS),
+            };
+            self.adopt(state);
+        }
+    }
+
+    fn step(&mut self, delta_time: f32) {
+        crate::step_context(
+            &mut self.context,
+            Vect::ZERO,
+            TimestepMode::Fixed {
+                dt: delta_time,
+                substeps: 1,
+            },
+            None,
+            &(),
+            delta_time,
+            &mut self.sim_to_render_time,
+        );
+    }
+
+    fn state(&self, id: u64, handle: RigidBodyHandle) -> Option<BodyState> {
+        let body = self.context.bodies.get(handle)?;
+        let collider = self.context.colliders.get(*body.colliders().first()?)?;
+        Some(BodyState {
+            id,
+            position: *body.position(),
+            linvel: (*body.linvel()).into(),
+            angvel: (*body.angvel()).into(),
+            shape: collider.shared_shape().clone().into(),
+        })
+    }
+
+    /// Collects the owned bodies that crossed the given boundary, and the ones
+    /// close enough to it to be mirrored on the other side.
+    fn outgoing(&self, side: Side, ghost_margin: Real, has_neighbour: bool) -> PartitionMessage {
+        let mut message = PartitionMessage::default();
+        if !has_neighbour {
+            return message;
+        }
+
+        for (&id, &handle) in &self.owned {
+            let x = match self.context.bodies.get(handle) {
+                Some(body) => body.translation().x,
+                None => continue,
+            };
+
+            let (crossed, near) = match side {
+                Side::Left => (x < self.region.min_x, x < self.region.min_x + ghost_margin),
+                Side::Right => (
+                    x >= self.region.max_x,
+                    x >= self.region.max_x - ghost_margin,
+                ),
+            };
+
+            if let Some(state) = self.state(id, handle) {
+                if crossed {
+                    message.handoff.push(state);
+                } else if near {
+                    message.ghosts.push(state);
+                }
+            }
+        }
+
+        message
+    }
+
+    /// Removes bodies handed off to a neighbour, returning how many there were.
+    fn release(&mut self, handoff: &[BodyState]) -> usize {
+        for state in handoff {
+            if let Some(handle) = self.owned.remove(&state.id) {
+                self.remove_body(handle);
+            }
+        }
+        handoff.len()
+    }
+
+    /// Takes ownership of a body, replacing its ghost if there is one.
+    fn adopt(&mut self, state: BodyState) {
+        if let Some(handle) = self.ghosts.remove(&state.id) {
+            self.remove_body(handle);
+        }
+
+        let handle = self.context.bodies.insert(
+            RigidBodyBuilder::dynamic()
+                .position(state.position)
+                .linvel(state.linvel.into())
+                .angvel(state.angvel.into())
+                .user_data(state.id.into()),
+        );
+        self.context.colliders.insert_with_parent(
+            ColliderBuilder::new(state.shape.raw).restitution(1.0),
+            handle,
+            &mut self.context.bodies,
+        );
+        self.owned.insert(state.id, handle);
+    }
+
+    fn update_ghosts(&mut self, ghosts: Vec<BodyState>) {
+        let mut seen = HashSet::new();
+        for state in ghosts {
+            // A body handed to us this tick may still be a ghost in the
+            // neighbour's view
+            if self.owned.contains_key(&state.id) {
+                continue;
+            }
+            seen.insert(state.id);
+
+            match self.ghosts.get(&state.id) {
+                Some(&handle) => {
+                    if let Some(body) = self.context.bodies.get_mut(handle) {
+                        body.set_next_kinematic_position(state.position);
+                    }
+                }
+                None => {
+                    let handle = self.context.bodies.insert(
+                        RigidBodyBuilder::kinematic_position_based()
+                            .position(state.position)
+                            .user_data(state.id.into()),
+                    );
+                    self.context.colliders.insert_with_parent(
+                        ColliderBuilder::new(state.shape.raw).restitution(1.0),
+                        handle,
+                        &mut self.context.bodies,
+                    );
+                    self.ghosts.insert(state.id, handle);
+                }
+            }
+        }
+
+        let stale: Vec<u64> = self
+            .ghosts
+            .keys()
+            .filter(|id| !seen.contains(id))
+            .copied()
+            .collect();
+        for id in stale {
+            if let Some(handle) = self.ghosts.remove(&id) {
+                self.remove_body(handle);
+            }
+        }
+    }
+
+    fn remove_body(&mut self, handle: RigidBodyHandle) {
+        let context = &mut self.context;
+        context.bodies.remove(
+            handle,
+            &mut context.islands,
+            &mut context.colliders,
+            &mut context.impulse_joints,
+            &mut context.multibody_joints,
+            true,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARGIN: Real = 2.0;
+
+    fn ball(id: u64, x: Real, linvel_x: Real) -> BodyState {
+        BodyState {
+            id,
+            position: Isometry::translation(x, 0.0, 0.0),
+            linvel: Vec3::new(linvel_x, 0.0, 0.0),
+            angvel: Vec3::ZERO,
+            shape: Collider::ball(BALL_RADIUS),
+        }
+    }
+
+    fn bodies_with_id(world: &RegionWorld, id: u64) -> usize {
+        world
+            .context
+            .bodies
+            .iter()
+            .filter(|(_, body)| body.user_data == id as u128)
+            .count()
+    }
+
+    fn assert_disjoint(world: &RegionWorld) {
+        assert!(world.owned.keys().all(|id| !world.ghosts.contains_key(id)));
+    }
+
+    /// One tick of two neighbouring regions, exchanging their messages the
+    /// way `run` does over the link.
+    fn tick(left: &mut RegionWorld, right: &mut RegionWorld) {
+        let delta_time = 1.0 / TICK_RATE as f32;
+        left.step(delta_time);
+        right.step(delta_time);
+
+        let to_right = left.outgoing(Side::Right, MARGIN, true);
+        let to_left = right.outgoing(Side::Left, MARGIN, true);
+        left.release(&to_right.handoff);
+        right.release(&to_left.handoff);
+
+        for (world, message) in [(&mut *left, to_left), (&mut *right, to_right)] {
+            for state in message.handoff {
+                world.adopt(state);
+            }
+            world.update_ghosts(message.ghosts);
+        }
+    }
+
+    #[test]
+    fn body_crossing_max_x_is_handed_to_the_neighbour_once() {
+        let mut left = RegionWorld::new(
+            Region {
+                min_x: 0.0,
+                max_x: 10.0,
+            },
+            true,
+            false,
+        );
+        let mut right = RegionWorld::new(
+            Region {
+                min_x: 10.0,
+                max_x: 20.0,
+            },
+            false,
+            true,
+        );
+        left.adopt(ball(7, 9.0, 10.0));
+
+        let mut crossed = false;
+        for _ in 0..2 * TICK_RATE {
+            tick(&mut left, &mut right);
+            assert_disjoint(&left);
+            assert_disjoint(&right);
+            if right.owned.contains_key(&7) {
+                crossed = true;
+                break;
+            }
+        }
+
+        assert!(crossed);
+        assert!(!left.owned.contains_key(&7));
+        assert_eq!(bodies_with_id(&right, 7), 1);
+        assert!(!right.ghosts.contains_key(&7));
+    }
+
+    #[test]
+    fn ghosts_are_replaced_by_the_latest_view() {
+        let region = Region {
+            min_x: 0.0,
+            max_x: 10.0,
+        };
+        let mut world = RegionWorld::new(region, true, false);
+
+        world.update_ghosts(vec![ball(1, 10.5, 0.0), ball(2, 11.0, 0.0)]);
+        let handle = world.ghosts[&1];
+        assert_eq!(world.ghosts.len(), 2);
+
+        // A ghost still in view is moved rather than recreated
+        world.update_ghosts(vec![ball(1, 10.8, 0.0)]);
+        assert_eq!(world.ghosts.len(), 1);
+        assert_eq!(world.ghosts[&1], handle);
+        assert_eq!(bodies_with_id(&world, 1), 1);
+        assert_eq!(bodies_with_id(&world, 2), 0);
+        let next = world.context.bodies[handle]
+            .next_position()
+            .translation
+            .vector
+            .x;
+        assert!((next - 10.8).abs() < 1e-6);
+
+        world.update_ghosts(vec![]);
+        assert!(world.ghosts.is_empty());
+        assert_eq!(bodies_with_id(&world, 1), 0);
+    }
+
+    #[test]
+    fn owned_bodies_are_never_ghosts() {
+        let region = Region {
+            min_x: 0.0,
+            max_x: 10.0,
+        };
+        let mut world = RegionWorld::new(region, true, false);
+        world.update_ghosts(vec![ball(3, 10.5, -5.0)]);
+
+        // Handed over while the neighbour still mirrors it
+        world.adopt(ball(3, 9.9, -5.0));
+        world.update_ghosts(vec![ball(3, 10.2, -5.0)]);
+
+        assert_disjoint(&world);
+        assert!(world.owned.contains_key(&3));
+        assert_eq!(bodies_with_id(&world, 3), 1);
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bevy_rapier3d::prelude::*;
+
+use crate::scene::{self, PreloadedScene};
+
+const WARMUP_DELTA_TIME: f32 = 1.0 / 60.0;
+
+/// A world made ready before the session it is handed to.
+#[derive(Default)]
+pub struct WarmWorld {
+    pub context: RapierContext,
+    pub scene: Option<PreloadedScene>,
+}
+
+/// When taken worlds are replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refill {
+    /// Right away, in the background.
+    Eager,
+    /// Never, once the pool is empty sessions start with a cold world.
+    Never,
+}
+
+/// Worlds created ahead of the sessions they are handed to, so that joining
+/// doesn't wait for the world and its scene.
+pub struct WorldPool {
+    size: usize,
+    scene: Option<String>,
+    scenes_dir: PathBuf,
+    refill: Refill,
+    worlds: Mutex<Vec<WarmWorld>>,
+}
+
+impl WorldPool {
+    pub fn new(
+        size: usize,
+        scene: Option<String>,
+        scenes_dir: PathBuf,
+        refill: Refill,
+    ) -> Arc<Self> {
+        let pool = Arc::new(Self {
+            size,
+            scene,
+            scenes_dir,
+            refill,
+            worlds: Mutex::new(Vec::with_capacity(size)),
+        });
+        pool.fill();
+        pool
+    }
+
+    pub fn take(self: &Arc<Self>) -> WarmWorld {
+        let world = self.worlds.lock().unwrap().pop();
+        if self.refill == Refill::Eager {
+            let pool = self.clone();
+            std::thread::spawn(move || pool.fill());
+        }
+
+        match world {
+            Some(world) => world,
+            None => {
+                println!("World pool empty, starting with a cold world");
+                WarmWorld::default()
+            }
+        }
+    }
+
+    fn fill(&self) {
+        while self.worlds.lock().unwrap().len() < self.size {
+            let world = self.warm();
+            // Another refill may have topped the pool up meanwhile
+            let mut worlds = self.worlds.lock().unwrap();
+            if worlds.len() < self.size {
+                worlds.push(world);
+            }
+        }
+    }
+
+    fn warm(&self) -> WarmWorld {
+        let start = Instant::now();
+        let mut context = RapierContext::default();
+        let scene = self.scene.as_ref().and_then(|name| {
+            scene::preload(name, &self.scenes_dir, &mut context)
+                .map_err(|err| println!("Failed to preload scene {}: {}", name, err))
+                .ok()
+        });
+
+        // The first step builds the broad-phase and query pipeline from scratch
+        crate::step_context(
+            &mut context,
+            Vect::ZERO,
+            TimestepMode::Variable {
+                max_dt: WARMUP_DELTA_TIME,
+                time_scale: 1.0,
+                substeps: 1,
+            },
+            None,
+            &(),
+            WARMUP_DELTA_TIME,
+            &mut SimulationToRenderTime::default(),
+        );
+
+        println!("Warmed a world in {:?}", start.elapsed());
+        WarmWorld { context, scene }
+    }
+}
@@ -0,0 +1,474 @@
+use serde::Serialize;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue, StatusCode};
+
+use shared::protocol::ProtocolVersion;
+
+/// Picks the protocol version of a connection from the client's
+/// `Sec-WebSocket-Protocol` header, answering with the one picked. Clients
+/// without the header get `ProtocolVersion::PREVIOUS`, and those asking only
+/// for versions this server doesn't speak are turned away.
+#[allow(clippy::result_large_err)]
+pub fn negotiate(
+    request: &Request,
+    mut response: Response,
+    version: &mut ProtocolVersion,
+) -> Result<Response, ErrorResponse> {
+    let offered = match request.headers().get(SEC_WEBSOCKET_PROTOCOL) {
+        Some(offered) => offered.to_str().unwrap_or_default(),
+        None => {
+            *version = ProtocolVersion::PREVIOUS;
+            return Ok(response);
+        }
+    };
+    match ProtocolVersion::negotiate(offered) {
+        Some(negotiated) => {
+            *version = negotiated;
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(negotiated.name()),
+            );
+            Ok(response)
+        }
+        None => {
+            let message = format!(
+                "Unsupported protocol {}, this server speaks {} and {}",
+                offered,
+                ProtocolVersion::CURRENT,
+                ProtocolVersion::PREVIOUS
+            );
+            Err(tungstenite::http::Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Some(message))
+                .unwrap())
+        }
+    }
+}
+
+/// A response as the client of a version reads it.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Versioned<'a> {
+    V1(v1::Response<'a>),
+    V2(&'a shared::Response),
+}
+
+impl<'a> Versioned<'a> {
+    pub fn new(version: ProtocolVersion, response: &'a shared::Response) -> Self {
+        match version {
+            ProtocolVersion::V1 => Self::V1(response.into()),
+            ProtocolVersion::V2 => Self::V2(response),
+        }
+    }
+}
+
+pub mod v1 {
+    use std::time::Duration;
+
+    use bevy::prelude::Transform;
+    use bevy_rapier3d::prelude::*;
+    use bevy_rapier3d::rapier::prelude::{ColliderHandle, Isometry, RigidBodyHandle};
+    use serde::{Deserialize, Serialize};
+
+    use shared::{
+        arena,
+        codec::Encoding,
+        compression::Compression,
+        degradation, framing, hooks, impairment, layers, metadata,
+        mirror::WorldState,
+        operator, pacing, profile,
+        ragdoll::{CreatedRagdoll, RagdollHandles},
+        rope::{CreatedRope, RopeSnapshot},
+        scene,
+        serializable::*,
+        BodyCommand, Controller, CreatedJoint, FluidVolume, JointBreak, JointHandle, RayCast,
+        StepEvents, TemplateInstance, UpdateRate,
+    };
+
+    /// `shared::CreatedBody` before bodies carried their damping, gravity
+    /// scale, CCD, dominance and locked axes.
+    #[derive(Deserialize)]
+    pub struct CreatedBody {
+        id: u64,
+        body: RigidBody,
+        transform: Option<Isometry<Real>>,
+        additional_mass_properties: Option<SerializableAdditionalMassProperties>,
+        tag: Option<String>,
+    }
+
+    impl From<CreatedBody> for shared::CreatedBody {
+        fn from(body: CreatedBody) -> Self {
+            Self {
+                id: body.id,
+                body: body.body,
+                transform: body.transform,
+                additional_mass_properties: body.additional_mass_properties,
+                damping: None,
+                gravity_scale: None,
+                ccd: false,
+                dominance_group: None,
+                locked_axes: None,
+                tag: body.tag,
+            }
+        }
+    }
+
+    /// `shared::BodyTemplate` before bodies carried their damping, gravity
+    /// scale, CCD, dominance and locked axes.
+    #[derive(Deserialize)]
+    pub struct BodyTemplate {
+        body: RigidBody,
+        additional_mass_properties: Option<SerializableAdditionalMassProperties>,
+        shape: Collider,
+        sensor: Option<SerializableSensor>,
+        mass_properties: Option<SerializableColliderMassProperties>,
+        friction: Option<SerializableFriction>,
+        restitution: Option<SerializableRestitution>,
+        collision_groups: Option<SerializableCollisionGroups>,
+        solver_groups: Option<SerializableSolverGroups>,
+        active_events: Option<ActiveEvents>,
+        contact_force_event_threshold: Option<Real>,
+    }
+
+    impl From<BodyTemplate> for shared::BodyTemplate {
+        fn from(template: BodyTemplate) -> Self {
+            Self {
+                body: template.body,
+                additional_mass_properties: template.additional_mass_properties,
+                damping: None,
+                gravity_scale: None,
+                ccd: false,
+                dominance_group: None,
+                locked_axes: None,
+                shape: template.shape,
+                scale: None,
+                sensor: template.sensor,
+                mass_properties: template.mass_properties,
+                friction: template.friction,
+                restitution: template.restitution,
+                collision_groups: template.collision_groups,
+                solver_groups: template.solver_groups,
+                active_events: template.active_events,
+                active_hooks: None,
+                active_collision_types: None,
+                contact_force_event_threshold: template.contact_force_event_threshold,
+            }
+        }
+    }
+
+    /// `shared::CreatedCollider` before colliders carried their active hooks
+    /// and collision types.
+    #[derive(Deserialize)]
+    pub struct CreatedCollider {
+        id: u64,
+        shape: Collider,
+        transform: Option<Isometry<Real>>,
+        sensor: Option<SerializableSensor>,
+        mass_properties: Option<SerializableColliderMassProperties>,
+        friction: Option<SerializableFriction>,
+        restitution: Option<SerializableRestitution>,
+        collision_groups: Option<SerializableCollisionGroups>,
+        solver_groups: Option<SerializableSolverGroups>,
+        active_events: Option<ActiveEvents>,
+        contact_force_event_threshold: Option<Real>,
+    }
+
+    impl From<CreatedCollider> for shared::CreatedCollider {
+        fn from(collider: CreatedCollider) -> Self {
+            Self {
+                id: collider.id,
+                shape: collider.shape,
+                scale: None,
+                parent_id: None,
+                transform: collider.transform,
+                sensor: collider.sensor,
+                mass_properties: collider.mass_properties,
+                friction: collider.friction,
+                restitution: collider.restitution,
+                collision_groups: collider.collision_groups,
+                solver_groups: collider.solver_groups,
+                active_events: collider.active_events,
+                active_hooks: None,
+                active_collision_types: None,
+                contact_force_event_threshold: collider.contact_force_event_threshold,
+            }
+        }
+    }
+
+    /// `shared::Request` as these clients send it, variant for variant.
+    #[derive(Deserialize)]
+    #[allow(clippy::enum_variant_names)]
+    pub enum Request {
+        BulkRequest(Vec<Request>),
+        UpdateConfig(SerializableRapierConfiguration),
+        CreateBodies(Vec<CreatedBody>),
+        CreateColliders(Vec<CreatedCollider>),
+        CreateJoints(Vec<CreatedJoint>),
+        RegisterTemplates(Vec<(u64, BodyTemplate)>),
+        SpawnInstances(Vec<TemplateInstance>),
+        ApplyCommands(Vec<(RigidBodyHandle, BodyCommand)>),
+        ApplyForces(
+            Vec<(
+                RigidBodyHandle,
+                Option<SerializableExternalForce>,
+                Option<SerializableExternalImpulse>,
+            )>,
+        ),
+        SimulateStep(f32),
+        GetState,
+        CastRays(Vec<RayCast>),
+        TakeImpacts,
+        TakeEvents,
+        SetUpdateRates(Vec<(u64, UpdateRate)>),
+        SetPriorities(Vec<(u64, f32)>),
+        SetFocus(Vect),
+        LoadScene {
+            name: String,
+            hash: Option<u64>,
+        },
+        SetControllers(Vec<(u64, Option<Controller>)>),
+        CreateRagdoll(CreatedRagdoll),
+        CreateRope(CreatedRope),
+        GetRopes,
+        AddFluidVolumes(Vec<FluidVolume>),
+        TakeJointBreaks,
+        PredictTrajectory {
+            body_template: BodyTemplate,
+            origin: Vect,
+            velocity: Vect,
+            steps: u32,
+        },
+        Ping {
+            payload: Vec<u8>,
+            reply_len: usize,
+        },
+        MeasureStep(usize),
+        ResetWorld,
+        RemoveBodies(Vec<u64>),
+        RemoveColliders(Vec<u64>),
+        UseProfile(profile::Profile),
+        TakeStepTime,
+        RegisterLayers(layers::LayerRegistry),
+        Handshake(metadata::RunMetadata, framing::Framing),
+        SetStepPacing(pacing::StepPacing),
+        GetStats,
+        CompactWorld,
+        SetImpairment {
+            key: String,
+            impairment: impairment::Impairment,
+        },
+        Idempotent {
+            key: u64,
+            request: Box<Request>,
+        },
+    }
+
+    impl From<Request> for shared::Request {
+        fn from(request: Request) -> Self {
+            use shared::Request as Current;
+            match request {
+                Request::BulkRequest(requests) => {
+                    Current::BulkRequest(requests.into_iter().map(Self::from).collect())
+                }
+                Request::UpdateConfig(config) => {
+                    Current::UpdateConfig(config, hooks::ContactRules::default())
+                }
+                Request::CreateBodies(bodies) => {
+                    Current::CreateBodies(bodies.into_iter().map(Into::into).collect())
+                }
+                Request::CreateColliders(colliders) => {
+                    Current::CreateColliders(colliders.into_iter().map(Into::into).collect())
+                }
+                Request::CreateJoints(joints) => Current::CreateJoints(joints),
+                Request::RegisterTemplates(templates) => Current::RegisterTemplates(
+                    templates
+                        .into_iter()
+                        .map(|(id, template)| (id, template.into()))
+                        .collect(),
+                ),
+                Request::SpawnInstances(instances) => Current::SpawnInstances(instances),
+                Request::ApplyCommands(commands) => Current::ApplyCommands(commands),
+                Request::ApplyForces(forces) => Current::ApplyForces(forces),
+                Request::SimulateStep(delta_time) => Current::SimulateStep(delta_time),
+                Request::GetState => Current::GetState,
+                Request::CastRays(rays) => Current::CastRays(rays),
+                // Impacts gave way to the collision events, which these
+                // clients read as well
+                Request::TakeImpacts => Current::TakeEvents,
+                Request::TakeEvents => Current::TakeEvents,
+                Request::SetUpdateRates(rates) => Current::SetUpdateRates(rates),
+                Request::SetPriorities(priorities) => Current::SetPriorities(priorities),
+                Request::SetFocus(focus) => Current::SetFocus(focus),
+                Request::LoadScene { name, hash } => Current::LoadScene { name, hash },
+                Request::SetControllers(controllers) => Current::SetControllers(controllers),
+                Request::CreateRagdoll(ragdoll) => Current::CreateRagdoll(ragdoll),
+                Request::CreateRope(rope) => Current::CreateRope(rope),
+                Request::GetRopes => Current::GetRopes,
+                Request::AddFluidVolumes(volumes) => Current::AddFluidVolumes(volumes),
+                Request::TakeJointBreaks => Current::TakeJointBreaks,
+                Request::PredictTrajectory {
+                    body_template,
+                    origin,
+                    velocity,
+                    steps,
+                } => Current::PredictTrajectory {
+                    body_template: body_template.into(),
+                    origin,
+                    velocity,
+                    steps,
+                },
+                Request::Ping { payload, reply_len } => Current::Ping { payload, reply_len },
+                Request::MeasureStep(bodies) => Current::MeasureStep(bodies),
+                Request::ResetWorld => Current::ResetWorld,
+                Request::RemoveBodies(ids) => Current::RemoveBodies(ids),
+                Request::RemoveColliders(ids) => Current::RemoveColliders(ids),
+                Request::UseProfile(profile) => Current::UseProfile(profile),
+                Request::TakeStepTime => Current::TakeStepTime,
+                Request::RegisterLayers(layers) => Current::RegisterLayers(layers),
+                Request::Handshake(metadata, framing) => {
+                    Current::Handshake(metadata, framing, Encoding::Bincode, Compression::None)
+                }
+                Request::SetStepPacing(pacing) => Current::SetStepPacing(pacing),
+                Request::GetStats => Current::GetStats,
+                Request::CompactWorld => Current::CompactWorld,
+                Request::SetImpairment { key, impairment } => {
+                    Current::SetImpairment { key, impairment }
+                }
+                Request::Idempotent { key, request } => Current::Idempotent {
+                    key,
+                    request: Box::new((*request).into()),
+                },
+            }
+        }
+    }
+
+    type Bodies<'a> = Vec<(&'a RigidBodyHandle, &'a (Transform, Velocity))>;
+    type Scene = Result<Vec<(ColliderHandle, scene::SceneCollider)>, scene::SceneError>;
+
+    /// `shared::Response` before steps carried sensor intersections, variant
+    /// for variant so that bincode numbers them the same.
+    #[derive(Serialize)]
+    #[allow(clippy::enum_variant_names)]
+    pub enum Response<'a> {
+        BulkResponse(Vec<Response<'a>>),
+        Degraded(&'a degradation::Degradation, Box<Response<'a>>),
+        Paced(&'a pacing::PacingReport, Box<Response<'a>>),
+        Status(&'a operator::SessionStatus, Box<Response<'a>>),
+        ConfigUpdated,
+        RigidBodyHandles(&'a [(u64, RigidBodyHandle)]),
+        ColliderHandles(&'a [(u64, ColliderHandle)]),
+        JointHandles(&'a [(u64, JointHandle)]),
+        TemplatesRegistered,
+        InstanceHandles(&'a [(u64, RigidBodyHandle, ColliderHandle)]),
+        CommandsApplied,
+        ForcesApplied,
+        SimulationResult(Bodies<'a>),
+        State(&'a WorldState),
+        RayHits(&'a [(u64, Option<(u64, Real)>)]),
+        /// Answered to `TakeImpacts` before impacts gave way to `Events`,
+        /// kept for bincode to number the variants after it the same.
+        #[allow(dead_code)]
+        Impacts,
+        Events(&'a StepEvents),
+        UpdateRatesSet,
+        PrioritiesSet,
+        FocusSet,
+        SceneLoaded(&'a Scene),
+        ControllersSet,
+        RagdollHandles(&'a RagdollHandles),
+        RopeCreated,
+        Ropes(&'a [RopeSnapshot]),
+        FluidVolumesAdded,
+        JointBreaks(&'a [JointBreak]),
+        Trajectory(&'a [Vect]),
+        Pong(&'a [u8]),
+        StepMeasured(&'a Duration),
+        WorldReset,
+        BodiesRemoved,
+        CollidersRemoved,
+        StepTime(&'a Duration),
+        LayersRegistered,
+        Handshake(&'a metadata::RunMetadata, &'a framing::Framing),
+        StepPacingSet(&'a pacing::StepPacing),
+        Stats(&'a arena::WorldStats),
+        WorldCompacted(&'a arena::CompactedWorld),
+        ImpairmentSet(&'a Result<impairment::Impairment, impairment::ImpairmentError>),
+    }
+
+    impl<'a> From<&'a shared::Response> for Response<'a> {
+        fn from(response: &'a shared::Response) -> Self {
+            use shared::Response as Current;
+            match response {
+                Current::BulkResponse(responses) => {
+                    Self::BulkResponse(responses.iter().map(Self::from).collect())
+                }
+                Current::Degraded(degradation, response) => {
+                    Self::Degraded(degradation, Box::new(response.as_ref().into()))
+                }
+                Current::Paced(report, response) => {
+                    Self::Paced(report, Box::new(response.as_ref().into()))
+                }
+                Current::Status(status, response) => {
+                    Self::Status(status, Box::new(response.as_ref().into()))
+                }
+                Current::ConfigUpdated => Self::ConfigUpdated,
+                Current::RigidBodyHandles(handles) => Self::RigidBodyHandles(handles),
+                Current::ColliderHandles(handles) => Self::ColliderHandles(handles),
+                Current::JointHandles(handles) => Self::JointHandles(handles),
+                Current::TemplatesRegistered => Self::TemplatesRegistered,
+                Current::InstanceHandles(handles, _) => Self::InstanceHandles(handles),
+                Current::CommandsApplied => Self::CommandsApplied,
+                Current::ForcesApplied => Self::ForcesApplied,
+                // Intersections are left out, which these clients can't read
+                Current::SimulationResult(results, ..) => {
+                    Self::SimulationResult(results.iter().collect())
+                }
+                Current::State(state) => Self::State(state),
+                Current::RayHits(hits) => Self::RayHits(hits),
+                Current::Events(events) => Self::Events(events),
+                Current::UpdateRatesSet => Self::UpdateRatesSet,
+                Current::PrioritiesSet => Self::PrioritiesSet,
+                Current::FocusSet => Self::FocusSet,
+                Current::SceneLoaded(scene) => Self::SceneLoaded(scene),
+                Current::ControllersSet => Self::ControllersSet,
+                Current::RagdollHandles(handles) => Self::RagdollHandles(handles),
+                Current::RopeCreated => Self::RopeCreated,
+                Current::Ropes(ropes) => Self::Ropes(ropes),
+                Current::FluidVolumesAdded => Self::FluidVolumesAdded,
+                Current::JointBreaks(breaks) => Self::JointBreaks(breaks),
+                Current::Trajectory(points) => Self::Trajectory(points),
+                Current::Pong(payload) => Self::Pong(payload),
+                Current::StepMeasured(duration) => Self::StepMeasured(duration),
+                Current::WorldReset => Self::WorldReset,
+                Current::BodiesRemoved => Self::BodiesRemoved,
+                Current::CollidersRemoved => Self::CollidersRemoved,
+                Current::StepTime(duration) => Self::StepTime(duration),
+                Current::LayersRegistered => Self::LayersRegistered,
+                Current::Handshake(metadata, framing, ..) => Self::Handshake(metadata, framing),
+                Current::StepPacingSet(pacing) => Self::StepPacingSet(pacing),
+                Current::Stats(stats) => Self::Stats(stats),
+                Current::WorldCompacted(compacted) => Self::WorldCompacted(compacted),
+                Current::ImpairmentSet(impairment) => Self::ImpairmentSet(impairment),
+                Current::BodiesUpdated
+                | Current::CollidersUpdated
+                | Current::BodyTransformsSet
+                | Current::RayHit(_)
+                | Current::ShapeHit(_)
+                | Current::ShapeIntersections(_)
+                | Current::Snapshot(_)
+                | Current::SnapshotRestored(_)
+                | Current::SimulationStateSet
+                | Current::Pushed(_)
+                | Current::TimeSync(_)
+                | Current::StreamingSet
+                | Current::InWorld(..)
+                | Current::WorldCreated(_)
+                | Current::WorldDestroyed(_)
+                | Current::Transaction(_)
+                | Current::Forbidden(_) => {
+                    unreachable!("answers to requests these clients can't send")
+                }
+            }
+        }
+    }
+}
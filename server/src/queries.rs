@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{RigidBodyHandle, SharedShape};
+
+use shared::{
+    ids::{IdMap, PhysicsId},
+    serializable::SerializableQueryFilter,
+    ShapeCastHit,
+};
+
+/// The colliders a filter excludes, as the entities bevy_rapier's queries
+/// make of their physics ids. The session's context doesn't map entities to
+/// handles, so bevy_rapier's own exclusion wouldn't find them.
+fn excluded_entities(
+    filter: &SerializableQueryFilter,
+    id2body: &IdMap<RigidBodyHandle>,
+    context: &RapierContext,
+) -> HashSet<Entity> {
+    let mut excluded: HashSet<Entity> = filter
+        .exclude_collider
+        .map(Entity::from_bits)
+        .into_iter()
+        .collect();
+
+    if let Some(rb) = filter
+        .exclude_rigid_body
+        .and_then(|id| id2body.get(PhysicsId(id)))
+        .and_then(|handle| context.bodies.get(handle))
+    {
+        excluded.extend(
+            rb.colliders()
+                .iter()
+                .filter_map(|&handle| context.colliders.get(handle))
+                .map(|collider| Entity::from_bits(collider.user_data as u64)),
+        );
+    }
+
+    excluded
+}
+
+/// Casts a ray through the session's world as of the last step, returning
+/// the physics id, time of impact and normal of the first hit.
+pub fn cast_ray(
+    origin: Vect,
+    dir: Vect,
+    max_toi: Real,
+    solid: bool,
+    filter: SerializableQueryFilter,
+    id2body: &IdMap<RigidBodyHandle>,
+    context: &RapierContext,
+) -> Option<(u64, Real, Vect)> {
+    let excluded = excluded_entities(&filter, id2body, context);
+    let predicate = |entity: Entity| !excluded.contains(&entity);
+
+    context
+        .cast_ray_and_get_normal(
+            origin,
+            dir,
+            max_toi,
+            solid,
+            query_filter(&filter, &predicate),
+        )
+        .map(|(entity, hit)| (entity.to_bits(), hit.toi, hit.normal))
+}
+
+/// Casts a shape moving at `shape_vel` through the session's world as of the
+/// last step, returning the physics id of the first collider it hits and
+/// where.
+#[allow(clippy::too_many_arguments)]
+pub fn cast_shape(
+    shape_pos: Vect,
+    shape_rot: Quat,
+    shape_vel: Vect,
+    shape: SharedShape,
+    max_toi: Real,
+    filter: SerializableQueryFilter,
+    id2body: &IdMap<RigidBodyHandle>,
+    context: &RapierContext,
+) -> Option<(u64, ShapeCastHit)> {
+    let excluded = excluded_entities(&filter, id2body, context);
+    let predicate = |entity: Entity| !excluded.contains(&entity);
+
+    context
+        .cast_shape(
+            shape_pos,
+            shape_rot,
+            shape_vel,
+            &Collider::from(shape),
+            max_toi,
+            query_filter(&filter, &predicate),
+        )
+        .map(|(entity, toi)| (entity.to_bits(), toi.into()))
+}
+
+/// The physics ids of the colliders intersecting the shape in the session's
+/// world as of the last step.
+pub fn intersections_with_shape(
+    shape_pos: Vect,
+    shape_rot: Quat,
+    shape: SharedShape,
+    filter: SerializableQueryFilter,
+    id2body: &IdMap<RigidBodyHandle>,
+    context: &RapierContext,
+) -> Vec<u64> {
+    let excluded = excluded_entities(&filter, id2body, context);
+    let predicate = |entity: Entity| !excluded.contains(&entity);
+
+    let mut intersections = vec![];
+    context.intersections_with_shape(
+        shape_pos,
+        shape_rot,
+        &Collider::from(shape),
+        query_filter(&filter, &predicate),
+        |entity| {
+            intersections.push(entity.to_bits());
+            true
+        },
+    );
+    intersections
+}
+
+/// The filter to query the session's context with, the excluded entities
+/// being left out by `predicate` instead.
+fn query_filter<'a>(
+    filter: &SerializableQueryFilter,
+    predicate: &'a dyn Fn(Entity) -> bool,
+) -> QueryFilter<'a> {
+    QueryFilter {
+        flags: QueryFilterFlags::from_bits_truncate(filter.flags),
+        groups: filter.groups.map(Into::into),
+        predicate: Some(predicate),
+        ..default()
+    }
+}
@@ -0,0 +1,130 @@
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{
+    GenericJointBuilder, JointAxesMask, JointAxis, Point, RigidBodyHandle, SphericalJointBuilder,
+    UnitVector, Vector,
+};
+
+use shared::ragdoll::*;
+use shared::{ids::IdMap, transform_to_iso, CreatedBody, CreatedCollider, Response};
+
+use crate::joint_breaks::JointBreaks;
+
+/// Builds the bodies, colliders and joints of a ragdoll. Bones without an id
+/// are skipped along with the bones hanging from them.
+pub fn create(
+    ragdoll: CreatedRagdoll,
+    context: &mut RapierContext,
+    id2body: &mut IdMap<RigidBodyHandle>,
+    joint_breaks: &mut JointBreaks,
+) -> Response {
+    println!("Creating ragdoll of {} bones", ragdoll.skeleton.bones.len());
+    let scale = context.physics_scale();
+    let skeleton = &ragdoll.skeleton;
+    let local_transforms = skeleton.bone_transforms();
+    let starts = skeleton.bone_starts();
+
+    let mut handles = vec![];
+    let mut bodies: Vec<Option<RigidBodyHandle>> = vec![];
+    for (i, bone) in skeleton.bones.iter().enumerate() {
+        let parent = match bone.parent {
+            Some(parent) => match bodies.get(parent).copied().flatten() {
+                Some(handle) => Some((parent, handle)),
+                None => {
+                    bodies.push(None);
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let id = match ragdoll.ids.get(i) {
+            Some(&id) => id,
+            None => {
+                bodies.push(None);
+                continue;
+            }
+        };
+
+        let transform = ragdoll.transform * transform_to_iso(&local_transforms[i], scale);
+        let body_handle = crate::create_body(
+            CreatedBody {
+                id,
+                body: RigidBody::Dynamic,
+                transform: Some(transform),
+                additional_mass_properties: None,
+                damping: None,
+                gravity_scale: None,
+                ccd: false,
+                dominance_group: None,
+                locked_axes: None,
+                tag: None,
+            },
+            context,
+            id2body,
+        );
+        let collider_handle = crate::create_collider(
+            CreatedCollider {
+                id,
+                shape: Skeleton::bone_collider(bone),
+                scale: None,
+                parent_id: None,
+                transform: Some(transform),
+                sensor: None,
+                mass_properties: None,
+                friction: None,
+                restitution: None,
+                collision_groups: None,
+                solver_groups: None,
+                active_events: None,
+                active_hooks: None,
+                active_collision_types: None,
+                contact_force_event_threshold: None,
+            },
+            context,
+            id2body,
+        );
+        bodies.push(Some(body_handle));
+        handles.push((id, body_handle, collider_handle));
+
+        if let Some((parent, parent_handle)) = parent {
+            let parent_transform = &local_transforms[parent];
+            let joint_point = parent_transform
+                .compute_matrix()
+                .inverse()
+                .transform_point3(starts[i]);
+            let anchor1 = Point::from(Vector::from(joint_point / scale));
+            let anchor2 = Point::new(0.0, -bone.length / 2.0 / scale, 0.0);
+
+            let joint = match bone.joint {
+                BoneJoint::Spherical { swing, twist } => {
+                    SphericalJointBuilder::new()
+                        .local_anchor1(anchor1)
+                        .local_anchor2(anchor2)
+                        .contacts_enabled(false)
+                        .limits(JointAxis::AngX, [-swing, swing])
+                        .limits(JointAxis::AngY, [-twist, twist])
+                        .limits(JointAxis::AngZ, [-swing, swing])
+                        .build()
+                        .data
+                }
+                BoneJoint::Revolute { axis, limits } => {
+                    let axis1 = parent_transform.rotation.inverse() * axis;
+                    let axis2 = local_transforms[i].rotation.inverse() * axis;
+                    GenericJointBuilder::new(JointAxesMask::LOCKED_REVOLUTE_AXES)
+                        .local_axis1(UnitVector::new_normalize(axis1.into()))
+                        .local_axis2(UnitVector::new_normalize(axis2.into()))
+                        .local_anchor1(anchor1)
+                        .local_anchor2(anchor2)
+                        .contacts_enabled(false)
+                        .limits(JointAxis::AngX, limits)
+                        .build()
+                }
+            };
+            let joint_handle =
+                context
+                    .impulse_joints
+                    .insert(parent_handle, body_handle, joint, true);
+            joint_breaks.insert(joint_handle, bone.break_force);
+        }
+    }
+    Response::RagdollHandles(handles)
+}
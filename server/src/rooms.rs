@@ -0,0 +1,411 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy_rapier3d::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tungstenite::handshake::server::{Request as HandshakeRequest, Response as HandshakeResponse};
+use tungstenite::http::HeaderValue;
+
+use shared::{
+    operator::SessionStatus,
+    protocol::{ROOM_HEADER, ROOM_MEMBER_HEADER},
+    Request, Response, StepEvents,
+};
+
+use crate::{events, idempotency, joint_breaks, worlds, SessionOptions};
+
+/// What every member of a room keeps apart from the others: the answers to
+/// its keyed requests, whose keys every client counts from zero, and what
+/// the room's steps reported since it last took it.
+#[derive(Default)]
+pub struct MemberState {
+    pub recent_results: idempotency::RecentResults,
+    pub events: events::EventCollector,
+    /// Of the joints the member created.
+    pub joint_breaks: joint_breaks::JointBreaks,
+}
+
+struct Member {
+    /// Where the results of the host's steps are pushed.
+    pushes: UnboundedSender<Response>,
+    /// While the session waits for its client to resume it.
+    parked: bool,
+    state: MemberState,
+}
+
+/// What the members of a room share.
+pub struct RoomState {
+    pub world: worlds::World,
+    /// The member that created every body and collider, by physics id.
+    owners: HashMap<u64, u64>,
+    /// By member number.
+    members: BTreeMap<u64, Member>,
+    next_member: u64,
+    /// Where the events of the host's steps are collected, to be handed to
+    /// every member.
+    step_events: events::EventCollector,
+    /// As an operator last set the clock of the room's world.
+    status: SessionStatus,
+}
+
+impl RoomState {
+    /// The member stepping the world for everyone, the one that joined first
+    /// of those connected.
+    fn host(&self) -> Option<u64> {
+        self.members
+            .iter()
+            .find(|(_, member)| !member.parked)
+            .map(|(&member, _)| member)
+    }
+
+    /// Hands the owners of the room and the member's own state to the member
+    /// for one of its requests.
+    pub fn turn(&mut self, member: u64) -> Turn {
+        Turn {
+            member,
+            host: self.host() == Some(member),
+            owners: std::mem::take(&mut self.owners),
+            state: self
+                .members
+                .get_mut(&member)
+                .map(|entry| std::mem::take(&mut entry.state))
+                .unwrap_or_default(),
+            step_events: std::mem::take(&mut self.step_events),
+            steps: vec![],
+            status: self.status,
+        }
+    }
+
+    /// Forgets what the member created, answered with their physics ids.
+    pub fn disown(&mut self, member: u64) -> Vec<u64> {
+        let owned: Vec<u64> = self
+            .owners
+            .iter()
+            .filter(|(_, &owner)| owner == member)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in &owned {
+            self.owners.remove(id);
+        }
+        owned
+    }
+
+    /// Takes the owners and the member's state back, handing what the steps
+    /// the member took reported to the others.
+    pub fn end_turn(&mut self, turn: Turn) {
+        self.owners = turn.owners;
+        self.step_events = turn.step_events;
+        for (&member, entry) in &mut self.members {
+            if member == turn.member || turn.steps.is_empty() {
+                continue;
+            }
+            for (step, events) in &turn.steps {
+                // Those of parked members would be stale when they're resumed
+                if !entry.parked {
+                    let _ = entry.pushes.send(Response::Pushed(Box::new(step.clone())));
+                }
+                entry.state.events.extend(events.clone());
+            }
+            entry.state.joint_breaks.record(&mut self.world.context);
+        }
+        if let Some(entry) = self.members.get_mut(&turn.member) {
+            entry.state = turn.state;
+        }
+    }
+}
+
+pub struct Room {
+    name: String,
+    pub state: Mutex<RoomState>,
+}
+
+/// The rooms of the server by name, that sessions join in the WebSocket
+/// handshake to act on the same world. A room is opened by its first member
+/// and closed when its last one leaves.
+#[derive(Default)]
+pub struct Rooms(Mutex<HashMap<String, Arc<Room>>>);
+
+impl Rooms {
+    pub fn join(self: &Arc<Self>, name: &str, options: &SessionOptions) -> Membership {
+        // Held until the member is in, for the room not to be closed first
+        let mut rooms = self.0.lock().unwrap();
+        let room = rooms
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                println!("Opening room {}", name);
+                let mut rng = StdRng::seed_from_u64(options.seed);
+                Arc::new(Room {
+                    name: name.to_string(),
+                    state: Mutex::new(RoomState {
+                        world: worlds::World::new(options, &mut rng),
+                        owners: HashMap::new(),
+                        members: BTreeMap::new(),
+                        next_member: 0,
+                        step_events: events::EventCollector::enabled(),
+                        status: SessionStatus::default(),
+                    }),
+                })
+            })
+            .clone();
+        let (sender, pushes) = unbounded_channel();
+        let member = {
+            let mut state = room.state.lock().unwrap();
+            let member = state.next_member;
+            state.next_member += 1;
+            state.members.insert(
+                member,
+                Member {
+                    pushes: sender,
+                    parked: false,
+                    state: MemberState::default(),
+                },
+            );
+            member
+        };
+        drop(rooms);
+        println!("Member {} joined room {}", member, name);
+        Membership {
+            rooms: self.clone(),
+            room,
+            member,
+            pushes,
+        }
+    }
+
+    /// Runs `update` on the clock of the room of the name, answered with what
+    /// it leaves it at, `None` if no room has the name.
+    pub fn update_status(
+        &self,
+        name: &str,
+        update: impl FnOnce(&mut SessionStatus),
+    ) -> Option<SessionStatus> {
+        let room = self.0.lock().unwrap().get(name)?.clone();
+        let mut state = room.state.lock().unwrap();
+        update(&mut state.status);
+        Some(state.status)
+    }
+
+    /// The open rooms by name, with their number of members and clock.
+    pub fn list(&self) -> Vec<(String, usize, SessionStatus)> {
+        let rooms = self.0.lock().unwrap();
+        let mut listed: Vec<_> = rooms
+            .values()
+            .map(|room| {
+                let state = room.state.lock().unwrap();
+                (room.name.clone(), state.members.len(), state.status)
+            })
+            .collect();
+        listed.sort_by(|(name1, ..), (name2, ..)| name1.cmp(name2));
+        listed
+    }
+}
+
+/// A session's place in a room, which it leaves when dropped with the
+/// session.
+pub struct Membership {
+    rooms: Arc<Rooms>,
+    pub room: Arc<Room>,
+    pub member: u64,
+    /// The results of the host's steps, pushed to the member.
+    pub pushes: UnboundedReceiver<Response>,
+}
+
+impl Membership {
+    /// Marks the member's session as waiting for its client, which another
+    /// member hosts the room for meanwhile, or as resumed.
+    pub fn set_parked(&self, parked: bool) {
+        let mut state = self.room.state.lock().unwrap();
+        if let Some(member) = state.members.get_mut(&self.member) {
+            member.parked = parked;
+        }
+    }
+
+    /// The seconds to step the room's world by if the member hosts the room
+    /// and its tick is due, the host stepping it for everyone.
+    pub fn take_due_tick(&self) -> Option<f32> {
+        let mut state = self.room.state.lock().unwrap();
+        if state.host() != Some(self.member) {
+            return None;
+        }
+        let stream = state.world.stream.as_mut()?;
+        stream.take_due().then_some(stream.period.as_secs_f32())
+    }
+
+    /// How long until the room's next tick is due if the member hosts it,
+    /// and a tick's period otherwise, for members to notice when they take
+    /// over from a host that left or lost its connection.
+    pub fn until_next_tick(&self) -> Option<Duration> {
+        let state = self.room.state.lock().unwrap();
+        let stream = state.world.stream.as_ref()?;
+        if state.host() == Some(self.member) {
+            Some(stream.until_next())
+        } else {
+            Some(stream.period)
+        }
+    }
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        let mut rooms = self.rooms.0.lock().unwrap();
+        let mut state = self.room.state.lock().unwrap();
+        state.members.remove(&self.member);
+        println!("Member {} left room {}", self.member, self.room.name);
+        if state.members.is_empty() {
+            println!("Closing room {}", self.room.name);
+            rooms.remove(&self.room.name);
+        }
+    }
+}
+
+/// A member's hold on the room while one of its requests is handled.
+pub struct Turn {
+    pub member: u64,
+    /// Whether the member steps the world for everyone, the steps of the
+    /// others not running.
+    pub host: bool,
+    owners: HashMap<u64, u64>,
+    /// The member's own, swapped into the session while the turn lasts.
+    pub state: MemberState,
+    /// Swapped in for the host's steps.
+    pub step_events: events::EventCollector,
+    /// The results and events of the steps the member took, for the others.
+    pub steps: Vec<(Response, StepEvents)>,
+    /// The room's clock as an operator set it.
+    pub status: SessionStatus,
+}
+
+impl Turn {
+    /// Why the member can't make the request in the room: requests acting on
+    /// the whole world can't be made by anyone, and those acting on bodies
+    /// and colliders only by the member that created them.
+    pub fn check(&self, request: &Request, context: &RapierContext) -> Result<(), String> {
+        let own = |id: u64| match self.owners.get(&id) {
+            Some(&owner) if owner != self.member => {
+                Err(format!("{} belongs to member {}", id, owner))
+            }
+            _ => Ok(()),
+        };
+        let own_body = |handle| match context.bodies.get(handle) {
+            Some(body) => own(body.user_data as u64),
+            None => Ok(()),
+        };
+        let own_collider = |handle| match context.colliders.get(handle) {
+            Some(collider) => own(collider.user_data as u64),
+            None => Ok(()),
+        };
+        match request {
+            Request::ResetWorld
+            | Request::RestoreSnapshot(_)
+            | Request::CompactWorld
+            | Request::SetStreaming(_)
+            | Request::CreateWorld(_)
+            | Request::DestroyWorld(_)
+            | Request::InWorld { .. } => {
+                return Err(format!("{} would act on the whole room", request.name()));
+            }
+            Request::UpdateConfig(..)
+            | Request::PatchConfig(_)
+            | Request::UseProfile(_)
+            | Request::SetSimulationState(_)
+                if !self.host =>
+            {
+                return Err(format!("only the room's host can make {}", request.name()));
+            }
+            Request::CreateBodies(bodies) => bodies.iter().try_for_each(|body| own(body.id))?,
+            Request::CreateColliders(colliders) => {
+                for collider in colliders {
+                    own(collider.id)?;
+                    if let Some(parent) = collider.parent_id {
+                        own(parent)?;
+                    }
+                }
+            }
+            Request::CreateJoints(joints) => {
+                for joint in joints {
+                    own_body(joint.body1)?;
+                    own_body(joint.body2)?;
+                }
+            }
+            Request::SpawnInstances(instances) => {
+                instances.iter().try_for_each(|instance| own(instance.id))?
+            }
+            Request::CreateRagdoll(ragdoll) => ragdoll.ids.iter().try_for_each(|&id| own(id))?,
+            Request::CreateRope(rope) => own(rope.id)?,
+            Request::ApplyCommands(commands) => commands
+                .iter()
+                .try_for_each(|(handle, _)| own_body(*handle))?,
+            Request::ApplyForces(forces) => forces
+                .iter()
+                .try_for_each(|(handle, ..)| own_body(*handle))?,
+            Request::UpdateBodies(updates) => updates
+                .iter()
+                .try_for_each(|(handle, _)| own_body(*handle))?,
+            Request::UpdateColliders(updates) => updates
+                .iter()
+                .try_for_each(|(handle, _)| own_collider(*handle))?,
+            Request::SetBodyTransforms(transforms) => {
+                transforms.iter().try_for_each(|(id, _)| own(*id))?
+            }
+            Request::SetControllers(controllers) => {
+                controllers.iter().try_for_each(|(id, _)| own(*id))?
+            }
+            Request::RemoveBodies(ids) | Request::RemoveColliders(ids) => {
+                ids.iter().try_for_each(|&id| own(id))?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Who created every body and collider of the room, by physics id.
+    pub fn owners(&self) -> &HashMap<u64, u64> {
+        &self.owners
+    }
+
+    /// Puts back the owners saved before a transaction that was rolled back.
+    pub fn restore_owners(&mut self, owners: HashMap<u64, u64>) {
+        self.owners = owners;
+    }
+
+    /// Makes the member the owner of what the request creates, forgetting
+    /// the owners of the bodies it removes.
+    pub fn apply(&mut self, request: &Request) {
+        let created: Vec<u64> = match request {
+            Request::CreateBodies(bodies) => bodies.iter().map(|body| body.id).collect(),
+            Request::CreateColliders(colliders) => {
+                colliders.iter().map(|collider| collider.id).collect()
+            }
+            Request::SpawnInstances(instances) => {
+                instances.iter().map(|instance| instance.id).collect()
+            }
+            Request::CreateRagdoll(ragdoll) => ragdoll.ids.clone(),
+            Request::CreateRope(rope) => vec![rope.id],
+            Request::RemoveBodies(ids) => {
+                for id in ids {
+                    self.owners.remove(id);
+                }
+                vec![]
+            }
+            _ => vec![],
+        };
+        for id in created {
+            self.owners.insert(id, self.member);
+        }
+    }
+}
+
+/// The room the client asks to join in the WebSocket handshake.
+pub fn requested(request: &HandshakeRequest) -> Option<String> {
+    let name = request.headers().get(ROOM_HEADER)?.to_str().ok()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Tells the client its member number in the room it joined.
+pub fn answer(response: &mut HandshakeResponse, member: u64) {
+    response
+        .headers_mut()
+        .insert(ROOM_MEMBER_HEADER, HeaderValue::from(member));
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{
+    ColliderBuilder, JointAxis, Point, RigidBodyBuilder, RigidBodyHandle, SphericalJointBuilder,
+    Vector,
+};
+
+use shared::rope::*;
+use shared::{
+    ids::{IdMap, PhysicsId},
+    transform_to_iso, Response,
+};
+
+use crate::joint_breaks::JointBreaks;
+use crate::snapshot::SnapshotFilter;
+
+struct Rope {
+    segments: Vec<RigidBodyHandle>,
+    /// Half the length of a segment, in physics space.
+    half_length: Real,
+}
+
+/// The ropes of a session, sent as compact snapshots of their joints instead
+/// of one entry per segment.
+#[derive(Default)]
+pub struct Ropes {
+    ropes: HashMap<u64, Rope>,
+}
+
+impl Ropes {
+    pub fn create(
+        &mut self,
+        created: CreatedRope,
+        context: &mut RapierContext,
+        id2body: &IdMap<RigidBodyHandle>,
+        snapshot_filter: &mut SnapshotFilter,
+        joint_breaks: &mut JointBreaks,
+    ) -> Response {
+        println!("Creating rope of {} segments", created.segments);
+        let scale = context.physics_scale();
+        let segments = created.segments.max(1);
+        let span = created.end - created.start;
+        let direction = span.normalize_or_zero();
+        let length = span.length() / segments as Real;
+        let rotation = Quat::from_rotation_arc(Vect::Y, direction);
+        let half_length = length / 2.0 / scale;
+        let shape = Collider::capsule_y((length / 2.0 - created.radius).max(0.0), created.radius);
+
+        let mut handles: Vec<RigidBodyHandle> = vec![];
+        for i in 0..segments {
+            let center = created.start + direction * length * (i as Real + 0.5);
+            let transform = Transform::from_translation(center).with_rotation(rotation);
+            let handle = context.bodies.insert(
+                RigidBodyBuilder::dynamic()
+                    .position(transform_to_iso(&transform, scale))
+                    .user_data(created.id.into()),
+            );
+            context.colliders.insert_with_parent(
+                ColliderBuilder::new(shape.raw.clone()).user_data(created.id.into()),
+                handle,
+                &mut context.bodies,
+            );
+            snapshot_filter.exclude(handle);
+
+            if let Some(&previous) = handles.last() {
+                let joint = rope_joint(
+                    Point::new(0.0, half_length, 0.0),
+                    Point::new(0.0, -half_length, 0.0),
+                    created.stiffness,
+                );
+                let joint_handle = context.impulse_joints.insert(previous, handle, joint, true);
+                joint_breaks.insert(joint_handle, created.break_force);
+            }
+            handles.push(handle);
+        }
+
+        let ends = [
+            (created.start_anchor, handles[0], -half_length),
+            (created.end_anchor, handles[handles.len() - 1], half_length),
+        ];
+        for (anchor, segment, end) in ends {
+            let (body, anchor1) = match anchor {
+                RopeAnchor::Free => continue,
+                RopeAnchor::Fixed(point) => {
+                    let body = context.bodies.insert(
+                        RigidBodyBuilder::fixed()
+                            .translation(Vector::from(point / scale))
+                            .user_data(created.id.into()),
+                    );
+                    (body, Point::origin())
+                }
+                RopeAnchor::Body { id, offset } => match id2body.get(PhysicsId(id)) {
+                    Some(body) => (body, Point::from(Vector::from(offset / scale))),
+                    None => {
+                        println!("Rope anchored to unknown body {}", id);
+                        continue;
+                    }
+                },
+            };
+            let joint = rope_joint(anchor1, Point::new(0.0, end, 0.0), 0.0);
+            let joint_handle = context.impulse_joints.insert(body, segment, joint, true);
+            joint_breaks.insert(joint_handle, created.break_force);
+        }
+
+        self.ropes.insert(
+            created.id,
+            Rope {
+                segments: handles,
+                half_length,
+            },
+        );
+        Response::RopeCreated
+    }
+
+    pub fn remap(&mut self, bodies: &HashMap<RigidBodyHandle, RigidBodyHandle>) {
+        for rope in self.ropes.values_mut() {
+            rope.segments = rope
+                .segments
+                .iter()
+                .filter_map(|segment| bodies.get(segment).copied())
+                .collect();
+        }
+    }
+
+    pub fn snapshots(&self, context: &RapierContext) -> Vec<RopeSnapshot> {
+        let scale = context.physics_scale();
+        self.ropes
+            .iter()
+            .map(|(&id, rope)| {
+                let mut points = vec![];
+                for (i, &handle) in rope.segments.iter().enumerate() {
+                    let rb = match context.bodies.get(handle) {
+                        Some(rb) => rb,
+                        None => continue,
+                    };
+                    if i == 0 {
+                        let start = rb.position() * Point::new(0.0, -rope.half_length, 0.0);
+                        points.push(Vect::from(start.coords) * scale);
+                    }
+                    let end = rb.position() * Point::new(0.0, rope.half_length, 0.0);
+                    points.push(Vect::from(end.coords) * scale);
+                }
+                RopeSnapshot::encode(id, &points)
+            })
+            .collect()
+    }
+}
+
+/// Joins two segments, or a segment and what it is tied to, end to end.
+fn rope_joint(
+    anchor1: Point<Real>,
+    anchor2: Point<Real>,
+    stiffness: Real,
+) -> SphericalJointBuilder {
+    let mut joint = SphericalJointBuilder::new()
+        .local_anchor1(anchor1)
+        .local_anchor2(anchor2)
+        .contacts_enabled(false);
+    if stiffness > 0.0 {
+        for axis in [JointAxis::AngX, JointAxis::AngY, JointAxis::AngZ] {
+            joint = joint.motor_position(axis, 0.0, stiffness, stiffness / 10.0);
+        }
+    }
+    joint
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{ColliderBuilder, ColliderHandle};
+
+use shared::scene::*;
+use shared::{transform_to_iso, Response};
+
+/// The ground added to new worlds on request, thin enough to stand in for a
+/// plane at y = 0.
+const GROUND_HALF_EXTENTS: Vect = Vect::new(500.0, 0.1, 500.0);
+
+/// A scene created in a pooled world before a session asked for it.
+pub struct PreloadedScene {
+    name: String,
+    hash: u64,
+    colliders: Vec<(ColliderHandle, SceneCollider)>,
+}
+
+/// Creates the colliders of `<scenes_dir>/<name>.ron` as fixed colliders,
+/// unless the client's copy of the scene has a different `hash`. A matching
+/// preloaded scene is handed out as is.
+pub fn load(
+    name: &str,
+    hash: Option<u64>,
+    scenes_dir: &Path,
+    context: &mut RapierContext,
+    preloaded: &mut Option<PreloadedScene>,
+) -> Response {
+    if preloaded.as_ref().is_some_and(|scene| scene.name == name) {
+        let scene = preloaded.take().unwrap();
+        if let Err(err) = check(name, hash, scene.hash) {
+            unload(scene, context);
+            return Response::SceneLoaded(Err(err));
+        }
+        println!("Scene {} was preloaded", name);
+        return Response::SceneLoaded(Ok(scene.colliders));
+    }
+
+    println!("Loading scene {}", name);
+    Response::SceneLoaded(
+        read(name, scenes_dir)
+            .and_then(|(scene, actual)| check(name, hash, actual).map(|()| scene))
+            .map(|scene| create(scene, context)),
+    )
+}
+
+/// Creates a scene ahead of the session that will ask for it.
+pub fn preload(
+    name: &str,
+    scenes_dir: &Path,
+    context: &mut RapierContext,
+) -> Result<PreloadedScene, SceneError> {
+    let (scene, hash) = read(name, scenes_dir)?;
+    Ok(PreloadedScene {
+        name: name.to_string(),
+        hash,
+        colliders: create(scene, context),
+    })
+}
+
+/// Creates a scene that is part of every world, which clients don't need to
+/// ask for.
+pub fn load_default(
+    name: &str,
+    scenes_dir: &Path,
+    context: &mut RapierContext,
+) -> Result<(), SceneError> {
+    let (scene, _) = read(name, scenes_dir)?;
+    create(scene, context);
+    Ok(())
+}
+
+/// Creates a large fixed ground with its top at y = 0, kept along with the
+/// scene when the world is reset.
+pub fn create_ground(context: &mut RapierContext) {
+    let half_extents = GROUND_HALF_EXTENTS / context.physics_scale();
+    context.colliders.insert(
+        ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+            .translation([0.0, -half_extents.y, 0.0].into())
+            .user_data(SCENE_ENTITY.into()),
+    );
+}
+
+impl PreloadedScene {
+    pub fn remap(&mut self, colliders: &HashMap<ColliderHandle, ColliderHandle>) {
+        self.colliders
+            .retain_mut(|(handle, _)| match colliders.get(handle) {
+                Some(&new) => {
+                    *handle = new;
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+/// Removes a preloaded scene the session didn't ask for.
+pub fn unload(scene: PreloadedScene, context: &mut RapierContext) {
+    for (handle, _) in scene.colliders {
+        context
+            .colliders
+            .remove(handle, &mut context.islands, &mut context.bodies, false);
+    }
+}
+
+fn check(name: &str, hash: Option<u64>, actual: u64) -> Result<(), SceneError> {
+    match hash.filter(|&expected| expected != actual) {
+        Some(expected) => {
+            println!("Scene {} differs from the client's", name);
+            Err(SceneError::HashMismatch { expected, actual })
+        }
+        None => Ok(()),
+    }
+}
+
+/// Parses a scene along with its content hash.
+fn read(name: &str, scenes_dir: &Path) -> Result<(Scene, u64), SceneError> {
+    // Scene names come from clients, keep them from reaching outside the directory
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(SceneError::NotFound(name.to_string()));
+    }
+
+    let path = scenes_dir.join(name).with_extension("ron");
+    let contents =
+        std::fs::read_to_string(path).map_err(|_| SceneError::NotFound(name.to_string()))?;
+
+    let scene = ron::from_str(&contents).map_err(|e| SceneError::Invalid(e.to_string()))?;
+    Ok((scene, content_hash(contents.as_bytes())))
+}
+
+fn create(scene: Scene, context: &mut RapierContext) -> Vec<(ColliderHandle, SceneCollider)> {
+    let scale = context.physics_scale();
+    scene
+        .colliders
+        .into_iter()
+        .map(|collider| {
+            let mut builder = ColliderBuilder::new(collider.shape.collider().raw)
+                .position(transform_to_iso(&collider.transform(), scale))
+                .user_data(SCENE_ENTITY.into());
+            if let Some(friction) = collider.friction {
+                builder = builder.friction(friction);
+            }
+            if let Some(restitution) = collider.restitution {
+                builder = builder.restitution(restitution);
+            }
+            (context.colliders.insert(builder), collider)
+        })
+        .collect()
+}
@@ -0,0 +1,196 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{RigidBody, RigidBodyHandle, RigidBodySet};
+
+use shared::UpdateRate;
+
+use crate::compaction::remap_keys;
+
+/// Bodies left out of this many snapshots in a row are part of the next one
+/// even if it goes over the budget, which bounds how stale a body can get
+/// however tight the budget is.
+const MAX_STALE_STEPS: u32 = 30;
+
+pub type SnapshotEntry = (RigidBodyHandle, (Transform, Velocity));
+
+/// Decides which bodies are part of a step's snapshot according to their
+/// update rate class and, if there is one, the snapshot byte budget.
+#[derive(Default)]
+pub struct SnapshotFilter {
+    rates: HashMap<RigidBodyHandle, UpdateRate>,
+    /// The sleep state last sent for `OnSleepChange` bodies.
+    sleeping: HashMap<RigidBodyHandle, bool>,
+    step: u64,
+    budget: Option<usize>,
+    priorities: HashMap<RigidBodyHandle, f32>,
+    focus: Option<Vect>,
+    /// Grows every step a body is left out, by how important it is.
+    accumulated: HashMap<RigidBodyHandle, f32>,
+    stale_steps: HashMap<RigidBodyHandle, u32>,
+    last_sent: HashMap<RigidBodyHandle, Vect>,
+    /// Bodies sent some other way, like rope segments.
+    excluded: HashSet<RigidBodyHandle>,
+    /// Bodies the client was told fell asleep, left out until they wake up.
+    reported_asleep: HashSet<RigidBodyHandle>,
+    /// How many bodies the budget left out of the last snapshot.
+    left_out: usize,
+}
+
+impl SnapshotFilter {
+    pub fn new(budget: Option<usize>) -> Self {
+        Self {
+            budget,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_priority(&mut self, handle: RigidBodyHandle, priority: f32) {
+        self.priorities.insert(handle, priority);
+    }
+
+    /// Bodies close to the focus, usually the player's camera, are preferred.
+    pub fn set_focus(&mut self, focus: Vect) {
+        self.focus = Some(focus);
+    }
+
+    pub fn set_rate(&mut self, handle: RigidBodyHandle, rate: UpdateRate) {
+        self.rates.insert(handle, rate);
+    }
+
+    /// Forgets every body, keeping the budget and focus.
+    pub fn clear(&mut self) {
+        *self = Self {
+            budget: self.budget,
+            focus: self.focus,
+            ..Default::default()
+        };
+    }
+
+    /// Moves everything known about bodies to their new handles.
+    pub fn remap(&mut self, bodies: &HashMap<RigidBodyHandle, RigidBodyHandle>) {
+        remap_keys(&mut self.rates, bodies);
+        remap_keys(&mut self.sleeping, bodies);
+        remap_keys(&mut self.priorities, bodies);
+        remap_keys(&mut self.accumulated, bodies);
+        remap_keys(&mut self.stale_steps, bodies);
+        remap_keys(&mut self.last_sent, bodies);
+        self.excluded = self
+            .excluded
+            .iter()
+            .filter_map(|handle| bodies.get(handle).copied())
+            .collect();
+        self.reported_asleep = self
+            .reported_asleep
+            .iter()
+            .filter_map(|handle| bodies.get(handle).copied())
+            .collect();
+    }
+
+    pub fn exclude(&mut self, handle: RigidBodyHandle) {
+        self.excluded.insert(handle);
+    }
+
+    pub fn next_step(&mut self) {
+        self.step += 1;
+    }
+
+    pub fn left_out(&self) -> usize {
+        self.left_out
+    }
+
+    pub fn includes(&mut self, handle: RigidBodyHandle, rb: &RigidBody) -> bool {
+        if self.excluded.contains(&handle) {
+            return false;
+        }
+        // Sleeping bodies don't move, and anything moving them wakes them up
+        if !rb.is_sleeping() {
+            self.reported_asleep.remove(&handle);
+        } else if self.reported_asleep.contains(&handle) {
+            return false;
+        }
+        match self.rates.get(&handle).copied().unwrap_or_default() {
+            UpdateRate::EveryStep => true,
+            UpdateRate::Every2 => self.step.is_multiple_of(2),
+            UpdateRate::Every4 => self.step.is_multiple_of(4),
+            UpdateRate::OnSleepChange => {
+                let sleeping = rb.is_sleeping();
+                self.sleeping.insert(handle, sleeping) != Some(sleeping)
+            }
+        }
+    }
+
+    /// Keeps the most important entries that fit into the byte budget. Entries
+    /// are ranked by their priority, how far they moved since they were last
+    /// sent and their distance to the focus, accumulated over the snapshots
+    /// they were left out of so that every body gets its turn. Entries left
+    /// out of `MAX_STALE_STEPS` snapshots are kept whatever the budget.
+    pub fn fit_budget(&mut self, mut entries: Vec<SnapshotEntry>) -> Vec<SnapshotEntry> {
+        self.left_out = 0;
+        // Every entry has the same size
+        let (budget, entry_size) = match (self.budget, entries.first()) {
+            (Some(budget), Some(entry)) => (budget, bincode::serialized_size(entry).unwrap_or(1)),
+            _ => return entries,
+        };
+        let capacity = (budget / entry_size.max(1) as usize).max(1);
+
+        for (handle, (transform, _)) in &entries {
+            let priority = self.priorities.get(handle).copied().unwrap_or(1.0);
+            let change = self
+                .last_sent
+                .get(handle)
+                .map_or(1.0, |last| last.distance(transform.translation));
+            let proximity = self.focus.map_or(1.0, |focus| {
+                1.0 / (1.0 + focus.distance(transform.translation))
+            });
+            *self.accumulated.entry(*handle).or_default() += priority * (1.0 + change) * proximity;
+            *self.stale_steps.entry(*handle).or_default() += 1;
+        }
+
+        if entries.len() > capacity {
+            let stale = entries
+                .iter()
+                .filter(|(handle, _)| self.stale_steps[handle] >= MAX_STALE_STEPS)
+                .count();
+            // The staleness bound wins over the budget
+            let capacity = capacity.max(stale);
+            entries.sort_by(|(a, _), (b, _)| {
+                let a_stale = self.stale_steps[a] >= MAX_STALE_STEPS;
+                let b_stale = self.stale_steps[b] >= MAX_STALE_STEPS;
+                b_stale.cmp(&a_stale).then_with(|| {
+                    self.accumulated[b]
+                        .partial_cmp(&self.accumulated[a])
+                        .unwrap_or(Ordering::Equal)
+                })
+            });
+            self.left_out = entries.len() - capacity;
+            entries.truncate(capacity);
+        }
+
+        for (handle, (transform, _)) in &entries {
+            self.accumulated.insert(*handle, 0.0);
+            self.stale_steps.insert(*handle, 0);
+            self.last_sent.insert(*handle, transform.translation);
+        }
+
+        entries
+    }
+
+    /// The bodies of the snapshot that fell asleep, which later snapshots
+    /// leave out until they wake up.
+    pub fn fell_asleep(
+        &mut self,
+        entries: &[SnapshotEntry],
+        bodies: &RigidBodySet,
+    ) -> Vec<RigidBodyHandle> {
+        let fell_asleep: Vec<RigidBodyHandle> = entries
+            .iter()
+            .map(|(handle, _)| *handle)
+            .filter(|&handle| bodies.get(handle).is_some_and(RigidBody::is_sleeping))
+            .collect();
+        self.reported_asleep.extend(fell_asleep.iter().copied());
+        fell_asleep
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::{rngs::OsRng, Rng};
+use tungstenite::handshake::server::{Request, Response};
+use tungstenite::http::HeaderValue;
+
+use shared::protocol::{SESSION_ID_HEADER, SESSION_RESUMED_HEADER};
+
+struct Parked<T> {
+    session: T,
+    until: Instant,
+}
+
+/// Keeps the sessions whose connection was lost for a grace period, so that
+/// their client can resume them on a new connection by presenting their id
+/// in the WebSocket handshake rather than losing its world.
+pub struct SessionStore<T> {
+    grace: Duration,
+    parked: Mutex<HashMap<u64, Parked<T>>>,
+}
+
+impl<T> SessionStore<T> {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            grace,
+            parked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// A random id for a new session, so that clients can't resume the
+    /// sessions of others by counting. The id is all a client needs to resume
+    /// a session, making it a credential, so it's drawn from the operating
+    /// system's cryptographically secure generator rather than a seeded one.
+    pub fn new_id(&self) -> u64 {
+        let parked = self.parked.lock().unwrap();
+        loop {
+            let id = OsRng.gen();
+            if !parked.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Keeps the session until the grace period is over, handing it back if
+    /// there is none.
+    pub fn park(&self, id: u64, session: T) -> Option<T> {
+        if self.grace.is_zero() {
+            return Some(session);
+        }
+        let until = Instant::now() + self.grace;
+        self.parked
+            .lock()
+            .unwrap()
+            .insert(id, Parked { session, until });
+        None
+    }
+
+    /// Takes the session back, `None` if there is none of the id or its grace
+    /// period is over.
+    pub fn resume(&self, id: u64) -> Option<T> {
+        let parked = self.parked.lock().unwrap().remove(&id)?;
+        (parked.until > Instant::now()).then_some(parked.session)
+    }
+
+    /// Takes the sessions whose grace period is over.
+    pub fn expire(&self) -> Vec<(u64, T)> {
+        let now = Instant::now();
+        let mut parked = self.parked.lock().unwrap();
+        let expired: Vec<u64> = parked
+            .iter()
+            .filter(|(_, parked)| parked.until <= now)
+            .map(|(&id, _)| id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| parked.remove(&id).map(|parked| (id, parked.session)))
+            .collect()
+    }
+}
+
+/// The session the client asks to resume in the WebSocket handshake.
+pub fn requested(request: &Request) -> Option<u64> {
+    request
+        .headers()
+        .get(SESSION_ID_HEADER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Tells the client the id of the session it's served by, and whether it's
+/// the one it asked to resume.
+pub fn answer(response: &mut Response, id: u64, resumed: bool) {
+    let headers = response.headers_mut();
+    headers.insert(SESSION_ID_HEADER, HeaderValue::from(id));
+    headers.insert(
+        SESSION_RESUMED_HEADER,
+        HeaderValue::from_static(if resumed { "true" } else { "false" }),
+    );
+}
@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+/// The fixed tick of a session whose results are pushed to the client rather
+/// than asked for.
+#[derive(Debug, Clone, Copy)]
+pub struct Stream {
+    pub period: Duration,
+    next: Instant,
+}
+
+impl Stream {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            next: Instant::now() + period,
+        }
+    }
+
+    /// How long until the next tick is due, zero if it already is.
+    pub fn until_next(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether a tick is due, scheduling the one after it if so. A session
+    /// that fell more than a tick behind skips the missed ones rather than
+    /// running them back to back.
+    pub fn take_due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        self.next += self.period;
+        if self.next <= now {
+            self.next = now + self.period;
+        }
+        true
+    }
+}
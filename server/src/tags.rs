@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+/// The tags clients gave the bodies they created, by physics id.
+#[derive(Default, Clone)]
+pub struct Tags(HashMap<u64, String>);
+
+impl Tags {
+    pub fn insert(&mut self, id: u64, tag: Option<String>) {
+        match tag {
+            Some(tag) => self.0.insert(id, tag),
+            None => self.0.remove(&id),
+        };
+    }
+
+    pub fn get(&self, id: u64) -> Option<&str> {
+        self.0.get(&id).map(String::as_str)
+    }
+
+    pub fn entries(&self) -> Vec<(u64, String)> {
+        self.0.iter().map(|(&id, tag)| (id, tag.clone())).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// The physics id, followed by its tag if it has one.
+    pub fn describe(&self, id: u64) -> String {
+        match self.get(id) {
+            Some(tag) => format!("{} ({})", id, tag),
+            None => id.to_string(),
+        }
+    }
+
+    /// How many of the bodies have every tag, most common first.
+    pub fn counts(&self, ids: impl IntoIterator<Item = u64>) -> String {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for id in ids {
+            *counts
+                .entry(self.get(id).unwrap_or("untagged"))
+                .or_default() += 1;
+        }
+        let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let counts: Vec<String> = counts
+            .into_iter()
+            .map(|(tag, count)| format!("{}: {}", tag, count))
+            .collect();
+        counts.join(", ")
+    }
+}
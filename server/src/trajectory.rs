@@ -0,0 +1,70 @@
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::Isometry;
+
+use shared::{ids::IdMap, BodyTemplate, TemplateInstance};
+
+/// The most steps a single prediction runs, so that a client can't hold up
+/// its session for long.
+const MAX_STEPS: u32 = 600;
+
+/// Steps a body made from `template` in a world of its own holding copies of
+/// the session's fixed colliders, and returns its position after every step.
+/// The session's world is left as it was, and other moving bodies are
+/// ignored.
+pub fn predict(
+    context: &RapierContext,
+    gravity: Vect,
+    template: &BodyTemplate,
+    origin: Vect,
+    velocity: Vect,
+    steps: u32,
+) -> Vec<Vect> {
+    let mut world = RapierContext::default();
+    world.integration_parameters = context.integration_parameters;
+
+    for (_, collider) in context.colliders.iter() {
+        let fixed = collider.parent().is_none_or(|parent| {
+            context
+                .bodies
+                .get(parent)
+                .is_some_and(|body| body.is_fixed())
+        });
+        if fixed {
+            // Keeps the world position of colliders attached to fixed bodies
+            world.colliders.insert(collider.clone());
+        }
+    }
+
+    let scale = world.physics_scale();
+    let origin = origin / scale;
+    let (body, collider) = template.instantiate(&TemplateInstance {
+        id: 0,
+        template_id: 0,
+        transform: Isometry::translation(origin.x, origin.y, origin.z),
+        velocity: None,
+        tag: None,
+    });
+    let mut id2body = IdMap::default();
+    let handle = crate::create_body(body, &mut world, &mut id2body);
+    crate::create_collider(collider, &mut world, &id2body);
+    world.bodies[handle].set_linvel((velocity / scale).into(), true);
+
+    let dt = world.integration_parameters.dt;
+    let timestep_mode = TimestepMode::Fixed { dt, substeps: 1 };
+    let mut sim_to_render_time = SimulationToRenderTime::default();
+
+    (0..steps.min(MAX_STEPS))
+        .map(|_| {
+            crate::step_context(
+                &mut world,
+                gravity,
+                timestep_mode,
+                None,
+                &(),
+                dt,
+                &mut sim_to_render_time,
+            );
+            Vect::from(*world.bodies[handle].translation()) * scale
+        })
+        .collect()
+}
@@ -0,0 +1,147 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::RigidBodyHandle;
+
+use shared::{
+    ids::{IdMap, PhysicsId},
+    BodyTemplate, CreatedJoint, Request,
+};
+
+use crate::tags::Tags;
+
+/// What the requests of a transaction can change, saved before the first so
+/// that the world can be put back as it was if one fails.
+pub struct Savepoint {
+    /// The `RapierContext`, encoded with bincode as snapshots are.
+    pub context: Vec<u8>,
+    pub id2body: IdMap<RigidBodyHandle>,
+    pub tags: Tags,
+    pub templates: HashMap<u64, BodyTemplate>,
+    /// Who created every body and collider of the room the session is in.
+    pub owners: Option<HashMap<u64, u64>>,
+}
+
+/// Why the request would fail in the world as it is, checked right before it
+/// is handled, as handlers skip what they can't do and go on with the rest.
+pub fn check(
+    request: &Request,
+    context: &RapierContext,
+    id2body: &IdMap<RigidBodyHandle>,
+    templates: &HashMap<u64, BodyTemplate>,
+    max_bodies: Option<usize>,
+) -> Result<(), String> {
+    let known = |id| id2body.contains(PhysicsId(id));
+    let fit = |count: usize| match max_bodies {
+        Some(max_bodies) if context.bodies.len() + count > max_bodies => Err(format!(
+            "{} bodies would take the world over its limit of {}",
+            count, max_bodies
+        )),
+        _ => Ok(()),
+    };
+    match request {
+        Request::CreateBodies(bodies) => {
+            fit(bodies.len())?;
+            if let Some(body) = bodies.iter().find(|body| known(body.id)) {
+                return Err(format!("body {} already exists", body.id));
+            }
+        }
+        Request::CreateColliders(colliders) => {
+            for collider in colliders {
+                match collider.parent_id {
+                    Some(parent) if !known(parent) => {
+                        return Err(format!(
+                            "collider {} is attached to unknown body {}",
+                            collider.id, parent
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Request::CreateJoints(joints) => {
+            if let Some(joint) = joints.iter().find(|joint| {
+                !context.bodies.contains(joint.body1) || !context.bodies.contains(joint.body2)
+            }) {
+                return Err(format!("joint {} is between unknown bodies", joint.id));
+            }
+            if let Some(id) = refused_multibody_joint(joints, context) {
+                return Err(format!(
+                    "multibody joint {} would close a loop or give a body a second parent",
+                    id
+                ));
+            }
+        }
+        Request::SpawnInstances(instances) => {
+            fit(instances.len())?;
+            for instance in instances {
+                if known(instance.id) {
+                    return Err(format!("body {} already exists", instance.id));
+                }
+                if !templates.contains_key(&instance.template_id) {
+                    return Err(format!(
+                        "instance {} of unknown template {}",
+                        instance.id, instance.template_id
+                    ));
+                }
+            }
+        }
+        Request::UpdateBodies(updates) => {
+            if let Some((handle, _)) = updates
+                .iter()
+                .find(|(handle, _)| !context.bodies.contains(*handle))
+            {
+                return Err(format!("update of unknown body {:?}", handle));
+            }
+        }
+        Request::UpdateColliders(updates) => {
+            if let Some((handle, _)) = updates
+                .iter()
+                .find(|(handle, _)| !context.colliders.contains(*handle))
+            {
+                return Err(format!("update of unknown collider {:?}", handle));
+            }
+        }
+        Request::SetBodyTransforms(transforms) => {
+            if let Some((id, _)) = transforms.iter().find(|(id, _)| !known(*id)) {
+                return Err(format!("transform for unknown body {}", id));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The first multibody joint rapier would refuse to insert, as one closing a
+/// loop or attaching a body that already has a parent, counting the joints
+/// before it in the request.
+fn refused_multibody_joint(joints: &[CreatedJoint], context: &RapierContext) -> Option<u64> {
+    let multibody_joints = &context.multibody_joints;
+    // The roots of the multibodies the joints before merged into others
+    let mut merged: HashMap<RigidBodyHandle, RigidBodyHandle> = HashMap::new();
+    let mut children = HashSet::new();
+    let root = |merged: &HashMap<RigidBodyHandle, RigidBodyHandle>, body| {
+        let mut root = multibody_joints
+            .rigid_body_link(body)
+            .and_then(|link| multibody_joints.get_multibody(link.multibody))
+            .map_or(body, |multibody| multibody.root().rigid_body_handle());
+        while let Some(&parent) = merged.get(&root) {
+            root = parent;
+        }
+        root
+    };
+    for joint in joints.iter().filter(|joint| joint.multibody) {
+        let root1 = root(&merged, joint.body1);
+        let root2 = root(&merged, joint.body2);
+        let parented = children.contains(&joint.body2)
+            || multibody_joints
+                .rigid_body_link(joint.body2)
+                .is_some_and(|link| link.id != 0);
+        if root1 == root2 || parented {
+            return Some(joint.id);
+        }
+        merged.insert(root2, root1);
+        children.insert(joint.body2);
+    }
+    None
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::RigidBodyHandle;
+use rand::{rngs::StdRng, SeedableRng};
+
+use shared::{hooks::ContactRules, ids::IdMap, BodyTemplate, SimulationState};
+
+use crate::{
+    controllers, events, fluids, idempotency, joint_breaks, pool, rope, scene, snapshot, streaming,
+    tags, SessionOptions,
+};
+
+/// Everything a session keeps about one of its worlds. The session holds the
+/// world requests act on in its own fields, and the others in these.
+pub struct World {
+    pub context: RapierContext,
+    pub config: Option<RapierConfiguration>,
+    pub contact_rules: ContactRules,
+    pub sim_to_render_time: SimulationToRenderTime,
+    pub id2body: IdMap<RigidBodyHandle>,
+    pub templates: HashMap<u64, BodyTemplate>,
+    pub tags: tags::Tags,
+    pub events: events::EventCollector,
+    pub snapshot_filter: snapshot::SnapshotFilter,
+    pub controllers: controllers::Controllers,
+    pub ropes: rope::Ropes,
+    pub fluids: fluids::FluidVolumes,
+    pub joint_breaks: joint_breaks::JointBreaks,
+    pub recent_results: idempotency::RecentResults,
+    pub preloaded_scene: Option<scene::PreloadedScene>,
+    pub unreported_steps: (Duration, u32),
+    pub compactions: u32,
+    pub simulation_state: SimulationState,
+    pub stream: Option<streaming::Stream>,
+}
+
+impl World {
+    /// A world the way the server's options start them, from the pool if it
+    /// has one.
+    pub fn new(options: &SessionOptions, rng: &mut StdRng) -> Self {
+        let mut world = match &options.pool {
+            Some(pool) => pool.take(),
+            None => pool::WarmWorld::default(),
+        };
+        if options.ground {
+            scene::create_ground(&mut world.context);
+        }
+        if let Some(name) = &options.default_scene {
+            if let Err(err) = scene::load_default(name, &options.scenes_dir, &mut world.context) {
+                println!("Failed to load default scene {}: {}", name, err);
+            }
+        }
+        Self {
+            context: world.context,
+            config: options.profile.map(shared::profile::Profile::config),
+            contact_rules: ContactRules::default(),
+            sim_to_render_time: SimulationToRenderTime::default(),
+            id2body: IdMap::default(),
+            templates: HashMap::new(),
+            tags: tags::Tags::default(),
+            events: events::EventCollector::default(),
+            snapshot_filter: snapshot::SnapshotFilter::new(options.snapshot_budget),
+            controllers: controllers::Controllers::new(StdRng::from_rng(rng).unwrap()),
+            ropes: rope::Ropes::default(),
+            fluids: fluids::FluidVolumes::default(),
+            joint_breaks: joint_breaks::JointBreaks::default(),
+            recent_results: idempotency::RecentResults::default(),
+            preloaded_scene: world.scene,
+            unreported_steps: (Duration::ZERO, 0),
+            compactions: 0,
+            simulation_state: SimulationState::Running,
+            stream: options.tick.map(streaming::Stream::new),
+        }
+    }
+}
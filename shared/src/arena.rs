@@ -0,0 +1,82 @@
+use std::fmt;
+
+use bevy_rapier3d::rapier::prelude::{ColliderHandle, RigidBodyHandle};
+use serde::{Deserialize, Serialize};
+
+/// How full one of the server's handle arenas is. Removing bodies leaves
+/// their slots free until new ones reuse them, so a world that grew and shrank
+/// spreads few bodies over many slots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArenaStats {
+    pub live: usize,
+    /// Up to and including the highest live handle.
+    pub slots: usize,
+}
+
+impl ArenaStats {
+    /// From the indices of the live handles.
+    pub fn of(indices: impl Iterator<Item = u32>) -> Self {
+        let mut stats = Self::default();
+        for index in indices {
+            stats.live += 1;
+            stats.slots = stats.slots.max(index as usize + 1);
+        }
+        stats
+    }
+
+    /// The share of slots in use, 1 for an empty arena.
+    pub fn occupancy(&self) -> f32 {
+        if self.slots == 0 {
+            1.0
+        } else {
+            self.live as f32 / self.slots as f32
+        }
+    }
+
+    pub fn free(&self) -> usize {
+        self.slots - self.live
+    }
+}
+
+impl fmt::Display for ArenaStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} slots ({:.0}%)",
+            self.live,
+            self.slots,
+            self.occupancy() * 100.0
+        )
+    }
+}
+
+/// The server's statistics of a session's world.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorldStats {
+    pub bodies: ArenaStats,
+    pub colliders: ArenaStats,
+    pub impulse_joints: ArenaStats,
+    /// How many times the world was compacted.
+    pub compactions: u32,
+}
+
+impl fmt::Display for WorldStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bodies {}, colliders {}, joints {}, {} compactions",
+            self.bodies, self.colliders, self.impulse_joints, self.compactions
+        )
+    }
+}
+
+/// The new handles of the client's bodies and colliders after the world was
+/// compacted, by physics id, and the world's statistics before and after. A
+/// world without free slots is left as is, with no new handles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactedWorld {
+    pub bodies: Vec<(u64, RigidBodyHandle)>,
+    pub colliders: Vec<(u64, ColliderHandle)>,
+    pub before: WorldStats,
+    pub after: WorldStats,
+}
@@ -0,0 +1,78 @@
+//! Writes a schema of the wire protocol as JSON, traced from the `Request` and
+//! `Response` types themselves so that it can't drift from them. Every type
+//! they contain is listed by name in serde-reflection's registry format, from
+//! which dissectors and other implementations can be generated.
+
+use std::process::exit;
+
+use serde::Serialize;
+use serde_reflection::{Registry, Tracer, TracerConfig};
+
+use shared::{channel::Channel, protocol::ProtocolVersion, Request, Response};
+
+#[derive(Serialize)]
+struct Schema {
+    /// Of the shared crate the schema was traced from.
+    version: &'static str,
+    /// The `Sec-WebSocket-Protocol` the schema describes.
+    protocol: &'static str,
+    encoding: &'static str,
+    /// The bytes in front of every serialized message, outermost first.
+    framing: Vec<&'static str>,
+    channels: Vec<(u8, &'static str)>,
+    /// What clients send and servers answer with, looked up in `types`.
+    request: &'static str,
+    response: &'static str,
+    types: Registry,
+}
+
+fn trace() -> serde_reflection::Result<Registry> {
+    let mut tracer = Tracer::new(TracerConfig::default());
+    tracer.trace_simple_type::<Request>()?;
+    tracer.trace_simple_type::<Response>()?;
+    tracer.registry()
+}
+
+fn main() {
+    let types = match trace() {
+        Ok(types) => types,
+        Err(err) => {
+            eprintln!("Failed to trace the protocol: {}", err);
+            exit(1);
+        }
+    };
+    let schema = Schema {
+        version: env!("CARGO_PKG_VERSION"),
+        protocol: ProtocolVersion::CURRENT.name(),
+        encoding: "bincode 1 with its default options: little-endian, fixed-size integers, \
+                   u64 lengths and u32 variant indices, unless the handshake picks \
+                   postcard, msgpack or cbor for the messages after it",
+        framing: vec![
+            "envelope: the magic \"PH\", u8 version 1, u8 flags 0, then the u32 length and \
+             CRC32 of the rest, little-endian",
+            "channel: u8, the id of one of `channels`, the same in a request and its response",
+            "compression: u8, only once the handshake picked a compression, \
+             0 for a raw message, 1 for a zlib stream, 2 for an lz4 block after its \
+             u32 decompressed size and 3 for a zstd frame",
+        ],
+        channels: Channel::ALL
+            .iter()
+            .map(|channel| (channel.id(), channel.name()))
+            .collect(),
+        request: "Request",
+        response: "Response",
+        types,
+    };
+
+    let json = serde_json::to_string_pretty(&schema).expect("The schema is valid JSON");
+    match std::env::args().nth(1) {
+        Some(path) => {
+            if let Err(err) = std::fs::write(&path, json) {
+                eprintln!("Failed to write {}: {}", path, err);
+                exit(1);
+            }
+            println!("Wrote the protocol schema to {}", path);
+        }
+        None => println!("{}", json),
+    }
+}
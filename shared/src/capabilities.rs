@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use bevy_rapier3d::prelude::Real;
+use serde::{Deserialize, Serialize};
+
+use crate::{codec::Encoding, compression::Compression, framing::Framing};
+
+/// What a server supports, sent in its answer to the handshake so that
+/// clients can adapt to it, or tell their user what to change when they
+/// can't.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub framings: Vec<Framing>,
+    pub encodings: Vec<Encoding>,
+    pub compressions: Vec<Compression>,
+    /// The most bodies a world can have, unlimited if `None`.
+    pub max_bodies: Option<usize>,
+    /// 2 or 3.
+    pub dimensions: u8,
+    /// The bits of the floats bodies are simulated with, 32 or 64.
+    pub real_bits: u8,
+    /// Whether ray casts and shape queries are answered.
+    pub queries: bool,
+    /// The period the server steps every world at on its own, pushing the
+    /// results, if it does. Its clients don't need to ask for steps.
+    pub tick: Option<Duration>,
+}
+
+impl Capabilities {
+    /// The dimensions this build simulates in.
+    pub const DIMENSIONS: u8 = 3;
+    pub const REAL_BITS: u8 = (std::mem::size_of::<Real>() * 8) as u8;
+
+    /// Why a client of this build can't work with the server, if it can't.
+    pub fn incompatibility(&self) -> Option<String> {
+        if self.dimensions != Self::DIMENSIONS {
+            return Some(format!(
+                "the server simulates in {}D while this client is built for {}D, connect to a {}D server",
+                self.dimensions,
+                Self::DIMENSIONS,
+                Self::DIMENSIONS
+            ));
+        }
+        if self.real_bits != Self::REAL_BITS {
+            return Some(format!(
+                "the server simulates with f{} while this client is built with f{}, build both with the same precision",
+                self.real_bits,
+                Self::REAL_BITS
+            ));
+        }
+        None
+    }
+}
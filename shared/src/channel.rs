@@ -0,0 +1,94 @@
+use std::io;
+
+use crate::Request;
+
+/// The logical channels messages travel on, each with its own flow control
+/// and, on transports that have them, its own stream. The channel is the first
+/// byte of every message, and responses travel on their request's channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// Configuration and the creation and changing of bodies.
+    Control,
+    /// Steps and state, the large responses.
+    Snapshots,
+    /// Small requests whose answers are wanted soon.
+    Queries,
+}
+
+impl Channel {
+    pub const ALL: [Self; 3] = [Self::Control, Self::Snapshots, Self::Queries];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Control => "control",
+            Self::Snapshots => "snapshots",
+            Self::Queries => "queries",
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.get(id as usize).copied()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|channel| channel.name() == name)
+    }
+
+    pub fn of(request: &Request) -> Self {
+        match request {
+            // A bulk request stays in order on the channel of the most
+            // important of its requests
+            Request::BulkRequest(requests) | Request::Transaction(requests) => requests
+                .iter()
+                .map(Self::of)
+                .min_by_key(|channel| channel.id())
+                .unwrap_or(Self::Control),
+            Request::Idempotent { request, .. } | Request::InWorld { request, .. } => {
+                Self::of(request)
+            }
+            Request::SimulateStep(_)
+            | Request::GetState
+            | Request::GetRopes
+            | Request::TakeSnapshot => Self::Snapshots,
+            Request::CastRays(_)
+            | Request::CastRay { .. }
+            | Request::CastShape { .. }
+            | Request::IntersectionsWithShape { .. }
+            | Request::TakeEvents
+            | Request::TakeJointBreaks
+            | Request::PredictTrajectory { .. }
+            | Request::Ping { .. }
+            | Request::TimeSync(_)
+            | Request::MeasureStep(_)
+            | Request::TakeStepTime
+            | Request::GetStats => Self::Queries,
+            _ => Self::Control,
+        }
+    }
+}
+
+/// Puts a message in the envelope of its channel.
+pub fn wrap(channel: Channel, message: &[u8]) -> Vec<u8> {
+    let mut enveloped = Vec::with_capacity(message.len() + 1);
+    enveloped.push(channel.id());
+    enveloped.extend_from_slice(message);
+    enveloped
+}
+
+/// Returns the channel and message of an envelope made by `wrap`.
+pub fn unwrap(enveloped: &[u8]) -> io::Result<(Channel, &[u8])> {
+    match enveloped.split_first() {
+        Some((&id, message)) => match Channel::from_id(id) {
+            Some(channel) => Ok((channel, message)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown channel {}", id),
+            )),
+        },
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "empty message")),
+    }
+}
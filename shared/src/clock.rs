@@ -0,0 +1,50 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Microseconds since the Unix epoch on this machine's wall clock.
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros() as u64)
+}
+
+/// The timestamps of a `TimeSync` exchange, in microseconds since the Unix
+/// epoch on the clock of the end that took them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSample {
+    pub client_sent: u64,
+    pub server_received: u64,
+    pub server_sent: u64,
+    /// Taken by the client when the answer arrives, so it doesn't travel.
+    #[serde(skip)]
+    pub client_received: u64,
+}
+
+impl TimeSample {
+    /// How far the server's clock is ahead of the client's, assuming the
+    /// request took as long as the answer.
+    pub fn offset(&self) -> i64 {
+        let there = self.server_received as i64 - self.client_sent as i64;
+        let back = self.server_sent as i64 - self.client_received as i64;
+        (there + back) / 2
+    }
+
+    /// The round trip without the time the server held the request.
+    pub fn rtt(&self) -> u64 {
+        let total = self.client_received.saturating_sub(self.client_sent);
+        total.saturating_sub(self.server_sent.saturating_sub(self.server_received))
+    }
+
+    /// The time the request took to reach the server, with the clocks `offset`
+    /// apart.
+    pub fn uplink(&self, offset: i64) -> i64 {
+        self.server_received as i64 - offset - self.client_sent as i64
+    }
+
+    /// The time the answer took to reach the client, with the clocks `offset`
+    /// apart.
+    pub fn downlink(&self, offset: i64) -> i64 {
+        self.client_received as i64 + offset - self.server_sent as i64
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// A serialization format that messages framed as `Framing::Binary` can be
+/// encoded in.
+pub trait Codec {
+    fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T>;
+}
+
+fn invalid(err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// bincode 1 with its default options.
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+        bincode::serialize(value).map_err(invalid)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+        bincode::deserialize(bytes).map_err(invalid)
+    }
+}
+
+/// postcard, with varint integers.
+pub struct Postcard;
+
+impl Codec for Postcard {
+    fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+        postcard::to_stdvec(value).map_err(invalid)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+        postcard::from_bytes(bytes).map_err(invalid)
+    }
+}
+
+/// MessagePack, with structs as arrays of their fields.
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+        rmp_serde::to_vec(value).map_err(invalid)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+        rmp_serde::from_slice(bytes).map_err(invalid)
+    }
+}
+
+/// CBOR, with structs as maps keyed by field name.
+pub struct Cbor;
+
+impl Codec for Cbor {
+    fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(value, &mut bytes).map_err(invalid)?;
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+        ciborium::de::from_reader(bytes).map_err(invalid)
+    }
+}
+
+/// The codec of the messages after the handshake, picked by the client in it.
+/// Only applies to `Framing::Binary`; the handshake itself always travels in
+/// bincode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    #[default]
+    Bincode,
+    Postcard,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    pub const ALL: [Self; 4] = [Self::Bincode, Self::Postcard, Self::MessagePack, Self::Cbor];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bincode => "bincode",
+            Self::Postcard => "postcard",
+            Self::MessagePack => "msgpack",
+            Self::Cbor => "cbor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|encoding| encoding.name() == name)
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Bincode => Bincode::encode(value),
+            Self::Postcard => Postcard::encode(value),
+            Self::MessagePack => MessagePack::encode(value),
+            Self::Cbor => Cbor::encode(value),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> io::Result<T> {
+        match self {
+            Self::Bincode => Bincode::decode(bytes),
+            Self::Postcard => Postcard::decode(bytes),
+            Self::MessagePack => MessagePack::decode(bytes),
+            Self::Cbor => Cbor::decode(bytes),
+        }
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
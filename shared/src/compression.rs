@@ -0,0 +1,136 @@
+use std::io::{self, Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression as ZlibLevel};
+use serde::{Deserialize, Serialize};
+
+/// Messages shorter than this are sent as is, as compressing a small request
+/// like a `SimulateStep` costs more time than the bytes it saves.
+pub const DEFAULT_THRESHOLD: usize = 256;
+
+/// The byte in front of every message telling how the rest is encoded.
+const RAW: u8 = 0;
+const ZLIB: u8 = 1;
+const LZ4: u8 = 2;
+const ZSTD: u8 = 3;
+
+/// How the binary messages after the handshake are compressed, picked by the
+/// client in it. With anything but `None`, every message starts with a byte
+/// telling how the rest is encoded, so that short ones can travel raw.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    #[default]
+    None,
+    Zlib,
+    /// LZ4 blocks after their decompressed size, without levels.
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub const ALL: [Self; 4] = [Self::None, Self::Zlib, Self::Lz4, Self::Zstd];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Zlib => "zlib",
+            Self::Lz4 => "lz4",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|compression| compression.name() == name)
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How one end compresses what it sends. Only the compression is agreed on
+/// in the handshake, the level and threshold are up to the sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compressor {
+    pub compression: Compression,
+    /// Clamped to the levels of the compression, its default if `None`.
+    pub level: Option<i32>,
+    /// Messages shorter than this many bytes are sent raw.
+    pub threshold: usize,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self {
+            compression: Compression::None,
+            level: None,
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+impl Compressor {
+    /// Frames a serialized message, compressed if it is at least `threshold`
+    /// bytes long.
+    pub fn encode(&self, serialized: &[u8]) -> io::Result<Vec<u8>> {
+        match self.compression {
+            Compression::None => Ok(serialized.to_vec()),
+            _ if serialized.len() < self.threshold => {
+                let mut framed = Vec::with_capacity(serialized.len() + 1);
+                framed.push(RAW);
+                framed.extend_from_slice(serialized);
+                Ok(framed)
+            }
+            Compression::Zlib => {
+                let level = self.level.map_or_else(ZlibLevel::default, |level| {
+                    ZlibLevel::new(level.clamp(0, 9) as u32)
+                });
+                let mut encoder = ZlibEncoder::new(vec![ZLIB], level);
+                encoder.write_all(serialized)?;
+                encoder.finish()
+            }
+            Compression::Lz4 => {
+                let mut framed = vec![LZ4];
+                framed.extend(lz4_flex::compress_prepend_size(serialized));
+                Ok(framed)
+            }
+            Compression::Zstd => {
+                let levels = zstd::compression_level_range();
+                let level = self
+                    .level
+                    .unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL)
+                    .clamp(*levels.start(), *levels.end());
+                let mut framed = vec![ZSTD];
+                zstd::stream::copy_encode(serialized, &mut framed, level)?;
+                Ok(framed)
+            }
+        }
+    }
+}
+
+/// Returns the serialized message of a frame made by `Compressor::encode`
+/// with `compression`.
+pub fn decode(compression: Compression, framed: &[u8]) -> io::Result<Vec<u8>> {
+    if compression == Compression::None {
+        return Ok(framed.to_vec());
+    }
+    match framed.split_first() {
+        Some((&RAW, serialized)) => Ok(serialized.to_vec()),
+        Some((&ZLIB, compressed)) => {
+            let mut decompressed = Vec::new();
+            ZlibDecoder::new(compressed).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+        Some((&LZ4, compressed)) => lz4_flex::decompress_size_prepended(compressed)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+        Some((&ZSTD, compressed)) => zstd::stream::decode_all(compressed),
+        Some((flag, _)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown message encoding {}", flag),
+        )),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "empty message")),
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// The ways the server lowered the quality of a response to keep up, one bit
+/// each, so that clients and experiment logs can tell server decisions from
+/// network effects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Degradation(pub u8);
+
+impl Degradation {
+    pub const NONE: Self = Self(0);
+    /// Bodies were left out of the step's snapshot to fit its byte budget.
+    pub const SNAPSHOT_BUDGET: Self = Self(1 << 0);
+    /// A request asked for more than the server allows and got less.
+    pub const REQUEST_LIMITED: Self = Self(1 << 1);
+    /// Shapes were simplified.
+    pub const LOD: Self = Self(1 << 2);
+    /// The solver ran fewer iterations than configured.
+    pub const SOLVER_ITERATIONS: Self = Self(1 << 3);
+    /// Work was dropped because the server is overloaded.
+    pub const LOAD_SHEDDING: Self = Self(1 << 4);
+
+    const NAMES: [(Self, &'static str); 5] = [
+        (Self::SNAPSHOT_BUDGET, "snapshot-budget"),
+        (Self::REQUEST_LIMITED, "request-limited"),
+        (Self::LOD, "lod"),
+        (Self::SOLVER_ITERATIONS, "solver-iterations"),
+        (Self::LOAD_SHEDDING, "load-shedding"),
+    ];
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// How many kinds of degradation there are, a rough level for display.
+    pub fn level(self) -> u32 {
+        self.0.count_ones()
+    }
+}
+
+impl std::ops::BitOr for Degradation {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Degradation {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// The names of the kinds of degradation separated by `|`, `none` if there
+/// are none.
+impl std::fmt::Display for Degradation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .filter(|(degradation, _)| self.contains(*degradation))
+            .map(|(_, name)| *name)
+            .collect();
+        f.write_str(&names.join("|"))
+    }
+}
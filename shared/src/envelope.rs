@@ -0,0 +1,116 @@
+use std::io;
+
+/// The first bytes of every envelope.
+pub const MAGIC: [u8; 2] = *b"PH";
+
+/// Of the envelope's layout, bumped when it changes.
+pub const VERSION: u8 = 1;
+
+/// The magic, version, flags, payload length and CRC32 of the payload.
+pub const HEADER_LEN: usize = 12;
+
+/// None are defined yet, envelopes with any set are refused so that later
+/// ones can't be misread.
+const KNOWN_FLAGS: u8 = 0;
+
+/// Why a binary message isn't a valid envelope, so that a corrupted or
+/// truncated message is reported as such rather than as whatever its payload
+/// fails to decode as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// Shorter than the header.
+    MissingHeader(usize),
+    BadMagic([u8; 2]),
+    UnsupportedVersion(u8),
+    UnknownFlags(u8),
+    /// The payload is of a different length than the header claims.
+    LengthMismatch {
+        claimed: usize,
+        actual: usize,
+    },
+    ChecksumMismatch {
+        claimed: u32,
+        actual: u32,
+    },
+}
+
+impl std::fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingHeader(len) => write!(
+                f,
+                "message of {} bytes is shorter than the {} bytes of its header",
+                len, HEADER_LEN
+            ),
+            Self::BadMagic(magic) => {
+                write!(f, "message starts with {:02x?} instead of magic", magic)
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "envelope version {} instead of {}", version, VERSION)
+            }
+            Self::UnknownFlags(flags) => write!(f, "unknown envelope flags {:#04x}", flags),
+            Self::LengthMismatch { claimed, actual } => write!(
+                f,
+                "payload of {} bytes claims to have {}, truncated or corrupted",
+                actual, claimed
+            ),
+            Self::ChecksumMismatch { claimed, actual } => write!(
+                f,
+                "payload checksum {:#010x} instead of {:#010x}, corrupted",
+                actual, claimed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+impl From<EnvelopeError> for io::Error {
+    fn from(err: EnvelopeError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Puts a message in an envelope checked by `open`.
+pub fn seal(payload: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::with_capacity(HEADER_LEN + payload.len());
+    sealed.extend_from_slice(&MAGIC);
+    sealed.push(VERSION);
+    sealed.push(KNOWN_FLAGS);
+    sealed.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    sealed.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    sealed.extend_from_slice(payload);
+    sealed
+}
+
+/// Returns the message of an envelope made by `seal`, if it arrived whole.
+pub fn open(sealed: &[u8]) -> Result<&[u8], EnvelopeError> {
+    if sealed.len() < HEADER_LEN {
+        return Err(EnvelopeError::MissingHeader(sealed.len()));
+    }
+    let (header, payload) = sealed.split_at(HEADER_LEN);
+    let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+
+    let magic = [header[0], header[1]];
+    if magic != MAGIC {
+        return Err(EnvelopeError::BadMagic(magic));
+    }
+    if header[2] != VERSION {
+        return Err(EnvelopeError::UnsupportedVersion(header[2]));
+    }
+    if header[3] & !KNOWN_FLAGS != 0 {
+        return Err(EnvelopeError::UnknownFlags(header[3]));
+    }
+    let claimed = u32_at(4) as usize;
+    if claimed != payload.len() {
+        return Err(EnvelopeError::LengthMismatch {
+            claimed,
+            actual: payload.len(),
+        });
+    }
+    let (claimed, actual) = (u32_at(8), crc32fast::hash(payload));
+    if claimed != actual {
+        return Err(EnvelopeError::ChecksumMismatch { claimed, actual });
+    }
+    Ok(payload)
+}
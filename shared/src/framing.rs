@@ -0,0 +1,87 @@
+use std::io;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::channel::Channel;
+
+/// How messages are put on the wire, picked by the client in the handshake.
+/// The handshake itself always travels in binary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Framing {
+    /// Encoded as picked in the handshake behind the channel byte, compressed
+    /// as picked in it too.
+    #[default]
+    Binary,
+    /// Text messages of JSON naming the channel and type of every message,
+    /// after its length and a newline, so that traffic can be read with
+    /// standard tools during development.
+    Json,
+}
+
+impl Framing {
+    pub const ALL: [Self; 2] = [Self::Binary, Self::Json];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::Json => "json",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|framing| framing.name() == name)
+    }
+}
+
+impl std::fmt::Display for Framing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Serialize)]
+struct JsonFrame<'a, T> {
+    channel: &'static str,
+    #[serde(rename = "type")]
+    type_name: &'static str,
+    message: &'a T,
+}
+
+#[derive(Deserialize)]
+struct OwnedJsonFrame<T> {
+    channel: String,
+    message: T,
+}
+
+/// Frames a message of the given type name as `Framing::Json`.
+pub fn to_json<T: Serialize>(
+    channel: Channel,
+    type_name: &'static str,
+    message: &T,
+) -> io::Result<String> {
+    let json = serde_json::to_string(&JsonFrame {
+        channel: channel.name(),
+        type_name,
+        message,
+    })?;
+    Ok(format!("{}\n{}", json.len(), json))
+}
+
+/// Returns the channel and message of a frame made by `to_json`.
+pub fn from_json<T: DeserializeOwned>(framed: &str) -> io::Result<(Channel, T)> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let (len, json) = framed
+        .split_once('\n')
+        .ok_or_else(|| invalid("missing the length of a JSON message".to_string()))?;
+    if len.parse::<usize>().ok() != Some(json.len()) {
+        return Err(invalid(format!(
+            "JSON message of {} bytes claims to have {}",
+            json.len(),
+            len
+        )));
+    }
+    let frame: OwnedJsonFrame<T> = serde_json::from_str(json)?;
+    let channel = Channel::from_name(&frame.channel)
+        .ok_or_else(|| invalid(format!("unknown channel {}", frame.channel)))?;
+    Ok((channel, frame.message))
+}
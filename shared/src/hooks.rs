@@ -0,0 +1,167 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::layers::LayerRegistry;
+
+/// How far from a one-way platform's up contacts can point and still hold a
+/// body on it, in radians.
+pub const ONE_WAY_ALLOWED_ANGLE: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContactAction {
+    Allow,
+    Deny,
+}
+
+/// Matches the pairs of colliders where one is a member of any of `groups1`
+/// and the other of any of `groups2`, by the bits of their `CollisionGroups`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactRule {
+    pub action: ContactAction,
+    pub groups1: u32,
+    pub groups2: u32,
+}
+
+impl ContactRule {
+    pub fn matches(&self, memberships1: u32, memberships2: u32) -> bool {
+        (memberships1 & self.groups1 != 0 && memberships2 & self.groups2 != 0)
+            || (memberships1 & self.groups2 != 0 && memberships2 & self.groups1 != 0)
+    }
+}
+
+/// The contact pair filtering the server runs in place of physics hooks,
+/// which can't be sent as closures. Pairs their collision groups let collide
+/// still go through the rules, the first matching a pair deciding whether it
+/// collides, and pairs no rule matches collide.
+#[derive(Resource, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactRules {
+    pub rules: Vec<ContactRule>,
+    /// Members of these groups are one-way platforms, only solid to what
+    /// comes from their local up, so that bodies can jump through them from
+    /// below.
+    pub one_way_platforms: u32,
+}
+
+impl ContactRules {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.one_way_platforms == 0
+    }
+
+    pub fn allow(mut self, groups1: u32, groups2: u32) -> Self {
+        self.rules.push(ContactRule {
+            action: ContactAction::Allow,
+            groups1,
+            groups2,
+        });
+        self
+    }
+
+    pub fn deny(mut self, groups1: u32, groups2: u32) -> Self {
+        self.rules.push(ContactRule {
+            action: ContactAction::Deny,
+            groups1,
+            groups2,
+        });
+        self
+    }
+
+    pub fn with_one_way_platforms(mut self, groups: u32) -> Self {
+        self.one_way_platforms |= groups;
+        self
+    }
+
+    /// Whether colliders of the given memberships collide.
+    pub fn allows(&self, memberships1: u32, memberships2: u32) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(memberships1, memberships2))
+            .is_none_or(|rule| rule.action == ContactAction::Allow)
+    }
+
+    pub fn is_one_way_platform(&self, memberships: u32) -> bool {
+        memberships & self.one_way_platforms != 0
+    }
+
+    /// Parses a comma separated list of `allow:<GROUPS>/<GROUPS>`,
+    /// `deny:<GROUPS>/<GROUPS>` and `one-way:<GROUPS>`, where groups are layer
+    /// names of the registry or bits in hex separated by `|`, or `all`. `none`
+    /// is no rules.
+    pub fn parse(rules: &str, layers: &LayerRegistry) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        if rules == "none" {
+            return Ok(parsed);
+        }
+        for rule in rules.split(',').filter(|rule| !rule.is_empty()) {
+            let (kind, groups) = rule
+                .split_once(':')
+                .ok_or_else(|| format!("expected <RULE>:<GROUPS>, got {}", rule))?;
+            let pair = || {
+                let (groups1, groups2) = groups
+                    .split_once('/')
+                    .ok_or_else(|| format!("expected <GROUPS>/<GROUPS>, got {}", groups))?;
+                Ok::<_, String>((
+                    parse_groups(groups1, layers)?,
+                    parse_groups(groups2, layers)?,
+                ))
+            };
+            parsed = match kind {
+                "allow" => {
+                    let (groups1, groups2) = pair()?;
+                    parsed.allow(groups1, groups2)
+                }
+                "deny" => {
+                    let (groups1, groups2) = pair()?;
+                    parsed.deny(groups1, groups2)
+                }
+                "one-way" => {
+                    let groups = parse_groups(groups, layers)?;
+                    parsed.with_one_way_platforms(groups)
+                }
+                _ => return Err(format!("unknown contact rule {}", kind)),
+            };
+        }
+        Ok(parsed)
+    }
+}
+
+fn parse_groups(groups: &str, layers: &LayerRegistry) -> Result<u32, String> {
+    if groups == "all" {
+        return Ok(u32::MAX);
+    }
+    groups.split('|').try_fold(0, |bits, group| {
+        let layer = match group.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => layers.bits(group),
+        };
+        layer
+            .map(|layer| bits | layer)
+            .ok_or_else(|| format!("unknown layer {}", group))
+    })
+}
+
+/// With the groups in hex, which `parse` reads with any registry.
+impl fmt::Display for ContactRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rules: Vec<String> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let action = match rule.action {
+                    ContactAction::Allow => "allow",
+                    ContactAction::Deny => "deny",
+                };
+                format!("{}:{:#x}/{:#x}", action, rule.groups1, rule.groups2)
+            })
+            .collect();
+        if self.one_way_platforms != 0 {
+            rules.push(format!("one-way:{:#x}", self.one_way_platforms));
+        }
+        if rules.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&rules.join(","))
+        }
+    }
+}
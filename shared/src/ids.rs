@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+/// Identifies what a client creates on the server: bodies, colliders, joints
+/// and ropes. Clients assign them from a counter rather than sending the bits
+/// of their entities, so that an entity spawned in the slot of a removed one
+/// can't be taken for it, and the server never makes up entities of its own
+/// from them. Requests and responses carry the `u64` inside.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct PhysicsId(pub u64);
+
+impl fmt::Display for PhysicsId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// The physics ids of one end and what they identify there, both ways, each
+/// id mapping to one value and each value to one id.
+#[derive(Debug, Clone)]
+pub struct IdMap<T> {
+    by_id: HashMap<PhysicsId, T>,
+    ids: HashMap<T, PhysicsId>,
+}
+
+impl<T> Default for IdMap<T> {
+    fn default() -> Self {
+        Self {
+            by_id: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> IdMap<T> {
+    /// Maps `id` to `value`, forgetting what either mapped to before.
+    pub fn insert(&mut self, id: PhysicsId, value: T) {
+        if let Some(old) = self.by_id.insert(id, value) {
+            self.ids.remove(&old);
+        }
+        if let Some(old) = self.ids.insert(value, id) {
+            if old != id {
+                self.by_id.remove(&old);
+            }
+        }
+    }
+
+    pub fn get(&self, id: PhysicsId) -> Option<T> {
+        self.by_id.get(&id).copied()
+    }
+
+    pub fn id(&self, value: T) -> Option<PhysicsId> {
+        self.ids.get(&value).copied()
+    }
+
+    pub fn contains(&self, id: PhysicsId) -> bool {
+        self.by_id.contains_key(&id)
+    }
+
+    pub fn remove(&mut self, id: PhysicsId) -> Option<T> {
+        let value = self.by_id.remove(&id)?;
+        self.ids.remove(&value);
+        Some(value)
+    }
+
+    pub fn remove_value(&mut self, value: T) -> Option<PhysicsId> {
+        let id = self.ids.remove(&value)?;
+        self.by_id.remove(&id);
+        Some(id)
+    }
+
+    /// Replaces what the ids map to, dropping those `remap` gives `None` for.
+    pub fn remap(&mut self, mut remap: impl FnMut(T) -> Option<T>) {
+        let entries: Vec<_> = self.by_id.drain().collect();
+        self.ids.clear();
+        for (id, value) in entries {
+            if let Some(value) = remap(value) {
+                self.insert(id, value);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.by_id.clear();
+        self.ids.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PhysicsId, T)> + '_ {
+        self.by_id.iter().map(|(&id, &value)| (id, value))
+    }
+}
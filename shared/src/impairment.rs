@@ -0,0 +1,115 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The latency a server adds to its responses, in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SimulatedLatency {
+    #[default]
+    None,
+    Fixed(u64),
+    /// Exponentially distributed above `min`.
+    Random {
+        min: u64,
+        mean: u64,
+    },
+}
+
+/// The network conditions a server simulates for a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Impairment {
+    pub latency: SimulatedLatency,
+    /// In kbps.
+    pub bandwidth: Option<u64>,
+    /// The share of responses lost and sent again after a retransmission
+    /// timeout, from 0 to 1.
+    pub loss: f32,
+}
+
+impl Impairment {
+    /// Parses a comma separated list of `latency=<MILLISECONDS>`,
+    /// `min=<MILLISECONDS>`, `bandwidth=<KBPS>` and `loss=<SHARE>`, where `min`
+    /// makes the latency random with the given mean, or `none`.
+    pub fn parse(impairment: &str) -> Result<Self, String> {
+        if impairment == "none" {
+            return Ok(Self::default());
+        }
+        let mut latency = None;
+        let mut min = None;
+        let mut parsed = Self::default();
+        for setting in impairment.split(',').filter(|setting| !setting.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected <NAME>=<VALUE>, got {}", setting))?;
+            let number = |value: &str| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("expected a number for {}, got {}", name, value))
+            };
+            match name {
+                "latency" => latency = Some(number(value)?),
+                "min" => min = Some(number(value)?),
+                "bandwidth" => parsed.bandwidth = Some(number(value)?),
+                "loss" => {
+                    parsed.loss = value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|loss| (0.0..=1.0).contains(loss))
+                        .ok_or_else(|| format!("expected a share from 0 to 1, got {}", value))?;
+                }
+                _ => return Err(format!("unknown impairment {}", name)),
+            }
+        }
+        parsed.latency = match (latency, min) {
+            (None, None) => SimulatedLatency::None,
+            (Some(latency), None) => SimulatedLatency::Fixed(latency),
+            (Some(mean), Some(min)) if min < mean => SimulatedLatency::Random { min, mean },
+            (Some(_), Some(_)) => return Err("min must be less than latency".to_string()),
+            (None, Some(_)) => return Err("min needs a latency".to_string()),
+        };
+        Ok(parsed)
+    }
+}
+
+/// Formatted the way `parse` reads it.
+impl fmt::Display for Impairment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut settings = vec![];
+        match self.latency {
+            SimulatedLatency::None => {}
+            SimulatedLatency::Fixed(latency) => settings.push(format!("latency={}", latency)),
+            SimulatedLatency::Random { min, mean } => {
+                settings.push(format!("latency={}", mean));
+                settings.push(format!("min={}", min));
+            }
+        }
+        if let Some(bandwidth) = self.bandwidth {
+            settings.push(format!("bandwidth={}", bandwidth));
+        }
+        if self.loss > 0.0 {
+            settings.push(format!("loss={}", self.loss));
+        }
+        if settings.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&settings.join(","))
+        }
+    }
+}
+
+/// Why a server didn't change a session's impairment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImpairmentError {
+    /// The server wasn't started with a key for clients to set impairments.
+    Disabled,
+    WrongKey,
+}
+
+impl fmt::Display for ImpairmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "the server doesn't let clients set impairments"),
+            Self::WrongKey => write!(f, "wrong impairment key"),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::{CollisionGroups, Group};
+use serde::{Deserialize, Serialize};
+
+/// The layers every registry starts with.
+pub const PRESETS: [(&str, u32); 4] = [
+    ("player", 1 << 0),
+    ("debris", 1 << 1),
+    ("sensor-zones", 1 << 2),
+    ("environment", 1 << 3),
+];
+
+/// Names for the bits of `CollisionGroups`, sent to the server with the first
+/// requests so that both ends log and show groups by the same names.
+#[derive(Resource, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayerRegistry {
+    layers: Vec<(String, u32)>,
+}
+
+impl Default for LayerRegistry {
+    fn default() -> Self {
+        Self {
+            layers: PRESETS
+                .iter()
+                .map(|(name, bits)| (name.to_string(), *bits))
+                .collect(),
+        }
+    }
+}
+
+impl LayerRegistry {
+    /// A registry without the presets.
+    pub fn empty() -> Self {
+        Self { layers: vec![] }
+    }
+
+    /// Names `bits`, replacing any layer of the same name.
+    pub fn register(&mut self, name: &str, bits: u32) {
+        self.layers.retain(|(layer, _)| layer != name);
+        self.layers.push((name.to_string(), bits));
+    }
+
+    pub fn layers(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.layers
+            .iter()
+            .map(|(name, bits)| (name.as_str(), *bits))
+    }
+
+    pub fn bits(&self, name: &str) -> Option<u32> {
+        self.layers()
+            .find(|(layer, _)| *layer == name)
+            .map(|(_, bits)| bits)
+    }
+
+    /// The groups of a collider on the `member` layer colliding with the
+    /// `filters` layers, `None` if any of them isn't registered.
+    pub fn groups(&self, member: &str, filters: &[&str]) -> Option<CollisionGroups> {
+        let memberships = self.bits(member)?;
+        let filters = filters
+            .iter()
+            .map(|name| self.bits(name))
+            .try_fold(0, |bits, layer| layer.map(|layer| bits | layer))?;
+        Some(CollisionGroups::new(
+            Group::from_bits_truncate(memberships),
+            Group::from_bits_truncate(filters),
+        ))
+    }
+
+    /// The names of the layers in `bits` separated by `|`, with the bits no
+    /// layer covers in hex, `none` if there are no bits.
+    pub fn describe(&self, bits: u32) -> String {
+        if bits == u32::MAX {
+            return "all".to_string();
+        }
+        let mut names = vec![];
+        let mut unnamed = bits;
+        for (name, layer) in self.layers() {
+            if layer != 0 && bits & layer == layer {
+                names.push(name.to_string());
+                unnamed &= !layer;
+            }
+        }
+        if unnamed != 0 {
+            names.push(format!("{:#x}", unnamed));
+        }
+        if names.is_empty() {
+            return "none".to_string();
+        }
+        names.join("|")
+    }
+}
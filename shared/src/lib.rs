@@ -1,14 +1,50 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use bevy::prelude::*;
 use bevy_rapier3d::{
     prelude::*,
-    rapier::prelude::{ColliderHandle, Isometry, RigidBodyHandle},
+    rapier::prelude::{
+        ColliderHandle, ImpulseJointHandle, Isometry, MultibodyJointHandle, RigidBodyHandle,
+        SharedShape,
+    },
 };
 
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
 
+pub mod arena;
+pub mod capabilities;
+pub mod channel;
+pub mod clock;
+pub mod codec;
+pub mod compression;
+pub mod degradation;
+pub mod envelope;
+pub mod framing;
+pub mod hooks;
+pub mod ids;
+pub mod impairment;
+pub mod layers;
+pub mod metadata;
+pub mod metrics;
+pub mod mirror;
+pub mod operator;
+pub mod pacing;
+pub mod partition;
+pub mod profile;
+pub mod protocol;
+pub mod ragdoll;
+pub mod recording;
+pub mod rope;
+pub mod scene;
 pub mod serializable;
+pub mod transaction;
+pub mod worlds;
+use mirror::WorldState;
+use ragdoll::{CreatedRagdoll, RagdollHandles};
+use rope::{CreatedRope, RopeSnapshot};
+use scene::{SceneCollider, SceneError};
 use serializable::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,57 +53,739 @@ pub struct CreatedBody {
     pub body: RigidBody,
     pub transform: Option<Isometry<Real>>,
     pub additional_mass_properties: Option<SerializableAdditionalMassProperties>,
+    pub damping: Option<SerializableDamping>,
+    pub gravity_scale: Option<Real>,
+    /// `Ccd::enabled`.
+    pub ccd: bool,
+    /// The `Dominance` group.
+    pub dominance_group: Option<i8>,
+    /// The `LockedAxes` bits.
+    pub locked_axes: Option<u8>,
+    pub tag: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreatedCollider {
     pub id: u64,
     pub shape: Collider,
+    /// Of the shape, from the `ColliderScale` and the scale of the collider's
+    /// `GlobalTransform` as bevy_rapier combines them, `None` if it's one.
+    pub scale: Option<Vect>,
+    /// The body the collider is attached to when it's on a descendant of the
+    /// body's entity rather than on the body's own, `transform` then being
+    /// relative to the body.
+    pub parent_id: Option<u64>,
     pub transform: Option<Isometry<Real>>,
     pub sensor: Option<SerializableSensor>,
     pub mass_properties: Option<SerializableColliderMassProperties>,
     pub friction: Option<SerializableFriction>,
     pub restitution: Option<SerializableRestitution>,
+    pub collision_groups: Option<SerializableCollisionGroups>,
+    pub solver_groups: Option<SerializableSolverGroups>,
+    pub active_events: Option<ActiveEvents>,
+    pub active_hooks: Option<SerializableActiveHooks>,
+    pub active_collision_types: Option<SerializableActiveCollisionTypes>,
+    /// The `ContactForceEventThreshold`.
+    pub contact_force_event_threshold: Option<Real>,
+}
+
+/// The components of a body that changed since it was created, `None` for
+/// those that didn't.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyUpdate {
+    pub body: Option<RigidBody>,
+    pub additional_mass_properties: Option<SerializableAdditionalMassProperties>,
+}
+
+/// The components of a collider that changed since it was created, `None` for
+/// those that didn't.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColliderUpdate {
+    /// Sent again with its new scale when only the scale changed.
+    pub shape: Option<Collider>,
+    /// Of `shape`, `None` if it's one.
+    pub scale: Option<Vect>,
+    pub mass_properties: Option<SerializableColliderMassProperties>,
+    pub friction: Option<SerializableFriction>,
+    pub restitution: Option<SerializableRestitution>,
+}
+
+/// A joint between two bodies created earlier, sent for the entity holding
+/// its component.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedJoint {
+    pub id: u64,
+    pub body1: RigidBodyHandle,
+    pub body2: RigidBodyHandle,
+    pub joint: SerializableJoint,
+    /// Whether it's a `MultibodyJoint` rather than an `ImpulseJoint`.
+    pub multibody: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum JointHandle {
+    Impulse(ImpulseJointHandle),
+    Multibody(MultibodyJointHandle),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyTemplate {
+    pub body: RigidBody,
+    pub additional_mass_properties: Option<SerializableAdditionalMassProperties>,
+    pub damping: Option<SerializableDamping>,
+    pub gravity_scale: Option<Real>,
+    /// `Ccd::enabled`.
+    pub ccd: bool,
+    /// The `Dominance` group.
+    pub dominance_group: Option<i8>,
+    /// The `LockedAxes` bits.
+    pub locked_axes: Option<u8>,
+    pub shape: Collider,
+    /// Of the shape, `None` if it's one.
+    pub scale: Option<Vect>,
+    pub sensor: Option<SerializableSensor>,
+    pub mass_properties: Option<SerializableColliderMassProperties>,
+    pub friction: Option<SerializableFriction>,
+    pub restitution: Option<SerializableRestitution>,
+    pub collision_groups: Option<SerializableCollisionGroups>,
+    pub solver_groups: Option<SerializableSolverGroups>,
+    pub active_events: Option<ActiveEvents>,
+    pub active_hooks: Option<SerializableActiveHooks>,
+    pub active_collision_types: Option<SerializableActiveCollisionTypes>,
+    /// The `ContactForceEventThreshold`.
+    pub contact_force_event_threshold: Option<Real>,
+}
+
+impl BodyTemplate {
+    /// Expands the template into the creation requests of a single instance.
+    pub fn instantiate(&self, instance: &TemplateInstance) -> (CreatedBody, CreatedCollider) {
+        let body = CreatedBody {
+            id: instance.id,
+            body: self.body,
+            transform: Some(instance.transform),
+            additional_mass_properties: self.additional_mass_properties.clone(),
+            damping: self.damping,
+            gravity_scale: self.gravity_scale,
+            ccd: self.ccd,
+            dominance_group: self.dominance_group,
+            locked_axes: self.locked_axes,
+            tag: instance.tag.clone(),
+        };
+        let collider = CreatedCollider {
+            id: instance.id,
+            parent_id: None,
+            shape: self.shape.clone(),
+            scale: self.scale,
+            transform: Some(instance.transform),
+            sensor: self.sensor.clone(),
+            mass_properties: self.mass_properties.clone(),
+            friction: self.friction.clone(),
+            restitution: self.restitution.clone(),
+            collision_groups: self.collision_groups,
+            solver_groups: self.solver_groups,
+            active_events: self.active_events,
+            active_hooks: self.active_hooks,
+            active_collision_types: self.active_collision_types,
+            contact_force_event_threshold: self.contact_force_event_threshold,
+        };
+        (body, collider)
+    }
+}
+
+/// The server's whole world, as taken by `TakeSnapshot` and loaded back by
+/// `RestoreSnapshot`, possibly into another session or server. Bodies and
+/// colliders keep the physics ids and handles they had, so a client restoring
+/// it has to have the same entities.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    /// The `RapierContext` with its bodies, colliders, joints and islands,
+    /// encoded with bincode whatever the framing.
+    pub context: Vec<u8>,
+    pub config: Option<SerializableRapierConfiguration>,
+    /// The tags of the bodies, by physics id.
+    pub tags: Vec<(u64, String)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateInstance {
+    pub id: u64,
+    pub template_id: u64,
+    pub transform: Isometry<Real>,
+    pub velocity: Option<Velocity>,
+    pub tag: Option<String>,
+}
+
+/// A short name for a body, like `player-ball`, that the server shows next to
+/// its physics id in logs and state listings.
+#[derive(Component, Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Tag(String);
+
+impl Tag {
+    /// Longer tags are cut short.
+    pub const MAX_LEN: usize = 32;
+
+    pub fn new(tag: &str) -> Self {
+        Self(tag.chars().take(Self::MAX_LEN).collect())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A change to an existing body requested by gameplay code.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum BodyCommand {
+    /// Replaces the force and torque applied at every step.
+    Force {
+        force: Vect,
+        torque: Vect,
+    },
+    Impulse {
+        impulse: Vect,
+        torque_impulse: Vect,
+    },
+    SetVelocity(Velocity),
+}
+
+/// Whether the client lets its session's world move. Steps of a paused world
+/// are answered with no bodies, as when an operator pauses the session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SimulationState {
+    #[default]
+    Running,
+    Paused,
+}
+
+/// How often a body is included in the step snapshots. Bodies without one
+/// are included in every step.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UpdateRate {
+    #[default]
+    EveryStep,
+    Every2,
+    Every4,
+    /// Only when the body falls asleep or wakes up, for bodies whose exact
+    /// motion doesn't matter.
+    OnSleepChange,
+}
+
+/// A steering behavior the server runs on a body every step, pushing it
+/// horizontally without any client roundtrip.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub enum Controller {
+    Seek {
+        target: Vect,
+        max_speed: Real,
+        max_force: Real,
+    },
+    Wander {
+        max_speed: Real,
+        max_force: Real,
+    },
+    /// Seeks the waypoints in turn, starting over after the last one.
+    Patrol {
+        waypoints: Vec<Vect>,
+        max_speed: Real,
+        max_force: Real,
+    },
+}
+
+/// A joint the server removed because it was pulled harder than its break
+/// force, identified by the physics ids of the bodies it joined.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct JointBreak {
+    pub entity1: u64,
+    pub entity2: u64,
+    pub force: Real,
+}
+
+/// A box of fluid that pushes up and slows down the bodies in it.
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FluidVolume {
+    pub min: Vect,
+    pub max: Vect,
+    /// In the same unit as collider densities, 1 by default.
+    pub density: Real,
+    pub linear_drag: Real,
+    pub angular_drag: Real,
+}
+
+/// A ray cast against the server's world, identified by `id` in the reply.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RayCast {
+    pub id: u64,
+    pub origin: Vect,
+    pub dir: Vect,
+    pub max_toi: Real,
+    pub solid: bool,
+}
+
+/// Where a cast shape first touches a collider, as bevy_rapier's `Toi` puts
+/// it: the witnesses and normals are local to the cast shape and to the
+/// collider, and undefined when the shape started out `penetrating` it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ShapeCastHit {
+    pub toi: Real,
+    pub witness1: Vect,
+    pub witness2: Vect,
+    pub normal1: Vect,
+    pub normal2: Vect,
+    pub penetrating: bool,
+}
+
+impl From<Toi> for ShapeCastHit {
+    fn from(toi: Toi) -> Self {
+        Self {
+            toi: toi.toi,
+            witness1: toi.witness1,
+            witness2: toi.witness2,
+            normal1: toi.normal1,
+            normal2: toi.normal2,
+            penetrating: toi.status == TOIStatus::Penetrating,
+        }
+    }
+}
+
+/// The start or end of a contact between two colliders, one of which has
+/// `ActiveEvents::COLLISION_EVENTS`, identified by the physics ids they were
+/// created with.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CollisionChange {
+    pub entity1: u64,
+    pub entity2: u64,
+    pub started: bool,
+    /// The bits of the event's `CollisionEventFlags`.
+    pub flags: u32,
+}
+
+/// The forces between two colliders, one of which has
+/// `ActiveEvents::CONTACT_FORCE_EVENTS`, during a step where their sum went
+/// over its `ContactForceEventThreshold`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ContactForce {
+    pub entity1: u64,
+    pub entity2: u64,
+    pub total_force: Vect,
+    pub total_force_magnitude: Real,
+    pub max_force_direction: Vect,
+    pub max_force_magnitude: Real,
+}
+
+/// The events bevy_rapier would have sent during the steps since the last
+/// time.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StepEvents {
+    pub collisions: Vec<CollisionChange>,
+    pub contact_forces: Vec<ContactForce>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
     BulkRequest(Vec<Request>),
-    UpdateConfig(SerializableRapierConfiguration),
+    /// Replaces the configuration and the contact rules of the world.
+    UpdateConfig(SerializableRapierConfiguration, hooks::ContactRules),
+    /// Changes some fields of the world's configuration, starting from the
+    /// default one if it has none yet, answered with `ConfigUpdated`.
+    PatchConfig(ConfigPatch),
     CreateBodies(Vec<CreatedBody>),
     CreateColliders(Vec<CreatedCollider>),
+    CreateJoints(Vec<CreatedJoint>),
+    RegisterTemplates(Vec<(u64, BodyTemplate)>),
+    SpawnInstances(Vec<TemplateInstance>),
+    ApplyCommands(Vec<(RigidBodyHandle, BodyCommand)>),
+    /// The `ExternalForce` and `ExternalImpulse` components of bodies that
+    /// changed. A force replaces the one applied at every step, an impulse is
+    /// applied once.
+    ApplyForces(
+        Vec<(
+            RigidBodyHandle,
+            Option<SerializableExternalForce>,
+            Option<SerializableExternalImpulse>,
+        )>,
+    ),
+    /// Steps the world by the given seconds. Optional on servers that step
+    /// every world at their tick rate on their own.
     SimulateStep(f32),
+    GetState,
+    CastRays(Vec<RayCast>),
+    /// Casts a single ray through the colliders the filter lets through,
+    /// answered with `RayHit`.
+    CastRay {
+        origin: Vect,
+        dir: Vect,
+        max_toi: Real,
+        solid: bool,
+        filter: SerializableQueryFilter,
+    },
+    /// Casts a shape moving at `shape_vel` through the colliders the filter
+    /// lets through, answered with `ShapeHit`.
+    CastShape {
+        shape_pos: Vect,
+        shape_rot: Quat,
+        shape_vel: Vect,
+        shape: SharedShape,
+        max_toi: Real,
+        filter: SerializableQueryFilter,
+    },
+    /// Answered with `ShapeIntersections`, the colliders the filter lets
+    /// through that intersect the shape.
+    IntersectionsWithShape {
+        shape_pos: Vect,
+        shape_rot: Quat,
+        shape: SharedShape,
+        filter: SerializableQueryFilter,
+    },
+    /// Fetches the events of the steps since the last time.
+    TakeEvents,
+    SetUpdateRates(Vec<(u64, UpdateRate)>),
+    SetPriorities(Vec<(u64, f32)>),
+    /// Moves the point that bodies closer to are sent first when the server
+    /// has a snapshot budget.
+    SetFocus(Vect),
+    /// Creates the static colliders of a scene file in the server's scenes
+    /// directory. Fails if `hash` is given and isn't the file's content hash.
+    LoadScene {
+        name: String,
+        hash: Option<u64>,
+    },
+    /// Attaches controllers to bodies by physics id, or detaches them if `None`.
+    SetControllers(Vec<(u64, Option<Controller>)>),
+    /// Builds a jointed ragdoll of one body per bone.
+    CreateRagdoll(CreatedRagdoll),
+    CreateRope(CreatedRope),
+    /// Fetches the joint positions of every rope.
+    GetRopes,
+    AddFluidVolumes(Vec<FluidVolume>),
+    /// Fetches the joints broken since the last time.
+    TakeJointBreaks,
+    /// Steps a body made from the template, thrown from `origin` at
+    /// `velocity`, in a throwaway copy of the world's fixed colliders,
+    /// answered with its position after every step.
+    PredictTrajectory {
+        body_template: BodyTemplate,
+        origin: Vect,
+        velocity: Vect,
+        steps: u32,
+    },
+    /// Answered with `reply_len` bytes, to measure round trips and bandwidth.
+    Ping {
+        payload: Vec<u8>,
+        reply_len: usize,
+    },
+    /// Answered with the server's timestamps, the client's time of sending in
+    /// microseconds since the Unix epoch echoed back.
+    TimeSync(u64),
+    /// Measures the server's step time of a throwaway world of the given
+    /// number of bodies.
+    MeasureStep(usize),
+    /// Drops every body and collider the client created, keeping the scene and
+    /// fluid volumes, so that the world can be created again from scratch.
+    ResetWorld,
+    SetSimulationState(SimulationState),
+    /// Has the server step the world every period on its own and push the
+    /// results, until set to `None`. Steps the client asks for still run.
+    SetStreaming(Option<Duration>),
+    /// Answered with a copy of the whole world. Ropes and controllers aren't
+    /// part of it, their bodies being restored as plain ones.
+    TakeSnapshot,
+    /// Replaces the world with a copy taken earlier, dropping what the client
+    /// created since.
+    RestoreSnapshot(WorldSnapshot),
+    /// Removes the bodies of the given physics ids, with their colliders and
+    /// joints.
+    RemoveBodies(Vec<u64>),
+    /// Removes the colliders of the given physics ids, leaving their bodies.
+    RemoveColliders(Vec<u64>),
+    /// Changes bodies created earlier, waking them up.
+    UpdateBodies(Vec<(RigidBodyHandle, BodyUpdate)>),
+    /// Changes colliders created earlier, waking up their bodies.
+    UpdateColliders(Vec<(ColliderHandle, ColliderUpdate)>),
+    /// Moves the bodies of the given physics ids: kinematic position-based
+    /// bodies get there by the next step, the others are teleported.
+    SetBodyTransforms(Vec<(u64, Isometry<Real>)>),
+    /// Switches to the configuration of a profile, and the restitution of
+    /// colliders created from now on that don't set their own.
+    UseProfile(profile::Profile),
+    /// The server's mean step time since last asked.
+    TakeStepTime,
+    /// Names collision group bits for the server's logs, replacing the
+    /// presets.
+    RegisterLayers(layers::LayerRegistry),
+    /// Sent first with the client's metadata and the framing, encoding and
+    /// compression of the messages after it, answered with the server's
+    /// metadata, the framing, encoding and compression it uses, which are the
+    /// client's unless the server doesn't support them, and what it
+    /// supports.
+    Handshake(
+        metadata::RunMetadata,
+        framing::Framing,
+        codec::Encoding,
+        compression::Compression,
+    ),
+    /// Switches how steps arriving in a burst are run, answered with the
+    /// pacing the session uses from now on.
+    SetStepPacing(pacing::StepPacing),
+    /// Fetches statistics of the world, like how full its handle arenas are.
+    GetStats,
+    /// Moves the world's bodies, colliders and joints to new handles without
+    /// free slots between them, answered with the new handles. Requests sent
+    /// before the answer arrives still use the old handles.
+    CompactWorld,
+    /// Overrides the simulated latency, bandwidth and loss of the session,
+    /// if `key` is the one the server was started with.
+    SetImpairment {
+        key: String,
+        impairment: impairment::Impairment,
+    },
+    /// A request that creates something, answered with the response to the
+    /// first request with the same key if the server still remembers it, so
+    /// that sending it again doesn't create duplicates.
+    Idempotent {
+        key: u64,
+        request: Box<Request>,
+    },
+    /// Creates an empty world next to the session's first one, of an id the
+    /// client picks so that it can send requests to it right away.
+    CreateWorld(u64),
+    /// Drops a world created with `CreateWorld`, with everything in it.
+    DestroyWorld(u64),
+    /// A request acting on a world created with `CreateWorld` rather than on
+    /// the session's first one, answered with its response in
+    /// `Response::InWorld`.
+    InWorld {
+        world_id: u64,
+        request: Box<Request>,
+    },
+    /// Requests applied all or none: if one fails, the world is put back as it
+    /// was before the first, so that compound objects are never left half
+    /// created. Only requests `transaction::allowed` lets through can be part
+    /// of one.
+    Transaction(Vec<Request>),
 }
 
 impl Request {
     pub fn name(&self) -> &'static str {
         match self {
             Self::BulkRequest(_) => "BulkRequest",
-            Self::UpdateConfig(_) => "UpdateConfig",
+            Self::UpdateConfig(..) => "UpdateConfig",
+            Self::PatchConfig(_) => "PatchConfig",
             Self::CreateBodies(_) => "CreateBodies",
             Self::CreateColliders(_) => "CreateColliders",
+            Self::CreateJoints(_) => "CreateJoints",
+            Self::RegisterTemplates(_) => "RegisterTemplates",
+            Self::SpawnInstances(_) => "SpawnInstances",
+            Self::ApplyCommands(_) => "ApplyCommands",
+            Self::ApplyForces(_) => "ApplyForces",
             Self::SimulateStep(_) => "SimulateStep",
+            Self::GetState => "GetState",
+            Self::CastRays(_) => "CastRays",
+            Self::CastRay { .. } => "CastRay",
+            Self::CastShape { .. } => "CastShape",
+            Self::IntersectionsWithShape { .. } => "IntersectionsWithShape",
+            Self::TakeEvents => "TakeEvents",
+            Self::SetUpdateRates(_) => "SetUpdateRates",
+            Self::SetPriorities(_) => "SetPriorities",
+            Self::SetFocus(_) => "SetFocus",
+            Self::LoadScene { .. } => "LoadScene",
+            Self::SetControllers(_) => "SetControllers",
+            Self::CreateRagdoll(_) => "CreateRagdoll",
+            Self::CreateRope(_) => "CreateRope",
+            Self::GetRopes => "GetRopes",
+            Self::AddFluidVolumes(_) => "AddFluidVolumes",
+            Self::TakeJointBreaks => "TakeJointBreaks",
+            Self::PredictTrajectory { .. } => "PredictTrajectory",
+            Self::Ping { .. } => "Ping",
+            Self::TimeSync(_) => "TimeSync",
+            Self::MeasureStep(_) => "MeasureStep",
+            Self::ResetWorld => "ResetWorld",
+            Self::SetSimulationState(_) => "SetSimulationState",
+            Self::SetStreaming(_) => "SetStreaming",
+            Self::TakeSnapshot => "TakeSnapshot",
+            Self::RestoreSnapshot(_) => "RestoreSnapshot",
+            Self::RemoveBodies(_) => "RemoveBodies",
+            Self::RemoveColliders(_) => "RemoveColliders",
+            Self::UpdateBodies(_) => "UpdateBodies",
+            Self::UpdateColliders(_) => "UpdateColliders",
+            Self::SetBodyTransforms(_) => "SetBodyTransforms",
+            Self::UseProfile(_) => "UseProfile",
+            Self::TakeStepTime => "TakeStepTime",
+            Self::RegisterLayers(_) => "RegisterLayers",
+            Self::Handshake(..) => "Handshake",
+            Self::SetStepPacing(_) => "SetStepPacing",
+            Self::GetStats => "GetStats",
+            Self::CompactWorld => "CompactWorld",
+            Self::SetImpairment { .. } => "SetImpairment",
+            Self::Idempotent { .. } => "Idempotent",
+            Self::CreateWorld(_) => "CreateWorld",
+            Self::DestroyWorld(_) => "DestroyWorld",
+            Self::InWorld { .. } => "InWorld",
+            Self::Transaction(_) => "Transaction",
         }
     }
 }
 
+// Maps are sent as lists of pairs, which is the same in bincode but lets
+// handles be keys in JSON
+#[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
     BulkResponse(Vec<Response>),
+    /// A response the server lowered the quality of, only sent when it did.
+    Degraded(degradation::Degradation, Box<Response>),
+    /// A step's response in a session that paces its steps.
+    Paced(pacing::PacingReport, Box<Response>),
+    /// The first response after an operator paused, resumed or rescaled the
+    /// session's clock.
+    Status(operator::SessionStatus, Box<Response>),
+    /// The result of a step the server ran on its own while streaming, sent
+    /// unasked on the snapshots channel.
+    Pushed(Box<Response>),
+    /// The response of a world created with `CreateWorld`, by its id, or
+    /// why the request didn't reach it.
+    InWorld(u64, Result<Box<Response>, worlds::WorldError>),
     ConfigUpdated,
     RigidBodyHandles(Vec<(u64, RigidBodyHandle)>),
     ColliderHandles(Vec<(u64, ColliderHandle)>),
-    SimulationResult(HashMap<RigidBodyHandle, (Transform, Velocity)>),
+    /// Without the joints whose bodies are gone, or that would close a loop
+    /// of multibody joints.
+    JointHandles(Vec<(u64, JointHandle)>),
+    TemplatesRegistered,
+    /// The handles of the instances spawned, and the physics ids of those
+    /// left out with the ids of their templates, which the server doesn't
+    /// know, for them to be registered again.
+    InstanceHandles(Vec<(u64, RigidBodyHandle, ColliderHandle)>, Vec<(u64, u64)>),
+    CommandsApplied,
+    ForcesApplied,
+    /// The bodies of the step, the physics ids of every pair of colliders
+    /// where a sensor intersects the other one, and the bodies that fell
+    /// asleep. Sleeping bodies are left out once they were reported asleep,
+    /// so the bodies of the step that aren't in the last list are awake.
+    SimulationResult(
+        #[serde_as(as = "Vec<(_, _)>")] HashMap<RigidBodyHandle, (Transform, Velocity)>,
+        Vec<(u64, u64)>,
+        Vec<RigidBodyHandle>,
+    ),
+    State(WorldState),
+    /// The physics id and time of impact of every ray cast's hit, by ray id.
+    RayHits(Vec<(u64, Option<(u64, Real)>)>),
+    /// The physics id, time of impact and normal of a single ray cast's hit.
+    RayHit(Option<(u64, Real, Vect)>),
+    /// The physics id of the collider a cast shape hit first, and where.
+    ShapeHit(Option<(u64, ShapeCastHit)>),
+    /// The physics ids of the colliders intersecting a shape.
+    ShapeIntersections(Vec<u64>),
+    Events(StepEvents),
+    UpdateRatesSet,
+    PrioritiesSet,
+    FocusSet,
+    /// The handle and layout of every collider of the scene, for rendering.
+    SceneLoaded(Result<Vec<(ColliderHandle, SceneCollider)>, SceneError>),
+    ControllersSet,
+    RagdollHandles(RagdollHandles),
+    RopeCreated,
+    Ropes(Vec<RopeSnapshot>),
+    FluidVolumesAdded,
+    JointBreaks(Vec<JointBreak>),
+    Trajectory(Vec<Vect>),
+    Pong(Vec<u8>),
+    TimeSync(clock::TimeSample),
+    StepMeasured(Duration),
+    WorldReset,
+    SimulationStateSet,
+    StreamingSet,
+    /// The copy of the world, or why it couldn't be encoded.
+    Snapshot(Result<WorldSnapshot, String>),
+    /// The number of bodies restored, or why the snapshot couldn't be read.
+    SnapshotRestored(Result<usize, String>),
+    BodiesRemoved,
+    CollidersRemoved,
+    BodiesUpdated,
+    CollidersUpdated,
+    BodyTransformsSet,
+    /// Zero if the world wasn't stepped since last asked.
+    StepTime(Duration),
+    LayersRegistered,
+    Handshake(
+        metadata::RunMetadata,
+        framing::Framing,
+        codec::Encoding,
+        compression::Compression,
+        capabilities::Capabilities,
+    ),
+    StepPacingSet(pacing::StepPacing),
+    Stats(arena::WorldStats),
+    WorldCompacted(arena::CompactedWorld),
+    /// The impairment the session uses from now on.
+    ImpairmentSet(Result<impairment::Impairment, impairment::ImpairmentError>),
+    WorldCreated(Result<u64, worlds::WorldError>),
+    WorldDestroyed(Result<u64, worlds::WorldError>),
+    /// The responses of a transaction's requests, or why it was rolled back.
+    Transaction(Result<Vec<Response>, transaction::TransactionError>),
+    /// Why the request wasn't handled, in a room where it would act on what
+    /// another member owns or on the whole room.
+    Forbidden(String),
 }
 
 impl Response {
     pub fn name(&self) -> &'static str {
         match self {
             Self::BulkResponse(_) => "BulkResponse",
+            Self::Degraded(..) => "Degraded",
+            Self::Status(..) => "Status",
+            Self::Paced(..) => "Paced",
+            Self::Pushed(_) => "Pushed",
+            Self::InWorld(..) => "InWorld",
             Self::ConfigUpdated => "ConfigUpdated",
             Self::RigidBodyHandles(_) => "RigidBodyHandles",
             Self::ColliderHandles(_) => "ColliderHandles",
-            Self::SimulationResult(_) => "SimulationResult",
+            Self::JointHandles(_) => "JointHandles",
+            Self::TemplatesRegistered => "TemplatesRegistered",
+            Self::InstanceHandles(..) => "InstanceHandles",
+            Self::CommandsApplied => "CommandsApplied",
+            Self::ForcesApplied => "ForcesApplied",
+            Self::SimulationResult(..) => "SimulationResult",
+            Self::State(_) => "State",
+            Self::RayHits(_) => "RayHits",
+            Self::RayHit(_) => "RayHit",
+            Self::ShapeHit(_) => "ShapeHit",
+            Self::ShapeIntersections(_) => "ShapeIntersections",
+            Self::Events(_) => "Events",
+            Self::UpdateRatesSet => "UpdateRatesSet",
+            Self::PrioritiesSet => "PrioritiesSet",
+            Self::FocusSet => "FocusSet",
+            Self::SceneLoaded(_) => "SceneLoaded",
+            Self::ControllersSet => "ControllersSet",
+            Self::RagdollHandles(_) => "RagdollHandles",
+            Self::RopeCreated => "RopeCreated",
+            Self::Ropes(_) => "Ropes",
+            Self::FluidVolumesAdded => "FluidVolumesAdded",
+            Self::JointBreaks(_) => "JointBreaks",
+            Self::Trajectory(_) => "Trajectory",
+            Self::Pong(_) => "Pong",
+            Self::TimeSync(_) => "TimeSync",
+            Self::StepMeasured(_) => "StepMeasured",
+            Self::WorldReset => "WorldReset",
+            Self::SimulationStateSet => "SimulationStateSet",
+            Self::StreamingSet => "StreamingSet",
+            Self::Snapshot(_) => "Snapshot",
+            Self::SnapshotRestored(_) => "SnapshotRestored",
+            Self::BodiesRemoved => "BodiesRemoved",
+            Self::CollidersRemoved => "CollidersRemoved",
+            Self::BodiesUpdated => "BodiesUpdated",
+            Self::CollidersUpdated => "CollidersUpdated",
+            Self::BodyTransformsSet => "BodyTransformsSet",
+            Self::StepTime(_) => "StepTime",
+            Self::LayersRegistered => "LayersRegistered",
+            Self::Handshake(..) => "Handshake",
+            Self::StepPacingSet(_) => "StepPacingSet",
+            Self::Stats(_) => "Stats",
+            Self::WorldCompacted(_) => "WorldCompacted",
+            Self::ImpairmentSet(_) => "ImpairmentSet",
+            Self::WorldCreated(_) => "WorldCreated",
+            Self::WorldDestroyed(_) => "WorldDestroyed",
+            Self::Transaction(_) => "Transaction",
+            Self::Forbidden(_) => "Forbidden",
         }
     }
 }
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::scene::content_hash;
+
+/// What a run of a program was built from and started with, written in front
+/// of its logs and result files so that they can be told apart and compared
+/// long after.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunMetadata {
+    pub program: String,
+    pub version: String,
+    /// `None` if the program doesn't run from a git checkout.
+    pub git_commit: Option<String>,
+    /// The cargo features the program was built with.
+    pub features: Vec<String>,
+    pub args: Vec<String>,
+    /// A hash of the arguments, equal for runs of the same scenario.
+    pub scenario_hash: u64,
+    /// The metadata the other end sent in the handshake, the server's for a
+    /// client.
+    pub peer: Option<Box<RunMetadata>>,
+}
+
+impl RunMetadata {
+    /// The metadata of the running program, given the features it was built
+    /// with.
+    pub fn collect(program: &str, version: &str, features: &[&str]) -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        Self {
+            program: program.to_string(),
+            version: version.to_string(),
+            git_commit: git_commit(),
+            features: features.iter().map(|feature| feature.to_string()).collect(),
+            scenario_hash: content_hash(args.join(" ").as_bytes()),
+            args,
+            peer: None,
+        }
+    }
+
+    /// One `key: value` line per field, the peer's prefixed with `peer`.
+    pub fn preamble(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("program: {} {}", self.program, self.version),
+            format!(
+                "git_commit: {}",
+                self.git_commit.as_deref().unwrap_or("unknown")
+            ),
+            format!("features: {}", self.features.join(" ")),
+            format!("args: {}", self.args.join(" ")),
+            format!("scenario_hash: {:016x}", self.scenario_hash),
+        ];
+        if let Some(peer) = &self.peer {
+            lines.extend(
+                peer.preamble()
+                    .into_iter()
+                    .map(|line| format!("peer {}", line)),
+            );
+        }
+        lines
+    }
+}
+
+/// The preamble on one line, for logs.
+impl fmt::Display for RunMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.preamble().join("; "))
+    }
+}
+
+/// The commit of the checkout the program was built in, marked `-dirty` if it
+/// has uncommitted changes.
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["describe", "--always", "--dirty"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,96 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Appends comma separated rows to a file, writing the header first if the
+/// file is new or empty. A file with another header, written by a build with
+/// other columns, is renamed with the current Unix time appended rather than
+/// appended to.
+pub struct CsvWriter {
+    file: File,
+}
+
+impl CsvWriter {
+    pub fn open<P: AsRef<Path>>(path: P, header: &[&str]) -> io::Result<Self> {
+        Self::open_with_preamble(path, header, &[])
+    }
+
+    /// Like `open`, writing every line of `preamble` as a `#` comment in front
+    /// of the rows of this run, before the header if the file is new.
+    pub fn open_with_preamble<P: AsRef<Path>>(
+        path: P,
+        header: &[&str],
+        preamble: &[String],
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let header = header.join(",");
+        let existing = existing_header(path)?;
+        if existing
+            .as_ref()
+            .is_some_and(|existing| *existing != header)
+        {
+            fs::rename(path, rotated_path(path))?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        for line in preamble {
+            writeln!(file, "# {}", line)?;
+        }
+        if existing.as_ref() != Some(&header) {
+            writeln!(file, "{}", header)?;
+        }
+        Ok(Self { file })
+    }
+
+    pub fn write_row(&mut self, row: &[String]) -> io::Result<()> {
+        writeln!(self.file, "{}", row.join(","))?;
+        self.file.flush()
+    }
+}
+
+/// The header of the file, its first line that isn't a comment, if it exists
+/// and has one.
+fn existing_header(path: &Path) -> io::Result<Option<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.starts_with('#') {
+            return Ok(Some(line));
+        }
+    }
+    Ok(None)
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", seconds));
+    PathBuf::from(name)
+}
+
+/// Returns the `p`th percentile (0.0..=1.0) of already sorted samples.
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
+
+pub fn millis(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64() * 1000.0)
+}
+
+pub fn unix_timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{:.3}", now.as_secs_f64())
+}
@@ -0,0 +1,37 @@
+use bevy_rapier3d::{
+    prelude::*,
+    rapier::prelude::{ColliderHandle, Isometry, RigidBodyHandle},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirroredBody {
+    pub handle: RigidBodyHandle,
+    pub id: u64,
+    pub body: RigidBody,
+    pub position: Isometry<Real>,
+    /// In physics units, like the position.
+    pub linvel: Vect,
+    pub angvel: Vect,
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirroredCollider {
+    pub handle: ColliderHandle,
+    pub id: u64,
+    pub parent: Option<RigidBodyHandle>,
+    /// Relative to the parent body if there is one, in world space otherwise.
+    pub position: Isometry<Real>,
+    pub shape: Collider,
+    pub sensor: bool,
+}
+
+/// The authoritative bodies and colliders of a session, sent on request so
+/// the client can keep an approximate copy of the world.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorldState {
+    pub bodies: Vec<MirroredBody>,
+    pub colliders: Vec<MirroredCollider>,
+}
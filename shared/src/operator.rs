@@ -0,0 +1,33 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// How an operator set a session's clock, paused too while the client pauses
+/// it, sent to its client along with the first response after it changes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SessionStatus {
+    /// Steps leave the world as it is.
+    pub paused: bool,
+    /// The simulated time per second of step time the client asks for, below
+    /// 1 slowing the world down and above 1 fast-forwarding it.
+    pub time_scale: f32,
+}
+
+impl Default for SessionStatus {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            time_scale: 1.0,
+        }
+    }
+}
+
+impl fmt::Display for SessionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.paused {
+            write!(f, "paused")
+        } else {
+            write!(f, "running at {}x", self.time_scale)
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How the server runs steps that arrive in a burst, e.g. after a latency
+/// spike, instead of fast-forwarding through them back to back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StepPacing {
+    /// Every step runs as soon as it arrives.
+    #[default]
+    Immediate,
+    /// At most `max_steps` steps run per `interval`, later ones waiting for
+    /// the next interval.
+    Capped { max_steps: u32, interval: Duration },
+    /// Steps arriving within `min_interval` of the previous one don't run,
+    /// their time being added to the next step that does.
+    Collapsed { min_interval: Duration },
+}
+
+impl StepPacing {
+    /// Parses `immediate`, `cap:<STEPS>/<MILLISECONDS>` or
+    /// `collapse:<MILLISECONDS>`.
+    pub fn parse(pacing: &str) -> Result<Self, String> {
+        let millis = |millis: &str| {
+            millis
+                .parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_| format!("expected milliseconds, got {}", millis))
+        };
+        if pacing == "immediate" {
+            Ok(Self::Immediate)
+        } else if let Some(cap) = pacing.strip_prefix("cap:") {
+            let (steps, interval) = cap
+                .split_once('/')
+                .ok_or_else(|| format!("expected cap:<STEPS>/<MILLISECONDS>, got {}", pacing))?;
+            let max_steps = steps
+                .parse::<u32>()
+                .ok()
+                .filter(|&steps| steps > 0)
+                .ok_or_else(|| format!("expected a positive number of steps, got {}", steps))?;
+            Ok(Self::Capped {
+                max_steps,
+                interval: millis(interval)?,
+            })
+        } else if let Some(min_interval) = pacing.strip_prefix("collapse:") {
+            Ok(Self::Collapsed {
+                min_interval: millis(min_interval)?,
+            })
+        } else {
+            Err(format!(
+                "expected immediate, cap:<STEPS>/<MILLISECONDS> or collapse:<MILLISECONDS>, got {}",
+                pacing
+            ))
+        }
+    }
+}
+
+/// Formatted the way `parse` reads it.
+impl fmt::Display for StepPacing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Immediate => write!(f, "immediate"),
+            Self::Capped {
+                max_steps,
+                interval,
+            } => write!(f, "cap:{}/{}", max_steps, interval.as_millis()),
+            Self::Collapsed { min_interval } => write!(f, "collapse:{}", min_interval.as_millis()),
+        }
+    }
+}
+
+/// What pacing did to a step, sent along with its result unless the session
+/// runs steps immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacingReport {
+    pub pacing: StepPacing,
+    /// Whether the step didn't run, its time being added to the next one. Its
+    /// result is empty.
+    pub deferred: bool,
+    /// How many deferred steps the step made up for.
+    pub caught_up: u32,
+    /// How long the step waited for its turn.
+    pub delay: Duration,
+}
@@ -0,0 +1,36 @@
+use bevy_rapier3d::{prelude::*, rapier::prelude::Isometry};
+
+use serde::{Deserialize, Serialize};
+
+/// A slab of space along the x axis owned by one server process.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Region {
+    pub min_x: Real,
+    pub max_x: Real,
+}
+
+impl Region {
+    pub fn owns(&self, x: Real) -> bool {
+        self.min_x <= x && x < self.max_x
+    }
+}
+
+/// Everything needed to recreate a body on another process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyState {
+    pub id: u64,
+    pub position: Isometry<Real>,
+    pub linvel: Vect,
+    pub angvel: Vect,
+    pub shape: Collider,
+}
+
+/// Exchanged between neighbouring regions once per tick.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PartitionMessage {
+    /// Bodies that crossed into the receiver's region and are now owned by it.
+    pub handoff: Vec<BodyState>,
+    /// Bodies close enough to the boundary to interact with the receiver's
+    /// bodies; the receiver mirrors them as kinematic ghosts.
+    pub ghosts: Vec<BodyState>,
+}
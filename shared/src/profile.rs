@@ -0,0 +1,66 @@
+use bevy_rapier3d::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::serializable::SerializableRestitution;
+
+/// A named set of physics parameters, so that both ends and their logs refer
+/// to an experiment's setup by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Profile {
+    Earth,
+    Moon,
+    ZeroG,
+    /// Earth gravity with nearly elastic collisions, to keep bodies bouncing
+    /// and the solver busy.
+    Stress,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 4] = [Self::Earth, Self::Moon, Self::ZeroG, Self::Stress];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Earth => "earth",
+            Self::Moon => "moon",
+            Self::ZeroG => "zero-g",
+            Self::Stress => "stress",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|profile| profile.name() == name)
+    }
+
+    pub fn gravity(self) -> Vect {
+        match self {
+            Self::Earth | Self::Stress => Vect::new(0.0, -9.81, 0.0),
+            Self::Moon => Vect::new(0.0, -1.62, 0.0),
+            Self::ZeroG => Vect::ZERO,
+        }
+    }
+
+    pub fn config(self) -> RapierConfiguration {
+        RapierConfiguration {
+            gravity: self.gravity(),
+            ..Default::default()
+        }
+    }
+
+    /// The restitution of colliders that don't set their own.
+    pub fn restitution(self) -> Option<SerializableRestitution> {
+        match self {
+            Self::Stress => Some(SerializableRestitution {
+                coefficient: 0.95,
+                combine_rule: CoefficientCombineRule::Max,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
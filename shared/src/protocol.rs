@@ -0,0 +1,71 @@
+/// The major versions of the protocol, bumped whenever a message changes in a
+/// way that the other end can't read. Servers speak the current and the
+/// previous one, so that clients don't have to upgrade in lockstep with them.
+///
+/// The version is negotiated per connection in the WebSocket handshake, the
+/// client listing the versions it speaks in the `Sec-WebSocket-Protocol`
+/// header and the server answering with the one it picked. Clients that send
+/// no such header predate the negotiation and speak `V1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProtocolVersion {
+    /// Steps are answered with the bodies only.
+    V1,
+    /// Steps are answered with the sensor intersections and the bodies that
+    /// fell asleep too, leaving out those already asleep. Binary messages
+    /// travel in an `envelope`.
+    V2,
+}
+
+impl ProtocolVersion {
+    pub const CURRENT: Self = Self::V2;
+    pub const PREVIOUS: Self = Self::V1;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::V1 => "physics.v1",
+            Self::V2 => "physics.v2",
+        }
+    }
+
+    /// Whether binary messages are sealed in an `envelope`.
+    pub fn seals_messages(self) -> bool {
+        self >= Self::V2
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::V1, Self::V2]
+            .into_iter()
+            .find(|version| version.name() == name)
+    }
+
+    /// The latest version named in a `Sec-WebSocket-Protocol` header, `None`
+    /// if it names none this build speaks.
+    pub fn negotiate(offered: &str) -> Option<Self> {
+        offered
+            .split(',')
+            .filter_map(|name| Self::from_name(name.trim()))
+            .max()
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Of the WebSocket handshake. The server answers every connection with the
+/// id of the session it's served by, which a client that lost its connection
+/// sends back on the next one to resume that session.
+pub const SESSION_ID_HEADER: &str = "x-physics-session";
+/// Answered with `true` when the session asked for was resumed, `false` when
+/// the connection got a new one.
+pub const SESSION_RESUMED_HEADER: &str = "x-physics-session-resumed";
+/// Of the WebSocket handshake, the name of the room whose world the session
+/// shares with the others that joined it, on servers that have rooms.
+pub const ROOM_HEADER: &str = "x-physics-room";
+/// Answered with the member number of the session in the room it joined.
+pub const ROOM_MEMBER_HEADER: &str = "x-physics-room-member";
+/// Members of a room number their physics ids from their member number
+/// shifted by this, so that they don't create bodies of the same id.
+pub const ROOM_MEMBER_ID_SHIFT: u32 = 40;
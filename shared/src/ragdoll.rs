@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use bevy_rapier3d::{
+    prelude::*,
+    rapier::prelude::{ColliderHandle, Isometry, RigidBodyHandle},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How a bone is jointed to its parent.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum BoneJoint {
+    /// Swings up to `swing` radians away from the parent in any direction and
+    /// twists up to `twist` radians around itself.
+    Spherical { swing: Real, twist: Real },
+    /// Bends around `axis`, given in the skeleton's space, within `limits`.
+    Revolute { axis: Vect, limits: [Real; 2] },
+}
+
+/// A capsule shaped bone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bone {
+    /// The index of an earlier bone this one hangs from, `None` for the root.
+    pub parent: Option<usize>,
+    /// Where along the parent the bone starts, from 0 at the parent's start
+    /// to 1 at its tip.
+    pub anchor: Real,
+    /// The direction from the bone's start to its tip in the skeleton's space.
+    pub direction: Vect,
+    pub length: Real,
+    pub radius: Real,
+    pub joint: BoneJoint,
+    /// How hard the joint can be pulled before it breaks, never if `None`.
+    pub break_force: Option<Real>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Skeleton {
+    pub bones: Vec<Bone>,
+}
+
+impl Skeleton {
+    /// A humanoid about 1.8 tall, rooted at the pelvis and standing along the y
+    /// axis.
+    pub fn humanoid() -> Self {
+        let spherical = |swing: Real, twist: Real| BoneJoint::Spherical { swing, twist };
+        let knee = BoneJoint::Revolute {
+            axis: Vect::X,
+            limits: [0.0, 2.4],
+        };
+        let elbow = BoneJoint::Revolute {
+            axis: Vect::X,
+            limits: [-2.4, 0.0],
+        };
+        let bone = |parent, anchor, direction: Vect, length, radius, joint| Bone {
+            parent,
+            anchor,
+            direction: direction.normalize(),
+            length,
+            radius,
+            joint,
+            break_force: None,
+        };
+        Self {
+            bones: vec![
+                bone(None, 0.0, Vect::Y, 0.6, 0.15, spherical(0.0, 0.0)),
+                bone(Some(0), 1.0, Vect::Y, 0.25, 0.12, spherical(0.5, 0.7)),
+                bone(
+                    Some(0),
+                    0.9,
+                    Vect::new(1.0, -0.2, 0.0),
+                    0.3,
+                    0.06,
+                    spherical(1.4, 0.7),
+                ),
+                bone(Some(2), 1.0, Vect::NEG_Y, 0.3, 0.05, elbow),
+                bone(
+                    Some(0),
+                    0.9,
+                    Vect::new(-1.0, -0.2, 0.0),
+                    0.3,
+                    0.06,
+                    spherical(1.4, 0.7),
+                ),
+                bone(Some(4), 1.0, Vect::NEG_Y, 0.3, 0.05, elbow),
+                bone(
+                    Some(0),
+                    0.0,
+                    Vect::new(0.2, -1.0, 0.0),
+                    0.45,
+                    0.08,
+                    spherical(1.0, 0.4),
+                ),
+                bone(Some(6), 1.0, Vect::NEG_Y, 0.45, 0.07, knee),
+                bone(
+                    Some(0),
+                    0.0,
+                    Vect::new(-0.2, -1.0, 0.0),
+                    0.45,
+                    0.08,
+                    spherical(1.0, 0.4),
+                ),
+                bone(Some(8), 1.0, Vect::NEG_Y, 0.45, 0.07, knee),
+            ],
+        }
+    }
+
+    /// Where every bone starts in the skeleton's space.
+    pub fn bone_starts(&self) -> Vec<Vect> {
+        let mut starts: Vec<Vect> = Vec::with_capacity(self.bones.len());
+        for bone in &self.bones {
+            let start = match bone
+                .parent
+                .and_then(|parent| self.bones.get(parent).zip(starts.get(parent)))
+            {
+                Some((parent, &parent_start)) => {
+                    parent_start + parent.direction * parent.length * bone.anchor
+                }
+                None => Vect::ZERO,
+            };
+            starts.push(start);
+        }
+        starts
+    }
+
+    /// The center and orientation of every bone in the skeleton's space, with
+    /// the capsules along their local y axis.
+    pub fn bone_transforms(&self) -> Vec<Transform> {
+        self.bones
+            .iter()
+            .zip(self.bone_starts())
+            .map(|(bone, start)| {
+                Transform::from_translation(start + bone.direction * bone.length / 2.0)
+                    .with_rotation(Quat::from_rotation_arc(Vect::Y, bone.direction))
+            })
+            .collect()
+    }
+
+    pub fn bone_collider(bone: &Bone) -> Collider {
+        Collider::capsule_y((bone.length / 2.0 - bone.radius).max(0.0), bone.radius)
+    }
+}
+
+/// A skeleton to build on the server, with the physics id of every bone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedRagdoll {
+    pub ids: Vec<u64>,
+    pub transform: Isometry<Real>,
+    pub skeleton: Skeleton,
+}
+
+/// The body and collider created for every bone, by physics id.
+pub type RagdollHandles = Vec<(u64, RigidBodyHandle, ColliderHandle)>;
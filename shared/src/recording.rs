@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_rapier3d::{prelude::*, rapier::prelude::RigidBodyHandle};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCollider {
+    pub id: u64,
+    pub parent: Option<RigidBodyHandle>,
+    /// Relative to the parent body if there is one, in world space otherwise.
+    pub transform: Transform,
+    pub shape: Collider,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordEntry {
+    Bodies(Vec<(u64, RigidBodyHandle)>),
+    Colliders(Vec<RecordedCollider>),
+    /// Leaves out the bodies that are asleep, of slower update rates or over
+    /// the snapshot budget, as sent to the client.
+    Step(HashMap<RigidBodyHandle, (Transform, Velocity)>),
+    /// The bodies removed since the previous step.
+    Removed(Vec<RigidBodyHandle>),
+}
+
+/// A collider drawn when playing back the snapshots a client recorded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayShape {
+    pub id: u64,
+    /// The entity of the body the collider moves with, which may be its own.
+    pub parent: Option<u64>,
+    /// Relative to the parent body if there is one, in world space otherwise.
+    pub transform: Transform,
+    pub shape: Collider,
+    pub color: Color,
+}
+
+/// What changed in a frame rendered by a client, by entity id. Ticks count
+/// frames from the start of the recording, and frames without changes aren't
+/// recorded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub tick: u64,
+    pub spawned: Vec<ReplayShape>,
+    pub moved: Vec<(u64, Transform)>,
+    pub despawned: Vec<u64>,
+}
+
+/// Appends record entries to a file, one bincode value after another.
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record<T: Serialize>(&mut self, entry: &T) -> bincode::Result<()> {
+        bincode::serialize_into(&mut self.writer, entry)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads every entry of a recording written by [`Recorder`].
+pub fn read_recording<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> bincode::Result<Vec<T>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut entries = vec![];
+    loop {
+        match bincode::deserialize_from(&mut reader) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => match *err {
+                bincode::ErrorKind::Io(ref io_err) if io_err.kind() == ErrorKind::UnexpectedEof => {
+                    break
+                }
+                _ => return Err(err),
+            },
+        }
+    }
+    Ok(entries)
+}
+
+/// Builds a mesh approximating a collider shape: balls are rendered as spheres,
+/// everything else as its bounding box.
+pub fn shape_mesh(shape: &Collider) -> Mesh {
+    if let Some(ball) = shape.raw.as_ball() {
+        return shape::UVSphere {
+            radius: ball.radius,
+            sectors: 18,
+            stacks: 9,
+        }
+        .into();
+    }
+
+    let aabb = shape.raw.compute_local_aabb();
+    shape::Box {
+        min_x: aabb.mins.x,
+        max_x: aabb.maxs.x,
+        min_y: aabb.mins.y,
+        max_y: aabb.maxs.y,
+        min_z: aabb.mins.z,
+        max_z: aabb.maxs.z,
+    }
+    .into()
+}
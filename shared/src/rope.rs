@@ -0,0 +1,77 @@
+use bevy_rapier3d::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+/// What an end of a rope is tied to.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum RopeAnchor {
+    Free,
+    /// Tied to a point of the world that never moves.
+    Fixed(Vect),
+    /// Tied to a body, by physics id, at an offset in the body's space.
+    Body {
+        id: u64,
+        offset: Vect,
+    },
+}
+
+/// A rope or chain of capsule segments jointed end to end.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedRope {
+    pub id: u64,
+    /// Where the rope starts and ends when created, even if tied to something
+    /// elsewhere.
+    pub start: Vect,
+    pub end: Vect,
+    pub start_anchor: RopeAnchor,
+    pub end_anchor: RopeAnchor,
+    pub segments: u32,
+    pub radius: Real,
+    /// How strongly segments resist bending, 0 for a limp rope.
+    pub stiffness: Real,
+    /// How hard a joint of the rope can be pulled before it breaks, never if
+    /// `None`.
+    pub break_force: Option<Real>,
+}
+
+/// The joint positions of a rope, from start to end, quantized relative to
+/// its start: a few bytes per segment instead of a full body snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RopeSnapshot {
+    pub id: u64,
+    pub origin: Vect,
+    /// The size of one quantization step.
+    pub step: Real,
+    pub offsets: Vec<[i16; 3]>,
+}
+
+impl RopeSnapshot {
+    pub fn encode(id: u64, points: &[Vect]) -> Self {
+        let origin = points.first().copied().unwrap_or_default();
+        let extent = points
+            .iter()
+            .map(|&point| (point - origin).abs().max_element())
+            .fold(0.0, Real::max);
+        let step = (extent / i16::MAX as Real).max(Real::EPSILON);
+        let offsets = points
+            .iter()
+            .map(|&point| {
+                let offset = ((point - origin) / step).round();
+                [offset.x as i16, offset.y as i16, offset.z as i16]
+            })
+            .collect();
+        Self {
+            id,
+            origin,
+            step,
+            offsets,
+        }
+    }
+
+    pub fn decode(&self) -> Vec<Vect> {
+        self.offsets
+            .iter()
+            .map(|&[x, y, z]| self.origin + Vect::new(x as Real, y as Real, z as Real) * self.step)
+            .collect()
+    }
+}
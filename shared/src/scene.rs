@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+/// The physics id scene colliders report in ray hits and events, as they
+/// don't belong to any client entity.
+pub const SCENE_ENTITY: u64 = u64::MAX;
+
+/// Hashes the content of a scene file, to check that the client and the
+/// server have the same version of a scene. FNV-1a, so that it's stable
+/// across builds and platforms.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Static level geometry the server loads from its scenes directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Scene {
+    pub colliders: Vec<SceneCollider>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SceneShape {
+    Cuboid {
+        half_extents: Vect,
+    },
+    Ball {
+        radius: Real,
+    },
+    Trimesh {
+        vertices: Vec<Vect>,
+        indices: Vec<[u32; 3]>,
+    },
+}
+
+impl SceneShape {
+    pub fn collider(&self) -> Collider {
+        match self {
+            Self::Cuboid { half_extents } => {
+                Collider::cuboid(half_extents.x, half_extents.y, half_extents.z)
+            }
+            Self::Ball { radius } => Collider::ball(*radius),
+            Self::Trimesh { vertices, indices } => {
+                Collider::trimesh(vertices.clone(), indices.clone())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneCollider {
+    pub shape: SceneShape,
+    #[serde(default)]
+    pub translation: Vect,
+    #[serde(default)]
+    pub rotation: Quat,
+    #[serde(default)]
+    pub friction: Option<Real>,
+    #[serde(default)]
+    pub restitution: Option<Real>,
+}
+
+impl SceneCollider {
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.translation).with_rotation(self.rotation)
+    }
+}
+
+/// Why the server couldn't load a scene.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SceneError {
+    NotFound(String),
+    Invalid(String),
+    /// The client's copy of the scene differs from the server's.
+    HashMismatch {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(name) => write!(f, "scene {} not found", name),
+            Self::Invalid(error) => write!(f, "invalid scene: {}", error),
+            Self::HashMismatch { expected, actual } => write!(
+                f,
+                "scene version mismatch, client has {:016x} but server has {:016x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
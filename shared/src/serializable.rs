@@ -1,4 +1,6 @@
+use bevy::prelude::Entity;
 use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::prelude::{JointAxesMask, JointAxis};
 
 use serde::{Deserialize, Serialize};
 
@@ -154,6 +156,144 @@ impl From<SerializableRestitution> for Restitution {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializableCollisionGroups {
+    pub memberships: u32,
+    pub filters: u32,
+}
+
+impl From<CollisionGroups> for SerializableCollisionGroups {
+    fn from(groups: CollisionGroups) -> Self {
+        Self {
+            memberships: groups.memberships.bits(),
+            filters: groups.filters.bits(),
+        }
+    }
+}
+
+impl From<SerializableCollisionGroups> for CollisionGroups {
+    fn from(groups: SerializableCollisionGroups) -> Self {
+        Self {
+            memberships: Group::from_bits_truncate(groups.memberships),
+            filters: Group::from_bits_truncate(groups.filters),
+        }
+    }
+}
+
+/// The `ActiveHooks` bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializableActiveHooks(pub u32);
+
+impl From<ActiveHooks> for SerializableActiveHooks {
+    fn from(hooks: ActiveHooks) -> Self {
+        Self(hooks.bits())
+    }
+}
+
+impl From<SerializableActiveHooks> for ActiveHooks {
+    fn from(hooks: SerializableActiveHooks) -> Self {
+        Self::from_bits_truncate(hooks.0)
+    }
+}
+
+/// The `ActiveCollisionTypes` bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializableActiveCollisionTypes(pub u16);
+
+impl From<ActiveCollisionTypes> for SerializableActiveCollisionTypes {
+    fn from(types: ActiveCollisionTypes) -> Self {
+        Self(types.bits())
+    }
+}
+
+impl From<SerializableActiveCollisionTypes> for ActiveCollisionTypes {
+    fn from(types: SerializableActiveCollisionTypes) -> Self {
+        Self::from_bits_truncate(types.0)
+    }
+}
+
+/// A `QueryFilter` without its predicate, the excluded collider and body
+/// identified by the bits of their entities, which clients replace with their
+/// physics ids when sending the filter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializableQueryFilter {
+    pub flags: u32,
+    pub groups: Option<SerializableCollisionGroups>,
+    pub exclude_collider: Option<u64>,
+    pub exclude_rigid_body: Option<u64>,
+}
+
+impl From<QueryFilter<'_>> for SerializableQueryFilter {
+    fn from(filter: QueryFilter) -> Self {
+        Self {
+            flags: filter.flags.bits(),
+            groups: filter.groups.map(Into::into),
+            exclude_collider: filter.exclude_collider.map(Entity::to_bits),
+            exclude_rigid_body: filter.exclude_rigid_body.map(Entity::to_bits),
+        }
+    }
+}
+
+impl From<SerializableQueryFilter> for QueryFilter<'_> {
+    fn from(filter: SerializableQueryFilter) -> Self {
+        Self {
+            flags: QueryFilterFlags::from_bits_truncate(filter.flags),
+            groups: filter.groups.map(Into::into),
+            exclude_collider: filter.exclude_collider.map(Entity::from_bits),
+            exclude_rigid_body: filter.exclude_rigid_body.map(Entity::from_bits),
+            predicate: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializableSolverGroups {
+    pub memberships: u32,
+    pub filters: u32,
+}
+
+impl From<SolverGroups> for SerializableSolverGroups {
+    fn from(groups: SolverGroups) -> Self {
+        Self {
+            memberships: groups.memberships.bits(),
+            filters: groups.filters.bits(),
+        }
+    }
+}
+
+impl From<SerializableSolverGroups> for SolverGroups {
+    fn from(groups: SerializableSolverGroups) -> Self {
+        Self {
+            memberships: Group::from_bits_truncate(groups.memberships),
+            filters: Group::from_bits_truncate(groups.filters),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SerializableDamping {
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+}
+
+impl From<Damping> for SerializableDamping {
+    fn from(damping: Damping) -> Self {
+        Self {
+            linear_damping: damping.linear_damping,
+            angular_damping: damping.angular_damping,
+        }
+    }
+}
+
+impl From<SerializableDamping> for Damping {
+    fn from(damping: SerializableDamping) -> Self {
+        Self {
+            linear_damping: damping.linear_damping,
+            angular_damping: damping.angular_damping,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SerializableTimestepMode {
     Fixed {
@@ -259,3 +399,216 @@ impl From<SerializableRapierConfiguration> for RapierConfiguration {
         }
     }
 }
+
+/// Some fields of a `RapierConfiguration`, to change without sending the
+/// others. The fields left `None` keep their value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigPatch {
+    pub gravity: Option<Vect>,
+    pub physics_pipeline_active: Option<bool>,
+    pub query_pipeline_active: Option<bool>,
+    pub timestep_mode: Option<SerializableTimestepMode>,
+    pub scaled_shape_subdivision: Option<u32>,
+    pub force_update_from_transform_changes: Option<bool>,
+}
+
+impl ConfigPatch {
+    pub fn is_empty(&self) -> bool {
+        self.gravity.is_none()
+            && self.physics_pipeline_active.is_none()
+            && self.query_pipeline_active.is_none()
+            && self.timestep_mode.is_none()
+            && self.scaled_shape_subdivision.is_none()
+            && self.force_update_from_transform_changes.is_none()
+    }
+
+    /// Adds the fields `other` sets, which take over those both set.
+    pub fn merge(&mut self, other: ConfigPatch) {
+        self.gravity = other.gravity.or(self.gravity);
+        self.physics_pipeline_active = other
+            .physics_pipeline_active
+            .or(self.physics_pipeline_active);
+        self.query_pipeline_active = other.query_pipeline_active.or(self.query_pipeline_active);
+        self.timestep_mode = other.timestep_mode.or(self.timestep_mode.take());
+        self.scaled_shape_subdivision = other
+            .scaled_shape_subdivision
+            .or(self.scaled_shape_subdivision);
+        self.force_update_from_transform_changes = other
+            .force_update_from_transform_changes
+            .or(self.force_update_from_transform_changes);
+    }
+
+    pub fn apply(self, config: &mut RapierConfiguration) {
+        if let Some(gravity) = self.gravity {
+            config.gravity = gravity;
+        }
+        if let Some(active) = self.physics_pipeline_active {
+            config.physics_pipeline_active = active;
+        }
+        if let Some(active) = self.query_pipeline_active {
+            config.query_pipeline_active = active;
+        }
+        if let Some(mode) = self.timestep_mode {
+            config.timestep_mode = mode.into();
+        }
+        if let Some(subdivision) = self.scaled_shape_subdivision {
+            config.scaled_shape_subdivision = subdivision;
+        }
+        if let Some(force) = self.force_update_from_transform_changes {
+            config.force_update_from_transform_changes = force;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SerializableExternalForce {
+    pub force: Vect,
+    pub torque: Vect,
+}
+
+impl From<ExternalForce> for SerializableExternalForce {
+    fn from(force: ExternalForce) -> Self {
+        Self {
+            force: force.force,
+            torque: force.torque,
+        }
+    }
+}
+
+impl From<SerializableExternalForce> for ExternalForce {
+    fn from(force: SerializableExternalForce) -> Self {
+        Self {
+            force: force.force,
+            torque: force.torque,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SerializableExternalImpulse {
+    pub impulse: Vect,
+    pub torque_impulse: Vect,
+}
+
+impl From<ExternalImpulse> for SerializableExternalImpulse {
+    fn from(impulse: ExternalImpulse) -> Self {
+        Self {
+            impulse: impulse.impulse,
+            torque_impulse: impulse.torque_impulse,
+        }
+    }
+}
+
+impl From<SerializableExternalImpulse> for ExternalImpulse {
+    fn from(impulse: SerializableExternalImpulse) -> Self {
+        Self {
+            impulse: impulse.impulse,
+            torque_impulse: impulse.torque_impulse,
+        }
+    }
+}
+
+/// The joints that can be sent, told apart by their locked axes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SerializableJointKind {
+    Fixed,
+    /// Turns around the x axis of its frames.
+    Revolute {
+        limits: Option<[Real; 2]>,
+    },
+    /// Slides along the x axis of its frames.
+    Prismatic {
+        limits: Option<[Real; 2]>,
+    },
+    /// With the limits of the x, y and z angles.
+    Spherical {
+        limits: [Option<[Real; 2]>; 3],
+    },
+}
+
+/// A joint without its motors, which aren't sent.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SerializableJoint {
+    pub kind: SerializableJointKind,
+    pub local_anchor1: Vect,
+    pub local_basis1: bevy_rapier3d::math::Rot,
+    pub local_anchor2: Vect,
+    pub local_basis2: bevy_rapier3d::math::Rot,
+    pub contacts_enabled: bool,
+}
+
+/// Fails with the locked axes of joints of other kinds.
+impl TryFrom<&GenericJoint> for SerializableJoint {
+    type Error = JointAxesMask;
+
+    fn try_from(joint: &GenericJoint) -> Result<Self, Self::Error> {
+        let limits = |axis| joint.limits(axis).map(|limits| [limits.min, limits.max]);
+        let locked_axes = joint.locked_axes();
+        let kind = if locked_axes == JointAxesMask::LOCKED_FIXED_AXES {
+            SerializableJointKind::Fixed
+        } else if locked_axes == JointAxesMask::LOCKED_REVOLUTE_AXES {
+            SerializableJointKind::Revolute {
+                limits: limits(JointAxis::AngX),
+            }
+        } else if locked_axes == JointAxesMask::LOCKED_PRISMATIC_AXES {
+            SerializableJointKind::Prismatic {
+                limits: limits(JointAxis::X),
+            }
+        } else if locked_axes == JointAxesMask::LOCKED_SPHERICAL_AXES {
+            SerializableJointKind::Spherical {
+                limits: [
+                    limits(JointAxis::AngX),
+                    limits(JointAxis::AngY),
+                    limits(JointAxis::AngZ),
+                ],
+            }
+        } else {
+            return Err(locked_axes);
+        };
+        Ok(Self {
+            kind,
+            local_anchor1: joint.local_anchor1(),
+            local_basis1: joint.local_basis1(),
+            local_anchor2: joint.local_anchor2(),
+            local_basis2: joint.local_basis2(),
+            contacts_enabled: joint.contacts_enabled(),
+        })
+    }
+}
+
+impl From<SerializableJoint> for GenericJoint {
+    fn from(joint: SerializableJoint) -> Self {
+        let (locked_axes, limits) = match joint.kind {
+            SerializableJointKind::Fixed => (JointAxesMask::LOCKED_FIXED_AXES, vec![]),
+            SerializableJointKind::Revolute { limits } => (
+                JointAxesMask::LOCKED_REVOLUTE_AXES,
+                vec![(JointAxis::AngX, limits)],
+            ),
+            SerializableJointKind::Prismatic { limits } => (
+                JointAxesMask::LOCKED_PRISMATIC_AXES,
+                vec![(JointAxis::X, limits)],
+            ),
+            SerializableJointKind::Spherical { limits } => (
+                JointAxesMask::LOCKED_SPHERICAL_AXES,
+                vec![
+                    (JointAxis::AngX, limits[0]),
+                    (JointAxis::AngY, limits[1]),
+                    (JointAxis::AngZ, limits[2]),
+                ],
+            ),
+        };
+        let mut generic = GenericJoint::new(locked_axes);
+        generic
+            .set_local_anchor1(joint.local_anchor1)
+            .set_local_basis1(joint.local_basis1)
+            .set_local_anchor2(joint.local_anchor2)
+            .set_local_basis2(joint.local_basis2)
+            .set_contacts_enabled(joint.contacts_enabled);
+        for (axis, limits) in limits {
+            if let Some(limits) = limits {
+                generic.set_limits(axis, limits);
+            }
+        }
+        generic
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Request;
+
+/// Whether the request can be part of a `Request::Transaction`: those that
+/// create, update or move bodies, colliders, joints and templates, which the
+/// server can put back as they were.
+pub fn allowed(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::CreateBodies(_)
+            | Request::CreateColliders(_)
+            | Request::CreateJoints(_)
+            | Request::RegisterTemplates(_)
+            | Request::SpawnInstances(_)
+            | Request::UpdateBodies(_)
+            | Request::UpdateColliders(_)
+            | Request::SetBodyTransforms(_)
+    )
+}
+
+/// Why a server rolled a transaction back, with the index in the transaction
+/// of the request that failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionError {
+    /// The request refers to something the world doesn't have, would create
+    /// something it already has, or isn't the session's to make in its room.
+    Failed { index: usize, reason: String },
+    /// The request isn't one a transaction can have, by name.
+    NotAllowed { index: usize, request: String },
+    /// The world couldn't be saved to roll back to, so nothing was applied.
+    Savepoint(String),
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed { index, reason } => write!(f, "request {} failed: {}", index, reason),
+            Self::NotAllowed { index, request } => {
+                write!(
+                    f,
+                    "request {} is a {}, which can't be rolled back",
+                    index, request
+                )
+            }
+            Self::Savepoint(err) => write!(f, "failed to save the world: {}", err),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The id of the world every session starts with, which requests that aren't
+/// wrapped in `Request::InWorld` act on.
+pub const DEFAULT_WORLD: u64 = 0;
+
+/// Why a server didn't create or destroy a world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorldError {
+    /// A world of the id already exists.
+    Exists(u64),
+    Unknown(u64),
+    /// The session's first world only goes away with the session.
+    Default,
+    /// A world can't be destroyed by a request acting on it.
+    InUse(u64),
+    /// The session already has as many worlds as the server allows.
+    TooMany(usize),
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exists(id) => write!(f, "world {} already exists", id),
+            Self::Unknown(id) => write!(f, "no world {}", id),
+            Self::Default => write!(f, "the default world can't be destroyed"),
+            Self::InUse(id) => write!(f, "world {} can't destroy itself", id),
+            Self::TooMany(max) => write!(f, "sessions can have at most {} worlds", max),
+        }
+    }
+}
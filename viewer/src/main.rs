@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    render::{mesh::PrimitiveTopology, view::NoFrustumCulling},
+};
+use bevy_rapier3d::{prelude::*, rapier::prelude::RigidBodyHandle};
+use clap::{arg, command, value_parser};
+
+use shared::recording::*;
+
+/// Number of past steps drawn behind every body.
+const TRAIL_LENGTH: usize = 120;
+
+#[derive(Resource, Default)]
+struct Recording {
+    /// The state of every body at every step. Steps leave out the bodies of
+    /// slower update rates and those that didn't fit the snapshot budget, so
+    /// these keep the state they were last recorded with until they're
+    /// removed.
+    steps: Vec<HashMap<RigidBodyHandle, (Transform, Velocity)>>,
+    bodies: HashMap<RigidBodyHandle, u64>,
+    colliders: Vec<RecordedCollider>,
+}
+
+#[derive(Resource, Default)]
+struct Playback {
+    step: usize,
+    paused: bool,
+    trails: bool,
+}
+
+#[derive(Component)]
+struct Body(RigidBodyHandle);
+
+#[derive(Component)]
+struct Trail {
+    body: RigidBodyHandle,
+    mesh: Handle<Mesh>,
+}
+
+fn main() {
+    let matches = command!()
+        .arg(arg!(<FILE> "The recording to play back").value_parser(value_parser!(String)))
+        .get_matches();
+
+    let path = matches.get_one::<String>("FILE").unwrap();
+    let entries = read_recording::<RecordEntry, _>(path).expect("Can't read recording");
+
+    let mut recording = Recording::default();
+    let mut latest = HashMap::new();
+    for entry in entries {
+        match entry {
+            RecordEntry::Bodies(bodies) => {
+                recording
+                    .bodies
+                    .extend(bodies.into_iter().map(|(id, handle)| (handle, id)));
+            }
+            RecordEntry::Colliders(colliders) => recording.colliders.extend(colliders),
+            RecordEntry::Step(step) => {
+                latest.extend(step);
+                recording.steps.push(latest.clone());
+            }
+            RecordEntry::Removed(bodies) => {
+                for handle in bodies {
+                    latest.remove(&handle);
+                }
+            }
+        }
+    }
+
+    println!(
+        "Loaded {} steps of {} bodies from {}",
+        recording.steps.len(),
+        recording.bodies.len(),
+        path
+    );
+    println!("Space: pause, Left/Right: scrub (hold Shift for x10), Home: restart, T: trails");
+
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .insert_resource(ClearColor(Color::rgb(0.9, 0.6, 0.3)))
+        .insert_resource(recording)
+        .insert_resource(Playback {
+            trails: true,
+            ..default()
+        })
+        .add_startup_system(setup_graphics)
+        .add_startup_system(setup_scene)
+        .add_system(playback_controls)
+        .add_system(apply_step.after(playback_controls))
+        .add_system(update_trails.after(apply_step))
+        .add_system(bevy::window::close_on_esc)
+        .run();
+}
+
+fn setup_graphics(mut commands: Commands) {
+    commands.spawn(Camera3dBundle {
+        projection: PerspectiveProjection {
+            fov: 50.0_f32.to_radians(),
+            ..default()
+        }
+        .into(),
+        transform: Transform::from_xyz(-10.0, 15.0, 25.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_translation(Vec3::new(1.0, 2.0, 3.0))
+            .looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+}
+
+fn setup_scene(
+    mut commands: Commands,
+    recording: Res<Recording>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let static_material = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.2, 0.5, 1.0),
+        perceptual_roughness: 0.3,
+        ..default()
+    });
+    let body_material = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.8, 0.2, 0.3),
+        perceptual_roughness: 0.6,
+        ..default()
+    });
+    let trail_material = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        unlit: true,
+        ..default()
+    });
+
+    let mut body_entities = HashMap::new();
+    for &handle in recording.bodies.keys() {
+        let entity = commands
+            .spawn((
+                SpatialBundle {
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                },
+                Body(handle),
+            ))
+            .id();
+        body_entities.insert(handle, entity);
+
+        let mesh = meshes.add(Mesh::new(PrimitiveTopology::LineStrip));
+        commands.spawn((
+            PbrBundle {
+                mesh: mesh.clone(),
+                material: trail_material.clone(),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            },
+            // Trails change every frame, so their bounds are never up to date
+            NoFrustumCulling,
+            Trail { body: handle, mesh },
+        ));
+    }
+
+    for collider in &recording.colliders {
+        let parent = collider
+            .parent
+            .and_then(|parent| body_entities.get(&parent).copied());
+
+        let material = if parent.is_some() {
+            body_material.clone()
+        } else {
+            static_material.clone()
+        };
+
+        let entity = commands
+            .spawn(PbrBundle {
+                mesh: meshes.add(shape_mesh(&collider.shape)),
+                material,
+                transform: collider.transform,
+                ..default()
+            })
+            .id();
+
+        if let Some(parent) = parent {
+            commands.entity(parent).add_child(entity);
+        }
+    }
+}
+
+fn playback_controls(
+    input: Res<Input<KeyCode>>,
+    recording: Res<Recording>,
+    mut playback: ResMut<Playback>,
+) {
+    let last_step = recording.steps.len().saturating_sub(1);
+    let stride = if input.pressed(KeyCode::LShift) {
+        10
+    } else {
+        1
+    };
+
+    if input.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if input.just_pressed(KeyCode::T) {
+        playback.trails = !playback.trails;
+    }
+    if input.just_pressed(KeyCode::Home) {
+        playback.step = 0;
+    }
+
+    if input.pressed(KeyCode::Right) {
+        playback.step = (playback.step + stride).min(last_step);
+    } else if input.pressed(KeyCode::Left) {
+        playback.step = playback.step.saturating_sub(stride);
+    } else if !playback.paused && playback.step < last_step {
+        playback.step += 1;
+    }
+}
+
+fn apply_step(
+    recording: Res<Recording>,
+    playback: Res<Playback>,
+    mut bodies: Query<(&Body, &mut Transform, &mut Visibility)>,
+) {
+    let step = match recording.steps.get(playback.step) {
+        Some(step) => step,
+        None => return,
+    };
+
+    for (body, mut transform, mut visibility) in bodies.iter_mut() {
+        match step.get(&body.0) {
+            Some((new_transform, _)) => {
+                transform.translation = new_transform.translation;
+                transform.rotation = new_transform.rotation;
+                visibility.is_visible = true;
+            }
+            None => visibility.is_visible = false,
+        }
+    }
+}
+
+fn update_trails(
+    recording: Res<Recording>,
+    playback: Res<Playback>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut trails: Query<(&Trail, &mut Visibility)>,
+) {
+    if recording.steps.is_empty() {
+        return;
+    }
+
+    let end = playback.step.min(recording.steps.len() - 1);
+    let start = end.saturating_sub(TRAIL_LENGTH);
+
+    for (trail, mut visibility) in trails.iter_mut() {
+        let positions: Vec<[f32; 3]> = recording.steps[start..=end]
+            .iter()
+            .filter_map(|step| step.get(&trail.body))
+            .map(|(transform, _)| transform.translation.to_array())
+            .collect();
+
+        visibility.is_visible = playback.trails && positions.len() >= 2;
+        if !visibility.is_visible {
+            continue;
+        }
+
+        if let Some(mesh) = meshes.get_mut(&trail.mesh) {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        }
+    }
+}